@@ -0,0 +1,106 @@
+use crate::config::Config;
+use crate::services::{FileService, TokenLocation, TokenStore, TOKEN_SERVICES};
+use anyhow::{Context, Result};
+use clap::Subcommand;
+use std::env;
+use std::io::{self, IsTerminal, Read};
+
+#[derive(Subcommand)]
+pub enum AuthCommands {
+    /// Store a registry token: prompted for on a terminal, otherwise read from stdin
+    Login {
+        /// Registry the token is for: gcs, github or gitlab. For gcs the token may
+        /// also be the contents of a service-account key or credentials file.
+        service: String,
+        /// Store the token in the credentials file even if a keyring is available
+        #[arg(long)]
+        plaintext: bool,
+    },
+    /// Forget a stored registry token
+    Logout {
+        /// Registry whose token to forget: gcs, github or gitlab
+        service: String,
+    },
+    /// Show where each registry's token comes from
+    Status,
+}
+
+// The environment variable that overrides a stored token of a service, if it is set
+fn env_override(service: &str) -> Option<&'static str> {
+    let name = match service {
+        "gcs" => "GOOGLE_APPLICATION_CREDENTIALS",
+        "github" => "GITHUB_TOKEN",
+        _ => "GITLAB_TOKEN",
+    };
+    env::var_os(name)
+        .filter(|value| !value.is_empty())
+        .map(|_| name)
+}
+
+impl AuthCommands {
+    pub fn execute(self, fs_service: &FileService) -> Result<()> {
+        let store = TokenStore::new(fs_service.data_dir.clone());
+
+        match self {
+            AuthCommands::Login { service, plaintext } => {
+                TokenStore::check_service(&service)?;
+                let token = if io::stdin().is_terminal() {
+                    rpassword::prompt_password(format!("Token for {}: ", service))?
+                } else {
+                    let mut token = String::new();
+                    io::stdin()
+                        .read_to_string(&mut token)
+                        .context("Failed to read the token from stdin")?;
+                    token
+                };
+                let token = token.trim();
+                if token.is_empty() {
+                    anyhow::bail!("No token given");
+                }
+
+                match store.save(&service, token, plaintext)? {
+                    TokenLocation::Keyring => {
+                        println!("Stored the {} token in the keyring", service)
+                    }
+                    TokenLocation::File => println!(
+                        "Stored the {} token in {}",
+                        service,
+                        fs_service.data_dir.join(Config::CREDENTIALS_FILE).display()
+                    ),
+                }
+                if let Some(name) = env_override(&service) {
+                    println!("{} is set and takes precedence over the stored token", name);
+                }
+                Ok(())
+            }
+            AuthCommands::Logout { service } => {
+                if store.delete(&service)? {
+                    println!("Forgot the {} token", service);
+                } else {
+                    println!("No {} token was stored", service);
+                }
+                Ok(())
+            }
+            AuthCommands::Status => {
+                for service in TOKEN_SERVICES {
+                    let source = match (env_override(service), store.load(service)?) {
+                        (Some(name), _) => format!("{} (environment)", name),
+                        (None, Some((_, TokenLocation::Keyring))) => "keyring".to_string(),
+                        (None, Some((_, TokenLocation::File))) => {
+                            Config::CREDENTIALS_FILE.to_string()
+                        }
+                        (None, None)
+                            if *service == "gcs"
+                                && Config::gcloud_adc_file().is_some_and(|path| path.is_file()) =>
+                        {
+                            "gcloud application default credentials".to_string()
+                        }
+                        (None, None) => "none".to_string(),
+                    };
+                    println!("{:<8} {}", service, source);
+                }
+                Ok(())
+            }
+        }
+    }
+}
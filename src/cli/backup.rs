@@ -0,0 +1,70 @@
+use crate::cli::commands::format_size;
+use crate::services::{BackupService, FileService, SupervisorService};
+use anyhow::Result;
+use clap::Subcommand;
+
+#[derive(Subcommand)]
+pub enum BackupCommands {
+    /// Write a backup of the app data, state and configuration file
+    Create {
+        /// Also upload it to the backup bucket (GEIST_BACKUP_URL or backup.url)
+        #[arg(long)]
+        upload: bool,
+    },
+    /// Put back a backup, replacing the current app data and configuration along with
+    /// its record of applied migrations
+    Restore {
+        /// Path of a backup, or the id of one in the backups directory or the bucket
+        #[arg(value_name = "FILE|REMOTE_ID")]
+        backup: String,
+        /// Restore even while the app is running
+        #[arg(long)]
+        force: bool,
+    },
+}
+
+impl BackupCommands {
+    pub fn execute(self, fs_service: &FileService) -> Result<()> {
+        let backups = BackupService::new(fs_service.data_dir.clone());
+
+        match self {
+            BackupCommands::Create { upload } => {
+                let path = backups.create()?;
+                println!(
+                    "Wrote {} ({})",
+                    path.display(),
+                    format_size(std::fs::metadata(&path)?.len())
+                );
+                if upload {
+                    let id = backups.upload(&path)?;
+                    println!("Uploaded as {}", id);
+                }
+                Ok(())
+            }
+            BackupCommands::Restore { backup, force } => {
+                let supervisor = SupervisorService::new(fs_service.data_dir.clone(), None);
+                if let Some(pid) = supervisor.running_app() {
+                    if !force {
+                        anyhow::bail!(
+                            "The app is running (pid {}); stop it first or pass --force",
+                            pid
+                        );
+                    }
+                }
+                let archive = backups.locate(&backup)?;
+                let info = backups.restore(&archive)?;
+                println!(
+                    "Restored the backup taken {} on {} (geist {}, version {})",
+                    info.created_at.format("%Y-%m-%d %H:%M:%S UTC"),
+                    info.hostname.as_deref().unwrap_or("an unknown host"),
+                    info.geist_version,
+                    info.current_version
+                );
+                if !info.config {
+                    println!("The backup held no configuration file; the current one was kept");
+                }
+                Ok(())
+            }
+        }
+    }
+}
@@ -0,0 +1,29 @@
+use crate::cli::commands::format_size;
+use crate::services::{BundleCache, FileService};
+use anyhow::Result;
+use clap::Subcommand;
+
+#[derive(Subcommand)]
+pub enum CacheCommands {
+    /// Remove every cached release bundle
+    Clean,
+}
+
+impl CacheCommands {
+    pub fn execute(self, fs_service: &FileService) -> Result<()> {
+        let cache = BundleCache::new(fs_service.data_dir.clone());
+
+        match self {
+            CacheCommands::Clean => {
+                let (count, _) = cache.usage()?;
+                let freed = cache.clean()?;
+                println!(
+                    "Removed {} cached release bundle(s), freeing {}",
+                    count,
+                    format_size(freed)
+                );
+                Ok(())
+            }
+        }
+    }
+}
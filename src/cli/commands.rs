@@ -1,22 +1,234 @@
+use crate::cli::auth::AuthCommands;
+use crate::cli::backup::BackupCommands;
+use crate::cli::cache::CacheCommands;
+use crate::cli::completions;
+use crate::cli::config::ConfigCommands;
+use crate::cli::fleet::FleetCommands;
+use crate::cli::keys::KeyCommands;
 use crate::cli::node::NodeCommands;
+use crate::cli::quarantine::QuarantineCommands;
+use crate::cli::releases::ReleasesCommands;
+use crate::cli::report::ReportCommands;
+use crate::cli::service::ServiceCommands;
 use crate::cli::topic::TopicCommands;
 use crate::config::Config;
+use crate::services::config_file;
+use crate::services::registry::{self, newest_release, open_registry};
+use crate::services::AppCapabilities;
+use crate::services::AppCgroup;
+use crate::services::AppService;
+use crate::services::BenchService;
+use crate::services::BinaryArch;
+use crate::services::BundleCache;
+use crate::services::BundleFormat;
+use crate::services::BundleOrigin;
+use crate::services::ConfigFile;
+use crate::services::ContainerImage;
+use crate::services::ContainerService;
+use crate::services::CosignPolicy;
+use crate::services::Cost;
+use crate::services::EventKind;
+use crate::services::EventService;
+use crate::services::ExportService;
 use crate::services::FileService;
-use crate::services::GcsService;
-use anyhow::Result;
+use crate::services::FileSide;
+use crate::services::Hold;
+use crate::services::HostService;
+use crate::services::KeyService;
+use crate::services::ManifestDiff;
+use crate::services::MeteredService;
+use crate::services::MigrationService;
+use crate::services::Plan;
+use crate::services::QuarantineRecord;
+use crate::services::QuarantineService;
+use crate::services::RegistryBackend;
+use crate::services::ReportService;
+use crate::services::RestartResult;
+use crate::services::Sandbox;
+use crate::services::Sbom;
+use crate::services::Slot;
+use crate::services::StateService;
+use crate::services::SupervisorService;
+use crate::services::TamperWatch;
+use crate::services::TargetsMetadata;
+use crate::services::UpgradeConstraint;
+use crate::services::UsbPolicy;
+use crate::services::UsbService;
+use crate::services::VersionDiff;
+use crate::services::VersionMark;
+use crate::services::{CurrentPointer, CurrentService};
+use anyhow::{Context, Result};
+use chrono::Utc;
 use clap::Subcommand;
+use clap_complete::ArgValueCandidates;
+use std::ffi::OsString;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
 use tempfile;
 
 #[derive(Subcommand)]
 pub enum Commands {
     /// Update to the specified version or the latest version if none is provided
-    Update { version: Option<String> },
-    /// Verify artifacts for the specified version
-    Verify { version: String },
-    /// Rollback to the specified version
-    Rollback { version: String },
+    Update {
+        /// Version, or a semver requirement such as "^1.4" or "~2.3.1" to install
+        /// the newest release it allows
+        #[arg(add = ArgValueCandidates::new(completions::known_versions))]
+        version: Option<String>,
+        /// Print the planned actions as JSON instead of updating
+        #[arg(long)]
+        dry_run: bool,
+        /// Registry to update from: gcs, github or gitlab (default: GEIST_REGISTRY_SOURCE, then gcs)
+        #[arg(long, value_name = "SOURCE")]
+        source: Option<String>,
+        /// Nobody is watching the update: refuse to start it when the power could
+        /// fail midway (low battery, under-voltage)
+        #[arg(long)]
+        unattended: bool,
+        /// Download the release bundle even on a metered connection or beyond the
+        /// data budget, and move off a version set with `pin`
+        #[arg(long)]
+        force: bool,
+        /// Install the release bundle without checking its detached signature;
+        /// for development builds only
+        #[arg(long)]
+        skip_signature: bool,
+        /// When the version can only be installed over a newer one than the
+        /// installed, install the next release on the way to it instead
+        #[arg(long)]
+        chain: bool,
+        /// Install a release bundle file instead of fetching one from a registry,
+        /// checked against the checksum file and signature next to it
+        #[arg(long, value_name = "BUNDLE", conflicts_with_all = ["version", "source", "chain"])]
+        from_file: Option<PathBuf>,
+    },
+    /// Verify a version: the installed copy by default, or the published release with --remote/--deep
+    Verify {
+        #[arg(add = ArgValueCandidates::new(completions::known_versions))]
+        version: String,
+        /// Recompute the hashes of the installed files and compare them with the
+        /// ones recorded at install time
+        #[arg(long, conflicts_with_all = ["remote", "deep"])]
+        local: bool,
+        /// Download and validate the release's checksum file and its signature
+        #[arg(long)]
+        remote: bool,
+        /// Download the release bundle to a temporary location and fully verify it
+        /// without installing; implies --remote
+        #[arg(long)]
+        deep: bool,
+        /// Registry to verify against: gcs, github or gitlab (default: GEIST_REGISTRY_SOURCE, then gcs)
+        #[arg(long, value_name = "SOURCE")]
+        source: Option<String>,
+        /// With --deep, download the release bundle even on a metered connection
+        /// or beyond the data budget
+        #[arg(long)]
+        force: bool,
+        /// Fail, rather than warn, when the release ships no SBOM or has files its
+        /// SBOM does not declare
+        #[arg(long)]
+        require_sbom: bool,
+    },
+    /// Print the software bill of materials of an installed version
+    Sbom {
+        #[arg(add = ArgValueCandidates::new(completions::installed_versions))]
+        version: String,
+        /// Print the SBOM as JSON
+        #[arg(long)]
+        json: bool,
+    },
+    /// Pack an installed version into a release bundle, e.g. for an offline device
+    Export {
+        #[arg(add = ArgValueCandidates::new(completions::installed_versions))]
+        version: String,
+        /// Bundle to write, compressed as its extension says (default:
+        /// release_bundle-<version>.tar.gz in the current directory)
+        #[arg(short, long, value_name = "PATH")]
+        output: Option<PathBuf>,
+    },
+    /// Roll back to an installed version, by default the last known-good one
+    Rollback {
+        #[arg(add = ArgValueCandidates::new(completions::installed_versions))]
+        version: Option<String>,
+        /// Roll back even to a version marked bad
+        #[arg(long)]
+        force: bool,
+    },
     /// Check the current status of the application
-    Status,
+    Status {
+        /// Print the status as JSON, including the sequence number of the latest event
+        #[arg(long)]
+        json: bool,
+    },
+    /// Print the state changes recorded after a sequence number as JSON
+    Events {
+        /// Only return events with a higher sequence number
+        #[arg(long, value_name = "N", default_value_t = 0)]
+        after_seq: u64,
+    },
+    /// List the versions installed in the data directory
+    Versions {
+        /// Print the versions with their install records as JSON
+        #[arg(long)]
+        json: bool,
+    },
+    /// Print the GitHub release notes between the current version and another
+    Changelog {
+        /// Version to read up to, or back to for an older one
+        #[arg(
+            add = ArgValueCandidates::new(completions::known_versions),
+            required_unless_present = "since_current"
+        )]
+        version: Option<String>,
+        /// Print the notes of every release newer than the current version
+        #[arg(long, conflicts_with = "version")]
+        since_current: bool,
+    },
+    /// Show the files and manifest entries that differ between two installed versions
+    Diff {
+        #[arg(add = ArgValueCandidates::new(completions::installed_versions))]
+        from: String,
+        #[arg(add = ArgValueCandidates::new(completions::installed_versions))]
+        to: String,
+        /// Print the differences as JSON
+        #[arg(long)]
+        json: bool,
+    },
+    /// Remove old versions, keeping the current and last known-good ones
+    Prune {
+        /// Number of most recent versions to keep
+        #[arg(long, default_value_t = 2)]
+        keep: usize,
+        /// Print the planned removals as JSON instead of removing anything
+        #[arg(long)]
+        dry_run: bool,
+    },
+    /// Freeze the device on a version: updates refuse to move off it until `unpin`
+    #[command(alias = "hold")]
+    Pin {
+        /// Installed version to pin (default: the current version)
+        #[arg(add = ArgValueCandidates::new(completions::installed_versions))]
+        version: Option<String>,
+        /// Why the device is pinned, shown by status and refused updates
+        #[arg(long)]
+        reason: Option<String>,
+    },
+    /// Release the pin set with `pin`
+    #[command(alias = "unhold")]
+    Unpin,
+    /// Vouch for an installed version, making it a rollback target again
+    MarkGood {
+        #[arg(add = ArgValueCandidates::new(completions::installed_versions))]
+        version: String,
+    },
+    /// Keep an installed version from ever being selected automatically
+    MarkBad {
+        #[arg(add = ArgValueCandidates::new(completions::installed_versions))]
+        version: String,
+        /// Why the version is bad, kept with the event
+        #[arg(long)]
+        reason: Option<String>,
+    },
     /// Delegate to node command implementation
     Node {
         #[command(subcommand)]
@@ -27,204 +239,1392 @@ pub enum Commands {
         #[command(subcommand)]
         command: TopicCommands,
     },
+    /// Browse the releases published in the registry
+    Releases {
+        #[command(subcommand)]
+        command: ReleasesCommands,
+    },
+    /// Store or forget the tokens used to read registries
+    Auth {
+        #[command(subcommand)]
+        command: AuthCommands,
+    },
+    /// Manage the keys trusted to sign updates
+    #[command(alias = "key")]
+    Keys {
+        #[command(subcommand)]
+        command: KeyCommands,
+    },
+    /// Inspect or clear bundles that failed verification or installation
+    Quarantine {
+        #[command(subcommand)]
+        command: QuarantineCommands,
+    },
+    /// Read and change the configuration files
+    #[command(after_long_help = config_file::precedence_help())]
+    Config {
+        #[command(subcommand)]
+        command: ConfigCommands,
+    },
+    /// Manage the cache of downloaded release bundles
+    Cache {
+        #[command(subcommand)]
+        command: CacheCommands,
+    },
+    /// Back up or restore the app data, state and configuration
+    Backup {
+        #[command(subcommand)]
+        command: BackupCommands,
+    },
+    /// Build and upload the device inventory report
+    Report {
+        #[command(subcommand)]
+        command: ReportCommands,
+    },
+    /// Run operations across the devices of an inventory
+    Fleet {
+        #[command(subcommand)]
+        command: FleetCommands,
+    },
+    /// Manage the systemd unit that runs the supervisor
+    Service {
+        #[command(subcommand)]
+        command: ServiceCommands,
+    },
     /// Run the application with the specified version or the default version if none is provided
-    Run { version: Option<String> },
+    Run {
+        #[arg(add = ArgValueCandidates::new(completions::installed_versions))]
+        version: Option<String>,
+        /// Replay a recorded session bag: the app is launched with `--replay <BAG>`
+        /// and GEIST_REPLAY_BAG to read its topics from the bag instead of the live
+        /// sensors, and geist feeds it the bag's messages over the control socket
+        #[arg(long, value_name = "BAG")]
+        replay: Option<PathBuf>,
+        /// Detach from the terminal, logging to the data directory
+        #[arg(long)]
+        daemon: bool,
+        /// Launch the app in its own mount and PID namespaces with a system call
+        /// allowlist, seeing only its version and the devices its manifest declares
+        #[arg(long)]
+        sandbox: bool,
+        /// Run an app built for another architecture, such as the ARM build on an
+        /// x86 laptop or CI runner, under QEMU user-mode emulation
+        #[arg(long, conflicts_with = "sandbox")]
+        emulate: bool,
+    },
+    /// Stop the running app and relaunch it, optionally switching to another installed version
+    Restart {
+        #[arg(add = ArgValueCandidates::new(completions::installed_versions))]
+        version: Option<String>,
+    },
+    /// Print the script that enables tab completion, including version names, for a shell
+    Completions {
+        /// bash, elvish, fish, powershell or zsh
+        shell: String,
+    },
+    /// Keep the current version running, restarting it when it crashes
+    Supervise {
+        /// Delay before the first restart after a crash, in seconds
+        #[arg(long, default_value_t = Config::SUPERVISE_INITIAL_BACKOFF_SECS)]
+        initial_backoff: u64,
+        /// Upper bound for the restart delay, in seconds
+        #[arg(long, default_value_t = Config::SUPERVISE_MAX_BACKOFF_SECS)]
+        max_backoff: u64,
+        /// Launch the app in its own mount and PID namespaces with a system call
+        /// allowlist, seeing only its version and the devices its manifest declares
+        #[arg(long)]
+        sandbox: bool,
+    },
+    /// Run several versions in turn and compare their performance
+    Bench {
+        /// Comma-separated list of installed versions to compare
+        #[arg(long, value_delimiter = ',', required = true)]
+        versions: Vec<String>,
+        /// How long to run each version, in seconds
+        #[arg(long, default_value_t = 60)]
+        duration: u64,
+        /// Replay the same recorded session bag to every version, as `run --replay`
+        /// does
+        #[arg(long, value_name = "BAG")]
+        replay: Option<PathBuf>,
+    },
+    /// Sets up the sandbox and runs the app in it; started by `run --sandbox`
+    #[command(hide = true)]
+    SandboxExec {
+        #[arg(trailing_var_arg = true, allow_hyphen_values = true, required = true)]
+        command: Vec<OsString>,
+    },
 }
 
 impl Commands {
-    pub fn execute(self) -> Result<()> {
+    pub fn execute(self, fs_service: &FileService) -> Result<()> {
         match self {
-            Commands::Update { version } => {
-                let target_version = version.unwrap_or_else(|| Config::DEFAULT_VERSION.to_string());
+            Commands::Update {
+                version,
+                dry_run,
+                source,
+                unattended,
+                force,
+                skip_signature,
+                chain,
+                from_file,
+            } => {
+                ConfigFile::check(&Config::config_files())?;
+                if let Some(bundle) = from_file {
+                    return update_from_file(
+                        fs_service,
+                        &bundle,
+                        dry_run,
+                        unattended,
+                        force,
+                        skip_signature,
+                    );
+                }
+                let registry = open_registry(source.as_deref(), &fs_service.data_dir)?;
+
+                // Registry requests the update can do without; what failed is reported at the end
+                let mut degraded = Vec::new();
+
+                // With signed metadata, nothing the registry says is trusted unless it lists it
+                let targets = if Config::signed_metadata() {
+                    Some(TargetsMetadata::fetch_verified(
+                        registry.as_ref(),
+                        &fs_service.data_dir,
+                    )?)
+                } else {
+                    None
+                };
+
+                let requested = version.unwrap_or_else(|| Config::DEFAULT_VERSION.to_string());
+                let target_version = if requested == Config::DEFAULT_VERSION {
+                    match &targets {
+                        Some(targets) => targets.latest().with_context(|| {
+                            format!("{} lists no releases", Config::TARGETS_METADATA_FILE)
+                        })?,
+                        // Falls back to the newest listed release, never to a release
+                        // literally named "latest"
+                        None => match registry.latest() {
+                            Ok(latest) if Config::version_offered(&latest) => latest,
+                            Ok(latest) => {
+                                tracing::info!(
+                                    "The registry's latest release {} is a prerelease; looking for the newest release",
+                                    latest
+                                );
+                                newest_release(registry.as_ref(), &semver::VersionReq::STAR)?
+                            }
+                            Err(e) => {
+                                tracing::warn!("Could not resolve the latest version: {:#}", e);
+                                let newest =
+                                    newest_release(registry.as_ref(), &semver::VersionReq::STAR)
+                                        .with_context(|| {
+                                            format!(
+                                                "Could not resolve the latest version ({:#})",
+                                                e
+                                            )
+                                        })?;
+                                degraded.push(format!(
+                                    "Latest version lookup failed ({:#}); picked {}, the newest listed release",
+                                    e, newest
+                                ));
+                                newest
+                            }
+                        },
+                    }
+                } else if let Some(requirement) = Config::version_requirement(&requested) {
+                    // The newest release the requirement allows, from the signed list
+                    // when there is one
+                    let newest = match &targets {
+                        Some(targets) => Config::newest_version(targets.versions(), &requirement)
+                            .with_context(|| {
+                            format!(
+                                "{} lists no release matching {}",
+                                Config::TARGETS_METADATA_FILE,
+                                requirement
+                            )
+                        })?,
+                        None => newest_release(registry.as_ref(), &requirement)?,
+                    };
+                    tracing::info!("{} resolved to {}", requested, newest);
+                    newest
+                } else {
+                    requested
+                };
+                let _span = tracing::info_span!("update", version = %target_version).entered();
                 tracing::info!("Updating to version: {}", target_version);
+                // Installed under v<version>, however it was given or resolved, which
+                // must sort and compare as semver
+                Config::parse_version(&target_version)?;
+                let target_version = format!("v{}", Config::normalize_version(&target_version));
 
-                let gcs = GcsService::new(String::new(), Config::REGISTRY_BASE_URL.to_string());
-                let data_dir = Config::data_dir();
+                let data_dir = fs_service.data_dir.clone();
                 tracing::info!("Using data_dir: {}", data_dir.display());
 
-                let fs_service = FileService::new(data_dir.clone());
+                check_hold(&data_dir, &target_version, force, unattended, dry_run)?;
+
+                // What the update moves away from, read before anything is fetched so a
+                // switch another process makes during the download is not undone
+                let previous = CurrentService::new(data_dir.clone()).load()?;
+
+                if Config::container_image().is_some() {
+                    return update_container(
+                        fs_service,
+                        &target_version,
+                        previous,
+                        targets.as_ref(),
+                        dry_run,
+                        unattended,
+                        skip_signature,
+                    );
+                }
+
+                // Releases whose migrations need an earlier release's data are reached
+                // one step at a time, each step booting before the next is installed
+                let installed_version = fs_service.current_version();
+                let target_version = if data_dir.join(&installed_version).is_dir() {
+                    let path = upgrade_path(
+                        registry.as_ref(),
+                        targets.as_ref(),
+                        &installed_version,
+                        &target_version,
+                    )?;
+                    match path.first() {
+                        None => target_version,
+                        Some(next) if chain => {
+                            println!(
+                                "{} cannot be installed over {}; installing {} first, on the way {} -> {}. Run the update again once it has booted.",
+                                target_version,
+                                installed_version,
+                                next,
+                                path.join(" -> "),
+                                target_version
+                            );
+                            format!("v{}", Config::normalize_version(next))
+                        }
+                        Some(next) => anyhow::bail!(
+                            "{} cannot be installed over {}: the device has to pass through {} first. Run 'geist update {}', or pass --chain to install the releases on the way one update at a time.",
+                            target_version,
+                            installed_version,
+                            path.join(" -> "),
+                            next
+                        ),
+                    }
+                } else {
+                    target_version
+                };
+                // A geist too old for the release's layout could install it wrongly
+                if let Some(constraint) = published_constraint(registry.as_ref(), &target_version)?
+                {
+                    constraint.check_supervisor()?;
+                }
 
                 // Verify permissions before starting
                 fs_service.verify_permissions()?;
 
+                let power_blocker = check_power(&data_dir, &target_version, unattended, dry_run)?;
+
                 // Strip the 'v' prefix if it exists when constructing paths
                 let normalized_version = target_version.trim_start_matches('v');
 
-                // Verify version exists
-                if !gcs.verify_version(normalized_version)? {
-                    anyhow::bail!("Version {} not found", target_version);
+                // A missing version is final; an unreachable check is left to the download
+                match registry.verify_version(normalized_version) {
+                    Ok(true) => {}
+                    Ok(false) => anyhow::bail!("Version {} not found", target_version),
+                    Err(e) => {
+                        tracing::warn!("Could not check that {} exists: {:#}", target_version, e);
+                        degraded.push(format!("Version check failed ({:#})", e));
+                    }
+                }
+
+                // The checksum file also tells which formats the bundle is published in;
+                // signed metadata replaces it, and a release it does not list is refused
+                let (expected, listed_size) = match &targets {
+                    Some(targets) => {
+                        let bundle = targets.bundle(normalized_version)?;
+                        (Ok((bundle.format, bundle.sha256)), Some(bundle.length))
+                    }
+                    None => (registry.expected_bundle_checksum(normalized_version), None),
+                };
+                let format = expected
+                    .as_ref()
+                    .map_or(BundleFormat::Gzip, |(format, _)| *format);
+                let expected_sha256 = expected.map(|(_, sha256)| sha256);
+
+                let bundle_size = match listed_size {
+                    Some(size) => Some(size),
+                    None => match registry.release_bundle_size(normalized_version, format) {
+                        Ok(size) => size,
+                        Err(e) => {
+                            tracing::warn!("Could not query the bundle size: {:#}", e);
+                            degraded.push(format!(
+                                "Bundle size query failed ({:#}); disk space was only checked after the download",
+                                e
+                            ));
+                            None
+                        }
+                    },
+                };
+                // Metered links only pay for a bundle that is not cached yet
+                let cache = BundleCache::new(data_dir.clone());
+                let metered = MeteredService::new(data_dir.clone());
+                let data_blocker = match expected_sha256
+                    .as_deref()
+                    .ok()
+                    .and_then(|sha256| cache.lookup(sha256))
+                {
+                    Some(_) => None,
+                    None => metered.download_blocker(bundle_size)?,
+                };
+
+                if dry_run {
+                    let mut plan = update_plan(
+                        registry.as_ref(),
+                        fs_service,
+                        &target_version,
+                        format,
+                        expected_sha256.as_deref().ok(),
+                        bundle_size,
+                    )?;
+                    if let Some(reason) = &data_blocker {
+                        let verdict = match (force, unattended) {
+                            (true, _) => "forced",
+                            (false, true) => "deferred",
+                            (false, false) => "needs --force",
+                        };
+                        plan.warn(format!("Bundle download {}: {}", verdict, reason));
+                    }
+                    for note in degraded {
+                        plan.warn(note);
+                    }
+                    if let Some(reason) = power_blocker {
+                        let verdict = if unattended { "deferred" } else { "at risk" };
+                        plan.warn(format!("Update {}: {}", verdict, reason));
+                    }
+                    return plan.print();
+                }
+
+                if let Some(reason) = &data_blocker {
+                    if !force && unattended {
+                        EventService::new(data_dir.clone()).record(
+                            EventKind::UpdateDeferred,
+                            Some(&target_version),
+                            Some(reason.clone()),
+                        );
+                        anyhow::bail!("Update to {} deferred: {}", target_version, reason);
+                    }
+                    if !force {
+                        anyhow::bail!(
+                            "Not downloading the release bundle of {}: {}. Pass --force to download it anyway",
+                            target_version,
+                            reason
+                        );
+                    }
+                    tracing::warn!("Downloading although {}", reason);
                 }
 
-                // Create temp directory and download release bundle
+                // Refuse to start an update that would fill the disk midway. Without a
+                // reported bundle size, the extracted size is checked after the download.
                 let temp_dir = tempfile::tempdir()?;
+                let estimate = fs_service.estimate_update_space(bundle_size.unwrap_or(0));
+                fs_service.ensure_update_space(&estimate, temp_dir.path(), &target_version)?;
+
+                // Download the release bundle into the temp directory
                 let bundle_path = temp_dir.path().join(Config::RELEASE_BUNDLE_NAME);
 
+                // A bundle cached by an earlier `verify --deep` or update is not downloaded again
                 tracing::info!("Downloading release bundle to: {}", bundle_path.display());
-                gcs.download_release_bundle(normalized_version, &bundle_path)?;
+                let fetched = cache.fetch(
+                    registry.as_ref(),
+                    normalized_version,
+                    format,
+                    expected_sha256.as_deref().ok(),
+                    &bundle_path,
+                )?;
+                if !fetched.from_cache {
+                    record_download(&metered, &bundle_path);
+                }
+                let downloaded_at = Utc::now();
+                let actual_sha256 = fetched.sha256;
 
-                // Extract and update files
-                tracing::info!("Extracting release bundle from: {}", bundle_path.display());
+                // Only a failed lookup is tolerated; a signature that does not verify is not
+                let signed_by = match checksum_signature(registry.as_ref(), normalized_version) {
+                    Ok(_) if skip_signature => None,
+                    Ok(signed) => checksum_signer(&data_dir, normalized_version, signed)?,
+                    Err(e) => {
+                        tracing::warn!("Could not look up the checksum signature: {:#}", e);
+                        degraded.push(format!(
+                            "Signature lookup failed ({:#}); the install record names no signer",
+                            e
+                        ));
+                        None
+                    }
+                };
+                let bundle_url = registry.release_bundle_url(normalized_version, format)?;
+                let mut origin = BundleOrigin {
+                    backend: registry.name().to_string(),
+                    source: bundle_url.clone(),
+                    signed_by,
+                    bundle_signed_by: None,
+                };
+
+                // Verify, extract and install; if any step fails the bundle is kept for forensics
+                let installed = (|| -> Result<()> {
+                    let expected = expected_sha256
+                        .as_ref()
+                        .map_err(|e| anyhow::anyhow!("{:#}", e))?;
+                    if skip_signature {
+                        tracing::warn!(
+                            "Not checking the release bundle signature (--skip-signature)"
+                        );
+                    } else {
+                        origin.bundle_signed_by = bundle_signer(
+                            registry.as_ref(),
+                            &data_dir,
+                            normalized_version,
+                            format,
+                            &bundle_path,
+                            &actual_sha256,
+                        )?;
+                    }
+                    fs_service.install_bundle(
+                        &bundle_path,
+                        temp_dir.path(),
+                        expected,
+                        &actual_sha256,
+                        &target_version,
+                        origin,
+                    )
+                })();
 
-                // Use the new extract_bundle_with_details method
-                let release_bundle_dir =
-                    fs_service.extract_bundle_with_details(&bundle_path, temp_dir.path())?;
+                if let Err(e) = installed {
+                    let record = QuarantineRecord {
+                        version: target_version.clone(),
+                        url: bundle_url,
+                        reason: format!("{:#}", e),
+                        expected_sha256: expected_sha256.ok(),
+                        actual_sha256: Some(actual_sha256),
+                        downloaded_at,
+                        quarantined_at: Utc::now(),
+                    };
+                    match QuarantineService::new(data_dir.clone()).quarantine(&bundle_path, &record)
+                    {
+                        Ok(path) => {
+                            tracing::error!("Release bundle quarantined at {}", path.display())
+                        }
+                        Err(qe) => tracing::warn!("Failed to quarantine release bundle: {}", qe),
+                    }
+                    return Err(e);
+                }
 
-                // Install the version
-                fs_service.install_version(&release_bundle_dir, target_version.as_str())?;
+                switch_to_installed(fs_service, &target_version, previous)?;
 
-                // Set as current version
-                if let Err(e) = Config::set_current_version(&target_version) {
-                    tracing::warn!("Failed to set current version: {}", e);
-                } else {
-                    tracing::info!("Set current version to: {}", target_version);
+                if !degraded.is_empty() {
+                    println!(
+                        "The registry was partially unavailable; the update went ahead without:"
+                    );
+                    for note in &degraded {
+                        println!("  - {}", note);
+                    }
                 }
 
                 Ok(())
             }
-            Commands::Verify { version } => {
-                tracing::info!("Verifying artifacts for version: {}", version);
+            Commands::Verify {
+                version,
+                local,
+                remote,
+                deep,
+                source,
+                force,
+                require_sbom,
+            } => {
+                let data_dir = fs_service.data_dir.clone();
 
-                let gcs = GcsService::new(String::new(), Config::REGISTRY_BASE_URL.to_string());
+                if !remote && !deep {
+                    tracing::info!("Verifying installed copy of version: {}", version);
+                    let mut problems = fs_service.verify_installed(&version)?;
+                    // A device cannot execute a binary built for another architecture,
+                    // which makes the copy as unusable as a damaged one
+                    let app = AppService::new(data_dir.join(&version));
+                    if let Ok(Some(binary)) = BinaryArch::of_binary(&app.binary_path()) {
+                        println!("roc_camera is built for {}", binary);
+                    }
+                    match app.arch_mismatch() {
+                        Ok(None) => {}
+                        Ok(Some(mismatch)) if !HostService::can_run_app() => println!(
+                            "{}; this host does not run the app, so that is expected",
+                            mismatch
+                        ),
+                        Ok(Some(mismatch)) => problems.push(mismatch),
+                        Err(e) => problems.push(format!("{:#}", e)),
+                    }
+                    // With --local the hash comparison says what changed, so it runs either way
+                    let changes = match local {
+                        true => fs_service.verify_hashes(&version)?,
+                        false => Vec::new(),
+                    };
+                    for problem in problems.iter().chain(&changes) {
+                        println!("{}", problem);
+                    }
+                    if !problems.is_empty() {
+                        anyhow::bail!("Installed copy of {} is damaged", version);
+                    }
+                    if !changes.is_empty() {
+                        anyhow::bail!(
+                            "{} file(s) of {} changed since it was installed",
+                            changes.len(),
+                            version
+                        );
+                    }
+                    println!("Installed copy of {} is intact", version);
+                    if local {
+                        println!(
+                            "Every file of {} matches its hash from install time",
+                            version
+                        );
+                    }
+                    check_sbom(&data_dir.join(&version), require_sbom)?;
+                    return Ok(());
+                }
 
-                if !gcs.verify_version(&version)? {
+                let _span = tracing::info_span!("verify", version = %version).entered();
+                tracing::info!("Verifying published artifacts for version: {}", version);
+
+                let registry = open_registry(source.as_deref(), &fs_service.data_dir)?;
+                let normalized_version = Config::normalize_version(&version);
+
+                if !registry.verify_version(&normalized_version)? {
                     anyhow::bail!("Version {} not found", version);
                 }
 
+                // The checksum file has to list the bundle with a well-formed digest
+                let checksums = registry.download_checksums(&normalized_version)?;
+                let format = registry.bundle_format(&normalized_version, &checksums);
+                let bundle_name = registry.published_bundle_name(&normalized_version, format);
+                let expected_sha256 = registry::find_checksum(&checksums, &bundle_name)
+                    .ok_or_else(|| anyhow::anyhow!("No checksum listed for {}", bundle_name))?;
+                if expected_sha256.len() != 64 || hex::decode(&expected_sha256).is_err() {
+                    anyhow::bail!(
+                        "Malformed checksum for {}: {}",
+                        bundle_name,
+                        expected_sha256
+                    );
+                }
+                println!("Checksum file lists {}", bundle_name);
+
+                // Once keys are trusted, an unsigned checksum file is a failure
+                let keys = KeyService::new(data_dir.clone());
+                match registry.download_checksums_signature(&normalized_version)? {
+                    Some(signature) => {
+                        let signer = keys.verify(checksums.as_bytes(), &signature)?;
+                        println!("Checksum file signed by trusted key {}", signer.key_id);
+                    }
+                    None if !keys.load()?.is_empty() => {
+                        anyhow::bail!("Checksum file for {} is not signed", version)
+                    }
+                    None => println!("Checksum file is not signed (no trusted keys configured)"),
+                }
+
+                // The signed metadata has to vouch for the same bundle
+                if Config::signed_metadata() {
+                    let targets = TargetsMetadata::fetch_verified(registry.as_ref(), &data_dir)?;
+                    let listed = targets.bundle(&normalized_version)?;
+                    if listed.format != format || listed.sha256 != expected_sha256 {
+                        anyhow::bail!(
+                            "{} lists {} with SHA-256 {}, the checksum file {} with {}",
+                            Config::TARGETS_METADATA_FILE,
+                            listed.format.file_name(&normalized_version),
+                            listed.sha256,
+                            bundle_name,
+                            expected_sha256
+                        );
+                    }
+                    println!(
+                        "Signed {} version {} lists the same bundle",
+                        Config::TARGETS_METADATA_FILE,
+                        targets.version
+                    );
+                }
+
+                if deep {
+                    let temp_dir = tempfile::tempdir()?;
+                    let bundle_path = temp_dir.path().join(Config::RELEASE_BUNDLE_NAME);
+                    let cache = BundleCache::new(data_dir.clone());
+                    let metered = MeteredService::new(data_dir.clone());
+                    if cache.lookup(&expected_sha256).is_none() {
+                        let size = registry
+                            .release_bundle_size(&normalized_version, format)
+                            .ok()
+                            .flatten();
+                        if let Some(reason) = metered.download_blocker(size)? {
+                            if !force {
+                                anyhow::bail!(
+                                    "Not downloading the release bundle of {}: {}. Pass --force to download it anyway",
+                                    version,
+                                    reason
+                                );
+                            }
+                            tracing::warn!("Downloading although {}", reason);
+                        }
+                    }
+
+                    tracing::info!("Downloading release bundle to: {}", bundle_path.display());
+                    let fetched = cache.fetch(
+                        registry.as_ref(),
+                        &normalized_version,
+                        format,
+                        Some(&expected_sha256),
+                        &bundle_path,
+                    )?;
+                    if !fetched.from_cache {
+                        record_download(&metered, &bundle_path);
+                    }
+
+                    let actual_sha256 = fetched.sha256;
+                    if actual_sha256 != expected_sha256 {
+                        anyhow::bail!(
+                            "Checksum mismatch for release bundle: expected {}, got {}",
+                            expected_sha256,
+                            actual_sha256
+                        );
+                    }
+                    println!("Release bundle matches its checksum");
+                    match bundle_signer(
+                        registry.as_ref(),
+                        &data_dir,
+                        &normalized_version,
+                        format,
+                        &bundle_path,
+                        &actual_sha256,
+                    )? {
+                        Some(signer) => println!("Release bundle signed by {}", signer),
+                        None => println!(
+                            "Release bundle signature not checked (unsigned, or no trusted keys configured)"
+                        ),
+                    }
+
+                    // Install into a scratch data directory to prove the bundle is complete
+                    let scratch = FileService::new(temp_dir.path().join("data"));
+                    let release_bundle_dir =
+                        scratch.extract_bundle_with_details(&bundle_path, temp_dir.path())?;
+                    scratch.install_version(&release_bundle_dir, &version)?;
+                    let problems = scratch.verify_installed(&version)?;
+                    if !problems.is_empty() {
+                        for problem in &problems {
+                            println!("{}", problem);
+                        }
+                        anyhow::bail!("Release bundle for {} is incomplete", version);
+                    }
+                    println!("Release bundle extracts to a complete installation");
+                    check_sbom(&scratch.data_dir.join(&version), require_sbom)?;
+                }
+
                 tracing::info!("Verification completed successfully!");
                 Ok(())
             }
-            Commands::Rollback { version: _ } => {
-                // tracing::info!("Rolling back to version: {}", version);
+            Commands::Sbom { version, json } => {
+                let version_dir = fs_service.data_dir.join(&version);
+                if !version_dir.is_dir() {
+                    anyhow::bail!("Version {} is not installed", version);
+                }
+                let sbom = Sbom::load(&version_dir)?
+                    .with_context(|| format!("Version {} ships no SBOM", version))?;
+                if json {
+                    println!("{}", serde_json::to_string_pretty(&sbom)?);
+                    return Ok(());
+                }
 
-                // let fs_service = FileService::new(data_dir);
+                println!("{:<32} {:<16} License", "Package", "Version");
+                for package in &sbom.packages {
+                    println!(
+                        "{:<32} {:<16} {}",
+                        package.name,
+                        package.version.as_deref().unwrap_or("-"),
+                        package.license.as_deref().unwrap_or("-")
+                    );
+                }
+                println!();
+                println!("{:<64}  File", "SHA-256");
+                for file in &sbom.files {
+                    println!(
+                        "{:<64}  {}",
+                        file.sha256.as_deref().unwrap_or("-"),
+                        file.path
+                    );
+                }
+                Ok(())
+            }
+            Commands::Export { version, output } => {
+                let output = output.unwrap_or_else(|| {
+                    PathBuf::from(
+                        BundleFormat::Gzip.file_name(&Config::normalize_version(&version)),
+                    )
+                });
+                let exported =
+                    ExportService::new(fs_service.data_dir.clone()).export(&version, &output)?;
+                println!(
+                    "Exported {} to {} ({}, sha256 {})",
+                    version,
+                    exported.path.display(),
+                    format_size(fs::metadata(&exported.path)?.len()),
+                    exported.sha256
+                );
+                match exported.original {
+                    true => println!("It is the bundle the version was installed from"),
+                    false => println!("Packed from the installed files"),
+                }
+                Ok(())
+            }
+            Commands::Rollback { version, force } => {
+                let data_dir = fs_service.data_dir.clone();
+                let current = fs_service.current_version();
+                let state = StateService::new(data_dir.clone()).load()?;
+                let version = match version {
+                    Some(version) => installed_version(fs_service, &version)?,
+                    // As an automatic rollback would pick it
+                    None => state
+                        .rollback_target(&current, |v| data_dir.join(v).is_dir())
+                        .context("No known-good version to roll back to; name one with 'geist rollback <version>'")?,
+                };
+                if version == current {
+                    anyhow::bail!("{} is already the current version", version);
+                }
+                if state.is_bad(&version) {
+                    if !force {
+                        anyhow::bail!(
+                            "{} is marked bad; pass --force to roll back to it anyway, or run 'geist mark-good {}' first",
+                            version,
+                            version
+                        );
+                    }
+                    tracing::warn!("Rolling back to {}, which is marked bad (--force)", version);
+                }
 
-                // fs_service.rollback_to_version(&version)?;
-                tracing::info!("Rollback completed successfully!");
+                tracing::info!("Rolling back from {} to {}", current, version);
+                fs_service.activate_version(&version)?;
+                CurrentService::new(data_dir.clone()).set(&version)?;
+                EventService::new(data_dir.clone()).record(
+                    EventKind::RolledBack,
+                    Some(&version),
+                    Some(format!("from {}", current)),
+                );
+                println!("Rolled back from {} to {}", current, version);
+                if SupervisorService::new(data_dir, None)
+                    .running_app()
+                    .is_some()
+                {
+                    println!(
+                        "The app keeps running {} until it is restarted, e.g. with 'geist restart'",
+                        current
+                    );
+                }
+                Ok(())
+            }
+            Commands::Pin { version, reason } => {
+                let version = installed_version(
+                    fs_service,
+                    &version.unwrap_or_else(|| fs_service.current_version()),
+                )?;
+                let hold = Hold {
+                    version: version.clone(),
+                    at: chrono::Utc::now(),
+                    reason,
+                };
+                StateService::new(fs_service.data_dir.clone())
+                    .update(|state| state.hold = Some(hold.clone()))?;
+                EventService::new(fs_service.data_dir.clone()).record(
+                    EventKind::Pinned,
+                    Some(&version),
+                    hold.reason.clone(),
+                );
+                println!("Device {}", hold.describe());
+                Ok(())
+            }
+            Commands::MarkGood { version } => {
+                let version = installed_version(fs_service, &version)?;
+                // Someone vouched for it, so earlier crashes no longer count towards a loop
+                StateService::new(fs_service.data_dir.clone()).update(|state| {
+                    state.mark_good(&version);
+                    state.crash_history.retain(|crash| crash.version != version);
+                })?;
+                EventService::new(fs_service.data_dir.clone()).record(
+                    EventKind::MarkedGood,
+                    Some(&version),
+                    None,
+                );
+                println!("Marked {} as good", version);
+                Ok(())
+            }
+            Commands::MarkBad { version, reason } => {
+                let version = installed_version(fs_service, &version)?;
+                StateService::new(fs_service.data_dir.clone())
+                    .update(|state| state.mark_bad(&version))?;
+                EventService::new(fs_service.data_dir.clone()).record(
+                    EventKind::MarkedBad,
+                    Some(&version),
+                    reason,
+                );
+                println!("Marked {} as bad", version);
+                if version == fs_service.current_version() {
+                    println!(
+                        "It is the current version and keeps running until it is switched away from, e.g. with 'geist restart <version>'"
+                    );
+                }
+                Ok(())
+            }
+            Commands::Unpin => {
+                let mut released = None;
+                StateService::new(fs_service.data_dir.clone())
+                    .update(|state| released = state.hold.take())?;
+                match released {
+                    Some(hold) => {
+                        EventService::new(fs_service.data_dir.clone()).record(
+                            EventKind::Unpinned,
+                            Some(&hold.version),
+                            None,
+                        );
+                        println!("Released the pin on {}", hold.version);
+                    }
+                    None => println!("The device is not pinned"),
+                }
                 Ok(())
             }
-            Commands::Status => {
+            Commands::Status { json } => {
                 tracing::info!("Checking application status");
 
+                let data_dir = fs_service.data_dir.clone();
+                let supervisor = SupervisorService::new(data_dir.clone(), None);
+                if json {
+                    let marker = FileService::new(data_dir.clone()).boot_marker()?;
+                    let state = StateService::new(data_dir.clone()).load()?;
+                    let current_version = fs_service.current_version();
+                    let current_capabilities =
+                        AppCapabilities::load(&data_dir.join(&current_version), &current_version);
+                    let current_generation = CurrentService::new(data_dir.clone()).generation()?;
+                    let data_usage = MeteredService::new(data_dir.clone()).usage()?;
+                    let status = serde_json::json!({
+                        "seq": EventService::new(data_dir).last_seq()?,
+                        "current_version": current_version,
+                        "current_generation": current_generation,
+                        "boot_marker": marker,
+                        "safe_mode": supervisor.safe_mode(),
+                        "supervisor_pid": supervisor.running_supervisor(),
+                        "app_pid": supervisor.running_app(),
+                        "resources": supervisor
+                            .running_app()
+                            .and_then(AppCgroup::of_process)
+                            .map(|cgroup| cgroup.usage()),
+                        "last_exit": state.last_exit,
+                        "last_known_good": state.last_known_good,
+                        "bad_versions": state.bad_versions,
+                        "good_versions": state.good_versions,
+                        "hold": state.hold,
+                        "migrations": state.migrations,
+                        "capabilities": current_capabilities.declared,
+                        "data_usage": {
+                            "metered": Config::metered(),
+                            "period_start": data_usage.period_start,
+                            "bytes": data_usage.bytes,
+                            "budget_bytes": Config::data_budget_bytes(),
+                        },
+                        "device": {
+                            "fake": HostService::fake_device().is_some(),
+                            "arch": HostService::arch(),
+                            "cpu_temperature_c": HostService::cpu_temperature(),
+                            "power": HostService::power_state(),
+                        },
+                    });
+                    println!("{}", serde_json::to_string_pretty(&status)?);
+                    return Ok(());
+                }
+
+                if let Some(reason) = supervisor.safe_mode() {
+                    println!("SAFE MODE: {}", reason);
+                    println!("The supervisor is not launching the app. Fix the cause, then run 'geist restart'.");
+                    println!();
+                }
+
                 // Get the current version using our new function
-                let current_version = Config::get_current_version();
+                let current_version = fs_service.current_version();
                 tracing::info!("Current version: {}", current_version);
 
                 println!("Current version: {}", current_version);
+                if let Some(hold) = StateService::new(data_dir.clone()).load()?.hold {
+                    println!("Hold: {}", hold.describe());
+                }
+
+                let marker = fs_service.boot_marker()?;
+                for slot in [Slot::A, Slot::B] {
+                    let version = marker.version(slot).map(String::as_str).unwrap_or("empty");
+                    let role = if marker.trial == Some(slot) {
+                        " (trial)"
+                    } else if marker.active == slot {
+                        " (active)"
+                    } else {
+                        ""
+                    };
+                    println!("Slot {}: {}{}", slot, version, role);
+                }
+
+                // Only known for an app in the cgroup that holds its resource limits
+                if let Some(cgroup) = supervisor.running_app().and_then(AppCgroup::of_process) {
+                    let usage = cgroup.usage();
+                    if let Some(current) = usage.memory_bytes {
+                        let mut memory = format_size(current);
+                        if let Some(limit) = usage.memory_limit_bytes {
+                            memory.push_str(&format!(" of {}", format_size(limit)));
+                        }
+                        if let Some(peak) = usage.memory_peak_bytes {
+                            memory.push_str(&format!(", peak {}", format_size(peak)));
+                        }
+                        println!("Memory: {}", memory);
+                    }
+                    if let Some(usec) = usage.cpu_usage_usec {
+                        let mut cpu = format!("{:.1}s", usec as f64 / 1_000_000.0);
+                        if let Some(percent) = usage.cpu_limit_percent {
+                            cpu.push_str(&format!(", limited to {}% CPU", percent));
+                        }
+                        println!("CPU time: {}", cpu);
+                    }
+                    if let Some(kills) = usage.oom_kills.filter(|kills| *kills > 0) {
+                        println!("Killed for reaching the memory limit: {} time(s)", kills);
+                    }
+                }
+                Ok(())
+            }
+            Commands::Completions { shell } => completions::write_registration(&shell),
+            Commands::SandboxExec { command } => Sandbox::exec(&command[0], &command[1..]),
+            Commands::Events { after_seq } => {
+                let page = EventService::new(fs_service.data_dir.clone()).after(after_seq)?;
+                println!("{}", serde_json::to_string_pretty(&page)?);
                 Ok(())
             }
-            Commands::Node { command } => command.execute(),
-            Commands::Topic { command } => command.execute(),
-            Commands::Run { version } => {
-                let data_dir = Config::data_dir();
-
-                // Determine which version to run
-                let target_version = match version {
-                    Some(v) => v,
-                    None => {
-                        // Find the latest version in the data directory
-                        let mut versions = Vec::new();
-                        for entry in std::fs::read_dir(&data_dir)? {
-                            let entry = entry?;
-                            if entry.file_type()?.is_dir() {
-                                if let Some(name) = entry.file_name().to_str() {
-                                    if name.starts_with('v') {
-                                        versions.push(name.to_string());
-                                    }
-                                }
+            Commands::Changelog {
+                version,
+                since_current: _,
+            } => {
+                let current = fs_service.current_version();
+                let current_semver = Config::parse_version(&current)?;
+                let target = version.as_deref().map(Config::parse_version).transpose()?;
+                // Going back undoes the releases after the target, up to the current one
+                let (after, up_to) = match &target {
+                    Some(target) if *target < current_semver => {
+                        (target.clone(), Some(current_semver.clone()))
+                    }
+                    Some(target) => (current_semver.clone(), Some(target.clone())),
+                    None => (current_semver.clone(), None),
+                };
+
+                let github = registry::open_github(&fs_service.data_dir)?;
+                let mut releases: Vec<_> = github
+                    .releases()?
+                    .into_iter()
+                    .filter_map(|release| {
+                        let version = Config::parse_version(&release.tag_name).ok()?;
+                        let in_range =
+                            version > after && up_to.as_ref().is_none_or(|up_to| version <= *up_to);
+                        // Prereleases only count when asked for by name
+                        let offered = Config::version_offered(&release.tag_name)
+                            || target.as_ref() == Some(&version);
+                        (in_range && offered).then_some((version, release))
+                    })
+                    .collect();
+                releases.sort_by(|a, b| b.0.cmp(&a.0));
+
+                match &target {
+                    Some(target) if *target < current_semver => {
+                        println!("Going back from {} to {} undoes:", current, target)
+                    }
+                    Some(target) => println!("Release notes from {} to {}:", current, target),
+                    None => println!("Release notes since {}:", current),
+                }
+                if releases.is_empty() {
+                    match target {
+                        Some(_) => println!("No releases in between"),
+                        None => println!("No newer releases"),
+                    }
+                }
+                for (_, release) in releases {
+                    println!();
+                    match release.published_at {
+                        Some(at) => {
+                            println!("{} ({})", release.tag_name, at.format("%Y-%m-%d"))
+                        }
+                        None => println!("{}", release.tag_name),
+                    }
+                    match release.body.as_deref().map(str::trim) {
+                        Some(body) if !body.is_empty() => {
+                            for line in body.lines() {
+                                println!("  {}", line.trim_end());
                             }
                         }
+                        _ => println!("  No release notes"),
+                    }
+                }
+                Ok(())
+            }
+            Commands::Diff { from, to, json } => {
+                let diff = VersionDiff::between(fs_service, &from, &to)?;
+                if json {
+                    println!("{}", serde_json::to_string_pretty(&diff)?);
+                    return Ok(());
+                }
 
-                        if versions.is_empty() {
-                            anyhow::bail!("No versions found. Please run 'update' first.");
+                if diff.files.is_empty() {
+                    println!("No files differ between {} and {}", from, to);
+                } else {
+                    println!("Files that differ from {} to {}:", from, to);
+                    let describe = |side: &FileSide| {
+                        format!("{} {}", format_size(side.size), &side.sha256[..12])
+                    };
+                    for change in &diff.files {
+                        match (&change.from, &change.to) {
+                            (Some(from), Some(to)) => println!(
+                                "  ~ {} ({} -> {})",
+                                change.path,
+                                describe(from),
+                                describe(to)
+                            ),
+                            (Some(from), None) => {
+                                println!("  - {} ({})", change.path, describe(from))
+                            }
+                            (None, Some(to)) => println!("  + {} ({})", change.path, describe(to)),
+                            (None, None) => {}
                         }
-
-                        // Sort versions to find the latest
-                        versions.sort();
-                        versions.last().unwrap().clone()
                     }
-                };
+                }
+                match &diff.manifest {
+                    Some(manifest) => manifest.print(),
+                    None => println!("No manifest.yaml to compare in both versions"),
+                }
+                Ok(())
+            }
+            Commands::Versions { json } => {
+                let versions = fs_service.list_versions()?;
 
-                tracing::info!("Running version: {}", target_version);
+                if json {
+                    println!("{}", serde_json::to_string_pretty(&versions)?);
+                    return Ok(());
+                }
+
+                if versions.is_empty() {
+                    println!("No versions installed. Please run 'update' first.");
+                    return Ok(());
+                }
+
+                let current_version = fs_service.current_version();
+                println!(
+                    "{:<2}{:<16} {:<20} {:>10}  {:<9} MARK",
+                    "", "VERSION", "INSTALLED", "SIZE", "MANIFEST"
+                );
+                for installed in versions {
+                    let marker = if installed.version == current_version {
+                        "*"
+                    } else {
+                        ""
+                    };
+                    let installed_at = installed
+                        .installed_at
+                        .map(|t| t.format("%Y-%m-%d %H:%M:%S").to_string())
+                        .unwrap_or_else(|| "unknown".to_string());
+                    let manifest = if installed.has_manifest {
+                        "yes"
+                    } else {
+                        "missing"
+                    };
+                    let mark = match installed.mark {
+                        Some(VersionMark::Good) => "good",
+                        Some(VersionMark::Bad) => "bad",
+                        None => "-",
+                    };
+                    println!(
+                        "{:<2}{:<16} {:<20} {:>10}  {:<9} {}",
+                        marker,
+                        installed.version,
+                        installed_at,
+                        format_size(installed.size_bytes),
+                        manifest,
+                        mark
+                    );
+                }
+                Ok(())
+            }
+            Commands::Restart { version } => {
+                let data_dir = fs_service.data_dir.clone();
+                let supervisor = SupervisorService::new(data_dir.clone(), None);
+
+                // Switching versions goes through the boot marker so the supervisor picks it up
+                if let Some(version) = &version {
+                    FileService::new(data_dir.clone()).activate_version(version)?;
+                    CurrentService::new(data_dir).set(version)?;
+                    tracing::info!("Switched current version to {}", version);
+                }
 
-                // Check if the version exists
-                let version_dir = data_dir.join(&target_version);
-                if !version_dir.exists() {
+                if let Some(pid) = supervisor.running_supervisor() {
+                    let current = fs_service.current_version();
+                    match supervisor.request_restart()? {
+                        Some(RestartResult::Relaunched) => {
+                            println!("The supervisor (pid {}) relaunched {}", pid, current)
+                        }
+                        Some(RestartResult::HandedOff { to }) => println!(
+                            "The supervisor (pid {}) handed the app's sockets over to {}",
+                            pid, to
+                        ),
+                        Some(RestartResult::Stopped) => println!(
+                            "The supervisor (pid {}) did not respond in time; stopped the app, which it relaunches as {}",
+                            pid, current
+                        ),
+                        Some(RestartResult::Kept { to }) => anyhow::bail!(
+                            "{} did not take over from the running instance, which keeps running; see the supervisor's log",
+                            to
+                        ),
+                        Some(RestartResult::Declined) => anyhow::bail!(
+                            "The app did not acknowledge the stop and keeps running; the restart was dropped"
+                        ),
+                        None => println!(
+                            "Asked the supervisor (pid {}) to relaunch {}; it has not reported the outcome yet",
+                            pid, current
+                        ),
+                    }
+                    return Ok(());
+                }
+
+                // Nothing is supervising the app, so relaunch it here like `run` does
+                if !HostService::can_run_app() {
+                    anyhow::bail!(
+                        "No supervisor is running, and relaunching requires an ARM host such as the Raspberry Pi to execute the 'roc_camera' binary; pass --force-run to try on this {} host anyway",
+                        HostService::arch()
+                    );
+                }
+                supervisor.stop_app();
+                supervisor.leave_safe_mode();
+                let (target_version, on_trial) = supervisor.boot_target(None)?;
+                let _span = tracing::info_span!("restart", version = %target_version).entered();
+                if !on_trial {
+                    supervisor.make_current(&target_version);
+                }
+                supervisor.run(target_version, on_trial)
+            }
+            Commands::Supervise {
+                initial_backoff,
+                max_backoff,
+                sandbox,
+            } => {
+                ConfigFile::check(&Config::config_files())?;
+                if !HostService::can_run_app() {
                     anyhow::bail!(
-                        "Version {} not found. Please run 'update {}' first.",
-                        target_version,
-                        target_version
+                        "Supervision requires an ARM host such as the Raspberry Pi to execute the 'roc_camera' binary; pass --force-run to try on this {} host anyway",
+                        HostService::arch()
                     );
                 }
 
-                // Record this as the current version
-                if let Err(e) = Config::set_current_version(&target_version) {
-                    tracing::warn!("Failed to set current version: {}", e);
+                // Through the environment, where GEIST_SANDBOX turns it on as well
+                if sandbox {
+                    std::env::set_var("GEIST_SANDBOX", "1");
                 }
 
-                // Find the binary
-                let binary_path = version_dir.join("roc_camera");
-                if !binary_path.exists() {
-                    anyhow::bail!("Binary not found for version {}", target_version);
+                let data_dir = fs_service.data_dir.clone();
+                let supervisor = SupervisorService::new(data_dir.clone(), None);
+                let safe_mode = supervisor.record_daemon_start()?;
+
+                // Offline operations from USB sticks only run when the policy allows some,
+                // and never in safe mode
+                let policy = UsbPolicy::from_config();
+                if !safe_mode && policy.allows_anything() {
+                    let usb = UsbService::new(data_dir.clone(), policy);
+                    std::thread::spawn(move || {
+                        usb.watch(Duration::from_secs(Config::USB_POLL_SECS))
+                    });
                 }
 
-                // Check if running on Raspberry Pi
-                #[cfg(target_arch = "arm")]
-                {
-                    // Make sure the binary is executable
-                    #[cfg(unix)]
-                    {
-                        use std::os::unix::fs::PermissionsExt;
-                        let mut perms = std::fs::metadata(&binary_path)?.permissions();
-                        perms.set_mode(0o755);
-                        std::fs::set_permissions(&binary_path, perms)?;
-                    }
+                // Reports are opt-in, and also sent in safe mode, where they matter most
+                if let Some(url) = Config::report_url() {
+                    let reports = ReportService::new(data_dir.clone(), Some(url));
+                    std::thread::spawn(move || reports.run_periodically());
+                }
 
-                    // Use the actual assets in the data directory instead of creating symlinks
-                    let flutter_assets_path = version_dir.join("roc_camera_app");
+                // Tamper checks are opt-in since hashing the installed tree costs I/O and CPU
+                if let Some(interval) = Config::tamper_check_interval() {
+                    let watch = TamperWatch::new(data_dir.clone());
+                    std::thread::spawn(move || watch.run_periodically(interval));
+                }
 
-                    if !flutter_assets_path.exists() {
-                        tracing::error!(
-                            "Flutter assets directory doesn't exist at: {}",
-                            flutter_assets_path.display()
-                        );
-                        return Err(anyhow::anyhow!("Flutter assets directory not found"));
-                    }
+                supervisor.supervise(
+                    Duration::from_secs(initial_backoff),
+                    Duration::from_secs(max_backoff),
+                )
+            }
+            Commands::Bench {
+                versions,
+                duration,
+                replay,
+            } => {
+                if !HostService::can_run_app() {
+                    anyhow::bail!(
+                        "Benchmarks require an ARM host such as the Raspberry Pi to execute the 'roc_camera' binary; pass --force-run to try on this {} host anyway",
+                        HostService::arch()
+                    );
+                }
 
-                    tracing::info!(
-                        "Using Flutter assets from: {}",
-                        flutter_assets_path.display()
+                let replay_bag =
+                    match replay {
+                        Some(bag) => Some(bag.canonicalize().map_err(|_| {
+                            anyhow::anyhow!("Replay bag not found: {}", bag.display())
+                        })?),
+                        None => None,
+                    };
+
+                let bench = BenchService::new(fs_service.data_dir.clone());
+                let mut results = Vec::new();
+                for version in &versions {
+                    results.push(bench.run_version(
+                        version,
+                        Duration::from_secs(duration),
+                        replay_bag.as_deref(),
+                    )?);
+                }
+
+                let format_metric = |value: Option<f64>| {
+                    value
+                        .map(|v| format!("{:.1}", v))
+                        .unwrap_or_else(|| "n/a".to_string())
+                };
+
+                println!(
+                    "{:<16} {:>8} {:>8} {:>12} {:>12} {:>8} {:>12}",
+                    "VERSION", "SAMPLES", "CPU %", "AVG RSS", "PEAK RSS", "FPS", "LATENCY ms"
+                );
+                for result in &results {
+                    println!(
+                        "{:<16} {:>8} {:>8.1} {:>12} {:>12} {:>8} {:>12}{}",
+                        result.version,
+                        result.samples,
+                        result.avg_cpu_percent,
+                        format_size(result.avg_rss_bytes),
+                        format_size(result.peak_rss_bytes),
+                        format_metric(result.avg_fps),
+                        format_metric(result.avg_latency_ms),
+                        if result.exited_early {
+                            "  (exited early)"
+                        } else {
+                            ""
+                        }
                     );
+                }
+                Ok(())
+            }
+            Commands::Prune { keep, dry_run } => {
+                let data_dir = fs_service.data_dir.clone();
+                let state = StateService::new(data_dir.clone()).load()?;
 
-                    // Run the binary
-                    tracing::info!("Executing binary: {}", binary_path.display());
-                    let mut command = std::process::Command::new(&binary_path);
+                // Never remove the running version, the rollback target, or a slot's version
+                let mut protected = vec![fs_service.current_version()];
+                protected.extend(state.last_known_good);
+                protected.extend(state.hold.map(|hold| hold.version));
+                protected.extend(fs_service.boot_marker()?.versions());
 
-                    // Set current directory to the version directory
-                    command.current_dir(&version_dir);
+                let candidates = fs_service.prune_candidates(keep, &protected)?;
+                if dry_run {
+                    let mut plan = Plan::new(&format!("prune --keep {}", keep));
+                    for version in &candidates {
+                        let size = FileService::size_of(&data_dir.join(version)).ok();
+                        plan.add("remove", version, size, Cost::Free);
+                    }
+                    return plan.print();
+                }
+
+                for version in &candidates {
+                    fs_service.remove_version(version)?;
+                    println!("Removed {}", version);
+                }
 
-                    // Add environment variables that point to the actual assets location
-                    command.env("FLUTTER_ASSETS_DIR", &flutter_assets_path);
-                    command.env("FLUTTER_ASSET_DIR", &flutter_assets_path);
-                    command.env("FLUTTER_BUNDLE_DIR", &flutter_assets_path);
-                    command.env("FLUTTER_APP_DIR", &flutter_assets_path);
-                    command.env("FLUTTER_PI_APP_DIR", &flutter_assets_path);
-                    command.env("APP_DIR", &flutter_assets_path);
+                // The bundle cache is held to its size limit here too, in case the limit was lowered
+                let freed = BundleCache::new(data_dir).gc()?;
+                if freed > 0 {
+                    println!("Freed {} of cached release bundles", format_size(freed));
+                } else if candidates.is_empty() {
+                    println!("Nothing to prune.");
+                }
+                Ok(())
+            }
+            Commands::Node { command } => command.execute(fs_service),
+            Commands::Topic { command } => command.execute(fs_service),
+            Commands::Releases { command } => command.execute(fs_service),
+            Commands::Auth { command } => command.execute(fs_service),
+            Commands::Keys { command } => command.execute(fs_service),
+            Commands::Quarantine { command } => command.execute(fs_service),
+            Commands::Config { command } => command.execute(),
+            Commands::Cache { command } => command.execute(fs_service),
+            Commands::Backup { command } => command.execute(fs_service),
+            Commands::Report { command } => command.execute(fs_service),
+            Commands::Fleet { command } => command.execute(),
+            Commands::Service { command } => command.execute(),
+            Commands::Run {
+                version,
+                replay,
+                daemon,
+                sandbox,
+                emulate,
+            } => {
+                let data_dir = fs_service.data_dir.clone();
+                if sandbox {
+                    std::env::set_var("GEIST_SANDBOX", "1");
+                }
+                if emulate {
+                    std::env::set_var("GEIST_EMULATE", "1");
+                }
 
-                    // Pass the flutter assets directory as a command-line argument
-                    command.arg("--flutter-assets-dir");
-                    command.arg(&flutter_assets_path);
+                if daemon {
+                    let supervisor = SupervisorService::new(data_dir.clone(), None);
+                    if let Some(pid) = supervisor.running_supervisor() {
+                        anyhow::bail!("Already running as pid {}", pid);
+                    }
 
-                    let status = command.status()?;
+                    // Relaunch the same command line without --daemon in the background
+                    let args: Vec<_> = std::env::args_os()
+                        .skip(1)
+                        .filter(|arg| arg != "--daemon")
+                        .collect();
+                    let log_file = data_dir.join(Config::DAEMON_LOG_FILE);
+                    let pid = HostService::spawn_detached(&args, &log_file)?;
 
-                    if !status.success() {
-                        anyhow::bail!("Process exited with status: {}", status);
+                    println!("Started in the background as pid {}", pid);
+                    println!("Logging to {}", log_file.display());
+                    return Ok(());
+                }
+
+                // Resolve the replay bag up front so the app gets an absolute path
+                let replay_bag = match replay {
+                    Some(bag) => {
+                        if !bag.is_file() {
+                            anyhow::bail!("Replay bag not found: {}", bag.display());
+                        }
+                        Some(bag.canonicalize()?)
                     }
+                    None => None,
+                };
+
+                let supervisor = SupervisorService::new(data_dir.clone(), replay_bag.clone());
+                let (target_version, on_trial) = supervisor.boot_target(version)?;
+
+                let _span = tracing::info_span!("run", version = %target_version).entered();
+                tracing::info!("Running version: {}", target_version);
+
+                // Record this as the current version, unless it still has to pass its trial boot
+                if !on_trial {
+                    supervisor.make_current(&target_version);
                 }
 
-                // If not on Raspberry Pi, show a message
-                #[cfg(not(target_arch = "arm"))]
-                {
-                    tracing::info!("Binary is compiled for Raspberry Pi and cannot be executed on this system.");
+                // Check if running on an ARM host such as the Raspberry Pi
+                if HostService::can_run_app() {
+                    supervisor.run(target_version, on_trial)?;
+                } else {
+                    // Otherwise, show a message
+                    let version_dir = data_dir.join(&target_version);
+                    tracing::info!(
+                        "Binary is compiled for Raspberry Pi and cannot be executed on this {} system.",
+                        HostService::arch()
+                    );
+                    tracing::info!("Pass --emulate to run the installed build under QEMU, or, with a build of roc_camera for this system installed, --force-run to execute it anyway.");
                     tracing::info!(
                         "The application has been successfully installed at: {}",
                         version_dir.display()
@@ -233,7 +1633,15 @@ impl Commands {
 
                     // Print the command that would be executed on a Raspberry Pi
                     tracing::info!("On a Raspberry Pi, the following command would be executed:");
-                    tracing::info!("cd {} && ./roc_camera", version_dir.display());
+                    match &replay_bag {
+                        Some(bag) => tracing::info!(
+                            "cd {} && GEIST_REPLAY_BAG={} ./roc_camera --replay {}",
+                            version_dir.display(),
+                            bag.display(),
+                            bag.display()
+                        ),
+                        None => tracing::info!("cd {} && ./roc_camera", version_dir.display()),
+                    }
                 }
 
                 Ok(())
@@ -241,3 +1649,966 @@ impl Commands {
         }
     }
 }
+
+// Refuses an update against a pin or hold unless forced; an unattended one is
+// recorded as deferred
+fn check_hold(
+    data_dir: &Path,
+    target_version: &str,
+    force: bool,
+    unattended: bool,
+    dry_run: bool,
+) -> Result<()> {
+    let state = StateService::new(data_dir.to_path_buf()).load()?;
+    let Some(hold) = state.hold_against(target_version) else {
+        return Ok(());
+    };
+    if !force {
+        if unattended && !dry_run {
+            EventService::new(data_dir.to_path_buf()).record(
+                EventKind::UpdateDeferred,
+                Some(target_version),
+                Some(hold.describe()),
+            );
+        }
+        anyhow::bail!(
+            "Not updating to {}: the device is {}. Run 'geist unpin' or pass --force.",
+            target_version,
+            hold.describe()
+        );
+    }
+    tracing::warn!("Updating although the device is {}", hold.describe());
+    Ok(())
+}
+
+// Losing power mid-write can brick the device, so unattended updates wait for it.
+// Returns what puts the update at risk, for the plan of a dry run.
+fn check_power(
+    data_dir: &Path,
+    target_version: &str,
+    unattended: bool,
+    dry_run: bool,
+) -> Result<Option<String>> {
+    let power_blocker =
+        HostService::power_state().update_blocker(Config::min_update_battery_percent());
+    if let Some(reason) = &power_blocker {
+        if unattended && !dry_run {
+            EventService::new(data_dir.to_path_buf()).record(
+                EventKind::UpdateDeferred,
+                Some(target_version),
+                Some(reason.clone()),
+            );
+            anyhow::bail!("Update to {} deferred: {}", target_version, reason);
+        }
+        tracing::warn!("Updating although {}", reason);
+    }
+    Ok(power_blocker)
+}
+
+// In container mode the release is the image tagged with the version, which the
+// runtime pulls and checks against its digest instead of a bundle from the registry
+fn update_container(
+    fs_service: &FileService,
+    target_version: &str,
+    previous: Option<CurrentPointer>,
+    targets: Option<&TargetsMetadata>,
+    dry_run: bool,
+    unattended: bool,
+    skip_signature: bool,
+) -> Result<()> {
+    let data_dir = fs_service.data_dir.clone();
+    fs_service.verify_permissions()?;
+    let power_blocker = check_power(&data_dir, target_version, unattended, dry_run)?;
+    let reference = ContainerService::reference(target_version)?;
+
+    if dry_run {
+        let mut plan = Plan::new(&format!("update {}", target_version));
+        plan.add("pull", &reference, None, Cost::Download);
+        plan_install(&mut plan, fs_service, target_version, None)?;
+        if let Err(e) = ContainerService::runtime() {
+            plan.warn(format!("{:#}", e));
+        }
+        if let Some(reason) = power_blocker {
+            let verdict = if unattended { "deferred" } else { "at risk" };
+            plan.warn(format!("Update {}: {}", verdict, reason));
+        }
+        return plan.print();
+    }
+
+    let image = ContainerService::new(data_dir.clone()).install(target_version, |image| {
+        if skip_signature {
+            tracing::warn!("Not checking the container image signature (--skip-signature)");
+            return Ok(None);
+        }
+        image_signer(&data_dir, target_version, image, targets)
+    })?;
+    println!("Pulled {} as {}", image.reference, image.pinned);
+    switch_to_installed(fs_service, target_version, previous)
+}
+
+// Takes a freshly installed version the rest of the way: shows what its manifest
+// changes, checks its upgrade constraints, migrates the app data and stages it
+// for a trial boot, or makes it current where there is nothing to fall back to.
+// `previous` is the current pointer as read before the version was fetched.
+fn switch_to_installed(
+    fs_service: &FileService,
+    target_version: &str,
+    previous: Option<CurrentPointer>,
+) -> Result<()> {
+    let data_dir = fs_service.data_dir.clone();
+    // Show what the new version changes before it is switched to
+    let target_manifest = fs::read_to_string(data_dir.join(target_version).join("manifest.yaml"));
+    match target_manifest
+        .map_err(anyhow::Error::from)
+        .and_then(|manifest| manifest_diff(&data_dir, target_version, &manifest))
+    {
+        Ok(Some(diff)) => diff.print(),
+        Ok(None) => {}
+        Err(e) => tracing::warn!("Could not compare the manifests: {:#}", e),
+    }
+
+    // The version we are moving away from becomes the rollback target
+    let current = CurrentService::new(data_dir.clone());
+    let previous_version = fs_service.current_version();
+
+    // Catches constraints of releases that publish no manifest next to the bundle
+    if let Some(constraint) =
+        UpgradeConstraint::load(&data_dir.join(target_version), target_version)?
+    {
+        // Extracted by a geist that may not know its layout, so not kept
+        if let Err(e) = constraint.check_supervisor() {
+            if previous_version != target_version {
+                fs_service.remove_version(target_version)?;
+            }
+            return Err(e);
+        }
+        if let Some(min) = constraint.min_upgrade_from.as_ref().filter(|_| {
+            data_dir.join(&previous_version).is_dir() && !constraint.allows(&previous_version)
+        }) {
+            anyhow::bail!(
+                "Version {} installed but not staged: it can only be installed over {} or later, and the device runs {}. Update to a release in between first.",
+                target_version,
+                min,
+                previous_version
+            );
+        }
+    }
+
+    // The app data is migrated before anything can start the new version;
+    // a failed migration leaves the data as it was and the version unstaged
+    let migrated = MigrationService::new(data_dir.clone())
+        .migrate(target_version, &previous_version)
+        .with_context(|| format!("Version {} installed but not staged", target_version))?;
+    if !migrated.is_empty() {
+        println!("Migrated the app data: {}", migrated.join(", "));
+    }
+
+    if previous_version != target_version && data_dir.join(&previous_version).is_dir() {
+        let state = StateService::new(data_dir.clone());
+        if let Err(e) = state.update(|s| s.last_known_good = Some(previous_version)) {
+            tracing::warn!("Failed to record last known-good version: {}", e);
+        }
+    }
+
+    // Stage the new version in the inactive slot; it becomes current
+    // once `run` has booted it successfully
+    if fs_service.stage_in_inactive_slot(target_version)? {
+        println!(
+            "Version {} staged for a trial boot. It becomes current once 'run' reaches a healthy state.",
+            target_version
+        );
+    } else {
+        // Fails rather than undo a switch another process made meanwhile
+        let generation = previous.map_or(0, |pointer| pointer.generation);
+        current
+            .compare_and_swap(generation, target_version)
+            .with_context(|| {
+                format!("Version {} installed but not made current", target_version)
+            })?;
+        tracing::info!("Set current version to: {}", target_version);
+    }
+    Ok(())
+}
+
+// The trusted key that signed a release's checksum file. Once keys are trusted, a
+// signature none of them made is refused, as with the bundle signature.
+fn checksum_signer(
+    data_dir: &Path,
+    version: &str,
+    signed: Option<(String, String)>,
+) -> Result<Option<String>> {
+    let Some((checksums, signature)) = signed else {
+        return Ok(None);
+    };
+    let keys = KeyService::new(data_dir.to_path_buf());
+    match keys.verify(checksums.as_bytes(), &signature) {
+        Ok(key) => Ok(Some(key.key_id)),
+        Err(e) if !keys.load()?.is_empty() => Err(e).with_context(|| {
+            format!(
+                "Checksum file signature of {} does not verify; pass --skip-signature to install it anyway",
+                version
+            )
+        }),
+        Err(_) => {
+            tracing::warn!(
+                "Checksum file of {} is signed, but no trusted keys are configured to check it",
+                version
+            );
+            Ok(None)
+        }
+    }
+}
+
+// The checksum file of a release with its minisign signature, if it has one
+fn checksum_signature(
+    registry: &dyn RegistryBackend,
+    version: &str,
+) -> Result<Option<(String, String)>> {
+    let checksums = registry.download_checksums(version)?;
+    Ok(registry
+        .download_checksums_signature(version)?
+        .map(|signature| (checksums, signature)))
+}
+
+// Checks the files of an installed version against the SBOM it ships. Gaps are
+// only reported unless `required`, since older releases ship no SBOM.
+fn check_sbom(version_dir: &Path, required: bool) -> Result<()> {
+    let Some(sbom) = Sbom::load(version_dir)? else {
+        if required {
+            anyhow::bail!("The release ships no SBOM");
+        }
+        println!("The release ships no SBOM; its files were not checked against one");
+        return Ok(());
+    };
+
+    let problems = sbom.check(version_dir)?;
+    if problems.is_empty() {
+        println!("Every file is declared in the SBOM");
+        return Ok(());
+    }
+    for problem in &problems {
+        println!("{}", problem);
+    }
+    if required {
+        anyhow::bail!("{} file(s) disagree with the SBOM", problems.len());
+    }
+    tracing::warn!("{} file(s) disagree with the SBOM", problems.len());
+    Ok(())
+}
+
+// Checks the detached signature of a downloaded release bundle and returns the
+// signer: a cosign identity when cosign is configured, otherwise a trusted minisign
+// key. Once either is configured, an unsigned bundle is refused.
+fn bundle_signer(
+    registry: &dyn RegistryBackend,
+    data_dir: &Path,
+    version: &str,
+    format: BundleFormat,
+    bundle_path: &Path,
+    sha256: &str,
+) -> Result<Option<String>> {
+    verify_bundle_signature(
+        data_dir,
+        version,
+        bundle_path,
+        sha256,
+        || registry.download_bundle_cosign(version, format),
+        || registry.download_bundle_signature(version, format),
+    )
+}
+
+// `bundle_signer` for a bundle file, whose signature lies next to it as
+// `<bundle>.minisig` or `<bundle>.cosign.bundle`
+fn file_bundle_signer(
+    data_dir: &Path,
+    version: &str,
+    bundle: &Path,
+    local_bundle: &Path,
+    sha256: &str,
+) -> Result<Option<String>> {
+    let sibling = |extension: &str| -> Result<Option<String>> {
+        let mut name = bundle.as_os_str().to_owned();
+        name.push(format!(".{}", extension));
+        let path = PathBuf::from(name);
+        match fs::read_to_string(&path) {
+            Ok(contents) => Ok(Some(contents)),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e).with_context(|| format!("Failed to read {}", path.display())),
+        }
+    };
+    verify_bundle_signature(
+        data_dir,
+        version,
+        local_bundle,
+        sha256,
+        || sibling(Config::COSIGN_BUNDLE_EXTENSION),
+        || sibling(Config::SIGNATURE_EXTENSION),
+    )
+}
+
+// The signature policy shared by both, given how to fetch the cosign bundle and
+// the minisign signature
+fn verify_bundle_signature(
+    data_dir: &Path,
+    version: &str,
+    bundle_path: &Path,
+    sha256: &str,
+    cosign_bundle: impl FnOnce() -> Result<Option<String>>,
+    signature: impl FnOnce() -> Result<Option<String>>,
+) -> Result<Option<String>> {
+    if let Some(policy) = CosignPolicy::from_config()? {
+        let Some(bundle) = cosign_bundle()? else {
+            anyhow::bail!(
+                "Release bundle of {} has no cosign bundle; pass --skip-signature to install it anyway",
+                version
+            );
+        };
+        let identity = policy
+            .verify(&bundle, sha256)
+            .context("Release bundle cosign signature does not verify")?;
+        return Ok(Some(identity));
+    }
+
+    let keys = KeyService::new(data_dir.to_path_buf());
+    let trusted = !keys.load()?.is_empty();
+    match signature()? {
+        Some(signature) if trusted => {
+            let signer = keys
+                .verify_file(bundle_path, &signature)
+                .context("Release bundle signature does not verify")?;
+            Ok(Some(signer.key_id))
+        }
+        Some(_) => {
+            tracing::warn!(
+                "Release bundle of {} is signed, but no trusted keys are configured to check it",
+                version
+            );
+            Ok(None)
+        }
+        None if trusted => anyhow::bail!(
+            "Release bundle of {} is not signed; pass --skip-signature to install it anyway",
+            version
+        ),
+        None => Ok(None),
+    }
+}
+
+// Checks a pulled container image under the policy bundles are held to: signed by
+// the cosign identity, or else listed in the signed targets metadata. Minisign keys
+// cannot sign an image, so with only those trusted it is refused.
+fn image_signer(
+    data_dir: &Path,
+    version: &str,
+    image: &ContainerImage,
+    targets: Option<&TargetsMetadata>,
+) -> Result<Option<String>> {
+    let policy = CosignPolicy::from_config()?;
+    if policy.is_none() && targets.is_none() {
+        if KeyService::new(data_dir.to_path_buf()).load()?.is_empty() {
+            return Ok(None);
+        }
+        anyhow::bail!(
+            "Container images cannot be checked against trusted minisign keys; configure a cosign identity or signed metadata, or pass --skip-signature to install {} anyway",
+            version
+        );
+    }
+    let Some(digest) = image.digest() else {
+        anyhow::bail!(
+            "{} has no registry digest to check its signature against; pass --skip-signature to install it anyway",
+            image.reference
+        );
+    };
+    if let Some(targets) = targets {
+        let listed = targets.image_digest(version)?;
+        if !listed.eq_ignore_ascii_case(digest) {
+            anyhow::bail!(
+                "{} is {}, but the signed {} lists {}",
+                image.reference,
+                digest,
+                Config::TARGETS_METADATA_FILE,
+                listed
+            );
+        }
+    }
+    match policy {
+        Some(policy) => {
+            let identity = policy
+                .verify_image(&image.pinned)
+                .context("Container image cosign signature does not verify")?;
+            Ok(Some(identity))
+        }
+        None => Ok(None),
+    }
+}
+
+// Counts a downloaded bundle against the data budget; losing the count is only logged
+fn record_download(metered: &MeteredService, bundle_path: &Path) {
+    if let Err(e) = fs::metadata(bundle_path)
+        .map_err(anyhow::Error::from)
+        .and_then(|metadata| metered.record(metadata.len()))
+    {
+        tracing::warn!("Failed to record the data used: {:#}", e);
+    }
+}
+
+// The steps of an update after the bundle is at hand, shared by both kinds of plans
+fn plan_install(
+    plan: &mut Plan,
+    fs_service: &FileService,
+    version: &str,
+    bundle_size: Option<u64>,
+) -> Result<()> {
+    // Releases are close in size, so the current install stands in for the new one
+    let current_dir = fs_service.data_dir.join(fs_service.current_version());
+    let installed_size = if current_dir.is_dir() {
+        FileService::size_of(&current_dir).ok()
+    } else {
+        bundle_size
+    };
+    plan.add("install", version, installed_size, Cost::Write);
+
+    // The same preflight the update runs, against the temp directory it would use
+    let estimate = fs_service.estimate_update_space(bundle_size.unwrap_or(0));
+    if let Err(e) = fs_service.ensure_update_space(&estimate, &std::env::temp_dir(), version) {
+        plan.warn(format!("{:#}", e));
+    }
+
+    // Mirrors `stage_in_inactive_slot`
+    let marker = fs_service.boot_marker()?;
+    match marker.version(marker.active) {
+        Some(active) if active != version => plan.add(
+            "stage",
+            &format!("slot {}", marker.active.other()),
+            None,
+            Cost::Write,
+        ),
+        _ => plan.add(
+            "activate",
+            &format!("slot {}", marker.active),
+            None,
+            Cost::Write,
+        ),
+    }
+    Ok(())
+}
+
+// Lays out what `update` would do for a version without touching the device
+fn update_plan(
+    registry: &dyn RegistryBackend,
+    fs_service: &FileService,
+    version: &str,
+    format: BundleFormat,
+    expected_sha256: Option<&str>,
+    bundle_size: Option<u64>,
+) -> Result<Plan> {
+    let normalized_version = version.trim_start_matches('v');
+    let mut plan = Plan::new(&format!("update {}", version));
+
+    let cached = expected_sha256
+        .and_then(|sha256| BundleCache::new(fs_service.data_dir.clone()).lookup(sha256));
+    match cached {
+        Some(path) => plan.add("copy", &path.display().to_string(), bundle_size, Cost::Read),
+        None => plan.add(
+            "download",
+            &registry.release_bundle_url(normalized_version, format)?,
+            bundle_size,
+            Cost::Download,
+        ),
+    }
+    plan.add(
+        "verify",
+        Config::RELEASE_BUNDLE_NAME,
+        bundle_size,
+        Cost::Read,
+    );
+
+    plan_install(&mut plan, fs_service, version, bundle_size)?;
+    if bundle_size.is_none() {
+        plan.warn("The registry did not report the bundle size".to_string());
+    }
+
+    // Without downloading the bundle, only a manifest published next to it can be compared
+    match registry.fetch(normalized_version, "manifest.yaml") {
+        Ok(Some(manifest)) => match manifest_diff(&fs_service.data_dir, version, &manifest) {
+            Ok(diff) => plan.manifest_diff = diff,
+            Err(e) => plan.warn(format!("Could not compare the manifests: {:#}", e)),
+        },
+        Ok(None) => plan.warn(format!(
+            "Release {} publishes no manifest.yaml next to its bundle; the update shows the manifest changes once it is installed",
+            version
+        )),
+        Err(e) => plan.warn(format!("Could not fetch the release's manifest: {:#}", e)),
+    }
+
+    Ok(plan)
+}
+
+// `update --from-file`: installs a release bundle from disk, e.g. one carried to a
+// unit without internet access, and takes it through the same checks and staging
+// as a bundle from a registry. The version comes from the file name, the expected
+// checksum from the checksum file next to it, and the signature, if any, from
+// `<bundle>.minisig` or `<bundle>.cosign.bundle`.
+fn update_from_file(
+    fs_service: &FileService,
+    bundle: &Path,
+    dry_run: bool,
+    unattended: bool,
+    force: bool,
+    skip_signature: bool,
+) -> Result<()> {
+    let data_dir = fs_service.data_dir.clone();
+    let file_name = bundle
+        .file_name()
+        .with_context(|| format!("{} is not a file", bundle.display()))?
+        .to_string_lossy()
+        .into_owned();
+    let version = BundleFormat::split_file_name(&file_name)
+        .and_then(|(stem, _)| {
+            stem.strip_prefix(Config::bundle_prefix().as_str())?
+                .strip_prefix('-')
+        })
+        .map(|version| format!("v{}", Config::normalize_version(version)))
+        .with_context(|| {
+            format!(
+                "Cannot tell the version of {}: expected a name like {}",
+                file_name,
+                BundleFormat::Gzip.file_name("1.2.3")
+            )
+        })?;
+    Config::parse_version(&version)?;
+    // As in `update`, before the bundle is copied and installed
+    let previous = CurrentService::new(data_dir.clone()).load()?;
+    let bundle_size = fs::metadata(bundle)
+        .with_context(|| format!("Failed to read {}", bundle.display()))?
+        .len();
+    let _span = tracing::info_span!("update", version = %version).entered();
+    tracing::info!("Updating to version {} from {}", version, bundle.display());
+
+    check_hold(&data_dir, &version, force, unattended, dry_run)?;
+    fs_service.verify_permissions()?;
+    let power_blocker = check_power(&data_dir, &version, unattended, dry_run)?;
+
+    let bundle_dir = bundle.parent().unwrap_or(Path::new("."));
+    let checksums_path = bundle_dir.join(Config::checksum_file_name());
+    let checksums = fs::read_to_string(&checksums_path).ok();
+    let expected_sha256 = checksums
+        .as_deref()
+        .and_then(|checksums| registry::find_checksum(checksums, &file_name));
+
+    if dry_run {
+        let mut plan = Plan::new(&format!("update {} --from-file", version));
+        plan.add(
+            "copy",
+            &bundle.display().to_string(),
+            Some(bundle_size),
+            Cost::Read,
+        );
+        plan.add("verify", &file_name, Some(bundle_size), Cost::Read);
+        plan_install(&mut plan, fs_service, &version, Some(bundle_size))?;
+        if expected_sha256.is_none() {
+            plan.warn(format!(
+                "{} does not list {}; the update would fail",
+                checksums_path.display(),
+                file_name
+            ));
+        }
+        match bundle_manifest(bundle) {
+            Ok(Some(manifest)) => match manifest_diff(&data_dir, &version, &manifest) {
+                Ok(diff) => plan.manifest_diff = diff,
+                Err(e) => plan.warn(format!("Could not compare the manifests: {:#}", e)),
+            },
+            Ok(None) => plan.warn(format!("{} holds no manifest.yaml", file_name)),
+            Err(e) => plan.warn(format!("Could not read the bundle's manifest: {:#}", e)),
+        }
+        if let Some(reason) = power_blocker {
+            let verdict = if unattended { "deferred" } else { "at risk" };
+            plan.warn(format!("Update {}: {}", verdict, reason));
+        }
+        return plan.print();
+    }
+
+    let expected_sha256 = expected_sha256.with_context(|| {
+        format!(
+            "No checksum listed for {} in {}",
+            file_name,
+            checksums_path.display()
+        )
+    })?;
+
+    let temp_dir = tempfile::tempdir()?;
+    let estimate = fs_service.estimate_update_space(bundle_size);
+    fs_service.ensure_update_space(&estimate, temp_dir.path(), &version)?;
+
+    // Work on a local copy, so a stick pulled or a share dropped mid-install can't
+    // corrupt anything and quarantining never deletes the original
+    let local_bundle = temp_dir.path().join(Config::RELEASE_BUNDLE_NAME);
+    let actual_sha256 = FileService::copy_with_sha256(bundle, &local_bundle)?;
+    let copied_at = Utc::now();
+
+    // Held to the same policy as a checksum file from a registry
+    let signature_path = bundle_dir.join(Config::checksum_signature_file_name());
+    let signed = match fs::read_to_string(&signature_path) {
+        Ok(signature) => Some((checksums.unwrap_or_default(), signature)),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => None,
+        Err(e) => {
+            return Err(e).with_context(|| format!("Failed to read {}", signature_path.display()))
+        }
+    };
+    let signed_by = if skip_signature {
+        None
+    } else {
+        checksum_signer(&data_dir, &version, signed)?
+    };
+    let source = std::path::absolute(bundle)?.display().to_string();
+    let mut origin = BundleOrigin {
+        backend: "file".to_string(),
+        source: source.clone(),
+        signed_by,
+        bundle_signed_by: None,
+    };
+
+    let installed = (|| -> Result<()> {
+        if skip_signature {
+            tracing::warn!("Not checking the release bundle signature (--skip-signature)");
+        } else {
+            origin.bundle_signed_by =
+                file_bundle_signer(&data_dir, &version, bundle, &local_bundle, &actual_sha256)?;
+        }
+        fs_service.install_bundle(
+            &local_bundle,
+            temp_dir.path(),
+            &expected_sha256,
+            &actual_sha256,
+            &version,
+            origin,
+        )
+    })();
+
+    if let Err(e) = installed {
+        let record = QuarantineRecord {
+            version: version.clone(),
+            url: source,
+            reason: format!("{:#}", e),
+            expected_sha256: Some(expected_sha256),
+            actual_sha256: Some(actual_sha256),
+            downloaded_at: copied_at,
+            quarantined_at: Utc::now(),
+        };
+        match QuarantineService::new(data_dir.clone()).quarantine(&local_bundle, &record) {
+            Ok(path) => tracing::error!("Release bundle quarantined at {}", path.display()),
+            Err(qe) => tracing::warn!("Failed to quarantine release bundle: {}", qe),
+        }
+        return Err(e);
+    }
+
+    switch_to_installed(fs_service, &version, previous)
+}
+
+// The top-level manifest.yaml of a bundle file, read without extracting the rest
+fn bundle_manifest(bundle: &Path) -> Result<Option<String>> {
+    let mut archive = tar::Archive::new(BundleFormat::open(bundle)?);
+    for entry in archive.entries()? {
+        let mut entry = entry?;
+        let path = entry.path()?.into_owned();
+        let mut parts = path
+            .components()
+            .filter(|part| !matches!(part, std::path::Component::CurDir));
+        if parts.next() == Some(std::path::Component::Normal("manifest.yaml".as_ref()))
+            && parts.next().is_none()
+        {
+            let mut manifest = String::new();
+            std::io::Read::read_to_string(&mut entry, &mut manifest)?;
+            return Ok(Some(manifest));
+        }
+    }
+    Ok(None)
+}
+
+// The releases to pass through, in order, before `target` can be installed over
+// `installed`, following the `min_upgrade_from` of each manifest the registry
+// publishes. Empty when `target` can be installed directly.
+fn upgrade_path(
+    registry: &dyn RegistryBackend,
+    targets: Option<&TargetsMetadata>,
+    installed: &str,
+    target: &str,
+) -> Result<Vec<String>> {
+    let mut path: Vec<String> = Vec::new();
+    let mut step = target.to_string();
+    loop {
+        let bridge = match published_constraint(registry, &step)? {
+            Some(constraint) if !constraint.allows(installed) => constraint.bridge()?,
+            _ => None,
+        };
+        let Some(bridge) = bridge else {
+            path.reverse();
+            return Ok(path);
+        };
+        // The newest release in between gets the device furthest in one step
+        let next = match targets {
+            Some(targets) => Config::newest_version(targets.versions(), &bridge),
+            None => Config::newest_version(
+                registry.list()?.into_iter().map(|release| release.version),
+                &bridge,
+            ),
+        }
+        .with_context(|| {
+            format!(
+                "{} cannot be installed over {}, and the registry lists no release in {} to pass through",
+                step, installed, bridge
+            )
+        })?;
+        path.push(next.clone());
+        step = next;
+    }
+}
+
+// The directory name of an installed version, given with or without the 'v' prefix
+fn installed_version(fs_service: &FileService, version: &str) -> Result<String> {
+    [
+        version.to_string(),
+        format!("v{}", Config::normalize_version(version)),
+    ]
+    .into_iter()
+    .find(|candidate| fs_service.data_dir.join(candidate).is_dir())
+    .with_context(|| format!("Version {} is not installed", version))
+}
+
+// The constraints declared by the manifest a release publishes next to its bundle.
+// Without one, they are checked once the release is installed.
+fn published_constraint(
+    registry: &dyn RegistryBackend,
+    version: &str,
+) -> Result<Option<UpgradeConstraint>> {
+    match registry.fetch(&Config::normalize_version(version), "manifest.yaml") {
+        Ok(Some(manifest)) => UpgradeConstraint::parse(version, &manifest),
+        Ok(None) => Ok(None),
+        Err(e) => {
+            tracing::warn!("Could not fetch the manifest of {}: {:#}", version, e);
+            Ok(None)
+        }
+    }
+}
+
+// How a target version's manifest differs from the current version's. None when no
+// other version is installed to compare with.
+fn manifest_diff(
+    data_dir: &Path,
+    target_version: &str,
+    target_manifest: &str,
+) -> Result<Option<ManifestDiff>> {
+    let current = FileService::new(data_dir.to_path_buf()).current_version();
+    let current_manifest = data_dir.join(&current).join("manifest.yaml");
+    if Config::normalize_version(&current) == Config::normalize_version(target_version)
+        || !current_manifest.is_file()
+    {
+        return Ok(None);
+    }
+    let contents = fs::read_to_string(&current_manifest)?;
+    ManifestDiff::between(&current, &contents, target_version, target_manifest).map(Some)
+}
+
+// Formats a byte count using binary units for display
+pub fn format_size(bytes: u64) -> String {
+    const UNITS: [&str; 4] = ["B", "KiB", "MiB", "GiB"];
+    let mut size = bytes as f64;
+    let mut unit = 0;
+    while size >= 1024.0 && unit < UNITS.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+    if unit == 0 {
+        format!("{} {}", bytes, UNITS[0])
+    } else {
+        format!("{:.1} {}", size, UNITS[unit])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::services::registry::ReleaseInfo;
+    use crate::services::{TargetFile, TargetHashes};
+    use sha2::{Digest, Sha256};
+    use std::collections::BTreeMap;
+
+    // Two minisign keys, and a signature of "release bundle\n" by each
+    const KEY_A: &str =
+        "untrusted comment: test key\nRWQLvufulHnXfsMBrbh6y1gRmLROEIQje2Of+cabvuHowV8W73Ab9qe4\n";
+    const KEY_B: &str =
+        "untrusted comment: test key\nRWQ6vhDCRcnin555CDRAiYgtpqVi970+fX8egAXiFaJlJZadlWEs8SSm\n";
+    const SIGNED_BY_A: &str = "untrusted comment: sig\nRUQLvufulHnXfiHpN7ZVYDFegRMLpZwgUzxO/vP1bUXCjWcI+F04TcCsJWR2hwq2QOAzvN3h0h1OSP+Ncnu1TUf0+9F4dZ/sygs=\ntrusted comment: timestamp:0\tfile:test\n4bJzXgouNCe2YsO5yilVj2/HVAj+XF8+57p4MVOLUERS8NtmH6u0a2Y5lJ4Jiynz3aeOmtF3RbENnRgqvn3RBg==\n";
+    const SIGNED_BY_B: &str = "untrusted comment: sig\nRUQ6vhDCRcnin2hFb86WTT5qs/ZridxTwgoXkRjSr1phvHiPTDQ7tTFakSKhkOODZWKUqEzxhUQAXixQZbldEeOxvx/yXByZwwc=\ntrusted comment: timestamp:0\tfile:test\nzSmKsgFsezBgicJv5CcmqmTgTDQScsNauIhfWawWSrM+Ja35sa3CSXLslxebnsiwE0/FyI2C8pBLtRauGFBlAQ==\n";
+    const CONTENTS: &str = "release bundle\n";
+
+    // Releases with the manifests they publish next to their bundles
+    struct FakeRegistry(BTreeMap<&'static str, &'static str>);
+
+    impl RegistryBackend for FakeRegistry {
+        fn name(&self) -> &'static str {
+            "fake"
+        }
+
+        fn verify_version(&self, version: &str) -> Result<bool> {
+            Ok(self.0.contains_key(version))
+        }
+
+        fn latest(&self) -> Result<String> {
+            anyhow::bail!("not supported")
+        }
+
+        fn list(&self) -> Result<Vec<ReleaseInfo>> {
+            Ok(self
+                .0
+                .keys()
+                .map(|version| ReleaseInfo {
+                    version: version.to_string(),
+                    published_at: None,
+                    bundle_size: None,
+                })
+                .collect())
+        }
+
+        fn artifact_url(&self, version: &str, file_name: &str) -> Result<String> {
+            Ok(format!("fake://{}/{}", version, file_name))
+        }
+
+        fn artifact_size(&self, _version: &str, _file_name: &str) -> Result<Option<u64>> {
+            Ok(None)
+        }
+
+        fn download(&self, _version: &str, file_name: &str, _output_path: &Path) -> Result<String> {
+            anyhow::bail!("{} is not served", file_name)
+        }
+
+        fn fetch(&self, version: &str, file_name: &str) -> Result<Option<String>> {
+            Ok(self
+                .0
+                .get(version)
+                .filter(|_| file_name == "manifest.yaml")
+                .map(|manifest| manifest.to_string()))
+        }
+    }
+
+    fn registry() -> FakeRegistry {
+        FakeRegistry(BTreeMap::from([
+            ("1.0.0", "version: 1.0.0\n"),
+            ("1.5.0", "version: 1.5.0\n"),
+            ("1.6.0", "version: 1.6.0\n"),
+            ("2.0.0", "min_upgrade_from: 1.5.0\n"),
+            ("3.0.0", "min_upgrade_from: 2.0.0\n"),
+            ("4.0.0", "min_upgrade_from: 3.5.0\n"),
+        ]))
+    }
+
+    #[test]
+    fn upgrade_path_passes_through_the_newest_bridging_releases() {
+        let path = upgrade_path(&registry(), None, "1.0.0", "3.0.0").unwrap();
+        assert_eq!(path, ["1.6.0", "2.0.0"]);
+        assert!(upgrade_path(&registry(), None, "2.0.0", "3.0.0")
+            .unwrap()
+            .is_empty());
+        assert!(upgrade_path(&registry(), None, "1.0.0", "1.6.0")
+            .unwrap()
+            .is_empty());
+    }
+
+    #[test]
+    fn upgrade_path_only_uses_releases_of_the_signed_metadata() {
+        let listed = |version: &str| {
+            let target = TargetFile {
+                length: 1,
+                hashes: TargetHashes {
+                    sha256: "00".to_string(),
+                },
+            };
+            (
+                format!("{}/{}", version, BundleFormat::Gzip.file_name(version)),
+                target,
+            )
+        };
+        let targets = TargetsMetadata {
+            version: 1,
+            expires: Utc::now() + chrono::Duration::days(1),
+            targets: ["1.5.0", "2.0.0", "3.0.0"].map(listed).into(),
+        };
+        let path = upgrade_path(&registry(), Some(&targets), "1.0.0", "3.0.0").unwrap();
+        assert_eq!(path, ["1.5.0", "2.0.0"]);
+    }
+
+    #[test]
+    fn upgrade_path_fails_without_a_bridging_release() {
+        let error = upgrade_path(&registry(), None, "1.0.0", "4.0.0").unwrap_err();
+        assert!(
+            error.to_string().contains("lists no release in"),
+            "{:#}",
+            error
+        );
+    }
+
+    // Checks a bundle of CONTENTS under the signature policy, with `trusted` keys and
+    // the minisign signature the release ships
+    fn bundle_signer(trusted: &[&str], signature: Option<&str>) -> Result<Option<String>> {
+        let data_dir = tempfile::tempdir().unwrap();
+        let keys = KeyService::new(data_dir.path().to_path_buf());
+        for key in trusted {
+            keys.add(key).unwrap();
+        }
+        let bundle = data_dir.path().join("release_bundle-v1.2.0.tar.gz");
+        fs::write(&bundle, CONTENTS).unwrap();
+        let sha256 = hex::encode(Sha256::digest(CONTENTS));
+        verify_bundle_signature(
+            data_dir.path(),
+            "1.2.0",
+            &bundle,
+            &sha256,
+            || Ok(None),
+            || Ok(signature.map(str::to_string)),
+        )
+    }
+
+    #[test]
+    fn bundle_signatures_are_optional_without_trusted_keys() {
+        assert_eq!(bundle_signer(&[], None).unwrap(), None);
+        assert_eq!(bundle_signer(&[], Some(SIGNED_BY_A)).unwrap(), None);
+    }
+
+    #[test]
+    fn bundle_signatures_name_the_trusted_signer() {
+        let signer = bundle_signer(&[KEY_B, KEY_A], Some(SIGNED_BY_A)).unwrap();
+        let key_a = KeyService::parse_public_key(KEY_A).unwrap();
+        assert_eq!(signer, Some(key_a.key_id));
+    }
+
+    #[test]
+    fn bundles_must_be_signed_by_a_trusted_key() {
+        let error = bundle_signer(&[KEY_A], None).unwrap_err();
+        assert!(error.to_string().contains("is not signed"), "{:#}", error);
+        let error = bundle_signer(&[KEY_A], Some(SIGNED_BY_B)).unwrap_err();
+        assert!(error.to_string().contains("does not verify"), "{:#}", error);
+    }
+
+    #[test]
+    fn checksum_signatures_must_verify_once_keys_are_trusted() {
+        let data_dir = tempfile::tempdir().unwrap();
+        let signed = |signature: &str| Some((CONTENTS.to_string(), signature.to_string()));
+
+        // Nothing to check it against yet
+        assert_eq!(
+            checksum_signer(data_dir.path(), "1.2.0", signed(SIGNED_BY_B)).unwrap(),
+            None
+        );
+
+        let keys = KeyService::new(data_dir.path().to_path_buf());
+        let key_a = keys.add(KEY_A).unwrap();
+        assert_eq!(
+            checksum_signer(data_dir.path(), "1.2.0", signed(SIGNED_BY_A)).unwrap(),
+            Some(key_a.key_id)
+        );
+        assert!(checksum_signer(data_dir.path(), "1.2.0", signed(SIGNED_BY_B)).is_err());
+        assert_eq!(
+            checksum_signer(data_dir.path(), "1.2.0", None).unwrap(),
+            None
+        );
+    }
+}
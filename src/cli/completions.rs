@@ -0,0 +1,76 @@
+use crate::config::Config;
+use crate::services::FileService;
+use anyhow::{Context, Result};
+use clap_complete::env::Shells;
+use clap_complete::CompletionCandidate;
+use std::env;
+use std::fs;
+use std::io;
+
+/// Prints the script that makes a shell ask geist for completions. Candidates
+/// are computed when Tab is pressed, so they follow the installed versions.
+pub fn write_registration(shell: &str) -> Result<()> {
+    let shells = Shells::builtins();
+    let completer = shells.completer(shell).with_context(|| {
+        format!(
+            "Unsupported shell '{}' (expected one of: {})",
+            shell,
+            shells.names().collect::<Vec<_>>().join(", ")
+        )
+    })?;
+    let exe = env::current_exe().context("Failed to locate the geist binary")?;
+    completer.write_registration(
+        "COMPLETE",
+        "geist",
+        "geist",
+        &exe.to_string_lossy(),
+        &mut io::stdout(),
+    )?;
+    Ok(())
+}
+
+/// Versions installed in the data directory, the current one first
+pub fn installed_versions() -> Vec<CompletionCandidate> {
+    let Ok(data_dir) = Config::data_dir() else {
+        return Vec::new();
+    };
+    let fs_service = FileService::new(data_dir);
+    let current = fs_service.current_version();
+    let mut versions = fs_service.installed_versions().unwrap_or_default();
+    versions.sort_by_key(|version| *version != current);
+
+    versions
+        .into_iter()
+        .map(|version| {
+            let help = if version == current {
+                "installed, current"
+            } else {
+                "installed"
+            };
+            CompletionCandidate::new(version).help(Some(help.into()))
+        })
+        .collect()
+}
+
+/// Installed versions followed by the releases seen the last time the registry was listed
+pub fn known_versions() -> Vec<CompletionCandidate> {
+    let mut candidates = installed_versions();
+    let installed: Vec<String> = candidates
+        .iter()
+        .map(|c| c.get_value().to_string_lossy().to_string())
+        .collect();
+
+    // Completion has to be instant, so it never asks the registry itself
+    let cached: Vec<String> = Config::data_dir()
+        .ok()
+        .and_then(|dir| fs::read_to_string(dir.join(Config::RELEASES_CACHE_FILE)).ok())
+        .and_then(|contents| serde_json::from_str(&contents).ok())
+        .unwrap_or_default();
+    candidates.extend(
+        cached
+            .into_iter()
+            .filter(|version| !installed.contains(version))
+            .map(|version| CompletionCandidate::new(version).help(Some("release".into()))),
+    );
+    candidates
+}
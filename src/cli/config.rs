@@ -0,0 +1,269 @@
+use crate::config::Config;
+use crate::services::{ConfigFile, ConfigKey, ConfigSecret, CONFIG_KEYS};
+use anyhow::{Context, Result};
+use clap::Subcommand;
+use std::collections::BTreeMap;
+use std::env;
+use std::fs;
+use std::io::{self, BufRead, IsTerminal, Write};
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+#[derive(Subcommand)]
+pub enum ConfigCommands {
+    /// Print the value the configuration files give a key, e.g. `registry.url`
+    Get {
+        /// Key as `section.key`
+        key: String,
+    },
+    /// Set a key in the user's configuration file, keeping its comments
+    Set {
+        /// Key as `section.key`
+        key: String,
+        /// New value; lists are given comma-separated
+        value: String,
+        /// Write the system-wide file, /etc/geist/config.toml, instead
+        #[arg(long)]
+        system: bool,
+        /// Store the value encrypted under the device key beside the file, e.g. for
+        /// tokens and webhook URLs; it is only ever decrypted in memory
+        #[arg(long)]
+        secret: bool,
+    },
+    /// List the keys the configuration files set
+    List {
+        /// Print a JSON object instead of TOML lines
+        #[arg(long)]
+        json: bool,
+    },
+    /// Open the user's configuration file in $VISUAL or $EDITOR, keeping the edit
+    /// only once it is valid
+    Edit {
+        /// Edit the system-wide file, /etc/geist/config.toml, instead
+        #[arg(long)]
+        system: bool,
+    },
+    /// Check the configuration files, reporting each problem with its line
+    Validate {
+        /// Print the problems as JSON
+        #[arg(long)]
+        json: bool,
+    },
+}
+
+// Strings are printed bare so scripts can use them as they are
+fn display(value: &toml::Value) -> String {
+    match value {
+        toml::Value::String(s) => s.clone(),
+        value => value.to_string(),
+    }
+}
+
+// The file `set --system` or `edit --system` work on, or the user's
+fn target_file(system: bool) -> Result<PathBuf> {
+    if system {
+        Ok(PathBuf::from(Config::SYSTEM_CONFIG_FILE))
+    } else {
+        Config::user_config_file().context("No home directory to keep the configuration file in")
+    }
+}
+
+fn run_editor(path: &Path) -> Result<()> {
+    let editor = ["VISUAL", "EDITOR"]
+        .into_iter()
+        .find_map(|name| {
+            env::var(name)
+                .ok()
+                .filter(|editor| !editor.trim().is_empty())
+        })
+        .unwrap_or_else(|| "vi".to_string());
+    // Editors are often given with options, e.g. `code --wait`
+    let mut words = editor.split_whitespace();
+    let program = words.next().unwrap_or("vi");
+    let status = Command::new(program)
+        .args(words)
+        .arg(path)
+        .status()
+        .with_context(|| format!("Failed to run the editor '{}'", editor))?;
+    if !status.success() {
+        anyhow::bail!("The editor '{}' failed ({})", editor, status);
+    }
+    Ok(())
+}
+
+// Asks on the terminal whether to edit again; never without one
+fn ask_edit_again() -> Result<bool> {
+    if !io::stdin().is_terminal() {
+        return Ok(false);
+    }
+    eprint!("Edit again? [Y/n] ");
+    io::stderr().flush()?;
+    let mut answer = String::new();
+    io::stdin().lock().read_line(&mut answer)?;
+    Ok(!matches!(
+        answer.trim().to_ascii_lowercase().as_str(),
+        "n" | "no"
+    ))
+}
+
+// Edits a copy of the file beside it, so the secret key and the permissions stay
+// the same, and replaces the file with it only once every layer checks out
+fn edit(path: &Path) -> Result<()> {
+    let dir = path.parent().unwrap_or(Path::new("."));
+    fs::create_dir_all(dir).with_context(|| format!("Failed to create {}", dir.display()))?;
+    let original = match fs::read_to_string(path) {
+        Ok(contents) => contents,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => String::new(),
+        Err(e) => return Err(e).with_context(|| format!("Failed to read {}", path.display())),
+    };
+    let copy = tempfile::Builder::new()
+        .prefix(".config-edit-")
+        .suffix(".toml")
+        .tempfile_in(dir)
+        .with_context(|| format!("Failed to create a copy to edit in {}", dir.display()))?;
+    fs::write(copy.path(), &original)?;
+    // A new file is readable by all, like one `config set` creates
+    match fs::metadata(path) {
+        Ok(metadata) => fs::set_permissions(copy.path(), metadata.permissions())?,
+        #[cfg(unix)]
+        Err(_) => {
+            use std::os::unix::fs::PermissionsExt;
+            fs::set_permissions(copy.path(), fs::Permissions::from_mode(0o644))?;
+        }
+        #[cfg(not(unix))]
+        Err(_) => {}
+    }
+
+    // The other layers are checked along with the edit, as `update` would see them
+    let files: Vec<PathBuf> = Config::config_files()
+        .into_iter()
+        .map(|file| {
+            if file == path {
+                copy.path().to_path_buf()
+            } else {
+                file
+            }
+        })
+        .collect();
+    loop {
+        run_editor(copy.path())?;
+        let mut problems = ConfigFile::validate(&files);
+        if problems.is_empty() {
+            break;
+        }
+        for problem in &mut problems {
+            if problem.file == copy.path() {
+                problem.file = path.to_path_buf();
+            }
+            eprintln!("{}", problem);
+        }
+        if !ask_edit_again()? {
+            anyhow::bail!(
+                "Found {} problem(s); {} was left unchanged",
+                problems.len(),
+                path.display()
+            );
+        }
+    }
+
+    if fs::read_to_string(copy.path())? == original {
+        println!("No changes to {}", path.display());
+        return Ok(());
+    }
+    copy.persist(path)
+        .with_context(|| format!("Failed to replace {}", path.display()))?;
+    println!("Saved {}", path.display());
+    Ok(())
+}
+
+impl ConfigCommands {
+    pub fn execute(self) -> Result<()> {
+        match self {
+            ConfigCommands::Get { key } => {
+                let key = ConfigKey::find(&key)?;
+                let merged = ConfigFile::merged(&Config::config_files())?;
+                let value = key.lookup(&merged).with_context(|| {
+                    format!("{} is not set in any configuration file", key.name)
+                })?;
+                println!("{}", display(value));
+                Ok(())
+            }
+            ConfigCommands::Set {
+                key,
+                value,
+                system,
+                secret,
+            } => {
+                let key = ConfigKey::find(&key)?;
+                let path = target_file(system)?;
+                let value = if secret {
+                    key.check_secret()?;
+                    let plaintext = key.parse_value(&value)?;
+                    let plaintext = plaintext.as_str().unwrap_or_default();
+                    ConfigSecret::open_or_create(&path)?
+                        .encrypt(key.name, plaintext)?
+                        .into()
+                } else {
+                    key.parse_value(&value)?
+                };
+                ConfigFile::set(&path, key, value)?;
+                if secret {
+                    println!(
+                        "Set {} in {}, encrypted under {}",
+                        key.name,
+                        path.display(),
+                        ConfigSecret::key_path(&path).display()
+                    );
+                } else {
+                    println!("Set {} in {}", key.name, path.display());
+                }
+                if env::var_os(key.env).is_some() {
+                    println!("{} is set and takes precedence over the file", key.env);
+                }
+                Ok(())
+            }
+            ConfigCommands::List { json } => {
+                let merged = ConfigFile::merged(&Config::config_files())?;
+                let set: Vec<_> = CONFIG_KEYS
+                    .iter()
+                    .filter_map(|key| Some((key.name, key.lookup(&merged)?)))
+                    .collect();
+                if json {
+                    let map: BTreeMap<_, _> = set.into_iter().collect();
+                    println!("{}", serde_json::to_string_pretty(&map)?);
+                } else {
+                    for (name, value) in set {
+                        println!("{} = {}", name, value);
+                    }
+                }
+                Ok(())
+            }
+            ConfigCommands::Edit { system } => edit(&target_file(system)?),
+            ConfigCommands::Validate { json } => {
+                let files: Vec<PathBuf> = Config::config_files()
+                    .into_iter()
+                    .filter(|path| path.is_file())
+                    .collect();
+                let problems = ConfigFile::validate(&files);
+                if json {
+                    println!("{}", serde_json::to_string_pretty(&problems)?);
+                } else if files.is_empty() {
+                    println!("No configuration file; the defaults apply");
+                } else if problems.is_empty() {
+                    for path in &files {
+                        println!("{}: OK", path.display());
+                    }
+                }
+                if !problems.is_empty() {
+                    if !json {
+                        for problem in &problems {
+                            eprintln!("{}", problem);
+                        }
+                    }
+                    anyhow::bail!("Found {} problem(s) in the configuration", problems.len());
+                }
+                Ok(())
+            }
+        }
+    }
+}
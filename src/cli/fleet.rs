@@ -0,0 +1,92 @@
+use crate::services::{FleetService, Inventory};
+use anyhow::Result;
+use clap::Subcommand;
+use std::path::PathBuf;
+use std::thread;
+use std::time::Duration;
+
+#[derive(Subcommand)]
+pub enum FleetCommands {
+    /// Update every device in an inventory, a few per failure domain at a time
+    Update {
+        /// Version to install on every device
+        version: String,
+        /// JSON inventory listing the devices and their tags
+        #[arg(long)]
+        inventory: PathBuf,
+        /// Comma-separated tags that name a failure domain
+        #[arg(long, value_delimiter = ',', default_value = "site,zone")]
+        domain_tags: Vec<String>,
+        /// Largest fraction of a failure domain that may be updating at once
+        #[arg(long, default_value_t = 0.25)]
+        max_down_fraction: f64,
+        /// Seconds to wait between waves so devices can come back up
+        #[arg(long, default_value_t = 60)]
+        pause: u64,
+        /// Only print the waves that would run
+        #[arg(long)]
+        dry_run: bool,
+    },
+}
+
+impl FleetCommands {
+    pub fn execute(self) -> Result<()> {
+        match self {
+            FleetCommands::Update {
+                version,
+                inventory,
+                domain_tags,
+                max_down_fraction,
+                pause,
+                dry_run,
+            } => {
+                if !(max_down_fraction > 0.0 && max_down_fraction <= 1.0) {
+                    anyhow::bail!("--max-down-fraction must be in (0, 1]");
+                }
+
+                let fleet = FleetService::new(Inventory::load(&inventory)?);
+                let waves = fleet.plan_waves(&domain_tags, max_down_fraction);
+
+                for (index, wave) in waves.iter().enumerate() {
+                    let devices: Vec<String> = wave
+                        .iter()
+                        .map(|d| {
+                            format!(
+                                "{} ({})",
+                                d.name,
+                                FleetService::failure_domain(d, &domain_tags)
+                            )
+                        })
+                        .collect();
+                    println!("Wave {}: {}", index + 1, devices.join(", "));
+                }
+                if dry_run {
+                    return Ok(());
+                }
+
+                let args = vec!["update".to_string(), version.clone()];
+                for (index, wave) in waves.iter().enumerate() {
+                    let _span = tracing::info_span!("wave", number = index + 1).entered();
+
+                    // Stop the rollout at the first failing wave to keep the rest covered
+                    let failed = FleetService::run_wave(wave, &args);
+                    if !failed.is_empty() {
+                        anyhow::bail!(
+                            "Wave {} failed on {}; stopping the rollout",
+                            index + 1,
+                            failed.join(", ")
+                        );
+                    }
+                    println!("Wave {} updated to {}", index + 1, version);
+
+                    if index + 1 < waves.len() {
+                        thread::sleep(Duration::from_secs(pause));
+                    }
+                }
+
+                println!("Updated {} waves to {}", waves.len(), version);
+                Ok(())
+            }
+        }
+    }
+}
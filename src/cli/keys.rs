@@ -0,0 +1,147 @@
+use crate::services::{FileService, KeyAction, KeyService};
+use anyhow::Result;
+use clap::Subcommand;
+use std::fs;
+use std::path::PathBuf;
+use std::time::Duration;
+
+#[derive(Subcommand)]
+pub enum KeyCommands {
+    /// List the trusted update-signing keys
+    List {
+        /// Print the keys as JSON
+        #[arg(long)]
+        json: bool,
+    },
+    /// Trust a minisign public key as an initial trust anchor
+    Add {
+        /// Path to the minisign public key file
+        #[arg(value_name = "PUBLIC_KEY")]
+        key: PathBuf,
+    },
+    /// Trust a new key that is signed by a currently trusted key
+    Rotate {
+        /// Path to the new minisign public key file
+        #[arg(value_name = "PUBLIC_KEY")]
+        key: PathBuf,
+        /// Minisign signature of the new key file, made with a trusted key
+        #[arg(long, value_name = "SIGNATURE")]
+        signature: PathBuf,
+        /// Days the signing key stays valid after the rotation
+        #[arg(long, default_value_t = 30)]
+        overlap_days: u64,
+    },
+    /// Stop trusting a key, e.g. one that leaked
+    Remove {
+        /// Key id as printed by `keys list`
+        key_id: String,
+    },
+    /// Print every change made to the trusted keys, oldest first
+    Audit {
+        /// Print the audit trail as JSON
+        #[arg(long)]
+        json: bool,
+    },
+}
+
+impl KeyCommands {
+    pub fn execute(self, fs_service: &FileService) -> Result<()> {
+        let keys = KeyService::new(fs_service.data_dir.clone());
+
+        match self {
+            KeyCommands::List { json } => {
+                let trusted = keys.load()?;
+                if json {
+                    println!("{}", serde_json::to_string_pretty(&trusted)?);
+                    return Ok(());
+                }
+                if trusted.is_empty() {
+                    println!("No trusted keys. Add one with 'keys add <PUBLIC_KEY>'.");
+                    return Ok(());
+                }
+
+                let now = chrono::Utc::now();
+                for key in trusted {
+                    let validity = match (key.not_before, key.not_after) {
+                        (None, None) => "always".to_string(),
+                        (from, until) => format!(
+                            "{} .. {}",
+                            from.map(|t| t.to_rfc3339()).unwrap_or_default(),
+                            until.map(|t| t.to_rfc3339()).unwrap_or_default()
+                        ),
+                    };
+                    let status = if key.is_valid_at(now) {
+                        "valid"
+                    } else {
+                        "expired"
+                    };
+                    println!("{}  {:<8} {}", key.key_id, status, validity);
+                }
+                Ok(())
+            }
+            KeyCommands::Add { key } => {
+                let added = keys.add(&fs::read_to_string(&key)?)?;
+                println!("Trusted key {}", added.key_id);
+                Ok(())
+            }
+            KeyCommands::Rotate {
+                key,
+                signature,
+                overlap_days,
+            } => {
+                let rotated = keys.rotate(
+                    &fs::read_to_string(&key)?,
+                    &fs::read_to_string(&signature)?,
+                    Duration::from_secs(overlap_days * 24 * 60 * 60),
+                )?;
+                println!(
+                    "Trusted key {} (signed by {})",
+                    rotated.key_id,
+                    rotated.signed_by.as_deref().unwrap_or("unknown")
+                );
+                Ok(())
+            }
+            KeyCommands::Remove { key_id } => {
+                let removed = keys.remove(&key_id)?;
+                println!("Removed trusted key {}", removed.key_id);
+                if keys.load()?.is_empty() {
+                    println!("No trusted keys left; signatures are no longer required.");
+                }
+                Ok(())
+            }
+            KeyCommands::Audit { json } => {
+                let trail = keys.audit_trail()?;
+                if json {
+                    println!("{}", serde_json::to_string_pretty(&trail)?);
+                    return Ok(());
+                }
+                if trail.is_empty() {
+                    println!("No changes to the trusted keys recorded.");
+                    return Ok(());
+                }
+
+                for entry in trail {
+                    let action = match entry.action {
+                        KeyAction::Added => "added",
+                        KeyAction::Rotated => "rotated",
+                        KeyAction::Removed => "removed",
+                    };
+                    let mut line = format!(
+                        "{}  {:<8} {}",
+                        entry.at.format("%Y-%m-%d %H:%M:%S"),
+                        action,
+                        entry.key_id
+                    );
+                    if let Some(signer) = &entry.signed_by {
+                        line.push_str(&format!(" (signed by {})", signer));
+                    }
+                    if let Some(by) = &entry.by {
+                        line.push_str(&format!(" by {}", by));
+                    }
+                    println!("{}", line);
+                }
+                Ok(())
+            }
+        }
+    }
+}
@@ -1,10 +1,26 @@
+use crate::config::Config;
+use crate::services::{config_file, DeviceFixture, FileService};
 use anyhow::Result;
 use clap::Parser;
+use std::env;
+use std::path::PathBuf;
 
+pub mod auth;
+pub mod backup;
+pub mod cache;
 pub mod commands;
+pub mod completions;
+pub mod config;
+pub mod fleet;
+pub mod keys;
 pub mod node;
+pub mod quarantine;
+pub mod releases;
+pub mod report;
+pub mod service;
 pub mod topic;
 
+use backup::BackupCommands;
 use commands::Commands;
 
 #[derive(Parser)]
@@ -12,17 +28,131 @@ use commands::Commands;
     author,
     version,
     about = "Geist Supervisor CLI - Manage and control your Geist Camera",
-    long_about = None
+    long_about = None,
+    after_long_help = config_file::precedence_help()
 )]
 pub struct Cli {
+    /// Directory versions and state are kept in, instead of
+    /// ~/.local/share/roc-supervisor
+    #[arg(long, global = true, value_name = "PATH")]
+    data_dir: Option<PathBuf>,
+
+    /// Run an isolated instance with its own versions, state, pidfiles and control
+    /// socket, kept under profiles/<NAME> in the data directory
+    #[arg(long, global = true, value_name = "NAME")]
+    profile: Option<String>,
+
+    /// Configuration file to use instead of ~/.config/geist/config.toml; settings
+    /// in /etc/geist/config.toml still apply unless it overrides them
+    #[arg(long, global = true, value_name = "PATH")]
+    config: Option<PathBuf>,
+
+    /// Simulate the device (arch, thermal, power) from a YAML fixture, or from
+    /// built-in Raspberry Pi values without one, and run the app binary on any
+    /// host. Meant for CI containers.
+    #[arg(
+        long,
+        global = true,
+        value_name = "FIXTURE",
+        num_args = 0..=1,
+        default_missing_value = "default"
+    )]
+    fake_device: Option<String>,
+
+    /// Launch the app binary even though the host is not ARM, e.g. on an x86
+    /// workstation with an x86 build of roc_camera
+    #[arg(long, global = true)]
+    force_run: bool,
+
+    /// PEM bundle, or directory of PEM files, with extra CAs to trust for registry
+    /// requests
+    #[arg(long, global = true, value_name = "PATH")]
+    ca_bundle: Option<PathBuf>,
+
+    /// Consider prereleases such as v2.0.0-rc.1 when resolving latest or a version
+    /// requirement, and list them
+    #[arg(long, global = true)]
+    allow_prerelease: bool,
+
+    /// Seconds allowed to connect to a registry (0: no limit)
+    #[arg(long, global = true, value_name = "SECS")]
+    connect_timeout: Option<u64>,
+
+    /// Seconds a registry request may stall without receiving data (0: no limit)
+    #[arg(long, global = true, value_name = "SECS")]
+    read_timeout: Option<u64>,
+
+    /// Seconds a whole registry request, download included, may take (0: no limit)
+    #[arg(long, global = true, value_name = "SECS")]
+    timeout: Option<u64>,
+
     #[command(subcommand)]
     command: Option<Commands>,
 }
 
 impl Cli {
     pub fn execute(self) -> Result<()> {
+        // Through the environment, so the processes geist starts read the same file
+        if let Some(path) = &self.config {
+            env::set_var("GEIST_CONFIG", std::path::absolute(path)?);
+        }
+        // The config commands read the files themselves, so they still work, and
+        // can repair, a file that is invalid; so can restoring a backup
+        if !matches!(
+            self.command,
+            Some(Commands::Config { .. })
+                | Some(Commands::Backup {
+                    command: BackupCommands::Restore { .. }
+                })
+        ) {
+            Config::load_files()?;
+        }
+        // Through the environment as well, for the supervisor's child processes
+        if let Some(path) = &self.data_dir {
+            env::set_var("GEIST_DATA_DIR", std::path::absolute(path)?);
+        }
+        if let Some(profile) = &self.profile {
+            Config::check_profile(profile)?;
+            env::set_var("GEIST_PROFILE", profile);
+        }
+
+        if let Some(spec) = self.fake_device.or_else(Config::fake_device) {
+            DeviceFixture::load(&spec)?;
+            // Through the environment, so the processes geist starts simulate it too
+            let spec = match spec.as_str() {
+                "default" => spec,
+                path => std::path::absolute(path)?.to_string_lossy().into_owned(),
+            };
+            env::set_var("GEIST_FAKE_DEVICE", spec);
+        }
+
+        // Through the environment, so `run --daemon` and the supervisor's children see it
+        if self.force_run {
+            env::set_var("GEIST_FORCE_RUN", "1");
+        }
+
+        if self.allow_prerelease {
+            env::set_var("GEIST_ALLOW_PRERELEASE", "1");
+        }
+        if let Some(path) = &self.ca_bundle {
+            env::set_var("GEIST_CA_BUNDLE", std::path::absolute(path)?);
+        }
+        // Through the environment, like the GEIST_HTTP_* variables they override
+        for (name, secs) in [
+            ("GEIST_HTTP_CONNECT_TIMEOUT_SECS", self.connect_timeout),
+            ("GEIST_HTTP_READ_TIMEOUT_SECS", self.read_timeout),
+            ("GEIST_HTTP_TIMEOUT_SECS", self.timeout),
+        ] {
+            if let Some(secs) = secs {
+                env::set_var(name, secs.to_string());
+            }
+        }
+
         match self.command {
-            Some(cmd) => cmd.execute(),
+            // Only touches the configuration files, so it can repair one that names an
+            // unusable data directory
+            Some(Commands::Config { command }) => command.execute(),
+            Some(cmd) => cmd.execute(&FileService::new(Config::data_dir()?)),
             None => {
                 println!("No command specified. Use --help for usage information.");
                 Ok(())
@@ -1,3 +1,4 @@
+use crate::services::{AppCapabilities, Capability, FileService, IpcService};
 use anyhow::Result;
 use clap::Subcommand;
 
@@ -20,20 +21,17 @@ pub enum NodeCommands {
 }
 
 impl NodeCommands {
-    pub fn execute(self) -> Result<()> {
-        match self {
-            NodeCommands::Start { name } => {
-                println!("Starting node: {}", name);
-                Ok(())
-            }
-            NodeCommands::Stop { name } => {
-                println!("Stopping node: {}", name);
-                Ok(())
-            }
-            NodeCommands::List => {
-                println!("Listing nodes");
-                Ok(())
-            }
-        }
+    pub fn execute(self, fs_service: &FileService) -> Result<()> {
+        let data_dir = fs_service.data_dir.clone();
+        AppCapabilities::require_current(&data_dir, &Capability::NODE_CONTROL)?;
+
+        let request = match self {
+            NodeCommands::Start { name } => format!("node start {}", name),
+            NodeCommands::Stop { name } => format!("node stop {}", name),
+            NodeCommands::List => "node list".to_string(),
+        };
+        let reply = IpcService::new(data_dir).request(&request)?;
+        print!("{}", reply);
+        Ok(())
     }
 }
@@ -0,0 +1,58 @@
+use crate::services::{FileService, QuarantineService};
+use anyhow::Result;
+use clap::Subcommand;
+
+#[derive(Subcommand)]
+pub enum QuarantineCommands {
+    /// List bundles that failed verification or installation
+    List,
+    /// Remove quarantined bundles
+    Clear {
+        /// Entry to remove; clears the whole quarantine if omitted
+        #[arg(value_name = "ID")]
+        id: Option<String>,
+    },
+}
+
+impl QuarantineCommands {
+    pub fn execute(self, fs_service: &FileService) -> Result<()> {
+        let quarantine = QuarantineService::new(fs_service.data_dir.clone());
+
+        match self {
+            QuarantineCommands::List => {
+                let entries = quarantine.list()?;
+                if entries.is_empty() {
+                    println!("Quarantine is empty.");
+                    return Ok(());
+                }
+
+                for entry in entries {
+                    let record = &entry.record;
+                    println!("{}", entry.id);
+                    println!("  Version:     {}", record.version);
+                    println!("  URL:         {}", record.url);
+                    println!("  Reason:      {}", record.reason);
+                    if let Some(expected) = &record.expected_sha256 {
+                        println!("  Expected:    {}", expected);
+                    }
+                    if let Some(actual) = &record.actual_sha256 {
+                        println!("  Actual:      {}", actual);
+                    }
+                    println!("  Downloaded:  {}", record.downloaded_at.to_rfc3339());
+                    println!("  Quarantined: {}", record.quarantined_at.to_rfc3339());
+                    println!("  Path:        {}", entry.path.display());
+                }
+                Ok(())
+            }
+            QuarantineCommands::Clear { id } => {
+                let removed = quarantine.clear(id.as_deref())?;
+                println!(
+                    "Removed {} quarantine entr{}",
+                    removed,
+                    if removed == 1 { "y" } else { "ies" }
+                );
+                Ok(())
+            }
+        }
+    }
+}
@@ -0,0 +1,108 @@
+use crate::cli::commands::format_size;
+use crate::config::Config;
+use crate::services::registry::cache_release_list;
+use crate::services::{open_registry, FileService, ReleaseInfo, TargetsMetadata};
+use anyhow::Result;
+use clap::Subcommand;
+use serde::Serialize;
+use std::cmp::Reverse;
+
+#[derive(Subcommand)]
+pub enum ReleasesCommands {
+    /// List the published releases, newest first, marking those installed here
+    List {
+        /// Registry to list: gcs, github or gitlab (default: GEIST_REGISTRY_SOURCE, then gcs)
+        #[arg(long, value_name = "SOURCE")]
+        source: Option<String>,
+        /// Print the releases as JSON
+        #[arg(long)]
+        json: bool,
+    },
+}
+
+// A listed release as printed by `--json`
+#[derive(Serialize)]
+struct ListedRelease {
+    #[serde(flatten)]
+    release: ReleaseInfo,
+    installed: bool,
+}
+
+impl ReleasesCommands {
+    pub fn execute(self, fs_service: &FileService) -> Result<()> {
+        match self {
+            ReleasesCommands::List { source, json } => {
+                let registry = open_registry(source.as_deref(), &fs_service.data_dir)?;
+                let mut releases = registry.list()?;
+                // Only what the signed metadata lists is offered
+                if Config::signed_metadata() {
+                    let listed =
+                        TargetsMetadata::fetch_verified(registry.as_ref(), &fs_service.data_dir)?
+                            .versions();
+                    releases.retain(|release| {
+                        listed.contains(&Config::normalize_version(&release.version))
+                    });
+                }
+                releases.retain(|release| Config::version_offered(&release.version));
+                // Newest first; releases without a date come after the dated ones,
+                // newest version first
+                releases.sort_by_cached_key(|release| {
+                    Reverse((
+                        release.published_at,
+                        Config::parse_version(&release.version).ok(),
+                    ))
+                });
+
+                let data_dir = fs_service.data_dir.clone();
+                if let Err(e) = cache_release_list(&data_dir, &releases) {
+                    tracing::warn!("{:#}", e);
+                }
+
+                let installed: Vec<String> = fs_service
+                    .installed_versions()?
+                    .iter()
+                    .map(|version| Config::normalize_version(version))
+                    .collect();
+                let listed: Vec<ListedRelease> = releases
+                    .into_iter()
+                    .map(|release| ListedRelease {
+                        installed: installed.contains(&Config::normalize_version(&release.version)),
+                        release,
+                    })
+                    .collect();
+
+                if json {
+                    println!("{}", serde_json::to_string_pretty(&listed)?);
+                    return Ok(());
+                }
+                if listed.is_empty() {
+                    println!("No releases published in the {} registry.", registry.name());
+                    return Ok(());
+                }
+
+                println!(
+                    "{:<16} {:<20} {:>10}  Installed",
+                    "Version", "Published", "Size"
+                );
+                for entry in listed {
+                    let release = &entry.release;
+                    let row = format!(
+                        "{:<16} {:<20} {:>10}  {}",
+                        release.version,
+                        release
+                            .published_at
+                            .map(|at| at.format("%Y-%m-%d %H:%M").to_string())
+                            .unwrap_or_else(|| "-".to_string()),
+                        release
+                            .bundle_size
+                            .map(format_size)
+                            .unwrap_or_else(|| "-".to_string()),
+                        if entry.installed { "yes" } else { "" }
+                    );
+                    println!("{}", row.trim_end());
+                }
+                Ok(())
+            }
+        }
+    }
+}
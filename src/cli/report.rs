@@ -0,0 +1,31 @@
+use crate::config::Config;
+use crate::services::{FileService, ReportService};
+use anyhow::Result;
+use clap::Subcommand;
+
+#[derive(Subcommand)]
+pub enum ReportCommands {
+    /// Print the device report as JSON without sending it
+    Show,
+    /// Send a device report, and any queued ones, to GEIST_REPORT_URL now
+    Send,
+}
+
+impl ReportCommands {
+    pub fn execute(self, fs_service: &FileService) -> Result<()> {
+        let reports = ReportService::new(fs_service.data_dir.clone(), Config::report_url());
+        let report = reports.build()?;
+
+        match self {
+            ReportCommands::Show => {
+                println!("{}", serde_json::to_string_pretty(&report)?);
+                Ok(())
+            }
+            ReportCommands::Send => {
+                let delivered = reports.send(&report)?;
+                println!("Delivered {} report(s)", delivered);
+                Ok(())
+            }
+        }
+    }
+}
@@ -0,0 +1,61 @@
+use crate::services::{HostService, SystemdService};
+use anyhow::Result;
+use clap::Subcommand;
+
+#[derive(Subcommand)]
+pub enum ServiceCommands {
+    /// Install and start a systemd unit for the supervisor
+    Install {
+        /// Install a user unit instead of a system-wide one
+        #[arg(long)]
+        user: bool,
+        /// Have systemd launch 'run' instead of 'supervise'
+        #[arg(long)]
+        run: bool,
+        /// Only print the unit that would be installed
+        #[arg(long)]
+        print: bool,
+    },
+    /// Stop, disable and remove the systemd unit
+    Uninstall {
+        /// Remove the user unit instead of the system-wide one
+        #[arg(long)]
+        user: bool,
+    },
+    /// Show the status of the systemd unit
+    Status {
+        /// Query the user unit instead of the system-wide one
+        #[arg(long)]
+        user: bool,
+    },
+}
+
+impl ServiceCommands {
+    pub fn execute(self) -> Result<()> {
+        if !matches!(self, ServiceCommands::Install { print: true, .. }) {
+            HostService::require_linux("systemd integration")?;
+        }
+
+        match self {
+            ServiceCommands::Install { user, run, print } => {
+                let systemd = SystemdService::new(user);
+                let subcommand = if run { "run" } else { "supervise" };
+
+                if print {
+                    print!("{}", systemd.render_unit(subcommand)?);
+                    return Ok(());
+                }
+
+                let unit_path = systemd.install(subcommand)?;
+                println!("Installed and started {}", unit_path.display());
+                Ok(())
+            }
+            ServiceCommands::Uninstall { user } => {
+                let unit_path = SystemdService::new(user).uninstall()?;
+                println!("Removed {}", unit_path.display());
+                Ok(())
+            }
+            ServiceCommands::Status { user } => SystemdService::new(user).status(),
+        }
+    }
+}
@@ -1,3 +1,4 @@
+use crate::services::{AppCapabilities, Capability, FileService, IpcService};
 use anyhow::Result;
 use clap::Subcommand;
 
@@ -14,15 +15,19 @@ pub enum TopicCommands {
 }
 
 impl TopicCommands {
-    pub fn execute(self) -> Result<()> {
+    pub fn execute(self, fs_service: &FileService) -> Result<()> {
+        let data_dir = fs_service.data_dir.clone();
+        let ipc = IpcService::new(data_dir.clone());
         match self {
             TopicCommands::List => {
-                println!("Listing topics");
+                AppCapabilities::require_current(&data_dir, &Capability::TOPIC_LIST)?;
+                print!("{}", ipc.request("topic list")?);
                 Ok(())
             }
+            // Messages keep coming until the app closes the topic or geist is interrupted
             TopicCommands::Echo { name } => {
-                println!("Echoing topic: {}", name);
-                Ok(())
+                AppCapabilities::require_current(&data_dir, &Capability::TOPIC_ECHO)?;
+                ipc.stream(&format!("topic echo {}", name), |line| println!("{}", line))
             }
         }
     }
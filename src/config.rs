@@ -1,45 +1,1050 @@
+use crate::services::ConfigFile;
+use anyhow::{Context, Result};
+use std::collections::BTreeMap;
 use std::env;
 use std::fs;
-use std::io::{self, Write};
 use std::path::PathBuf;
+use std::sync::OnceLock;
+use std::time::Duration;
+
+// Settings of the configuration files, read once per process
+static SETTINGS: OnceLock<ConfigFile> = OnceLock::new();
+
+// `1`, `true` or `yes` turn a boolean variable on, anything else off
+fn env_flag(name: &str) -> Option<bool> {
+    env::var(name)
+        .ok()
+        .map(|v| matches!(v.trim().to_ascii_lowercase().as_str(), "1" | "true" | "yes"))
+}
+
+fn env_parse<T: std::str::FromStr>(name: &str) -> Option<T> {
+    env::var(name).ok().and_then(|v| v.trim().parse().ok())
+}
 
 pub struct Config;
 
 impl Config {
+    // Configuration files: the system-wide one, then the user's, which overrides it.
+    // Environment variables and command-line flags override both.
+    pub const SYSTEM_CONFIG_FILE: &'static str = "/etc/geist/config.toml";
+    // Secrets in a configuration file are stored with this prefix, encrypted under
+    // the key file beside it
+    pub const SECRET_PREFIX: &'static str = "enc:v1:";
+    pub const SECRET_KEY_FILE: &'static str = "secret.key";
+
+    /// The user's configuration file: GEIST_CONFIG, which `--config` sets, or
+    /// `geist/config.toml` in XDG_CONFIG_HOME, by default `~/.config`
+    pub fn user_config_file() -> Option<PathBuf> {
+        if let Some(path) = env::var_os("GEIST_CONFIG").filter(|path| !path.is_empty()) {
+            return Some(PathBuf::from(path));
+        }
+        let config_dir = env::var_os("XDG_CONFIG_HOME")
+            .filter(|dir| !dir.is_empty())
+            .map(PathBuf::from)
+            .or_else(|| env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")))?;
+        Some(config_dir.join("geist/config.toml"))
+    }
+
+    /// The configuration files in the order they are applied
+    pub fn config_files() -> Vec<PathBuf> {
+        std::iter::once(PathBuf::from(Self::SYSTEM_CONFIG_FILE))
+            .chain(Self::user_config_file())
+            .collect()
+    }
+
+    /// Reads the configuration files, failing on the first invalid one. Settings
+    /// are read without it too, but an invalid file is then only warned about.
+    pub fn load_files() -> Result<()> {
+        let settings = ConfigFile::load_layers(&Self::config_files())?;
+        let _ = SETTINGS.set(settings);
+        Ok(())
+    }
+
+    fn file() -> &'static ConfigFile {
+        SETTINGS.get_or_init(|| {
+            ConfigFile::load_layers(&Self::config_files()).unwrap_or_else(|e| {
+                tracing::warn!("Ignoring the configuration files: {:#}", e);
+                ConfigFile::default()
+            })
+        })
+    }
+
     // Application version from Cargo.toml
     pub const PKG_VERSION: &'static str = env!("CARGO_PKG_VERSION");
 
+    /// How geist itself is updated, for releases that need a newer one
+    pub const SELF_UPDATE_COMMAND: &'static str = "cargo install geist_supervisor";
+
     // GCS (Google Cloud Storage) settings
     pub const REGISTRY_BASE_URL: &'static str =
         "https://storage.googleapis.com/roc-camera-releases";
 
-    // Installation paths
-    pub fn data_dir() -> PathBuf {
-        let home = env::var("HOME").expect("Failed to get HOME directory");
-        tracing::info!("Home directory: {}", home);
-        let dir = PathBuf::from(home).join(".local/share/roc-supervisor");
-        tracing::info!("Attempting to create data directory at: {}", dir.display());
-        match fs::create_dir_all(&dir) {
-            Ok(_) => tracing::info!("Successfully created or verified data directory"),
-            Err(e) => {
-                tracing::error!("Error creating data directory: {}", e);
-                tracing::error!(
-                    "Current permissions: {:?}",
-                    fs::metadata(dir.parent().unwrap())
-                );
-                panic!("Failed to create data directory: {}", e);
+    /// Base URL of the GCS registry, e.g. a local test registry. Read from
+    /// GEIST_REGISTRY_URL or `registry.url`.
+    pub fn registry_base_url() -> String {
+        env::var("GEIST_REGISTRY_URL")
+            .ok()
+            .or_else(|| Self::file().registry.url.clone())
+            .map(|url| url.trim().trim_end_matches('/').to_string())
+            .filter(|url| !url.is_empty())
+            .unwrap_or_else(|| Self::REGISTRY_BASE_URL.to_string())
+    }
+
+    /// Copies of the GCS registry tried in order when the one at `registry_base_url`
+    /// fails. Read from GEIST_REGISTRY_MIRRORS, a comma-separated list of base URLs,
+    /// or `registry.mirrors`.
+    pub fn registry_mirrors() -> Vec<String> {
+        let mirrors = match env::var("GEIST_REGISTRY_MIRRORS") {
+            Ok(list) => list.split(',').map(str::to_string).collect(),
+            Err(_) => Self::file().registry.mirrors.clone().unwrap_or_default(),
+        };
+        mirrors
+            .iter()
+            .map(|url| url.trim().trim_end_matches('/').to_string())
+            .filter(|url| !url.is_empty())
+            .collect()
+    }
+
+    /// Whether registry mirrors may be read over plain http. Read from
+    /// GEIST_ALLOW_INSECURE_MIRRORS or `registry.allow_insecure_mirrors`.
+    pub fn allow_insecure_mirrors() -> bool {
+        env_flag("GEIST_ALLOW_INSECURE_MIRRORS")
+            .or(Self::file().registry.allow_insecure_mirrors)
+            .unwrap_or(false)
+    }
+
+    /// Device fixture of `--fake-device`: `default` or the path of a YAML file.
+    /// Read from GEIST_FAKE_DEVICE, which the flag sets for child processes too.
+    pub fn fake_device() -> Option<String> {
+        env::var("GEIST_FAKE_DEVICE")
+            .ok()
+            .map(|spec| spec.trim().to_string())
+            .filter(|spec| !spec.is_empty())
+    }
+
+    /// Whether `--force-run` asked to launch the app binary on a host that does not
+    /// look like the device. Read from GEIST_FORCE_RUN, which the flag sets for
+    /// child processes too.
+    pub fn force_run() -> bool {
+        env_flag("GEIST_FORCE_RUN").unwrap_or(false)
+    }
+
+    /// Whether `run --emulate` asked to launch a binary built for another
+    /// architecture under QEMU user-mode emulation. Read from GEIST_EMULATE, which
+    /// the flag sets.
+    pub fn emulate() -> bool {
+        env_flag("GEIST_EMULATE").unwrap_or(false)
+    }
+
+    // OAuth token endpoint and scope used to authenticate against private buckets
+    pub const GCS_TOKEN_URI: &'static str = "https://oauth2.googleapis.com/token";
+    pub const GCS_OAUTH_SCOPE: &'static str =
+        "https://www.googleapis.com/auth/devstorage.read_only";
+
+    /// Service-account key or credentials file for the bucket, from
+    /// GOOGLE_APPLICATION_CREDENTIALS
+    pub fn gcs_credentials_file() -> Option<PathBuf> {
+        env::var_os("GOOGLE_APPLICATION_CREDENTIALS")
+            .filter(|path| !path.is_empty())
+            .map(PathBuf::from)
+    }
+
+    /// Where `gcloud auth application-default login` stores its credentials
+    pub fn gcloud_adc_file() -> Option<PathBuf> {
+        let config_dir = env::var_os("CLOUDSDK_CONFIG")
+            .map(PathBuf::from)
+            .or_else(|| {
+                env::var_os("HOME").map(|home| PathBuf::from(home).join(".config/gcloud"))
+            })?;
+        Some(config_dir.join("application_default_credentials.json"))
+    }
+
+    // GitHub repository whose releases the `github` registry source uses, and how its
+    // assets are named: `{file}` is the release file name, `{version}` the version
+    // without and `{tag}` with its `v` prefix
+    pub const GITHUB_API_URL: &'static str = "https://api.github.com";
+    pub const GITHUB_REPOSITORY: &'static str = "faust-machines/roc_camera";
+    pub const GITHUB_ASSET_PATTERN: &'static str = "{file}";
+
+    // GitLab instance whose project releases the `gitlab` registry source uses
+    pub const GITLAB_URL: &'static str = "https://gitlab.com";
+
+    /// Registry backend used when no `--source` is given: `gcs` (the default),
+    /// `github` or `gitlab`. Read from GEIST_REGISTRY_SOURCE or `registry.source`.
+    pub fn registry_source() -> String {
+        env::var("GEIST_REGISTRY_SOURCE")
+            .ok()
+            .or_else(|| Self::file().registry.source.clone())
+            .map(|source| source.trim().to_lowercase())
+            .filter(|source| !source.is_empty())
+            .unwrap_or_else(|| "gcs".to_string())
+    }
+
+    /// Whether prereleases such as v2.0.0-rc.1 are considered when resolving
+    /// `latest` or a requirement and shown by `releases list`. Read from
+    /// GEIST_ALLOW_PRERELEASE, which `--allow-prerelease` sets, or
+    /// `registry.allow_prerelease`.
+    pub fn allow_prerelease() -> bool {
+        env_flag("GEIST_ALLOW_PRERELEASE")
+            .or(Self::file().registry.allow_prerelease)
+            .unwrap_or(false)
+    }
+
+    /// Token for the GitHub API, needed for private repositories. Read from GITHUB_TOKEN.
+    pub fn github_token() -> String {
+        env::var("GITHUB_TOKEN").unwrap_or_default()
+    }
+
+    /// Base URL of the GitHub API, e.g. `https://github.example.com/api/v3` for GitHub
+    /// Enterprise. Read from GEIST_GITHUB_API_URL or `github.api_url`.
+    pub fn github_api_url() -> String {
+        env::var("GEIST_GITHUB_API_URL")
+            .ok()
+            .or_else(|| Self::file().github.api_url.clone())
+            .filter(|url| !url.trim().is_empty())
+            .unwrap_or_else(|| Self::GITHUB_API_URL.to_string())
+    }
+
+    /// `owner/repo` of the GitHub repository releases are published in. Read from
+    /// GEIST_GITHUB_REPOSITORY or `github.repository`.
+    pub fn github_repository() -> String {
+        env::var("GEIST_GITHUB_REPOSITORY")
+            .ok()
+            .or_else(|| Self::file().github.repository.clone())
+            .map(|repository| repository.trim().to_string())
+            .filter(|repository| !repository.is_empty())
+            .unwrap_or_else(|| Self::GITHUB_REPOSITORY.to_string())
+    }
+
+    /// Asset name pattern, e.g. `roc_camera-{version}-{file}`. Read from
+    /// GEIST_GITHUB_ASSET_PATTERN or `github.asset_pattern`.
+    pub fn github_asset_pattern() -> String {
+        env::var("GEIST_GITHUB_ASSET_PATTERN")
+            .ok()
+            .or_else(|| Self::file().github.asset_pattern.clone())
+            .filter(|pattern| !pattern.trim().is_empty())
+            .unwrap_or_else(|| Self::GITHUB_ASSET_PATTERN.to_string())
+    }
+
+    /// Base URL of the GitLab instance, for self-managed installations. Read from
+    /// GEIST_GITLAB_URL or `gitlab.url`.
+    pub fn gitlab_url() -> String {
+        env::var("GEIST_GITLAB_URL")
+            .ok()
+            .or_else(|| Self::file().gitlab.url.clone())
+            .filter(|url| !url.trim().is_empty())
+            .unwrap_or_else(|| Self::GITLAB_URL.to_string())
+    }
+
+    /// ID or full path of the GitLab project releases are published in. Read from
+    /// GEIST_GITLAB_PROJECT or `gitlab.project`.
+    pub fn gitlab_project() -> Option<String> {
+        env::var("GEIST_GITLAB_PROJECT")
+            .ok()
+            .or_else(|| Self::file().gitlab.project.clone())
+            .map(|project| project.trim().to_string())
+            .filter(|project| !project.is_empty())
+    }
+
+    /// Token for the GitLab API, needed for private projects. Read from GITLAB_TOKEN.
+    pub fn gitlab_token() -> String {
+        env::var("GITLAB_TOKEN").unwrap_or_default()
+    }
+
+    // Installation paths: GEIST_DATA_DIR, which `--data-dir` sets, `data_dir` from
+    // the configuration files, or this directory in XDG_DATA_HOME, by default
+    // ~/.local/share. Resolved once by the CLI and handed to the services from there.
+    pub const DATA_DIR_NAME: &'static str = "roc-supervisor";
+    // Where system services, which run as root and often without HOME, keep their data
+    pub const SYSTEM_DATA_DIR: &'static str = "/var/lib/geist";
+
+    pub fn data_dir() -> Result<PathBuf> {
+        let dir = match env::var_os("GEIST_DATA_DIR")
+            .filter(|dir| !dir.is_empty())
+            .map(PathBuf::from)
+            .or_else(|| Self::file().data_dir.clone())
+        {
+            Some(dir) => dir,
+            None => Self::default_data_dir(),
+        };
+        let dir = match Self::profile() {
+            Some(profile) => {
+                Self::check_profile(&profile)?;
+                dir.join(Self::PROFILES_DIR).join(profile)
             }
+            None => dir,
+        };
+        tracing::info!("Attempting to create data directory at: {}", dir.display());
+        fs::create_dir_all(&dir).with_context(|| {
+            format!(
+                "Failed to create data directory {}; choose another with --data-dir or GEIST_DATA_DIR",
+                dir.display()
+            )
+        })?;
+        Ok(dir)
+    }
+
+    // Each `--profile` keeps its versions, state, pidfiles and control socket in its
+    // own directory under the data directory, so instances never share a file
+    pub const PROFILES_DIR: &'static str = "profiles";
+
+    /// Name of the instance chosen with `--profile` or GEIST_PROFILE, if any
+    pub fn profile() -> Option<String> {
+        env::var("GEIST_PROFILE")
+            .ok()
+            .filter(|profile| !profile.is_empty())
+    }
+
+    /// Fails for profile names that could not name a directory and a systemd unit
+    pub fn check_profile(profile: &str) -> Result<()> {
+        let valid = profile
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_');
+        if !valid || profile.len() > 32 {
+            anyhow::bail!(
+                "Invalid profile name '{}': use up to 32 letters, digits, '-' and '_'",
+                profile
+            );
         }
-        dir
+        Ok(())
     }
 
-    // Version file
+    // Appends the profile to names shared by the whole machine
+    fn with_profile(name: &str) -> String {
+        match Self::profile() {
+            Some(profile) => format!("{}-{}", name, profile),
+            None => name.to_string(),
+        }
+    }
+
+    fn default_data_dir() -> PathBuf {
+        // The XDG spec says to ignore relative paths
+        if let Some(data_home) = env::var_os("XDG_DATA_HOME")
+            .map(PathBuf::from)
+            .filter(|dir| dir.is_absolute())
+        {
+            return data_home.join(Self::DATA_DIR_NAME);
+        }
+        // Windows has no HOME, so developers there fall back to the profile directory
+        let home = env::var_os("HOME")
+            .or_else(|| env::var_os("USERPROFILE"))
+            .filter(|home| !home.is_empty());
+        let Some(home) = home else {
+            tracing::info!("No home directory; using {}", Self::SYSTEM_DATA_DIR);
+            return PathBuf::from(Self::SYSTEM_DATA_DIR);
+        };
+        let user_dir = PathBuf::from(home)
+            .join(".local/share")
+            .join(Self::DATA_DIR_NAME);
+        // Root keeps a data directory it already has in its home, so installs made
+        // before the system-wide path existed keep their versions
+        if Self::running_as_root() && !user_dir.is_dir() {
+            return PathBuf::from(Self::SYSTEM_DATA_DIR);
+        }
+        user_dir
+    }
+
+    fn running_as_root() -> bool {
+        #[cfg(unix)]
+        {
+            unsafe { libc::geteuid() == 0 }
+        }
+        #[cfg(not(unix))]
+        {
+            false
+        }
+    }
+
+    // Current-version pointer with its generation, the lock held while it is swapped,
+    // and the plain copy of the version kept for scripts and older releases
+    pub const CURRENT_POINTER_FILE: &'static str = "current.json";
+    pub const CURRENT_LOCK_FILE: &'static str = "current.lock";
     pub const CURRENT_VERSION_FILE: &'static str = "current_version";
 
-    // Release artifact names
+    // Provenance record written into each version directory at install time
+    pub const INSTALL_RECORD_FILE: &'static str = "install.json";
+    // The file hashes of each version, kept out of reach of its directory
+    pub const HASH_RECORDS_DIR: &'static str = "hash_records";
+
+    // Suffixes of the directory a version is installed into before it is renamed
+    // into place, and of the copy it replaces until the rename is done
+    pub const STAGING_SUFFIX: &'static str = ".staging";
+    pub const REPLACED_SUFFIX: &'static str = ".replaced";
+
+    // Software bill of materials a release bundle may ship next to its manifest:
+    // SPDX JSON, then CycloneDX JSON
+    pub const SBOM_FILE_NAMES: &'static [&'static str] = &["sbom.spdx.json", "sbom.cdx.json"];
+
+    // Log of state changes read by `geist events`, its lock, and how many events it keeps
+    pub const EVENTS_FILE: &'static str = "events.jsonl";
+    pub const EVENTS_LOCK_FILE: &'static str = "events.lock";
+    pub const MAX_EVENTS: usize = 1000;
+
+    // Versions seen the last time the registry was listed, offered by shell completion
+    pub const RELEASES_CACHE_FILE: &'static str = "releases_cache.json";
+
+    // Supervisor state file
+    pub const STATE_FILE: &'static str = "state.json";
+
+    // Device backups: where `backup create` writes them below the data directory,
+    // the prefix of their objects in the backup bucket, and the description each
+    // archive carries
+    pub const BACKUPS_DIR: &'static str = "backups";
+    pub const BACKUP_OBJECT_PREFIX: &'static str = "backups";
+    pub const BACKUP_INFO_FILE: &'static str = "backup.json";
+
+    // Pid files of the supervising geist process and the app it launched, the
+    // marker `restart` leaves for the supervisor, and the supervisor's answer
+    pub const SUPERVISOR_PID_FILE: &'static str = "supervisor.pid";
+    pub const APP_PID_FILE: &'static str = "app.pid";
+    pub const RESTART_REQUEST_FILE: &'static str = "restart_requested";
+    pub const RESTART_RESULT_FILE: &'static str = "restart_result.json";
+    pub const RESTART_GRACE_SECS: u64 = 10;
+
+    // Zero-downtime restarts of versions that serve sockets: how long a new instance
+    // without a health probe has to stay up to count as ready, and how long it gets
+    pub const HANDOFF_SETTLE_SECS: u64 = 5;
+    pub const HANDOFF_READY_TIMEOUT_SECS: u64 = 30;
+
+    // Directory below the data directory that the app and the supervisor share
+    // files in, the only part of it a sandboxed app can see
+    pub const APP_RUN_DIR: &'static str = "run";
+
+    // Directory below the data directory the app keeps its captures and settings
+    // in. Every version shares it, through a link of the same name in its version
+    // directory, so the data survives updates and rollbacks.
+    pub const APP_DATA_DIR: &'static str = "data";
+
+    // Data migrations: the directory of a bundle and of a version that holds their
+    // scripts, how long one may run unless its manifest says, and where copies of
+    // the app data taken before they run are kept, the newest few of them
+    pub const MIGRATIONS_DIR: &'static str = "migrations";
+    pub const MIGRATION_TIMEOUT_SECS: u64 = 300;
+    pub const MIGRATION_BACKUPS_DIR: &'static str = "migration_backups";
+    pub const MIGRATION_BACKUPS_KEPT: usize = 3;
+
+    // File the app creates to acknowledge a pre-stop signal, relative to the run
+    // directory, and how long it gets to acknowledge if its manifest does not say
+    pub const STOP_ACK_FILE: &'static str = "stop_ack";
+    pub const PRE_STOP_GRACE_SECS: u64 = 10;
+
+    // Control socket the app serves node and topic commands on, relative to the run
+    // directory, and how long a command waits for an answer
+    pub const APP_SOCKET_FILE: &'static str = "app.sock";
+    pub const IPC_TIMEOUT_SECS: u64 = 5;
+    // How long a replayed app has to open the control socket before it is fed
+    pub const REPLAY_CONNECT_TIMEOUT_SECS: u64 = 30;
+
+    // Output of `run --daemon`, relative to the data directory
+    pub const DAEMON_LOG_FILE: &'static str = "logs/geist.log";
+
+    // A/B slot boot marker and how long a trial boot must stay up to count as healthy
+    pub const BOOT_MARKER_FILE: &'static str = "boot_slot.json";
+    pub const SLOT_HEALTHY_AFTER_SECS: u64 = 30;
+
+    // Health probe a trial boot has to pass after its trial window: how long it gets,
+    // how often the probe is retried, and how fresh a heartbeat file has to be
+    pub const HEALTH_PROBE_TIMEOUT_SECS: u64 = 60;
+    pub const HEALTH_PROBE_INTERVAL_SECS: u64 = 5;
+    pub const HEALTH_HEARTBEAT_MAX_AGE_SECS: u64 = 15;
+
+    // Smoke test a freshly installed version has to pass before it is staged: how long
+    // it may run, and how many of its last output lines a failure reports
+    pub const SMOKE_TEST_TIMEOUT_SECS: u64 = 120;
+    pub const SMOKE_TEST_OUTPUT_LINES: usize = 40;
+
+    /// Health probe replacing the manifest's, e.g. `http://127.0.0.1:8080/healthz`,
+    /// `exec:./check.sh` or `heartbeat:/run/roc_camera/heartbeat`. Read from
+    /// GEIST_HEALTH_PROBE or `supervise.health_probe`.
+    pub fn health_probe() -> Option<String> {
+        env::var("GEIST_HEALTH_PROBE")
+            .ok()
+            .or_else(|| Self::file().supervise.health_probe.clone())
+            .map(|spec| spec.trim().to_string())
+            .filter(|spec| !spec.is_empty())
+    }
+
+    // Bundles that failed verification or installation are kept here
+    pub const QUARANTINE_DIR: &'static str = "quarantine";
+
+    // Timeouts of registry and authentication requests, in seconds; 0 disables one
+    pub const HTTP_CONNECT_TIMEOUT_SECS: u64 = 15;
+    pub const HTTP_READ_TIMEOUT_SECS: u64 = 60;
+    pub const HTTP_TIMEOUT_SECS: u64 = 0;
+
+    /// PEM bundle, or directory of `.pem`/`.crt` files, with CAs to trust besides the
+    /// system ones, e.g. of a TLS-intercepting gateway or a private registry.
+    /// Read from GEIST_CA_BUNDLE, which `--ca-bundle` sets, or `http.ca_bundle`.
+    pub fn ca_bundle() -> Option<PathBuf> {
+        env::var_os("GEIST_CA_BUNDLE")
+            .map(PathBuf::from)
+            .or_else(|| Self::file().http.ca_bundle.clone())
+            .filter(|path| !path.as_os_str().is_empty())
+    }
+
+    /// Public key or certificate hashes each registry host has to present, so a
+    /// certificate from a compromised CA is refused. Read from GEIST_TLS_PINS:
+    /// whitespace- or comma-separated `host=pin` entries, where a pin is
+    /// `sha256/<base64>` of the public key or `cert-sha256/<hex>` of the certificate;
+    /// a host with several entries accepts any of them. The file takes a list of
+    /// entries in `http.tls_pins`.
+    pub fn tls_pins() -> BTreeMap<String, Vec<String>> {
+        let entries = env::var("GEIST_TLS_PINS")
+            .ok()
+            .or_else(|| Some(Self::file().http.tls_pins.as_ref()?.join(",")))
+            .unwrap_or_default();
+        let mut pins: BTreeMap<String, Vec<String>> = BTreeMap::new();
+        for entry in entries
+            .split(|c: char| c == ',' || c.is_whitespace())
+            .filter(|entry| !entry.is_empty())
+        {
+            let (host, pin) = entry.split_once('=').unwrap_or((entry, ""));
+            pins.entry(host.to_ascii_lowercase())
+                .or_default()
+                .push(pin.to_string());
+        }
+        pins
+    }
+
+    /// Identity a cosign keyless signature of the release bundle has to be made by,
+    /// e.g. the release workflow's URL. Setting it verifies bundles with cosign
+    /// instead of minisign. Read from GEIST_COSIGN_IDENTITY; a trailing `*` matches
+    /// any identity starting with what precedes it. The file sets it in
+    /// `verification.cosign_identity`.
+    pub fn cosign_identity() -> Option<String> {
+        env::var("GEIST_COSIGN_IDENTITY")
+            .ok()
+            .or_else(|| Self::file().verification.cosign_identity.clone())
+            .filter(|identity| !identity.is_empty())
+    }
+
+    /// OIDC issuer the cosign identity has to come from, e.g.
+    /// `https://token.actions.githubusercontent.com`. Read from GEIST_COSIGN_ISSUER
+    /// or `verification.cosign_issuer`.
+    pub fn cosign_issuer() -> Option<String> {
+        env::var("GEIST_COSIGN_ISSUER")
+            .ok()
+            .or_else(|| Self::file().verification.cosign_issuer.clone())
+            .filter(|issuer| !issuer.is_empty())
+    }
+
+    /// PEM file with the Fulcio root and intermediate certificates that issue the
+    /// signing certificates. Read from GEIST_COSIGN_ROOTS or `verification.cosign_roots`.
+    pub fn cosign_roots() -> Option<PathBuf> {
+        env::var_os("GEIST_COSIGN_ROOTS")
+            .map(PathBuf::from)
+            .or_else(|| Self::file().verification.cosign_roots.clone())
+            .filter(|path| !path.as_os_str().is_empty())
+    }
+
+    /// PEM public key of the Rekor transparency log that has to have recorded the
+    /// signature. Read from GEIST_COSIGN_REKOR_KEY or `verification.cosign_rekor_key`.
+    pub fn cosign_rekor_key() -> Option<PathBuf> {
+        env::var_os("GEIST_COSIGN_REKOR_KEY")
+            .map(PathBuf::from)
+            .or_else(|| Self::file().verification.cosign_rekor_key.clone())
+            .filter(|path| !path.as_os_str().is_empty())
+    }
+
+    /// Whether releases are only trusted when the registry's signed targets
+    /// metadata lists them. Read from GEIST_SIGNED_METADATA (`1`, `true` or `yes`)
+    /// or `verification.signed_metadata`.
+    pub fn signed_metadata() -> bool {
+        env_flag("GEIST_SIGNED_METADATA")
+            .or(Self::file().verification.signed_metadata)
+            .unwrap_or(false)
+    }
+
+    fn timeout_from_env(name: &str, file_secs: Option<u64>, default_secs: u64) -> Option<Duration> {
+        let secs = env_parse(name).or(file_secs).unwrap_or(default_secs);
+        (secs > 0).then(|| Duration::from_secs(secs))
+    }
+
+    /// Time allowed to establish a connection. Can be overridden with
+    /// GEIST_HTTP_CONNECT_TIMEOUT_SECS, `--connect-timeout` or `http.connect_timeout_secs`.
+    pub fn http_connect_timeout() -> Option<Duration> {
+        Self::timeout_from_env(
+            "GEIST_HTTP_CONNECT_TIMEOUT_SECS",
+            Self::file().http.connect_timeout_secs,
+            Self::HTTP_CONNECT_TIMEOUT_SECS,
+        )
+    }
+
+    /// Time a request may wait for the server without receiving anything, so a
+    /// stalled connection fails instead of hanging. Can be overridden with
+    /// GEIST_HTTP_READ_TIMEOUT_SECS, `--read-timeout` or `http.read_timeout_secs`.
+    pub fn http_read_timeout() -> Option<Duration> {
+        Self::timeout_from_env(
+            "GEIST_HTTP_READ_TIMEOUT_SECS",
+            Self::file().http.read_timeout_secs,
+            Self::HTTP_READ_TIMEOUT_SECS,
+        )
+    }
+
+    /// Time a whole request may take, body included; unlimited by default because
+    /// bundles on slow links take long. Can be overridden with GEIST_HTTP_TIMEOUT_SECS,
+    /// `--timeout` or `http.timeout_secs`.
+    pub fn http_timeout() -> Option<Duration> {
+        Self::timeout_from_env(
+            "GEIST_HTTP_TIMEOUT_SECS",
+            Self::file().http.timeout_secs,
+            Self::HTTP_TIMEOUT_SECS,
+        )
+    }
+
+    // Large downloads are fetched as ranged chunks over parallel connections
+    pub const DOWNLOAD_CHUNK_MB: u64 = 8;
+    pub const DOWNLOAD_CONCURRENCY: usize = 4;
+    pub const DOWNLOAD_CHUNK_ATTEMPTS: u32 = 3;
+
+    /// Size of one ranged download request. Can be overridden with
+    /// GEIST_DOWNLOAD_CHUNK_MB or `downloads.chunk_mb`.
+    pub fn download_chunk_bytes() -> u64 {
+        env_parse("GEIST_DOWNLOAD_CHUNK_MB")
+            .or(Self::file().downloads.chunk_mb)
+            .filter(|mb| *mb > 0)
+            .unwrap_or(Self::DOWNLOAD_CHUNK_MB)
+            * 1024
+            * 1024
+    }
+
+    /// Connections a download uses at once; 1 downloads in one piece.
+    /// Can be overridden with GEIST_DOWNLOAD_CONCURRENCY or `downloads.concurrency`.
+    pub fn download_concurrency() -> usize {
+        env_parse("GEIST_DOWNLOAD_CONCURRENCY")
+            .or(Self::file().downloads.concurrency)
+            .unwrap_or(Self::DOWNLOAD_CONCURRENCY)
+    }
+
+    // Power checks before unattended updates
+    pub const POWER_SUPPLY_DIR: &'static str = "/sys/class/power_supply";
+    pub const THROTTLED_FILE: &'static str = "/sys/devices/platform/soc/soc:firmware/get_throttled";
+    pub const MIN_UPDATE_BATTERY_PERCENT: u8 = 30;
+
+    /// Where power supplies are listed. Can be overridden with GEIST_POWER_SUPPLY_DIR.
+    pub fn power_supply_dir() -> PathBuf {
+        env::var("GEIST_POWER_SUPPLY_DIR")
+            .map(PathBuf::from)
+            .unwrap_or_else(|_| PathBuf::from(Self::POWER_SUPPLY_DIR))
+    }
+
+    /// Battery charge below which unattended updates wait for external power.
+    /// Can be overridden with GEIST_MIN_UPDATE_BATTERY_PERCENT or
+    /// `downloads.min_battery_percent`.
+    pub fn min_update_battery_percent() -> u8 {
+        env_parse("GEIST_MIN_UPDATE_BATTERY_PERCENT")
+            .or(Self::file().downloads.min_battery_percent)
+            .unwrap_or(Self::MIN_UPDATE_BATTERY_PERCENT)
+    }
+
+    // Metered links and monthly data caps
+    pub const BILLING_DAY: u32 = 1;
+
+    /// Whether the device is on a metered link, where release downloads need
+    /// `--force`. Read from GEIST_METERED (`1`, `true` or `yes`) or `downloads.metered`.
+    pub fn metered() -> bool {
+        env_flag("GEIST_METERED")
+            .or(Self::file().downloads.metered)
+            .unwrap_or(false)
+    }
+
+    /// Bytes of release bundles that may be downloaded per billing period without
+    /// `--force`; unlimited if unset or 0. Read from GEIST_DATA_BUDGET_MB or
+    /// `downloads.data_budget_mb`.
+    pub fn data_budget_bytes() -> Option<u64> {
+        env_parse::<u64>("GEIST_DATA_BUDGET_MB")
+            .or(Self::file().downloads.data_budget_mb)
+            .filter(|mb| *mb > 0)
+            .map(|mb| mb * 1024 * 1024)
+    }
+
+    /// Day of the month a billing period starts on.
+    /// Can be overridden with GEIST_BILLING_DAY (1-31) or `downloads.billing_day`.
+    pub fn billing_day() -> u32 {
+        env_parse("GEIST_BILLING_DAY")
+            .or(Self::file().downloads.billing_day)
+            .filter(|day| (1..=31).contains(day))
+            .unwrap_or(Self::BILLING_DAY)
+    }
+
+    // Registry answers revalidated with ETag/Last-Modified instead of downloaded again
+    pub const HTTP_CACHE_DIR: &'static str = "cache/http";
+
+    // Verified release bundles kept to avoid downloading them again
+    pub const BUNDLE_CACHE_DIR: &'static str = "cache/bundles";
+    pub const BUNDLE_CACHE_MAX_MB: u64 = 1024;
+
+    /// Size the bundle cache is trimmed to; 0 disables caching.
+    /// Can be overridden with GEIST_BUNDLE_CACHE_MAX_MB or `downloads.bundle_cache_max_mb`.
+    pub fn bundle_cache_max_bytes() -> u64 {
+        env_parse("GEIST_BUNDLE_CACHE_MAX_MB")
+            .or(Self::file().downloads.bundle_cache_max_mb)
+            .unwrap_or(Self::BUNDLE_CACHE_MAX_MB)
+            * 1024
+            * 1024
+    }
+
+    // Device reports, sent daily by `supervise` once an endpoint is configured
+    pub const REPORT_QUEUE_DIR: &'static str = "reports";
+    pub const REPORT_LAST_SENT_FILE: &'static str = "report_last_sent";
+    pub const REPORT_INTERVAL_SECS: u64 = 24 * 60 * 60;
+    pub const REPORT_RETRY_INTERVAL_SECS: u64 = 15 * 60;
+    pub const REPORT_QUEUE_MAX: usize = 30;
+    pub const REPORT_TIMEOUT_SECS: u64 = 30;
+    pub const REPORT_ATTEMPTS: u32 = 3;
+    pub const REPORT_RETRY_DELAY_SECS: u64 = 5;
+
+    /// Endpoint device reports are POSTed to; reporting is off without one.
+    /// Read from GEIST_REPORT_URL or `report.url`.
+    pub fn report_url() -> Option<String> {
+        env::var("GEIST_REPORT_URL")
+            .ok()
+            .or_else(|| Self::file().report.url.clone())
+            .map(|url| url.trim().to_string())
+            .filter(|url| !url.is_empty())
+    }
+
+    /// Bucket URL backups are uploaded to by `backup create --upload` and fetched
+    /// from by `backup restore`, e.g. `https://storage.googleapis.com/my-backups`.
+    /// Read from GEIST_BACKUP_URL or `backup.url`.
+    pub fn backup_url() -> Option<String> {
+        env::var("GEIST_BACKUP_URL")
+            .ok()
+            .or_else(|| Self::file().backup.url.clone())
+            .map(|url| url.trim().trim_end_matches('/').to_string())
+            .filter(|url| !url.is_empty())
+    }
+
+    /// Bearer token sent with device reports. Read from GEIST_REPORT_TOKEN or `report.token`.
+    pub fn report_token() -> Option<String> {
+        env::var("GEIST_REPORT_TOKEN")
+            .ok()
+            .or_else(|| Self::file().report.token.clone())
+            .filter(|token| !token.is_empty())
+    }
+
+    /// How often `supervise` re-hashes the current version's files to detect
+    /// tampering. Off unless GEIST_TAMPER_CHECK_SECS or `supervise.tamper_check_secs`
+    /// is set above 0.
+    pub fn tamper_check_interval() -> Option<Duration> {
+        env_parse("GEIST_TAMPER_CHECK_SECS")
+            .or(Self::file().supervise.tamper_check_secs)
+            .filter(|secs| *secs > 0)
+            .map(Duration::from_secs)
+    }
+
+    /// Whether a version whose files changed since it was installed is refused at
+    /// launch. Read from GEIST_TAMPER_REFUSE_LAUNCH (`1`, `true` or `yes`) or
+    /// `supervise.tamper_refuse_launch`.
+    pub fn tamper_refuse_launch() -> bool {
+        env_flag("GEIST_TAMPER_REFUSE_LAUNCH")
+            .or(Self::file().supervise.tamper_refuse_launch)
+            .unwrap_or(false)
+    }
+
+    /// Whether `run` and `supervise` launch the app in the sandbox. Read from
+    /// GEIST_SANDBOX (`1`, `true` or `yes`), which their `--sandbox` flag sets, or
+    /// `supervise.sandbox`.
+    pub fn sandbox() -> bool {
+        env_flag("GEIST_SANDBOX")
+            .or(Self::file().supervise.sandbox)
+            .unwrap_or(false)
+    }
+
+    /// User, by name or uid, that a supervisor running as root launches the app as,
+    /// e.g. `roc` for a systemd system service. Read from GEIST_APP_USER or
+    /// `supervise.app_user`.
+    pub fn app_user() -> Option<String> {
+        env::var("GEIST_APP_USER")
+            .ok()
+            .or_else(|| Self::file().supervise.app_user.clone())
+            .map(|user| user.trim().to_string())
+            .filter(|user| !user.is_empty())
+    }
+
+    /// Primary group of the app, by name or gid; unset uses the app user's own.
+    /// Read from GEIST_APP_GROUP or `supervise.app_group`.
+    pub fn app_group() -> Option<String> {
+        env::var("GEIST_APP_GROUP")
+            .ok()
+            .or_else(|| Self::file().supervise.app_group.clone())
+            .map(|group| group.trim().to_string())
+            .filter(|group| !group.is_empty())
+    }
+
+    /// Supplementary groups, by name or gid, that versions may ask for in their
+    /// manifest; the app gets none of the others. Read from GEIST_APP_GROUPS
+    /// (comma-separated) or `supervise.app_groups`.
+    pub fn app_groups() -> Vec<String> {
+        let groups = match env::var("GEIST_APP_GROUPS") {
+            Ok(list) => list.split(',').map(str::to_string).collect(),
+            Err(_) => Self::file()
+                .supervise
+                .app_groups
+                .clone()
+                .unwrap_or_default(),
+        };
+        groups
+            .iter()
+            .map(|group| group.trim().to_string())
+            .filter(|group| !group.is_empty())
+            .collect()
+    }
+
+    // The sandbox root is mounted on this empty directory below the data directory.
+    // Besides the version, a sandboxed app sees these host paths read-only.
+    pub const SANDBOX_ROOT_DIR: &'static str = "sandbox_root";
+    pub const SANDBOX_SYSTEM_PATHS: &'static [&'static str] =
+        &["/usr", "/bin", "/sbin", "/lib", "/lib64", "/etc"];
+
+    /// Image repository of the app, e.g. `ghcr.io/faust-machines/roc_camera`. Setting
+    /// it puts geist in container mode, where versions are OCI images: `update` pulls
+    /// `<image>:<version>` and `run` and `supervise` start containers. Read from
+    /// GEIST_CONTAINER_IMAGE or `container.image`.
+    pub fn container_image() -> Option<String> {
+        env::var("GEIST_CONTAINER_IMAGE")
+            .ok()
+            .or_else(|| Self::file().container.image.clone())
+            .map(|image| image.trim().to_string())
+            .filter(|image| !image.is_empty())
+    }
+
+    /// Container runtime, `docker` or `podman`; unset picks whichever is installed,
+    /// podman first. Read from GEIST_CONTAINER_RUNTIME or `container.runtime`.
+    pub fn container_runtime() -> Option<String> {
+        env::var("GEIST_CONTAINER_RUNTIME")
+            .ok()
+            .or_else(|| Self::file().container.runtime.clone())
+            .filter(|runtime| !runtime.is_empty())
+    }
+
+    // A version installed from an image holds its description in place of the
+    // binary. Images carry the release's manifest at the manifest path, and the
+    // container sees the shared app data at the data path.
+    pub const CONTAINER_IMAGE_FILE: &'static str = "image.json";
+    pub const CONTAINER_MANIFEST_PATH: &'static str = "/manifest.yaml";
+    pub const CONTAINER_DATA_PATH: &'static str = "/data";
+    // Containers are named after the version they run, behind this prefix
+    pub const CONTAINER_NAME_PREFIX: &'static str = "geist-roc_camera-";
+    // Camera devices mapped into the container when the version's manifest declares
+    // none in its sandbox section; those missing on the device are skipped
+    pub const CONTAINER_DEFAULT_DEVICES: &'static [&'static str] =
+        &["/dev/video0", "/dev/media0", "/dev/vchiq", "/dev/dri"];
+
+    // Restart backoff for `supervise`; a run longer than the reset period starts over
+    pub const SUPERVISE_INITIAL_BACKOFF_SECS: u64 = 1;
+    pub const SUPERVISE_MAX_BACKOFF_SECS: u64 = 300;
+    pub const SUPERVISE_BACKOFF_RESET_SECS: u64 = 60;
+
+    /// Restart policy entries such as `42=update` or `on_failure=stop`, applied over
+    /// the manifest's `restart_policy`. Read from GEIST_RESTART_POLICY (comma-separated)
+    /// or `supervise.restart_policy`.
+    pub fn restart_policy() -> Vec<String> {
+        let entries = match env::var("GEIST_RESTART_POLICY") {
+            Ok(list) => list.split(',').map(str::to_string).collect(),
+            Err(_) => Self::file()
+                .supervise
+                .restart_policy
+                .clone()
+                .unwrap_or_default(),
+        };
+        entries
+            .iter()
+            .map(|entry| entry.trim().to_string())
+            .filter(|entry| !entry.is_empty())
+            .collect()
+    }
+
+    // Self-protection for `supervise`: this many daemon starts within the window put
+    // it into safe mode, where it only idles until an operator runs `restart`
+    pub const DAEMON_STARTS_FILE: &'static str = "daemon_starts";
+    pub const SAFE_MODE_FILE: &'static str = "safe_mode";
+    pub const DAEMON_LOOP_THRESHOLD: usize = 5;
+    pub const DAEMON_LOOP_WINDOW_SECS: u64 = 600;
+
+    // Throughput assumed when estimating the duration of `--dry-run` plans
+    pub const PLAN_DOWNLOAD_BYTES_PER_SEC: u64 = 2 * 1024 * 1024;
+    pub const PLAN_DISK_BYTES_PER_SEC: u64 = 20 * 1024 * 1024;
+
+    // Disk and memory held back for the supervisor so it can always recover the device
+    pub const RESERVED_DISK_MB: u64 = 256;
+    pub const RESERVED_MEMORY_MB: u64 = 64;
+
+    /// Free disk space `update` must leave untouched.
+    /// Can be overridden with GEIST_RESERVED_DISK_MB or `downloads.reserved_disk_mb`.
+    pub fn reserved_disk_bytes() -> u64 {
+        env_parse("GEIST_RESERVED_DISK_MB")
+            .or(Self::file().downloads.reserved_disk_mb)
+            .unwrap_or(Self::RESERVED_DISK_MB)
+            * 1024
+            * 1024
+    }
+
+    /// Memory kept out of the app's reach, by capping the memory limit of its
+    /// cgroup or container.
+    /// Can be overridden with GEIST_RESERVED_MEMORY_MB or `supervise.reserved_memory_mb`.
+    pub fn reserved_memory_bytes() -> u64 {
+        env_parse("GEIST_RESERVED_MEMORY_MB")
+            .or(Self::file().supervise.reserved_memory_mb)
+            .unwrap_or(Self::RESERVED_MEMORY_MB)
+            * 1024
+            * 1024
+    }
+
+    /// Memory the app may use, replacing the manifest's `resources.memory_mb`.
+    /// Read from GEIST_MEMORY_LIMIT_MB or `supervise.memory_limit_mb`.
+    pub fn memory_limit_mb() -> Option<u64> {
+        env_parse("GEIST_MEMORY_LIMIT_MB")
+            .or(Self::file().supervise.memory_limit_mb)
+            .filter(|mb| *mb > 0)
+    }
+
+    /// CPU time the app may use in percent of one CPU, e.g. 150 for one and a half,
+    /// replacing the manifest's `resources.cpu_percent`. Read from GEIST_CPU_PERCENT
+    /// or `supervise.cpu_percent`.
+    pub fn cpu_percent() -> Option<u64> {
+        env_parse("GEIST_CPU_PERCENT")
+            .or(Self::file().supervise.cpu_percent)
+            .filter(|percent| *percent > 0)
+    }
+
+    // The resource limits are applied to a cgroup of this name made for the app.
+    // The supervisor moves to its own next to it, since only a cgroup without
+    // processes hands controllers down. CPU limits are enforced per period.
+    pub const APP_CGROUP: &'static str = "roc_camera";
+    pub const SUPERVISOR_CGROUP: &'static str = "geist";
+    pub const CPU_PERIOD_USEC: u64 = 100_000;
+
+    // Offline operations from USB sticks carrying a `geist/` directory
+    pub const USB_DIR: &'static str = "geist";
+    pub const USB_MOUNT_ROOTS: &'static str = "/media:/run/media:/mnt";
+    pub const USB_POLL_SECS: u64 = 5;
+
+    /// Directories scanned for mounted USB sticks.
+    /// Can be overridden with GEIST_USB_MOUNT_ROOTS (colon-separated) or `usb.mount_roots`.
+    pub fn usb_mount_roots() -> Vec<PathBuf> {
+        match env::var("GEIST_USB_MOUNT_ROOTS") {
+            Ok(list) => list
+                .split(':')
+                .filter(|root| !root.is_empty())
+                .map(PathBuf::from)
+                .collect(),
+            Err(_) => Self::file().usb.mount_roots.clone().unwrap_or_else(|| {
+                Self::USB_MOUNT_ROOTS
+                    .split(':')
+                    .map(PathBuf::from)
+                    .collect()
+            }),
+        }
+    }
+
+    /// Operations a USB stick may trigger: `import`, `export` and `plan`. Nothing is
+    /// allowed unless listed in GEIST_USB_POLICY (comma-separated) or `usb.policy`.
+    pub fn usb_policy() -> Vec<String> {
+        let ops = match env::var("GEIST_USB_POLICY") {
+            Ok(list) => list.split(',').map(str::to_string).collect(),
+            Err(_) => Self::file().usb.policy.clone().unwrap_or_default(),
+        };
+        ops.iter()
+            .map(|op| op.trim().to_lowercase())
+            .filter(|op| !op.is_empty())
+            .collect()
+    }
+
+    // Name of the systemd unit installed by `service install`, geist-<profile>.service
+    // for a profile
+    pub const SYSTEMD_UNIT_NAME: &'static str = "geist";
+
+    pub fn systemd_unit_name() -> String {
+        format!("{}.service", Self::with_profile(Self::SYSTEMD_UNIT_NAME))
+    }
+    pub const SYSTEMD_WATCHDOG_SECS: u64 = 60;
+
+    // Trusted update-signing keys, and how long a rotated-out key stays valid
+    pub const TRUSTED_KEYS_FILE: &'static str = "trusted_keys.json";
+    // Registry tokens saved by `auth login`: the keyring service they are stored
+    // under, suffixed with the profile, and the file used where no keyring is available
+    pub const KEYRING_SERVICE: &'static str = "geist";
+
+    pub fn keyring_service() -> String {
+        Self::with_profile(Self::KEYRING_SERVICE)
+    }
+    pub const CREDENTIALS_FILE: &'static str = "credentials.json";
+
+    /// Append-only record of every change to the trusted keys
+    pub const KEY_AUDIT_FILE: &'static str = "trusted_keys_audit.jsonl";
+    pub const KEY_ROTATION_OVERLAP_DAYS: u64 = 30;
+
+    // Crash-loop detection: this many failed exits within the window trigger a rollback
+    pub const CRASH_LOOP_THRESHOLD: usize = 3;
+    pub const CRASH_LOOP_WINDOW_SECS: u64 = 300;
+
+    /// Number of crashes within the window that counts as a crash loop.
+    /// Can be overridden with GEIST_CRASH_LOOP_THRESHOLD or `supervise.crash_loop_threshold`.
+    pub fn crash_loop_threshold() -> usize {
+        env_parse("GEIST_CRASH_LOOP_THRESHOLD")
+            .or(Self::file().supervise.crash_loop_threshold)
+            .unwrap_or(Self::CRASH_LOOP_THRESHOLD)
+    }
+
+    /// Window in which crashes are counted towards a crash loop. Can be overridden
+    /// with GEIST_CRASH_LOOP_WINDOW_SECS or `supervise.crash_loop_window_secs`.
+    pub fn crash_loop_window() -> Duration {
+        let secs = env_parse("GEIST_CRASH_LOOP_WINDOW_SECS")
+            .or(Self::file().supervise.crash_loop_window_secs)
+            .unwrap_or(Self::CRASH_LOOP_WINDOW_SECS);
+        Duration::from_secs(secs)
+    }
+
+    // Release artifact names: the local name a downloaded bundle is saved under, the
+    // prefix of bundle names in a release, and its checksum list
     pub const RELEASE_BUNDLE_NAME: &'static str = "release_bundle.tar.gz";
+    pub const BUNDLE_PREFIX: &'static str = "release_bundle";
     pub const CHECKSUM_FILE_NAME: &'static str = "checksums.txt";
 
+    /// Bundles of a release are named `<prefix>-<version>.<extension>`.
+    /// Can be overridden with GEIST_BUNDLE_PREFIX or `registry.bundle_prefix`.
+    pub fn bundle_prefix() -> String {
+        env::var("GEIST_BUNDLE_PREFIX")
+            .ok()
+            .or_else(|| Self::file().registry.bundle_prefix.clone())
+            .map(|prefix| prefix.trim().to_string())
+            .filter(|prefix| !prefix.is_empty())
+            .unwrap_or_else(|| Self::BUNDLE_PREFIX.to_string())
+    }
+
+    /// Checksum list of a release. Can be overridden with GEIST_CHECKSUM_FILE or
+    /// `registry.checksum_file`.
+    pub fn checksum_file_name() -> String {
+        env::var("GEIST_CHECKSUM_FILE")
+            .ok()
+            .or_else(|| Self::file().registry.checksum_file.clone())
+            .map(|name| name.trim().to_string())
+            .filter(|name| !name.is_empty())
+            .unwrap_or_else(|| Self::CHECKSUM_FILE_NAME.to_string())
+    }
+
+    /// Detached minisign signature of the checksum list
+    pub fn checksum_signature_file_name() -> String {
+        format!(
+            "{}.{}",
+            Self::checksum_file_name(),
+            Self::SIGNATURE_EXTENSION
+        )
+    }
+    /// Appended to a release file's name for its detached minisign signature
+    pub const SIGNATURE_EXTENSION: &'static str = "minisig";
+    /// Appended to the release bundle's name for its cosign bundle, as written by
+    /// `cosign sign-blob --bundle`
+    pub const COSIGN_BUNDLE_EXTENSION: &'static str = "cosign.bundle";
+    /// Signed metadata listing every release bundle with its length and hash,
+    /// published at `releases/` next to the release directories
+    pub const TARGETS_METADATA_FILE: &'static str = "targets.json";
+    pub const TARGETS_SIGNATURE_FILE: &'static str = "targets.json.minisig";
+    /// Name under a release's version in the targets metadata for its container
+    /// image, hashed by the image's manifest digest
+    pub const TARGETS_IMAGE_NAME: &'static str = "image";
+
     // Version related
     pub const DEFAULT_VERSION: &'static str = "latest";
 
@@ -48,26 +1053,58 @@ impl Config {
         version.trim_start_matches('v').to_string()
     }
 
-    /// Gets the current installed version
-    pub fn get_current_version() -> String {
-        // First check if it's set in environment
-        if let Ok(version) = env::var("GEIST_CURRENT_VERSION") {
-            return version;
+    /// Parses a version, with or without the 'v' prefix, as semver
+    pub fn parse_version(version: &str) -> Result<semver::Version> {
+        semver::Version::parse(&Self::normalize_version(version))
+            .with_context(|| format!("'{}' is not a semantic version such as v1.2.3", version))
+    }
+
+    /// The semver requirement a version argument such as `^1.4` or `~2.3.1` states,
+    /// if it is one rather than a single version
+    pub fn version_requirement(version: &str) -> Option<semver::VersionReq> {
+        if Self::parse_version(version).is_ok() {
+            return None;
         }
+        semver::VersionReq::parse(&Self::normalize_version(version)).ok()
+    }
 
-        // Then check the version file
-        let version_file = Self::data_dir().join(Self::CURRENT_VERSION_FILE);
-        match fs::read_to_string(version_file) {
-            Ok(version) => version.trim().to_string(),
-            Err(_) => format!("v{}", Self::PKG_VERSION), // Default to package version if no version file
+    /// Whether a version meets a requirement under the prerelease policy. Without
+    /// it, prereleases only meet requirements naming a prerelease of the same
+    /// version; with it, they also meet those their release would.
+    pub fn version_matches(version: &semver::Version, requirement: &semver::VersionReq) -> bool {
+        if requirement.matches(version) {
+            return true;
         }
+        if version.pre.is_empty() || !Self::allow_prerelease() {
+            return false;
+        }
+        let release = semver::Version::new(version.major, version.minor, version.patch);
+        requirement.matches(&release)
     }
 
-    /// Sets the current version
-    pub fn set_current_version(version: &str) -> io::Result<()> {
-        let version_file = Self::data_dir().join(Self::CURRENT_VERSION_FILE);
-        let mut file = fs::File::create(version_file)?;
-        file.write_all(version.as_bytes())?;
-        Ok(())
+    /// Whether a listed version may be offered under the prerelease policy
+    pub fn version_offered(version: &str) -> bool {
+        Self::allow_prerelease()
+            || Self::parse_version(version).map_or(true, |parsed| parsed.pre.is_empty())
+    }
+
+    /// The newest of the versions by semver that meets the requirement. Versions
+    /// that are not semver are never picked.
+    pub fn newest_version(
+        versions: impl IntoIterator<Item = String>,
+        requirement: &semver::VersionReq,
+    ) -> Option<String> {
+        versions
+            .into_iter()
+            .filter_map(|version| Some((Self::parse_version(&version).ok()?, version)))
+            .filter(|(parsed, _)| Self::version_matches(parsed, requirement))
+            .max_by(|(a, _), (b, _)| a.cmp(b))
+            .map(|(_, version)| version)
+    }
+
+    /// Sorts versions oldest first by semver, so v1.10.0 follows v1.9.0. Versions
+    /// that are not semver come first, by name.
+    pub fn sort_versions(versions: &mut [String]) {
+        versions.sort_by_cached_key(|version| (Self::parse_version(version).ok(), version.clone()));
     }
 }
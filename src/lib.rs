@@ -0,0 +1,6 @@
+pub mod cli;
+pub mod config;
+pub mod services;
+#[cfg(feature = "testutil")]
+pub mod testutil;
+pub mod utils;
@@ -1,16 +1,16 @@
 use anyhow::Result;
-use clap::Parser;
+use clap::{CommandFactory, Parser};
+use clap_complete::CompleteEnv;
+use geist_supervisor::cli::Cli;
+use geist_supervisor::utils;
 use std::env;
-pub mod cli;
-pub mod config;
-pub mod services;
-pub mod utils;
-
-use cli::Cli;
 
 const PKG_VERSION: &str = env!("CARGO_PKG_VERSION");
 
 fn main() -> Result<()> {
+    // Answers tab-completion requests from the shell script printed by `completions`
+    CompleteEnv::with_factory(Cli::command).complete();
+
     // Set default log level if not already set
     if env::var("RUST_LOG").is_err() {
         env::set_var("RUST_LOG", "info");
@@ -21,5 +21,9 @@ fn main() -> Result<()> {
 
     tracing::info!("Starting Geist Supervisor v{}", PKG_VERSION);
     let cli = Cli::parse();
-    cli.execute()
+    let result = cli.execute();
+
+    // Make sure exported spans reach the collector before exiting
+    utils::telemetry::shutdown();
+    result
 }
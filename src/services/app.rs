@@ -0,0 +1,367 @@
+use crate::config::Config;
+use crate::services::{
+    AppCgroup, AppCredentials, BinaryArch, ContainerImage, ContainerService, ExecLabel,
+    HostService, MacProfile, Sandbox,
+};
+use anyhow::{Context, Result};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command, ExitStatus};
+use std::thread;
+use std::time::{Duration, Instant};
+use tracing::{info, warn};
+
+/// Builds and controls roc_camera processes for an installed version
+pub struct AppService {
+    pub version_dir: PathBuf,
+    sandbox: Option<Sandbox>,
+    cgroup: Option<AppCgroup>,
+}
+
+impl AppService {
+    pub fn new(version_dir: PathBuf) -> Self {
+        Self {
+            version_dir,
+            sandbox: None,
+            cgroup: None,
+        }
+    }
+
+    /// Launches the app in `sandbox` instead of directly
+    pub fn sandboxed(mut self, sandbox: Sandbox) -> Self {
+        self.sandbox = Some(sandbox);
+        self
+    }
+
+    /// Launches the app in `cgroup`, which holds its resource limits
+    pub fn limited(mut self, cgroup: AppCgroup) -> Self {
+        self.cgroup = Some(cgroup);
+        self
+    }
+
+    pub fn binary_path(&self) -> PathBuf {
+        self.version_dir.join("roc_camera")
+    }
+
+    pub fn assets_path(&self) -> PathBuf {
+        self.version_dir.join("roc_camera_app")
+    }
+
+    /// The image the version runs, if it was installed in container mode
+    pub fn image(&self) -> Option<ContainerImage> {
+        ContainerImage::load(&self.version_dir)
+    }
+
+    /// Why the binary cannot run on this host, if it is built for an architecture
+    /// the host does not execute. Binaries that are not ELF, e.g. scripts, hosts of
+    /// unknown architecture and fake devices, whose binaries are stand-ins, are not
+    /// judged, nor are images, whose runtime picks the build for the host.
+    pub fn arch_mismatch(&self) -> Result<Option<String>> {
+        if Config::fake_device().is_some() || self.image().is_some() {
+            return Ok(None);
+        }
+        let Some(binary) = BinaryArch::of_binary(&self.binary_path())? else {
+            return Ok(None);
+        };
+        let Some(host) = BinaryArch::host() else {
+            return Ok(None);
+        };
+        if host.runs(binary) {
+            return Ok(None);
+        }
+        Ok(Some(format!(
+            "roc_camera in {} is built for {}, but this host is {}",
+            self.version_dir.display(),
+            binary,
+            host
+        )))
+    }
+
+    // Runs the binary under QEMU's user-mode emulator for its architecture, from the
+    // qemu-user or qemu-user-static package. A dynamically linked build loads its
+    // libraries from the cross sysroot, unless QEMU_LD_PREFIX names another one.
+    fn emulator(&self) -> Result<Command> {
+        let arch = BinaryArch::of_binary(&self.binary_path())?
+            .context("Only ELF binaries can be emulated")?;
+        let names = [
+            format!("qemu-{}", arch.qemu_name()),
+            format!("qemu-{}-static", arch.qemu_name()),
+        ];
+        let qemu = names
+            .iter()
+            .find_map(|name| HostService::find_program(name))
+            .with_context(|| {
+                format!(
+                    "Emulating the {} build needs {} from the qemu-user or qemu-user-static package",
+                    arch, names[0]
+                )
+            })?;
+        let mut command = Command::new(qemu);
+        let sysroot = Path::new(arch.cross_sysroot());
+        if std::env::var_os("QEMU_LD_PREFIX").is_none() && sysroot.is_dir() {
+            command.env("QEMU_LD_PREFIX", sysroot);
+        }
+        command.arg(self.binary_path());
+        Ok(command)
+    }
+
+    /// The directory the app keeps its data in, shared by all versions installed
+    /// next to this one
+    pub fn data_path(&self) -> PathBuf {
+        self.version_dir
+            .parent()
+            .unwrap_or(Path::new("."))
+            .join(Config::APP_DATA_DIR)
+    }
+
+    // The account to launch the app under, if it is switched to. It is given the
+    // shared data and the run directory first, since a supervisor running as root
+    // created them, while the version is kept from it.
+    fn credentials(&self) -> Result<Option<AppCredentials>> {
+        let Some(credentials) = AppCredentials::resolve(&self.version_dir)? else {
+            return Ok(None);
+        };
+        let run_path = self
+            .version_dir
+            .parent()
+            .unwrap_or(Path::new("."))
+            .join(Config::APP_RUN_DIR);
+        fs::create_dir_all(&run_path)
+            .with_context(|| format!("Failed to create {}", run_path.display()))?;
+        // The version stays root's: the app may read it, never rewrite its own
+        // binary or install record
+        credentials.protect(&self.version_dir)?;
+        for dir in [&self.data_path(), &run_path] {
+            credentials.own(dir)?;
+        }
+        credentials.announce();
+        Ok(Some(credentials))
+    }
+
+    // Creates the shared data directory and links it into the version directory, so
+    // the app finds it under `data/` of its working directory too. Versions
+    // installed before there was one get their link on their next launch.
+    fn link_data_dir(&self) -> Result<()> {
+        let data_path = self.data_path();
+        fs::create_dir_all(&data_path)
+            .with_context(|| format!("Failed to create {}", data_path.display()))?;
+
+        let link = self.version_dir.join(Config::APP_DATA_DIR);
+        match fs::symlink_metadata(&link) {
+            Ok(metadata) if metadata.file_type().is_symlink() => return Ok(()),
+            Ok(_) => {
+                warn!(
+                    "Not linking the shared data directory into {}: {} already exists",
+                    self.version_dir.display(),
+                    link.display()
+                );
+                return Ok(());
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+            Err(e) => return Err(e).with_context(|| format!("Failed to read {}", link.display())),
+        }
+        #[cfg(unix)]
+        std::os::unix::fs::symlink(Path::new("..").join(Config::APP_DATA_DIR), &link)
+            .with_context(|| format!("Failed to link {}", link.display()))?;
+        Ok(())
+    }
+
+    /// Prepares the command that launches roc_camera from the version directory
+    pub fn command(&self, replay_bag: Option<&Path>) -> Result<Command> {
+        HostService::require_linux("Launching roc_camera")?;
+
+        // Isolated by the container runtime instead of the sandbox
+        if let Some(image) = self.image() {
+            if self.sandbox.is_some() {
+                warn!("Not sandboxing {}: it runs as a container", image.reference);
+            }
+            let data_dir = self.version_dir.parent().unwrap_or(Path::new("."));
+            return ContainerService::new(data_dir.to_path_buf()).command(
+                &self.version_dir,
+                &image,
+                replay_bag,
+            );
+        }
+
+        let binary_path = self.binary_path();
+        if !binary_path.exists() {
+            anyhow::bail!("Binary not found at: {}", binary_path.display());
+        }
+
+        // A build for another architecture would only fail with an exec format error,
+        // unless it is emulated
+        let emulator = match self.arch_mismatch()? {
+            None => None,
+            Some(mismatch) if Config::emulate() => {
+                let emulator = self.emulator()?;
+                info!(
+                    "{}; emulating it with {}",
+                    mismatch,
+                    emulator.get_program().to_string_lossy()
+                );
+                Some(emulator)
+            }
+            Some(mismatch) if Config::force_run() => {
+                warn!("{}; launching it anyway (--force-run)", mismatch);
+                None
+            }
+            Some(mismatch) => anyhow::bail!(
+                "{}. Install the release built for {}, pass --emulate to run it under QEMU, or pass --force-run to try anyway",
+                mismatch,
+                HostService::arch()
+            ),
+        };
+        if emulator.is_some() && self.sandbox.is_some() {
+            anyhow::bail!("An emulated app cannot run in the sandbox; leave out --sandbox");
+        }
+
+        // Make sure the binary is executable
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mut perms = fs::metadata(&binary_path)?.permissions();
+            perms.set_mode(0o755);
+            fs::set_permissions(&binary_path, perms)?;
+        }
+
+        // Use the actual assets in the data directory instead of creating symlinks
+        let flutter_assets_path = self.assets_path();
+
+        if !flutter_assets_path.exists() {
+            tracing::error!(
+                "Flutter assets directory doesn't exist at: {}",
+                flutter_assets_path.display()
+            );
+            return Err(anyhow::anyhow!("Flutter assets directory not found"));
+        }
+
+        info!(
+            "Using Flutter assets from: {}",
+            flutter_assets_path.display()
+        );
+
+        self.link_data_dir()?;
+        let mut credentials = self.credentials()?;
+
+        // Confined by the profile the version brings, if the device enforces its kind.
+        // Loaded on every launch, since a profile of the same name from another
+        // version, such as one rolled back from, may have replaced it.
+        let label = match MacProfile::load(&self.version_dir) {
+            Some(profile) => {
+                profile.load_into_kernel(&self.version_dir)?;
+                profile.exec_label()?
+            }
+            None => None,
+        };
+
+        let mut command = match &self.sandbox {
+            // The sandbox applies the label and switches to the app user itself,
+            // since it has to mount first
+            Some(sandbox) => {
+                let extra: Vec<&Path> = replay_bag.into_iter().collect();
+                sandbox.command(
+                    &self.version_dir,
+                    &binary_path,
+                    &extra,
+                    label,
+                    credentials.take(),
+                )?
+            }
+            None => {
+                let mut command = emulator.unwrap_or_else(|| Command::new(&binary_path));
+                #[cfg(unix)]
+                if let Some(label) = label {
+                    use std::os::unix::ffi::OsStrExt;
+                    use std::os::unix::process::CommandExt;
+                    let attr_path = std::ffi::CString::new(label.attr_path.as_os_str().as_bytes())?;
+                    let value = std::ffi::CString::new(label.value)?;
+                    unsafe {
+                        command.pre_exec(move || ExecLabel::write(&attr_path, &value));
+                    }
+                }
+                command
+            }
+        };
+
+        // Joined while the process still has the rights to
+        if let Some(cgroup) = &self.cgroup {
+            cgroup.add(&mut command)?;
+        }
+
+        // Last, since the label needs root
+        #[cfg(unix)]
+        if let Some(credentials) = credentials {
+            use std::os::unix::process::CommandExt;
+            unsafe {
+                command.pre_exec(move || credentials.apply());
+            }
+        }
+
+        // Set current directory to the version directory
+        command.current_dir(&self.version_dir);
+
+        // Add environment variables that point to the actual assets location
+        command.env("FLUTTER_ASSETS_DIR", &flutter_assets_path);
+        command.env("FLUTTER_ASSET_DIR", &flutter_assets_path);
+        command.env("FLUTTER_BUNDLE_DIR", &flutter_assets_path);
+        command.env("FLUTTER_APP_DIR", &flutter_assets_path);
+        command.env("FLUTTER_PI_APP_DIR", &flutter_assets_path);
+        command.env("APP_DIR", &flutter_assets_path);
+
+        // Where the app keeps data that has to outlive the version
+        command.env("GEIST_APP_DATA_DIR", self.data_path());
+
+        // Pass the flutter assets directory as a command-line argument
+        command.arg("--flutter-assets-dir");
+        command.arg(&flutter_assets_path);
+
+        // Tells the app to read its topics from the bag, which whoever launches it
+        // feeds over the control socket
+        if let Some(bag) = replay_bag {
+            info!("Replaying session bag: {}", bag.display());
+            command.env("GEIST_REPLAY_BAG", bag);
+            command.arg("--replay");
+            command.arg(bag);
+        }
+
+        Ok(command)
+    }
+
+    /// Waits up to `timeout` for the child to exit, returning its status if it did
+    pub fn wait_timeout(child: &mut Child, timeout: Duration) -> Result<Option<ExitStatus>> {
+        let deadline = Instant::now() + timeout;
+        loop {
+            if let Some(status) = child.try_wait()? {
+                return Ok(Some(status));
+            }
+            if Instant::now() >= deadline {
+                return Ok(None);
+            }
+            thread::sleep(Duration::from_millis(100));
+        }
+    }
+
+    /// Asks the child to exit with SIGTERM and kills it if it outlives the grace period
+    pub fn terminate(child: &mut Child, grace: Duration) -> Result<ExitStatus> {
+        if let Some(status) = child.try_wait()? {
+            return Ok(status);
+        }
+
+        #[cfg(unix)]
+        unsafe {
+            libc::kill(child.id() as libc::pid_t, libc::SIGTERM);
+        }
+
+        if let Some(status) = Self::wait_timeout(child, grace)? {
+            return Ok(status);
+        }
+
+        tracing::warn!(
+            "Process {} did not exit within {:?}, killing it",
+            child.id(),
+            grace
+        );
+        child.kill().context("Failed to kill process")?;
+        Ok(child.wait()?)
+    }
+}
@@ -0,0 +1,82 @@
+use crate::services::BinaryArch;
+use anyhow::{Context, Result};
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+
+/// The builds of the app a release ships, one per architecture, of which the one
+/// for the device is installed as `roc_camera`. Releases without the section ship
+/// a single `roc_camera`.
+///
+/// Declared in the `artifacts` section of a version's `manifest.yaml`, keyed by
+/// architecture as `uname -m` names it or its family (`armv7`, `aarch64`,
+/// `x86_64`), with file names relative to the manifest:
+///
+/// ```yaml
+/// artifacts:
+///   armv7: roc_camera-armv7
+///   aarch64: roc_camera-aarch64
+/// ```
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(transparent)]
+pub struct AppArtifacts {
+    pub files: BTreeMap<String, String>,
+}
+
+// Only the part of the manifest the artifacts care about
+#[derive(Deserialize)]
+struct Manifest {
+    #[serde(default)]
+    artifacts: Option<AppArtifacts>,
+}
+
+impl AppArtifacts {
+    /// The builds declared by an extracted bundle or an installed version, if any.
+    /// Unlike other sections, an unreadable one is an error, since guessing would
+    /// install the wrong binary.
+    pub fn load(manifest_dir: &Path) -> Result<Option<Self>> {
+        let path = manifest_dir.join("manifest.yaml");
+        let contents = match fs::read_to_string(&path) {
+            Ok(contents) => contents,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e).with_context(|| format!("Failed to read {}", path.display())),
+        };
+        let manifest: Manifest = serde_yaml::from_str(&contents)
+            .with_context(|| format!("Invalid artifacts section in {}", path.display()))?;
+        let Some(artifacts) = manifest
+            .artifacts
+            .filter(|artifacts| !artifacts.files.is_empty())
+        else {
+            return Ok(None);
+        };
+        for file in artifacts.files.values() {
+            if Path::new(file).components().count() != 1 {
+                anyhow::bail!("Artifact '{}' must be a plain file name", file);
+            }
+        }
+        Ok(Some(artifacts))
+    }
+
+    /// The file of the build a host of `arch` runs: the one for its own
+    /// architecture, or else a 32-bit one it can execute
+    pub fn select(&self, arch: BinaryArch) -> Result<&str> {
+        let built_for = |key: &str| BinaryArch::from_machine(key);
+        self.files
+            .iter()
+            .find(|(key, _)| built_for(key) == Some(arch))
+            .or_else(|| {
+                self.files
+                    .iter()
+                    .find(|(key, _)| built_for(key).is_some_and(|built| arch.runs(built)))
+            })
+            .map(|(_, file)| file.as_str())
+            .with_context(|| {
+                format!(
+                    "The release has no build of roc_camera for {} (it ships {})",
+                    arch,
+                    self.files.keys().cloned().collect::<Vec<_>>().join(", ")
+                )
+            })
+    }
+}
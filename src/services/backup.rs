@@ -0,0 +1,264 @@
+use crate::config::Config;
+use crate::services::{
+    EventKind, EventService, FileService, GcsCredentials, GcsService, HostService, StateService,
+    SupervisorState,
+};
+use anyhow::{Context, Result};
+use chrono::{DateTime, Utc};
+use flate2::read::GzDecoder;
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::{Path, PathBuf};
+use tracing::info;
+
+// Names of the parts of a backup inside its archive
+const DATA_ENTRY: &str = "data";
+const STATE_ENTRY: &str = "state.json";
+const CONFIG_ENTRY: &str = "config.toml";
+
+/// Where and when a backup was taken, stored in its archive as `backup.json`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BackupInfo {
+    pub created_at: DateTime<Utc>,
+    pub hostname: Option<String>,
+    pub geist_version: String,
+    pub current_version: String,
+    /// Whether the archive holds the user's configuration file
+    pub config: bool,
+}
+
+/// Backups of what a device cannot get back from a registry: the shared app data,
+/// the supervisor state and the user's configuration file. Versions are left out,
+/// since they can be installed again. Archives are written to the backups
+/// directory and can be kept in a bucket, to restore a device after a failed
+/// update or to move its data to a replacement.
+pub struct BackupService {
+    data_dir: PathBuf,
+}
+
+impl BackupService {
+    pub fn new(data_dir: PathBuf) -> Self {
+        Self { data_dir }
+    }
+
+    fn backups_dir(&self) -> PathBuf {
+        self.data_dir.join(Config::BACKUPS_DIR)
+    }
+
+    fn bucket(&self) -> Result<GcsService> {
+        let url = Config::backup_url()
+            .context("No backup bucket is configured; set GEIST_BACKUP_URL or backup.url")?;
+        GcsService::new(
+            GcsCredentials::application_default(&self.data_dir)?,
+            url,
+            self.data_dir.clone(),
+        )
+    }
+
+    /// Writes a backup into the backups directory and returns its path. The file
+    /// name, e.g. `20260302T141000Z-camera-04.tar.gz`, is also its id once uploaded.
+    pub fn create(&self) -> Result<PathBuf> {
+        let app_data_dir = self.data_dir.join(Config::APP_DATA_DIR);
+        fs::create_dir_all(&app_data_dir)
+            .with_context(|| format!("Failed to create {}", app_data_dir.display()))?;
+        let backups_dir = self.backups_dir();
+        fs::create_dir_all(&backups_dir)
+            .with_context(|| format!("Failed to create {}", backups_dir.display()))?;
+        FileService::new(self.data_dir.clone())
+            .ensure_disk_reserve(FileService::size_of(&app_data_dir)?, "the backup")?;
+
+        let created_at = Utc::now();
+        let hostname = HostService::hostname();
+        let host: String = hostname
+            .as_deref()
+            .unwrap_or("geist")
+            .chars()
+            .map(
+                |c| match c.is_ascii_alphanumeric() || c == '-' || c == '_' {
+                    true => c,
+                    false => '_',
+                },
+            )
+            .collect();
+        let name = format!("{}-{}.tar.gz", created_at.format("%Y%m%dT%H%M%SZ"), host);
+        let path = backups_dir.join(&name);
+        let partial = backups_dir.join(format!("{}.partial", name));
+
+        let state_file = self.data_dir.join(Config::STATE_FILE);
+        let config_file = Config::user_config_file().filter(|path| path.is_file());
+        let backup_info = BackupInfo {
+            created_at,
+            hostname,
+            geist_version: Config::PKG_VERSION.to_string(),
+            current_version: FileService::new(self.data_dir.clone()).current_version(),
+            config: config_file.is_some(),
+        };
+
+        let file = fs::File::create(&partial)
+            .with_context(|| format!("Failed to create {}", partial.display()))?;
+        let mut builder = tar::Builder::new(GzEncoder::new(file, Compression::default()));
+        // Links in the app data are kept as links
+        builder.follow_symlinks(false);
+        let info_json = serde_json::to_vec_pretty(&backup_info)?;
+        let mut header = tar::Header::new_gnu();
+        header.set_size(info_json.len() as u64);
+        header.set_mode(0o644);
+        header.set_mtime(created_at.timestamp().max(0) as u64);
+        header.set_cksum();
+        builder.append_data(&mut header, Config::BACKUP_INFO_FILE, info_json.as_slice())?;
+        builder
+            .append_dir_all(DATA_ENTRY, &app_data_dir)
+            .with_context(|| format!("Failed to archive {}", app_data_dir.display()))?;
+        if state_file.is_file() {
+            builder.append_path_with_name(&state_file, STATE_ENTRY)?;
+        }
+        if let Some(config_file) = &config_file {
+            builder.append_path_with_name(config_file, CONFIG_ENTRY)?;
+        }
+        let file = builder.into_inner()?.finish()?;
+        file.sync_all()?;
+        fs::rename(&partial, &path)
+            .with_context(|| format!("Failed to write {}", path.display()))?;
+
+        info!("Wrote backup {}", path.display());
+        Ok(path)
+    }
+
+    /// Uploads a backup to the backup bucket and returns its id
+    pub fn upload(&self, path: &Path) -> Result<String> {
+        let id = path
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned())
+            .with_context(|| format!("{} is not a file", path.display()))?;
+        let object = format!("{}/{}", Config::BACKUP_OBJECT_PREFIX, id);
+        self.bucket()?.upload_object(&object, path)?;
+        info!("Uploaded backup {} as {}", path.display(), object);
+        Ok(id)
+    }
+
+    /// The archive of a backup given as a path, as the name of one in the backups
+    /// directory, or as the id of an uploaded one, which is downloaded there first
+    pub fn locate(&self, backup: &str) -> Result<PathBuf> {
+        let path = Path::new(backup);
+        if path.is_file() {
+            return Ok(path.to_path_buf());
+        }
+        let local = self.backups_dir().join(backup);
+        if local.is_file() {
+            return Ok(local);
+        }
+        if backup.is_empty() || backup.contains(['/', '\\']) || backup.starts_with('.') {
+            anyhow::bail!("Backup {} not found", backup);
+        }
+
+        fs::create_dir_all(self.backups_dir())?;
+        let partial = self.backups_dir().join(format!("{}.partial", backup));
+        let object = format!("{}/{}", Config::BACKUP_OBJECT_PREFIX, backup);
+        info!("Downloading backup {} from the backup bucket", backup);
+        self.bucket()
+            .with_context(|| format!("Backup {} not found locally", backup))?
+            .download_object(&object, &partial)
+            .with_context(|| format!("Backup {} not found locally or in the bucket", backup))?;
+        fs::rename(&partial, &local)
+            .with_context(|| format!("Failed to write {}", local.display()))?;
+        Ok(local)
+    }
+
+    /// Puts the app data and configuration file of a backup in place, along with the
+    /// migrations its state records as applied to the data. The archive is unpacked
+    /// and checked before anything is replaced.
+    pub fn restore(&self, archive: &Path) -> Result<BackupInfo> {
+        fs::create_dir_all(self.backups_dir())?;
+        // Next to the data directory, so the data can be renamed into place
+        let staging = tempfile::Builder::new()
+            .prefix(".restore")
+            .tempdir_in(self.backups_dir())?;
+        let file = fs::File::open(archive)
+            .with_context(|| format!("Failed to open {}", archive.display()))?;
+        let mut unpacker = tar::Archive::new(GzDecoder::new(file));
+        unpacker.set_preserve_permissions(true);
+        unpacker.set_preserve_mtime(true);
+        unpacker
+            .unpack(staging.path())
+            .with_context(|| format!("Failed to unpack {}", archive.display()))?;
+
+        let info_path = staging.path().join(Config::BACKUP_INFO_FILE);
+        let backup_info: BackupInfo = fs::read_to_string(&info_path)
+            .ok()
+            .and_then(|contents| serde_json::from_str(&contents).ok())
+            .with_context(|| format!("{} is not a geist backup", archive.display()))?;
+        let restored_data = staging.path().join(DATA_ENTRY);
+        if !restored_data.is_dir() {
+            anyhow::bail!("{} holds no app data", archive.display());
+        }
+        let state = match fs::read_to_string(staging.path().join(STATE_ENTRY)) {
+            Ok(contents) => Some(
+                serde_json::from_str::<SupervisorState>(&contents)
+                    .context("The supervisor state in the backup is invalid")?,
+            ),
+            Err(_) => None,
+        };
+
+        // The current data is moved aside until the restored copy is in place
+        let app_data_dir = self.data_dir.join(Config::APP_DATA_DIR);
+        let replaced = self.data_dir.join(format!(
+            "{}{}",
+            Config::APP_DATA_DIR,
+            Config::REPLACED_SUFFIX
+        ));
+        if replaced.exists() {
+            fs::remove_dir_all(&replaced)
+                .with_context(|| format!("Failed to remove {}", replaced.display()))?;
+        }
+        if app_data_dir.exists() {
+            fs::rename(&app_data_dir, &replaced)
+                .with_context(|| format!("Failed to move {} aside", app_data_dir.display()))?;
+        }
+        fs::rename(&restored_data, &app_data_dir)
+            .with_context(|| format!("Failed to restore {}", app_data_dir.display()))?;
+        if replaced.exists() {
+            fs::remove_dir_all(&replaced)
+                .with_context(|| format!("Failed to remove {}", replaced.display()))?;
+        }
+
+        // Only what describes the restored data is taken from the backup. The rest of
+        // the state is about the versions installed now, and the targets version
+        // may only go up, or an old backup would reopen a metadata rollback.
+        if let Some(restored) = state {
+            StateService::new(self.data_dir.clone()).update(|state| {
+                state.migrations = restored.migrations;
+                state.targets_version = state.targets_version.max(restored.targets_version);
+            })?;
+        }
+
+        let restored_config = staging.path().join(CONFIG_ENTRY);
+        if restored_config.is_file() {
+            let config_file = Config::user_config_file()
+                .context("Nowhere to restore the configuration file to; set GEIST_CONFIG")?;
+            if let Some(parent) = config_file.parent() {
+                fs::create_dir_all(parent)
+                    .with_context(|| format!("Failed to create {}", parent.display()))?;
+            }
+            let tmp_path = config_file.with_extension("toml.tmp");
+            fs::copy(&restored_config, &tmp_path)
+                .with_context(|| format!("Failed to write {}", tmp_path.display()))?;
+            fs::rename(&tmp_path, &config_file)
+                .with_context(|| format!("Failed to replace {}", config_file.display()))?;
+        }
+
+        EventService::new(self.data_dir.clone()).record(
+            EventKind::Restored,
+            None,
+            Some(
+                archive
+                    .file_name()
+                    .unwrap_or_default()
+                    .to_string_lossy()
+                    .into_owned(),
+            ),
+        );
+        Ok(backup_info)
+    }
+}
@@ -0,0 +1,187 @@
+use crate::config::Config;
+use crate::services::{AppService, IpcService, ReplayBag};
+use anyhow::{Context, Result};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::{Duration, Instant};
+use tracing::info;
+
+/// Metrics sampled while running a single version
+pub struct BenchResult {
+    pub version: String,
+    pub samples: usize,
+    pub avg_cpu_percent: f64,
+    pub avg_rss_bytes: u64,
+    pub peak_rss_bytes: u64,
+    pub avg_fps: Option<f64>,
+    pub avg_latency_ms: Option<f64>,
+    pub exited_early: bool,
+}
+
+pub struct BenchService {
+    pub data_dir: PathBuf,
+}
+
+impl BenchService {
+    // How often process statistics are sampled
+    const SAMPLE_INTERVAL: Duration = Duration::from_secs(1);
+
+    pub fn new(data_dir: PathBuf) -> Self {
+        Self { data_dir }
+    }
+
+    /// Runs a version for the given duration and samples its resource usage.
+    ///
+    /// The app is pointed at a metrics file through `GEIST_METRICS_FILE`; versions
+    /// that append JSON lines with `fps` and `latency_ms` get those averaged too.
+    pub fn run_version(
+        &self,
+        version: &str,
+        duration: Duration,
+        replay_bag: Option<&Path>,
+    ) -> Result<BenchResult> {
+        let version_dir = self.data_dir.join(version);
+        if !version_dir.exists() {
+            anyhow::bail!(
+                "Version {} not found. Please run 'update {}' first.",
+                version,
+                version
+            );
+        }
+
+        let temp_dir = tempfile::tempdir()?;
+        let metrics_file = temp_dir.path().join("metrics.jsonl");
+
+        let app = AppService::new(version_dir);
+        let replay = replay_bag.map(ReplayBag::load).transpose()?;
+        let mut command = app.command(replay_bag)?;
+        command.env("GEIST_METRICS_FILE", &metrics_file);
+        // The control socket the bag is fed over, as under the supervisor
+        let ipc = IpcService::new(self.data_dir.clone());
+        fs::create_dir_all(self.data_dir.join(Config::APP_RUN_DIR))?;
+        command.env("GEIST_IPC_SOCKET", ipc.socket_path());
+
+        info!("Benchmarking version {} for {:?}", version, duration);
+        let mut child = command
+            .spawn()
+            .with_context(|| format!("Failed to launch version {}", version))?;
+        let pid = child.id();
+        if let Some(bag) = replay {
+            bag.feed(ipc, pid);
+        }
+
+        let (ticks_per_sec, page_size) = clock_ticks_and_page_size();
+
+        let start = Instant::now();
+        let mut last_ticks = read_cpu_ticks(pid);
+        let mut last_sample = start;
+        let mut cpu_percents = Vec::new();
+        let mut rss_samples = Vec::new();
+        let mut exited_early = false;
+
+        while start.elapsed() < duration {
+            thread::sleep(Self::SAMPLE_INTERVAL);
+
+            if child.try_wait()?.is_some() {
+                tracing::warn!("Version {} exited before the benchmark finished", version);
+                exited_early = true;
+                break;
+            }
+
+            let now = Instant::now();
+            if let (Some(prev), Some(ticks)) = (last_ticks, read_cpu_ticks(pid)) {
+                let elapsed = now.duration_since(last_sample).as_secs_f64();
+                let cpu_secs = ticks.saturating_sub(prev) as f64 / ticks_per_sec;
+                cpu_percents.push(cpu_secs / elapsed * 100.0);
+                last_ticks = Some(ticks);
+            }
+            last_sample = now;
+
+            if let Some(pages) = read_rss_pages(pid) {
+                rss_samples.push(pages * page_size);
+            }
+        }
+
+        AppService::terminate(&mut child, Duration::from_secs(5))?;
+
+        let (avg_fps, avg_latency_ms) = read_app_metrics(&metrics_file);
+
+        Ok(BenchResult {
+            version: version.to_string(),
+            samples: cpu_percents.len(),
+            avg_cpu_percent: mean(&cpu_percents).unwrap_or(0.0),
+            avg_rss_bytes: if rss_samples.is_empty() {
+                0
+            } else {
+                rss_samples.iter().sum::<u64>() / rss_samples.len() as u64
+            },
+            peak_rss_bytes: rss_samples.iter().copied().max().unwrap_or(0),
+            avg_fps,
+            avg_latency_ms,
+            exited_early,
+        })
+    }
+}
+
+// Units of the /proc counters: clock ticks per second and bytes per page
+#[cfg(unix)]
+fn clock_ticks_and_page_size() -> (f64, u64) {
+    unsafe {
+        (
+            libc::sysconf(libc::_SC_CLK_TCK) as f64,
+            libc::sysconf(libc::_SC_PAGESIZE) as u64,
+        )
+    }
+}
+
+// There is no /proc to sample off Linux, so the values are never used
+#[cfg(not(unix))]
+fn clock_ticks_and_page_size() -> (f64, u64) {
+    (100.0, 4096)
+}
+
+// Total user + system CPU ticks consumed by a process
+fn read_cpu_ticks(pid: u32) -> Option<u64> {
+    let stat = fs::read_to_string(format!("/proc/{}/stat", pid)).ok()?;
+    // The command name may contain spaces, so parse the fields after it
+    let fields: Vec<&str> = stat.rsplit_once(')')?.1.split_whitespace().collect();
+    let utime: u64 = fields.get(11)?.parse().ok()?;
+    let stime: u64 = fields.get(12)?.parse().ok()?;
+    Some(utime + stime)
+}
+
+// Resident set size of a process in pages
+fn read_rss_pages(pid: u32) -> Option<u64> {
+    let statm = fs::read_to_string(format!("/proc/{}/statm", pid)).ok()?;
+    statm.split_whitespace().nth(1)?.parse().ok()
+}
+
+// Averages the fps and latency values reported by the app, if any
+fn read_app_metrics(metrics_file: &Path) -> (Option<f64>, Option<f64>) {
+    let Ok(contents) = fs::read_to_string(metrics_file) else {
+        return (None, None);
+    };
+
+    let mut fps = Vec::new();
+    let mut latency = Vec::new();
+    for line in contents.lines() {
+        if let Ok(value) = serde_json::from_str::<serde_json::Value>(line) {
+            if let Some(v) = value.get("fps").and_then(|v| v.as_f64()) {
+                fps.push(v);
+            }
+            if let Some(v) = value.get("latency_ms").and_then(|v| v.as_f64()) {
+                latency.push(v);
+            }
+        }
+    }
+    (mean(&fps), mean(&latency))
+}
+
+fn mean(values: &[f64]) -> Option<f64> {
+    if values.is_empty() {
+        None
+    } else {
+        Some(values.iter().sum::<f64>() / values.len() as f64)
+    }
+}
@@ -0,0 +1,131 @@
+use crate::services::HostService;
+use anyhow::{Context, Result};
+use serde::Serialize;
+use std::fs::File;
+use std::io::Read;
+use std::path::Path;
+
+// ELF identification and the `e_machine` values of the architectures geist knows
+const ELF_MAGIC: &[u8; 4] = b"\x7fELF";
+const ELF_DATA_LSB: u8 = 1;
+const EM_386: u16 = 3;
+const EM_ARM: u16 = 40;
+const EM_X86_64: u16 = 62;
+const EM_AARCH64: u16 = 183;
+const EM_RISCV: u16 = 243;
+
+/// CPU architecture a host runs or an executable is built for. The camera runs
+/// `arm` on 32-bit Raspberry Pi OS and `aarch64` on the 64-bit one; the others are
+/// development machines.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum BinaryArch {
+    Arm,
+    Aarch64,
+    X86,
+    X86_64,
+    Riscv64,
+}
+
+impl BinaryArch {
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Arm => "arm",
+            Self::Aarch64 => "aarch64",
+            Self::X86 => "x86",
+            Self::X86_64 => "x86_64",
+            Self::Riscv64 => "riscv64",
+        }
+    }
+
+    /// The architecture of a machine name as `uname -m` reports it
+    pub fn from_machine(machine: &str) -> Option<Self> {
+        match machine {
+            "aarch64" | "arm64" | "aarch64_be" => Some(Self::Aarch64),
+            "x86_64" | "amd64" => Some(Self::X86_64),
+            "i386" | "i486" | "i586" | "i686" | "x86" => Some(Self::X86),
+            "riscv64" => Some(Self::Riscv64),
+            // armv6l, armv7l, and armv8l for a 32-bit userland on a 64-bit CPU
+            machine if machine.starts_with("arm") => Some(Self::Arm),
+            _ => None,
+        }
+    }
+
+    /// Name of the architecture in QEMU's user-mode emulators, e.g. `qemu-aarch64`
+    pub fn qemu_name(self) -> &'static str {
+        match self {
+            Self::X86 => "i386",
+            arch => arch.name(),
+        }
+    }
+
+    /// Where Debian's cross-compilation packages put the libraries of the
+    /// architecture, which emulated binaries load instead of the host's
+    pub fn cross_sysroot(self) -> &'static str {
+        match self {
+            Self::Arm => "/usr/arm-linux-gnueabihf",
+            Self::Aarch64 => "/usr/aarch64-linux-gnu",
+            Self::X86 => "/usr/i686-linux-gnu",
+            Self::X86_64 => "/usr/x86_64-linux-gnu",
+            Self::Riscv64 => "/usr/riscv64-linux-gnu",
+        }
+    }
+
+    /// The architecture of this host, or of the fake device
+    pub fn host() -> Option<Self> {
+        Self::from_machine(&HostService::arch())
+    }
+
+    /// Whether this is one of the architectures the camera runs
+    pub fn is_device(self) -> bool {
+        matches!(self, Self::Arm | Self::Aarch64)
+    }
+
+    /// The architecture an executable is built for, from its ELF header. `None` for
+    /// files that are not ELF, such as scripts, which run wherever their
+    /// interpreter does.
+    pub fn of_binary(path: &Path) -> Result<Option<Self>> {
+        let mut header = [0u8; 20];
+        let mut file =
+            File::open(path).with_context(|| format!("Failed to open {}", path.display()))?;
+        let read = file
+            .read(&mut header)
+            .with_context(|| format!("Failed to read {}", path.display()))?;
+        if read < header.len() || &header[..4] != ELF_MAGIC {
+            return Ok(None);
+        }
+        let machine = match header[5] {
+            ELF_DATA_LSB => u16::from_le_bytes([header[18], header[19]]),
+            _ => u16::from_be_bytes([header[18], header[19]]),
+        };
+        match machine {
+            EM_ARM => Ok(Some(Self::Arm)),
+            EM_AARCH64 => Ok(Some(Self::Aarch64)),
+            EM_386 => Ok(Some(Self::X86)),
+            EM_X86_64 => Ok(Some(Self::X86_64)),
+            EM_RISCV => Ok(Some(Self::Riscv64)),
+            other => anyhow::bail!(
+                "{} is built for an unknown architecture (ELF machine {})",
+                path.display(),
+                other
+            ),
+        }
+    }
+
+    /// Whether a host of this architecture executes binaries built for `binary`:
+    /// its own, and the 32-bit builds of the same family, which 64-bit kernels run
+    /// in compat mode (given the 32-bit libraries they link against)
+    pub fn runs(self, binary: Self) -> bool {
+        self == binary
+            || matches!(
+                (self, binary),
+                (Self::Aarch64, Self::Arm) | (Self::X86_64, Self::X86)
+            )
+    }
+}
+
+impl std::fmt::Display for BinaryArch {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.name())
+    }
+}
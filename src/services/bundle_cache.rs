@@ -0,0 +1,169 @@
+use crate::config::Config;
+use crate::services::{BundleFormat, FileService, RegistryBackend};
+use anyhow::{Context, Result};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+use tracing::{info, warn};
+
+/// A release bundle put in place by `BundleCache::fetch`
+pub struct FetchedBundle {
+    /// Hex SHA-256 of the bundle, computed while it was downloaded or copied
+    pub sha256: String,
+    pub from_cache: bool,
+}
+
+/// Release bundles kept after they were downloaded and matched their checksum, so
+/// e.g. `verify --deep` followed by `update`, reinstalling a version or moving back
+/// and forth between versions downloads each bundle once. Entries are addressed by
+/// their SHA-256 alone, so identical bundles published under several versions or
+/// mirrors share one entry; the least recently used ones are evicted once the cache
+/// outgrows `Config::bundle_cache_max_bytes`.
+pub struct BundleCache {
+    dir: PathBuf,
+    max_bytes: u64,
+}
+
+impl BundleCache {
+    pub fn new(data_dir: PathBuf) -> Self {
+        Self {
+            dir: data_dir.join(Config::BUNDLE_CACHE_DIR),
+            max_bytes: Config::bundle_cache_max_bytes(),
+        }
+    }
+
+    fn entry_path(&self, sha256: &str, format: BundleFormat) -> PathBuf {
+        self.dir.join(format!(
+            "{}.{}",
+            sha256.trim().to_ascii_lowercase(),
+            format.extension()
+        ))
+    }
+
+    /// The cached bundle with the given digest, if there is one
+    pub fn lookup(&self, sha256: &str) -> Option<PathBuf> {
+        BundleFormat::PREFERRED
+            .into_iter()
+            .map(|format| self.entry_path(sha256, format))
+            .find(|path| path.is_file())
+    }
+
+    /// Puts the release bundle of `version` at `output_path`, from the cache if it
+    /// holds one with the expected digest and from the registry otherwise. A
+    /// download that matches the digest is added to the cache; without a digest
+    /// the cache is bypassed.
+    pub fn fetch(
+        &self,
+        registry: &dyn RegistryBackend,
+        version: &str,
+        format: BundleFormat,
+        expected_sha256: Option<&str>,
+        output_path: &Path,
+    ) -> Result<FetchedBundle> {
+        if let Some(cached) = expected_sha256.and_then(|sha256| self.lookup(sha256)) {
+            // The modification time records the last use, for eviction
+            if let Err(e) = fs::File::options()
+                .write(true)
+                .open(&cached)
+                .and_then(|file| file.set_modified(SystemTime::now()))
+            {
+                warn!("Failed to mark {} as used: {}", cached.display(), e);
+            }
+
+            // Callers get their own copy, which they may move to the quarantine. The
+            // copy is hashed again, so a cache entry damaged on disk is caught.
+            let sha256 = match fs::hard_link(&cached, output_path) {
+                Ok(()) => FileService::sha256_file(output_path)?,
+                Err(_) => FileService::copy_with_sha256(&cached, output_path)
+                    .context("Failed to copy the cached bundle")?,
+            };
+            info!("Using cached release bundle {}", cached.display());
+            return Ok(FetchedBundle {
+                sha256,
+                from_cache: true,
+            });
+        }
+
+        let sha256 = registry.download_release_bundle(version, format, output_path)?;
+
+        if expected_sha256 == Some(sha256.as_str()) {
+            if let Err(e) = self.insert(&sha256, output_path) {
+                warn!("Failed to cache the release bundle: {:#}", e);
+            }
+        }
+        Ok(FetchedBundle {
+            sha256,
+            from_cache: false,
+        })
+    }
+
+    /// Adds a bundle whose digest was checked, then enforces the size limit
+    pub fn insert(&self, sha256: &str, bundle_path: &Path) -> Result<()> {
+        if self.max_bytes == 0 {
+            return Ok(());
+        }
+        fs::create_dir_all(&self.dir).context("Failed to create the bundle cache")?;
+
+        // Copy under a temporary name so a partial entry is never found
+        let path = self.entry_path(sha256, BundleFormat::detect(bundle_path)?);
+        let partial = path.with_extension("partial");
+        fs::copy(bundle_path, &partial).context("Failed to copy the bundle into the cache")?;
+        fs::rename(&partial, &path).context("Failed to add the bundle to the cache")?;
+
+        self.gc()?;
+        Ok(())
+    }
+
+    // Cached files as (last use, size, path)
+    fn entries(&self) -> Result<Vec<(SystemTime, u64, PathBuf)>> {
+        let Ok(entries) = fs::read_dir(&self.dir) else {
+            return Ok(Vec::new());
+        };
+        let mut bundles = Vec::new();
+        for entry in entries {
+            let entry = entry?;
+            let metadata = entry.metadata()?;
+            if metadata.is_file() {
+                bundles.push((metadata.modified()?, metadata.len(), entry.path()));
+            }
+        }
+        Ok(bundles)
+    }
+
+    /// Number and total size of the cached bundles
+    pub fn usage(&self) -> Result<(usize, u64)> {
+        let bundles = self.entries()?;
+        Ok((bundles.len(), bundles.iter().map(|(_, size, _)| size).sum()))
+    }
+
+    /// Evicts the least recently used bundles until the cache fits its size limit.
+    /// Returns the number of bytes freed.
+    pub fn gc(&self) -> Result<u64> {
+        let mut bundles = self.entries()?;
+        let mut total: u64 = bundles.iter().map(|(_, size, _)| size).sum();
+        bundles.sort();
+        let mut freed = 0;
+        for (_, size, path) in bundles {
+            if total <= self.max_bytes {
+                break;
+            }
+            fs::remove_file(&path)
+                .with_context(|| format!("Failed to evict {}", path.display()))?;
+            info!("Evicted {} from the bundle cache", path.display());
+            total -= size;
+            freed += size;
+        }
+        Ok(freed)
+    }
+
+    /// Removes every cached bundle. Returns the number of bytes freed.
+    pub fn clean(&self) -> Result<u64> {
+        let mut freed = 0;
+        for (_, size, path) in self.entries()? {
+            fs::remove_file(&path)
+                .with_context(|| format!("Failed to remove {}", path.display()))?;
+            freed += size;
+        }
+        Ok(freed)
+    }
+}
@@ -0,0 +1,82 @@
+use crate::config::Config;
+use anyhow::{Context, Result};
+use flate2::read::GzDecoder;
+use serde::Serialize;
+use std::fs::File;
+use std::io::{BufReader, Read};
+use std::path::Path;
+
+/// Compression of a release bundle tarball, ordered from the most to the least
+/// preferred. Releases may publish the bundle in several formats; the first of
+/// `PREFERRED` the checksum file lists is downloaded.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum BundleFormat {
+    Zstd,
+    Xz,
+    Gzip,
+}
+
+impl BundleFormat {
+    /// zstd is the smallest to download after xz and by far the fastest to unpack
+    /// on a Pi; gzip is what every release has
+    pub const PREFERRED: [Self; 3] = [Self::Zstd, Self::Xz, Self::Gzip];
+
+    pub fn extension(self) -> &'static str {
+        match self {
+            Self::Zstd => "tar.zst",
+            Self::Xz => "tar.xz",
+            Self::Gzip => "tar.gz",
+        }
+    }
+
+    /// `<prefix>-<version>.<extension>`, as listed in the checksum file, where the
+    /// prefix is `release_bundle` unless configured otherwise
+    pub fn file_name(self, version: &str) -> String {
+        format!(
+            "{}-{}.{}",
+            Config::bundle_prefix(),
+            version,
+            self.extension()
+        )
+    }
+
+    /// The format a file name ends in, and the name without that extension
+    pub fn split_file_name(name: &str) -> Option<(&str, Self)> {
+        Self::PREFERRED.into_iter().find_map(|format| {
+            name.strip_suffix(format.extension())?
+                .strip_suffix('.')
+                .map(|stem| (stem, format))
+        })
+    }
+
+    /// The format of a bundle on disk, by its magic number
+    pub fn detect(path: &Path) -> Result<Self> {
+        let mut magic = [0u8; 6];
+        let read = File::open(path)
+            .and_then(|mut file| file.read(&mut magic))
+            .with_context(|| format!("Failed to read {}", path.display()))?;
+        match &magic[..read] {
+            [0x1f, 0x8b, ..] => Ok(Self::Gzip),
+            [0x28, 0xb5, 0x2f, 0xfd, ..] => Ok(Self::Zstd),
+            [0xfd, b'7', b'z', b'X', b'Z', 0x00] => Ok(Self::Xz),
+            _ => anyhow::bail!(
+                "{} is not a gzip, zstd or xz compressed bundle",
+                path.display()
+            ),
+        }
+    }
+
+    /// The decompressed tarball of a bundle file, whatever its format
+    pub fn open(path: &Path) -> Result<Box<dyn Read>> {
+        let format = Self::detect(path)?;
+        let file = BufReader::new(File::open(path)?);
+        Ok(match format {
+            Self::Gzip => Box::new(GzDecoder::new(file)),
+            Self::Zstd => Box::new(
+                zstd::Decoder::with_buffer(file).context("Failed to start zstd decompression")?,
+            ),
+            Self::Xz => Box::new(xz2::read::XzDecoder::new(file)),
+        })
+    }
+}
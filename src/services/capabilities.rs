@@ -0,0 +1,130 @@
+use crate::config::Config;
+use crate::services::FileService;
+use anyhow::Result;
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+use tracing::warn;
+
+/// A level of one of the app's control APIs that a geist command relies on, and
+/// the first app release that offers it
+#[derive(Debug, Clone, Copy)]
+pub struct Capability {
+    pub api: &'static str,
+    pub level: u32,
+    pub since: &'static str,
+    pub description: &'static str,
+}
+
+impl Capability {
+    pub const NODE_CONTROL: Self = Self {
+        api: "node_api",
+        level: 1,
+        since: "1.0.0",
+        description: "listing, starting and stopping nodes",
+    };
+    pub const TOPIC_LIST: Self = Self {
+        api: "topic_api",
+        level: 1,
+        since: "1.0.0",
+        description: "listing topics",
+    };
+    pub const TOPIC_ECHO: Self = Self {
+        api: "topic_api",
+        level: 1,
+        since: "1.0.0",
+        description: "echoing topics",
+    };
+}
+
+/// The control API levels of an installed version, so node and topic commands can
+/// tell the user to update instead of failing with an IPC error the app does not
+/// explain.
+///
+/// Declared in the `capabilities` section of a version's `manifest.yaml`:
+///
+/// ```yaml
+/// capabilities:
+///   node_api: 2
+///   topic_api: 1
+/// ```
+///
+/// Versions without the section are judged by their version number alone.
+#[derive(Debug, Clone)]
+pub struct AppCapabilities {
+    pub version: String,
+    pub declared: Option<BTreeMap<String, u32>>,
+}
+
+// Only the part of the manifest the capabilities care about
+#[derive(Deserialize)]
+struct Manifest {
+    #[serde(default)]
+    capabilities: Option<BTreeMap<String, u32>>,
+}
+
+impl AppCapabilities {
+    /// The capabilities of an installed version
+    pub fn load(version_dir: &Path, version: &str) -> Self {
+        let path = version_dir.join("manifest.yaml");
+        let declared =
+            fs::read_to_string(&path).ok().and_then(|contents| {
+                match serde_yaml::from_str::<Manifest>(&contents) {
+                    Ok(manifest) => manifest.capabilities,
+                    Err(e) => {
+                        warn!("Ignoring capabilities in {}: {}", path.display(), e);
+                        None
+                    }
+                }
+            });
+        Self {
+            version: Config::normalize_version(version),
+            declared,
+        }
+    }
+
+    /// Fails with what to update to if the version lacks `capability`
+    pub fn require(&self, capability: &Capability) -> Result<()> {
+        let declared = self
+            .declared
+            .as_ref()
+            .map(|apis| apis.get(capability.api).copied().unwrap_or(0));
+        let installed = semver::Version::parse(&self.version).ok();
+        let since = semver::Version::parse(capability.since).ok();
+        let released = match (&installed, &since) {
+            (Some(installed), Some(since)) => installed >= since,
+            // Versions that are not semver cannot be judged by number
+            _ => true,
+        };
+
+        match declared {
+            Some(level) if level >= capability.level => Ok(()),
+            Some(level) if released => anyhow::bail!(
+                "installed app v{} does not support {} ({} level {}, it declares {}); update to a release that declares it",
+                self.version,
+                capability.description,
+                capability.api,
+                capability.level,
+                level
+            ),
+            None if released => Ok(()),
+            _ => anyhow::bail!(
+                "installed app v{} does not support {}; update to ≥{}",
+                self.version,
+                capability.description,
+                capability.since
+            ),
+        }
+    }
+
+    /// Checks the current version, if it is installed, before a command talks to the app
+    pub fn require_current(data_dir: &Path, capability: &Capability) -> Result<()> {
+        let version = FileService::new(data_dir.to_path_buf()).current_version();
+        let version_dir = data_dir.join(&version);
+        if !version_dir.is_dir() {
+            return Ok(());
+        }
+        Self::load(&version_dir, &version).require(capability)
+    }
+}
@@ -0,0 +1,299 @@
+use crate::config::Config;
+use crate::services::HostService;
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use tracing::warn;
+
+// Controllers enabled for the app's cgroup where the device offers them, so its
+// usage can be reported even for limits that are not set
+const CONTROLLERS: [&str; 2] = ["memory", "cpu"];
+
+/// How much of the device the app may use, so a leaking or spinning app cannot
+/// starve the supervisor and the rest of the system.
+///
+/// Declared in the `resources` section of a version's `manifest.yaml`:
+///
+/// ```yaml
+/// resources:
+///   memory_mb: 512
+///   cpu_percent: 150
+/// ```
+#[derive(Debug, Clone, Copy, Default, Deserialize)]
+pub struct ResourceLimits {
+    /// Memory the app may use before the kernel kills it, with no swap on top
+    pub memory_mb: Option<u64>,
+    /// CPU time the app may use, in percent of one CPU
+    pub cpu_percent: Option<u64>,
+}
+
+// Only the part of the manifest the limits care about
+#[derive(Deserialize)]
+struct Manifest {
+    #[serde(default)]
+    resources: Option<ResourceLimits>,
+}
+
+impl ResourceLimits {
+    /// The limits of an installed version. GEIST_MEMORY_LIMIT_MB and
+    /// GEIST_CPU_PERCENT each replace the manifest's value.
+    pub fn load(version_dir: &Path) -> Self {
+        let path = version_dir.join("manifest.yaml");
+        let declared = match fs::read_to_string(&path) {
+            Ok(contents) => match serde_yaml::from_str::<Manifest>(&contents) {
+                Ok(manifest) => manifest.resources.unwrap_or_default(),
+                Err(e) => {
+                    warn!("Ignoring resource limits in {}: {}", path.display(), e);
+                    Self::default()
+                }
+            },
+            Err(_) => Self::default(),
+        };
+        Self {
+            memory_mb: Config::memory_limit_mb().or(declared.memory_mb.filter(|mb| *mb > 0)),
+            cpu_percent: Config::cpu_percent()
+                .or(declared.cpu_percent.filter(|percent| *percent > 0)),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.memory_mb.is_none() && self.cpu_percent.is_none()
+    }
+
+    /// e.g. `512 MiB of memory and 150% CPU`
+    pub fn describe(&self) -> String {
+        let memory = self.memory_mb.map(|mb| format!("{} MiB of memory", mb));
+        let cpu = self.cpu_percent.map(|percent| format!("{}% CPU", percent));
+        [memory, cpu]
+            .into_iter()
+            .flatten()
+            .collect::<Vec<_>>()
+            .join(" and ")
+    }
+}
+
+/// What the app uses of the device, read from its cgroup. Counters run from when
+/// the cgroup was made, normally when the supervisor first launched the app.
+#[derive(Debug, Clone, Serialize)]
+pub struct ResourceUsage {
+    pub memory_bytes: Option<u64>,
+    /// Most memory the app used at once, on kernels that track it
+    pub memory_peak_bytes: Option<u64>,
+    pub memory_limit_bytes: Option<u64>,
+    pub cpu_usage_usec: Option<u64>,
+    pub cpu_limit_percent: Option<u64>,
+    /// Times the kernel killed the app for reaching its memory limit
+    pub oom_kills: Option<u64>,
+}
+
+/// The cgroup (v2) the app runs in when it has resource limits: `roc_camera` in
+/// the cgroup the supervisor was started in, such as that of its systemd service,
+/// which then needs `Delegate=yes`. The supervisor moves to `geist` next to it.
+pub struct AppCgroup {
+    pub path: PathBuf,
+}
+
+impl AppCgroup {
+    /// Creates the app's cgroup, or takes over the one an earlier launch made, and
+    /// sets `limits` on it, lifting those that are no longer configured
+    pub fn prepare(limits: &ResourceLimits) -> Result<Self> {
+        let own = Self::cgroup_of("self")
+            .context("Resource limits need the unified cgroup (v2) hierarchy, which this device does not use")?;
+        // Moved there by an earlier launch
+        let parent = match own.file_name() {
+            Some(name) if name == Config::SUPERVISOR_CGROUP => {
+                own.parent().unwrap_or(&own).to_path_buf()
+            }
+            _ => own.clone(),
+        };
+
+        let available = Self::read(&parent.join("cgroup.controllers"))?;
+        let available: Vec<&str> = available.split_whitespace().collect();
+        let needed = [
+            limits.memory_mb.map(|_| "memory"),
+            limits.cpu_percent.map(|_| "cpu"),
+        ];
+        for controller in needed.into_iter().flatten() {
+            if !available.contains(&controller) {
+                anyhow::bail!(
+                    "The {} controller is not available in {}; a systemd service needs Delegate=yes",
+                    controller,
+                    parent.display()
+                );
+            }
+        }
+        let enabled = Self::read(&parent.join("cgroup.subtree_control"))?;
+        let missing: Vec<String> = CONTROLLERS
+            .into_iter()
+            .filter(|controller| available.contains(controller))
+            .filter(|controller| !enabled.split_whitespace().any(|on| on == *controller))
+            .map(|controller| format!("+{}", controller))
+            .collect();
+        if !missing.is_empty() {
+            // A cgroup that hands controllers down may hold no processes itself
+            if own == parent {
+                let supervisor = parent.join(Config::SUPERVISOR_CGROUP);
+                Self::create(&supervisor)?;
+                Self::write(
+                    &supervisor.join("cgroup.procs"),
+                    &std::process::id().to_string(),
+                )?;
+            }
+            Self::write(&parent.join("cgroup.subtree_control"), &missing.join(" "))
+                .with_context(|| {
+                    format!(
+                        "Other processes share {} with the supervisor; run it as a systemd service with Delegate=yes",
+                        parent.display()
+                    )
+                })?;
+        }
+
+        let cgroup = Self {
+            path: parent.join(Config::APP_CGROUP),
+        };
+        Self::create(&cgroup.path)?;
+        cgroup.set(
+            "memory.max",
+            limits.memory_mb.map(|mb| Self::memory_max(mb).to_string()),
+        )?;
+        // Swapping a leak out would only make the device crawl until the kill
+        cgroup.set("memory.swap.max", limits.memory_mb.map(|_| "0".to_string()))?;
+        cgroup.set(
+            "cpu.max",
+            limits.cpu_percent.map(|percent| {
+                format!(
+                    "{} {}",
+                    percent * Config::CPU_PERIOD_USEC / 100,
+                    Config::CPU_PERIOD_USEC
+                )
+            }),
+        )?;
+        Ok(cgroup)
+    }
+
+    /// The app's cgroup, if the process `pid` runs in it
+    pub fn of_process(pid: u32) -> Option<Self> {
+        let path = Self::cgroup_of(&pid.to_string())?;
+        (path.file_name()? == Config::APP_CGROUP).then_some(Self { path })
+    }
+
+    /// Makes the process `command` starts join the cgroup before it runs anything
+    /// else, so the processes it forks, such as the sandboxed app, are in it too
+    pub fn add(&self, command: &mut Command) -> Result<()> {
+        let path = self.path.join("cgroup.procs");
+        // Opened now, since the process may have dropped the rights to by then
+        let procs = fs::OpenOptions::new()
+            .write(true)
+            .open(&path)
+            .with_context(|| format!("Failed to open {}", path.display()))?;
+        #[cfg(unix)]
+        unsafe {
+            use std::os::unix::io::AsRawFd;
+            use std::os::unix::process::CommandExt;
+            command.pre_exec(move || {
+                // 0 stands for the writing process
+                if libc::write(procs.as_raw_fd(), b"0".as_ptr().cast(), 1) < 0 {
+                    return Err(std::io::Error::last_os_error());
+                }
+                Ok(())
+            });
+        }
+        #[cfg(not(unix))]
+        let _ = (command, procs);
+        Ok(())
+    }
+
+    /// What the processes in the cgroup use now
+    pub fn usage(&self) -> ResourceUsage {
+        let read = |file: &str| fs::read_to_string(self.path.join(file)).ok();
+        let number = |file: &str| read(file)?.trim().parse().ok();
+        // A line of a flat-keyed file such as cpu.stat
+        let field = |file: &str, key: &str| {
+            read(file)?.lines().find_map(|line| {
+                let (name, value) = line.split_once(' ')?;
+                if name != key {
+                    return None;
+                }
+                value.trim().parse().ok()
+            })
+        };
+        let cpu_limit_percent = read("cpu.max").and_then(|max| {
+            let (quota, period) = max.trim().split_once(' ')?;
+            Some(quota.parse::<u64>().ok()? * 100 / period.parse::<u64>().ok()?.max(1))
+        });
+        ResourceUsage {
+            memory_bytes: number("memory.current"),
+            memory_peak_bytes: number("memory.peak"),
+            // `max` when there is none
+            memory_limit_bytes: number("memory.max"),
+            cpu_usage_usec: field("cpu.stat", "usage_usec"),
+            cpu_limit_percent,
+            oom_kills: field("memory.events", "oom_kill"),
+        }
+    }
+
+    // A memory limit in bytes, lowered where it would reach into the supervisor's
+    // memory reserve
+    fn memory_max(memory_mb: u64) -> u64 {
+        let limit = memory_mb * 1024 * 1024;
+        match HostService::total_memory() {
+            Ok(total) => limit.min(total.saturating_sub(Config::reserved_memory_bytes())),
+            Err(e) => {
+                warn!(
+                    "Not keeping the memory reserve out of the app's reach: {:#}",
+                    e
+                );
+                limit
+            }
+        }
+    }
+
+    // Sets a limit, or lifts it with `max`. A controller the device does not offer
+    // has no files, and nothing to lift.
+    fn set(&self, file: &str, value: Option<String>) -> Result<()> {
+        let path = self.path.join(file);
+        if !path.exists() {
+            return Ok(());
+        }
+        Self::write(&path, value.as_deref().unwrap_or("max"))
+    }
+
+    // The cgroup of a process, `self` included, in the unified hierarchy
+    fn cgroup_of(pid: &str) -> Option<PathBuf> {
+        let root = Self::mount_point()?;
+        let cgroups = fs::read_to_string(format!("/proc/{}/cgroup", pid)).ok()?;
+        let path = cgroups.lines().find_map(|line| line.strip_prefix("0::"))?;
+        match path.trim_start_matches('/') {
+            "" => Some(root),
+            path => Some(root.join(path)),
+        }
+    }
+
+    // Where the unified hierarchy is mounted: /sys/fs/cgroup, or
+    // /sys/fs/cgroup/unified next to the v1 controllers on hybrid systems
+    fn mount_point() -> Option<PathBuf> {
+        let mountinfo = fs::read_to_string("/proc/self/mountinfo").ok()?;
+        mountinfo.lines().find_map(|line| {
+            let (mount, source) = line.split_once(" - ")?;
+            if source.split_whitespace().next() != Some("cgroup2") {
+                return None;
+            }
+            mount.split_whitespace().nth(4).map(PathBuf::from)
+        })
+    }
+
+    fn create(path: &Path) -> Result<()> {
+        fs::create_dir_all(path).with_context(|| format!("Failed to create {}", path.display()))
+    }
+
+    fn read(path: &Path) -> Result<String> {
+        fs::read_to_string(path).with_context(|| format!("Failed to read {}", path.display()))
+    }
+
+    fn write(path: &Path, value: &str) -> Result<()> {
+        fs::write(path, value)
+            .with_context(|| format!("Failed to write {} to {}", value, path.display()))
+    }
+}
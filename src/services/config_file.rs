@@ -0,0 +1,925 @@
+use crate::services::{ConfigSecret, ExitAction, HealthProbe};
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::fs;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+
+/// Settings of a TOML configuration file. Every key is optional: what a file
+/// leaves out comes from the layer below it, and in the end from the built-in
+/// defaults in `Config`.
+///
+/// ```toml
+/// data_dir = "/var/lib/geist"
+///
+/// [registry]
+/// url = "https://storage.example.com/releases"
+///
+/// [http]
+/// connect_timeout_secs = 30
+/// ```
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ConfigFile {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub data_dir: Option<PathBuf>,
+    pub registry: RegistrySettings,
+    pub github: GitHubSettings,
+    pub gitlab: GitLabSettings,
+    pub http: HttpSettings,
+    pub verification: VerificationSettings,
+    pub downloads: DownloadSettings,
+    pub supervise: SuperviseSettings,
+    pub report: ReportSettings,
+    pub usb: UsbSettings,
+    pub backup: BackupSettings,
+    pub container: ContainerSettings,
+}
+
+/// Where releases are fetched from and how their files are named
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RegistrySettings {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub url: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub source: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub mirrors: Option<Vec<String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub allow_insecure_mirrors: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub bundle_prefix: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub checksum_file: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub allow_prerelease: Option<bool>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct GitHubSettings {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub api_url: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub repository: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub asset_pattern: Option<String>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct GitLabSettings {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub url: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub project: Option<String>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct HttpSettings {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub connect_timeout_secs: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub read_timeout_secs: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub timeout_secs: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ca_bundle: Option<PathBuf>,
+    /// `host=pin` entries, as in GEIST_TLS_PINS
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tls_pins: Option<Vec<String>>,
+}
+
+/// How release bundles are verified
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct VerificationSettings {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub signed_metadata: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cosign_identity: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cosign_issuer: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cosign_roots: Option<PathBuf>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cosign_rekor_key: Option<PathBuf>,
+}
+
+/// How and when release bundles are downloaded
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct DownloadSettings {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub chunk_mb: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub concurrency: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub bundle_cache_max_mb: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub metered: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub data_budget_mb: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub billing_day: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub min_battery_percent: Option<u8>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub reserved_disk_mb: Option<u64>,
+}
+
+/// How `run` and `supervise` launch and watch the app
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SuperviseSettings {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub crash_loop_threshold: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub crash_loop_window_secs: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub restart_policy: Option<Vec<String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub health_probe: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub reserved_memory_mb: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sandbox: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tamper_check_secs: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tamper_refuse_launch: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub app_user: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub app_group: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub app_groups: Option<Vec<String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub memory_limit_mb: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cpu_percent: Option<u64>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ReportSettings {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub url: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub token: Option<String>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct BackupSettings {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub url: Option<String>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ContainerSettings {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub image: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub runtime: Option<String>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct UsbSettings {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub mount_roots: Option<Vec<PathBuf>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub policy: Option<Vec<String>>,
+}
+
+/// Type of a configuration key's value
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum KeyKind {
+    String,
+    Path,
+    Integer,
+    Bool,
+    /// List of strings, given to `config set` comma-separated
+    List,
+}
+
+impl fmt::Display for KeyKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::String => "string",
+            Self::Path => "path",
+            Self::Integer => "integer",
+            Self::Bool => "true|false",
+            Self::List => "list",
+        })
+    }
+}
+
+/// A key of the configuration files, named `section.key`
+#[derive(Debug, Clone, Copy)]
+pub struct ConfigKey {
+    pub name: &'static str,
+    pub kind: KeyKind,
+    /// Environment variable overriding the key
+    pub env: &'static str,
+}
+
+const fn key(name: &'static str, kind: KeyKind, env: &'static str) -> ConfigKey {
+    ConfigKey { name, kind, env }
+}
+
+/// Every key the configuration files accept, in the order `config list` shows them
+pub const CONFIG_KEYS: &[ConfigKey] = &[
+    key("data_dir", KeyKind::Path, "GEIST_DATA_DIR"),
+    key("registry.url", KeyKind::String, "GEIST_REGISTRY_URL"),
+    key("registry.source", KeyKind::String, "GEIST_REGISTRY_SOURCE"),
+    key("registry.mirrors", KeyKind::List, "GEIST_REGISTRY_MIRRORS"),
+    key(
+        "registry.allow_insecure_mirrors",
+        KeyKind::Bool,
+        "GEIST_ALLOW_INSECURE_MIRRORS",
+    ),
+    key(
+        "registry.bundle_prefix",
+        KeyKind::String,
+        "GEIST_BUNDLE_PREFIX",
+    ),
+    key(
+        "registry.checksum_file",
+        KeyKind::String,
+        "GEIST_CHECKSUM_FILE",
+    ),
+    key(
+        "registry.allow_prerelease",
+        KeyKind::Bool,
+        "GEIST_ALLOW_PRERELEASE",
+    ),
+    key("github.api_url", KeyKind::String, "GEIST_GITHUB_API_URL"),
+    key(
+        "github.repository",
+        KeyKind::String,
+        "GEIST_GITHUB_REPOSITORY",
+    ),
+    key(
+        "github.asset_pattern",
+        KeyKind::String,
+        "GEIST_GITHUB_ASSET_PATTERN",
+    ),
+    key("gitlab.url", KeyKind::String, "GEIST_GITLAB_URL"),
+    key("gitlab.project", KeyKind::String, "GEIST_GITLAB_PROJECT"),
+    key(
+        "http.connect_timeout_secs",
+        KeyKind::Integer,
+        "GEIST_HTTP_CONNECT_TIMEOUT_SECS",
+    ),
+    key(
+        "http.read_timeout_secs",
+        KeyKind::Integer,
+        "GEIST_HTTP_READ_TIMEOUT_SECS",
+    ),
+    key(
+        "http.timeout_secs",
+        KeyKind::Integer,
+        "GEIST_HTTP_TIMEOUT_SECS",
+    ),
+    key("http.ca_bundle", KeyKind::Path, "GEIST_CA_BUNDLE"),
+    key("http.tls_pins", KeyKind::List, "GEIST_TLS_PINS"),
+    key(
+        "verification.signed_metadata",
+        KeyKind::Bool,
+        "GEIST_SIGNED_METADATA",
+    ),
+    key(
+        "verification.cosign_identity",
+        KeyKind::String,
+        "GEIST_COSIGN_IDENTITY",
+    ),
+    key(
+        "verification.cosign_issuer",
+        KeyKind::String,
+        "GEIST_COSIGN_ISSUER",
+    ),
+    key(
+        "verification.cosign_roots",
+        KeyKind::Path,
+        "GEIST_COSIGN_ROOTS",
+    ),
+    key(
+        "verification.cosign_rekor_key",
+        KeyKind::Path,
+        "GEIST_COSIGN_REKOR_KEY",
+    ),
+    key(
+        "downloads.chunk_mb",
+        KeyKind::Integer,
+        "GEIST_DOWNLOAD_CHUNK_MB",
+    ),
+    key(
+        "downloads.concurrency",
+        KeyKind::Integer,
+        "GEIST_DOWNLOAD_CONCURRENCY",
+    ),
+    key(
+        "downloads.bundle_cache_max_mb",
+        KeyKind::Integer,
+        "GEIST_BUNDLE_CACHE_MAX_MB",
+    ),
+    key("downloads.metered", KeyKind::Bool, "GEIST_METERED"),
+    key(
+        "downloads.data_budget_mb",
+        KeyKind::Integer,
+        "GEIST_DATA_BUDGET_MB",
+    ),
+    key(
+        "downloads.billing_day",
+        KeyKind::Integer,
+        "GEIST_BILLING_DAY",
+    ),
+    key(
+        "downloads.min_battery_percent",
+        KeyKind::Integer,
+        "GEIST_MIN_UPDATE_BATTERY_PERCENT",
+    ),
+    key(
+        "downloads.reserved_disk_mb",
+        KeyKind::Integer,
+        "GEIST_RESERVED_DISK_MB",
+    ),
+    key(
+        "supervise.crash_loop_threshold",
+        KeyKind::Integer,
+        "GEIST_CRASH_LOOP_THRESHOLD",
+    ),
+    key(
+        "supervise.crash_loop_window_secs",
+        KeyKind::Integer,
+        "GEIST_CRASH_LOOP_WINDOW_SECS",
+    ),
+    key(
+        "supervise.restart_policy",
+        KeyKind::List,
+        "GEIST_RESTART_POLICY",
+    ),
+    key(
+        "supervise.health_probe",
+        KeyKind::String,
+        "GEIST_HEALTH_PROBE",
+    ),
+    key(
+        "supervise.reserved_memory_mb",
+        KeyKind::Integer,
+        "GEIST_RESERVED_MEMORY_MB",
+    ),
+    key("supervise.sandbox", KeyKind::Bool, "GEIST_SANDBOX"),
+    key(
+        "supervise.tamper_check_secs",
+        KeyKind::Integer,
+        "GEIST_TAMPER_CHECK_SECS",
+    ),
+    key(
+        "supervise.tamper_refuse_launch",
+        KeyKind::Bool,
+        "GEIST_TAMPER_REFUSE_LAUNCH",
+    ),
+    key("supervise.app_user", KeyKind::String, "GEIST_APP_USER"),
+    key("supervise.app_group", KeyKind::String, "GEIST_APP_GROUP"),
+    key("supervise.app_groups", KeyKind::List, "GEIST_APP_GROUPS"),
+    key(
+        "supervise.memory_limit_mb",
+        KeyKind::Integer,
+        "GEIST_MEMORY_LIMIT_MB",
+    ),
+    key(
+        "supervise.cpu_percent",
+        KeyKind::Integer,
+        "GEIST_CPU_PERCENT",
+    ),
+    key("report.url", KeyKind::String, "GEIST_REPORT_URL"),
+    key("report.token", KeyKind::String, "GEIST_REPORT_TOKEN"),
+    key("usb.mount_roots", KeyKind::List, "GEIST_USB_MOUNT_ROOTS"),
+    key("usb.policy", KeyKind::List, "GEIST_USB_POLICY"),
+    key("backup.url", KeyKind::String, "GEIST_BACKUP_URL"),
+    key("container.image", KeyKind::String, "GEIST_CONTAINER_IMAGE"),
+    key(
+        "container.runtime",
+        KeyKind::String,
+        "GEIST_CONTAINER_RUNTIME",
+    ),
+];
+
+/// Where settings come from, with every key and the variable overriding it, for
+/// `--help`
+pub fn precedence_help() -> String {
+    let mut help = String::from(
+        "Settings, from the highest precedence to the lowest:\n  \
+         1. command-line flags\n  \
+         2. environment variables\n  \
+         3. the user's configuration file: --config, GEIST_CONFIG, or\n     \
+         $XDG_CONFIG_HOME/geist/config.toml (~/.config/geist/config.toml)\n  \
+         4. /etc/geist/config.toml\n  \
+         5. built-in defaults\n\n\
+         Configuration keys and their environment variables:\n",
+    );
+    let names: Vec<String> = CONFIG_KEYS
+        .iter()
+        .map(|key| format!("{} <{}>", key.name, key.kind))
+        .collect();
+    let width = names.iter().map(String::len).max().unwrap_or(0);
+    for (name, key) in names.iter().zip(CONFIG_KEYS) {
+        help.push_str(&format!("  {:<width$}  {}\n", name, key.env));
+    }
+    help.push_str(
+        "\nLists are comma-separated in environment variables, except\n\
+         GEIST_USB_MOUNT_ROOTS, which is colon-separated.\n",
+    );
+    help
+}
+
+impl ConfigKey {
+    /// The key of the given name
+    pub fn find(name: &str) -> Result<&'static Self> {
+        CONFIG_KEYS
+            .iter()
+            .find(|key| key.name == name)
+            .with_context(|| format!("Unknown configuration key '{}'", name))
+    }
+
+    /// Converts a value given on the command line to the key's type
+    pub fn parse_value(&self, raw: &str) -> Result<toml_edit::Value> {
+        let raw = raw.trim();
+        Ok(match self.kind {
+            KeyKind::String | KeyKind::Path => raw.into(),
+            KeyKind::Integer => raw
+                .parse::<i64>()
+                .with_context(|| format!("{} takes a whole number, not '{}'", self.name, raw))?
+                .into(),
+            KeyKind::Bool => match raw.to_ascii_lowercase().as_str() {
+                "1" | "true" | "yes" => true.into(),
+                "0" | "false" | "no" => false.into(),
+                _ => anyhow::bail!("{} takes true or false, not '{}'", self.name, raw),
+            },
+            KeyKind::List => raw
+                .split(',')
+                .map(str::trim)
+                .filter(|item| !item.is_empty())
+                .collect::<toml_edit::Array>()
+                .into(),
+        })
+    }
+
+    /// Fails for keys whose values can't be stored encrypted: only strings can
+    pub fn check_secret(&self) -> Result<()> {
+        if self.kind != KeyKind::String {
+            anyhow::bail!(
+                "Only string keys can be stored as secrets, and {} is not one",
+                self.name
+            );
+        }
+        Ok(())
+    }
+
+    /// The value of the key in merged settings, if set
+    pub fn lookup<'a>(&self, table: &'a toml::Table) -> Option<&'a toml::Value> {
+        let (section, name) = match self.name.split_once('.') {
+            Some((section, name)) => (table.get(section)?.as_table()?, name),
+            None => (table, self.name),
+        };
+        section.get(name)
+    }
+
+    // Where the key's value is in a parsed file
+    fn span(&self, document: &toml_edit::Document<String>) -> Option<Range<usize>> {
+        self.name
+            .split('.')
+            .try_fold(document.as_item(), |item, part| item.get(part))?
+            .span()
+    }
+
+    // What is wrong with a value of the key, beyond its type
+    fn check(&self, value: &toml::Value) -> Option<String> {
+        let strings = || -> Vec<&str> {
+            match value {
+                toml::Value::Array(items) => {
+                    items.iter().filter_map(|item| item.as_str()).collect()
+                }
+                value => value.as_str().into_iter().collect(),
+            }
+        };
+        let integer = value.as_integer().unwrap_or_default();
+        match self.name {
+            "registry.url" | "registry.mirrors" | "github.api_url" | "gitlab.url" | "report.url"
+            | "backup.url" => {
+                strings().into_iter().find_map(check_url)
+            }
+            "container.image" => strings()
+                .into_iter()
+                .find(|image| image.is_empty() || image.contains(['@', ' ']))
+                .map(|image| {
+                    format!("'{}' is not an image repository; leave out the tag and digest", image)
+                }),
+            "container.runtime" => strings()
+                .into_iter()
+                .find(|runtime| !["docker", "podman"].contains(runtime))
+                .map(|runtime| format!("unknown runtime '{}' (expected docker or podman)", runtime)),
+            "registry.source" => strings()
+                .into_iter()
+                .find(|source| !["gcs", "github", "gitlab"].contains(source))
+                .map(|source| format!("unknown registry '{}' (expected gcs, github or gitlab)", source)),
+            "registry.bundle_prefix" | "registry.checksum_file" => strings()
+                .into_iter()
+                .find(|name| name.is_empty() || name.contains('/'))
+                .map(|name| format!("'{}' is not a file name", name)),
+            "github.repository" => strings()
+                .into_iter()
+                .find(|repository| {
+                    !matches!(repository.split_once('/'), Some((owner, repo))
+                        if !owner.is_empty() && !repo.is_empty() && !repo.contains('/'))
+                })
+                .map(|repository| format!("'{}' is not of the form owner/repo", repository)),
+            "data_dir" => strings().into_iter().find_map(|dir| {
+                let dir = Path::new(dir);
+                if !dir.is_absolute() {
+                    Some(format!("{} is not an absolute path", dir.display()))
+                } else if dir.exists() && !dir.is_dir() {
+                    Some(format!("{} is not a directory", dir.display()))
+                } else {
+                    None
+                }
+            }),
+            "http.ca_bundle" | "verification.cosign_roots" | "verification.cosign_rekor_key" => {
+                strings()
+                    .into_iter()
+                    .find(|path| !Path::new(path).exists())
+                    .map(|path| format!("{} does not exist", path))
+            }
+            "http.tls_pins" => strings()
+                .into_iter()
+                .find(|entry| {
+                    !matches!(entry.split_once('='), Some((host, pin))
+                        if !host.is_empty()
+                            && (pin.starts_with("sha256/") || pin.starts_with("cert-sha256/")))
+                })
+                .map(|entry| {
+                    format!("'{}' is not host=sha256/<base64> or host=cert-sha256/<hex>", entry)
+                }),
+            "downloads.chunk_mb" | "downloads.concurrency" if integer < 1 => {
+                Some("must be at least 1".to_string())
+            }
+            "supervise.memory_limit_mb" | "supervise.cpu_percent" if integer < 1 => {
+                Some("must be at least 1".to_string())
+            }
+            "downloads.billing_day" if !(1..=31).contains(&integer) => {
+                Some("must be a day of the month, 1 to 31".to_string())
+            }
+            "downloads.min_battery_percent" if integer > 100 => {
+                Some("must be a percentage, 0 to 100".to_string())
+            }
+            "supervise.restart_policy" => strings()
+                .into_iter()
+                .find(|entry| {
+                    !matches!(entry.split_once('='), Some((code, action))
+                        if (code.trim() == "on_failure" || code.trim().parse::<i32>().is_ok())
+                            && ExitAction::parse(action.trim()).is_some())
+                })
+                .map(|entry| {
+                    format!(
+                        "'{}' is not <exit code>=<action> or on_failure=<action>, with restart, stop or update as the action",
+                        entry
+                    )
+                }),
+            "supervise.health_probe" => strings()
+                .into_iter()
+                .find(|spec| HealthProbe::parse(spec).is_none())
+                .map(|spec| {
+                    format!("'{}' is not an http(s) URL, exec:<command> or heartbeat:<path>", spec)
+                }),
+            "usb.policy" => strings()
+                .into_iter()
+                .find(|op| !["import", "export", "plan"].contains(&op.to_lowercase().as_str()))
+                .map(|op| format!("unknown operation '{}' (expected import, export or plan)", op)),
+            _ => None,
+        }
+    }
+}
+
+fn check_url(url: &str) -> Option<String> {
+    match reqwest::Url::parse(url) {
+        Ok(parsed) if matches!(parsed.scheme(), "http" | "https") => None,
+        Ok(_) => Some(format!("'{}' is not an http or https URL", url)),
+        Err(e) => Some(format!("'{}' is not a valid URL: {}", url, e)),
+    }
+}
+
+// Settings that only make sense together: the key to blame and what is wrong.
+// A missing key whose environment variable is set counts as given.
+fn check_combinations(merged: &toml::Table) -> Vec<(&'static str, String)> {
+    let get = |name: &str| {
+        let key = ConfigKey::find(name).ok()?;
+        key.lookup(merged)
+            .cloned()
+            .or_else(|| std::env::var(key.env).ok().map(toml::Value::String))
+    };
+    let mut problems = Vec::new();
+
+    let source = get("registry.source");
+    let source = source.as_ref().and_then(|source| source.as_str());
+    if let Some(source @ ("github" | "gitlab")) = source {
+        if get("verification.signed_metadata").and_then(|v| v.as_bool()) == Some(true) {
+            problems.push((
+                "verification.signed_metadata",
+                format!(
+                    "the {} registry cannot publish signed metadata; use the gcs registry or turn this off",
+                    source
+                ),
+            ));
+        }
+    }
+    if source == Some("gitlab") && get("gitlab.project").is_none() {
+        problems.push((
+            "registry.source",
+            "the gitlab registry needs gitlab.project".to_string(),
+        ));
+    }
+    if get("verification.cosign_identity").is_some() {
+        for needed in [
+            "verification.cosign_issuer",
+            "verification.cosign_roots",
+            "verification.cosign_rekor_key",
+        ] {
+            if get(needed).is_none() {
+                problems.push((
+                    "verification.cosign_identity",
+                    format!("cosign verification needs {} too", needed),
+                ));
+            }
+        }
+    }
+    problems
+}
+
+/// A problem `config validate` found in a configuration file
+#[derive(Debug, Clone, Serialize)]
+pub struct ConfigProblem {
+    pub file: PathBuf,
+    /// Line and column of what is at fault, counted from 1, when known
+    pub line: Option<usize>,
+    pub column: Option<usize>,
+    pub message: String,
+}
+
+impl ConfigProblem {
+    fn new(file: &Path, contents: &str, span: Option<Range<usize>>, message: String) -> Self {
+        // Lines and columns of the first character of the span
+        let position = span.map(|span| {
+            let before = &contents[..span.start.min(contents.len())];
+            let line_start = before.rfind('\n').map_or(0, |i| i + 1);
+            (
+                before.matches('\n').count() + 1,
+                before[line_start..].chars().count() + 1,
+            )
+        });
+        Self {
+            file: file.to_path_buf(),
+            line: position.map(|(line, _)| line),
+            column: position.map(|(_, column)| column),
+            message,
+        }
+    }
+}
+
+impl fmt::Display for ConfigProblem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.file.display())?;
+        if let (Some(line), Some(column)) = (self.line, self.column) {
+            write!(f, ":{}:{}", line, column)?;
+        }
+        write!(f, ": {}", self.message)
+    }
+}
+
+// The plaintext of a secret, or why it can't be had
+fn reveal(secret: &Result<ConfigSecret>, name: &str, value: &str) -> Result<String> {
+    match secret {
+        Ok(secret) => secret.decrypt(name, value),
+        Err(e) => Err(anyhow::anyhow!("{:#}", e)),
+    }
+}
+
+// Replaces the secrets of a parsed file with their plaintext. One that can't be
+// decrypted, e.g. because only root may read the key, is left unset; `config
+// validate`, and so `update` and `supervise`, report it.
+fn decrypt_secrets(path: &Path, table: &mut toml::Table) {
+    let secret = ConfigSecret::open(path);
+    for key in CONFIG_KEYS {
+        let (section, name) = match key.name.split_once('.') {
+            Some((section, name)) => match table.get_mut(section).and_then(|t| t.as_table_mut()) {
+                Some(section) => (section, name),
+                None => continue,
+            },
+            None => (&mut *table, key.name),
+        };
+        let Some(toml::Value::String(value)) = section.get_mut(name) else {
+            continue;
+        };
+        if !ConfigSecret::is_encrypted(value) {
+            continue;
+        }
+        match reveal(&secret, key.name, value) {
+            Ok(plaintext) => *value = plaintext,
+            Err(e) => {
+                tracing::warn!("Ignoring {} in {}: {:#}", key.name, path.display(), e);
+                section.remove(name);
+            }
+        }
+    }
+}
+
+// Lays `over` onto `base`, table by table, so a layer only replaces the keys it sets
+fn merge(base: &mut toml::Table, over: toml::Table) {
+    for (key, value) in over {
+        match (base.get_mut(&key), value) {
+            (Some(toml::Value::Table(base)), toml::Value::Table(over)) => merge(base, over),
+            (_, value) => {
+                base.insert(key, value);
+            }
+        }
+    }
+}
+
+impl ConfigFile {
+    /// Parses one configuration file, failing with the line of the first problem
+    pub fn parse(path: &Path) -> Result<toml::Table> {
+        let contents = fs::read_to_string(path)
+            .with_context(|| format!("Failed to read {}", path.display()))?;
+        // Typed first, so a wrong key or type is reported where it is in the file
+        toml::from_str::<Self>(&contents)
+            .with_context(|| format!("Invalid configuration in {}", path.display()))?;
+        Ok(toml::from_str(&contents)?)
+    }
+
+    /// The keys the given files set, each file overriding the ones before it.
+    /// Files that do not exist are skipped.
+    pub fn merged(paths: &[PathBuf]) -> Result<toml::Table> {
+        let mut merged = toml::Table::new();
+        for path in paths.iter().filter(|path| path.is_file()) {
+            merge(&mut merged, Self::parse(path)?);
+        }
+        Ok(merged)
+    }
+
+    /// Everything wrong with the given files: syntax, unknown keys and types,
+    /// values such as URLs and paths, and combinations of settings. Files that do
+    /// not exist are skipped.
+    pub fn validate(paths: &[PathBuf]) -> Vec<ConfigProblem> {
+        let mut problems = Vec::new();
+        let mut parsed = Vec::new();
+        for path in paths.iter().filter(|path| path.is_file()) {
+            let contents = match fs::read_to_string(path) {
+                Ok(contents) => contents,
+                Err(e) => {
+                    problems.push(ConfigProblem::new(path, "", None, e.to_string()));
+                    continue;
+                }
+            };
+            if let Err(e) = toml::from_str::<Self>(&contents) {
+                problems.push(ConfigProblem::new(
+                    path,
+                    &contents,
+                    e.span(),
+                    e.message().trim().to_string(),
+                ));
+                continue;
+            }
+            let (Ok(document), Ok(table)) = (
+                toml_edit::Document::parse(contents.clone()),
+                toml::from_str::<toml::Table>(&contents),
+            ) else {
+                continue;
+            };
+            let secret = ConfigSecret::open(path);
+            for key in CONFIG_KEYS {
+                // Secrets are checked for their plaintext
+                let value = match key.lookup(&table) {
+                    Some(toml::Value::String(value)) if ConfigSecret::is_encrypted(value) => {
+                        match reveal(&secret, key.name, value) {
+                            Ok(plaintext) => Some(toml::Value::String(plaintext)),
+                            Err(e) => {
+                                problems.push(ConfigProblem::new(
+                                    path,
+                                    &contents,
+                                    key.span(&document),
+                                    format!("{}: {:#}", key.name, e),
+                                ));
+                                continue;
+                            }
+                        }
+                    }
+                    value => value.cloned(),
+                };
+                if let Some(message) = value.and_then(|value| key.check(&value)) {
+                    problems.push(ConfigProblem::new(
+                        path,
+                        &contents,
+                        key.span(&document),
+                        format!("{}: {}", key.name, message),
+                    ));
+                }
+            }
+            parsed.push((path, contents, document, table));
+        }
+
+        let mut merged = toml::Table::new();
+        for (_, _, _, table) in &parsed {
+            merge(&mut merged, table.clone());
+        }
+        for (name, message) in check_combinations(&merged) {
+            let Ok(key) = ConfigKey::find(name) else {
+                continue;
+            };
+            // Blamed on the file whose setting counts, the last one to set the key
+            let Some((path, contents, document, _)) = parsed
+                .iter()
+                .rev()
+                .find(|(_, _, _, table)| key.lookup(table).is_some())
+            else {
+                continue;
+            };
+            problems.push(ConfigProblem::new(
+                path,
+                contents,
+                key.span(document),
+                format!("{}: {}", name, message),
+            ));
+        }
+        problems
+    }
+
+    /// Fails listing every problem of the given files, as `config validate` would
+    pub fn check(paths: &[PathBuf]) -> Result<()> {
+        let problems = Self::validate(paths);
+        if !problems.is_empty() {
+            let list: Vec<String> = problems.iter().map(ToString::to_string).collect();
+            anyhow::bail!(
+                "Invalid configuration (see `geist config validate`):\n  {}",
+                list.join("\n  ")
+            );
+        }
+        Ok(())
+    }
+
+    /// The settings of the given files, each overriding the ones before it, with
+    /// their secrets decrypted
+    pub fn load_layers(paths: &[PathBuf]) -> Result<Self> {
+        let mut merged = toml::Table::new();
+        for path in paths.iter().filter(|path| path.is_file()) {
+            let mut table = Self::parse(path)?;
+            decrypt_secrets(path, &mut table);
+            merge(&mut merged, table);
+        }
+        Ok(toml::Value::Table(merged).try_into()?)
+    }
+
+    /// Sets a key in a configuration file, creating the file if needed. Comments
+    /// and the layout of the rest of the file are kept.
+    pub fn set(path: &Path, key: &ConfigKey, value: toml_edit::Value) -> Result<()> {
+        let contents = match fs::read_to_string(path) {
+            Ok(contents) => contents,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => String::new(),
+            Err(e) => return Err(e).with_context(|| format!("Failed to read {}", path.display())),
+        };
+        let mut document: toml_edit::DocumentMut = contents
+            .parse()
+            .with_context(|| format!("Invalid configuration in {}", path.display()))?;
+        match key.name.split_once('.') {
+            Some((section, name)) => {
+                // A new section gets a `[section]` header rather than an inline table
+                if document.get(section).is_none() {
+                    document[section] = toml_edit::table();
+                }
+                document[section][name] = toml_edit::value(value);
+            }
+            None => document[key.name] = toml_edit::value(value),
+        }
+
+        let contents = document.to_string();
+        if let Err(e) = toml::from_str::<Self>(&contents) {
+            anyhow::bail!("Invalid value for {}: {}", key.name, e.message());
+        }
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)
+                .with_context(|| format!("Failed to create {}", parent.display()))?;
+        }
+        // Replaced in one step, so a reader never sees a half-written file
+        let tmp_path = path.with_extension("toml.tmp");
+        fs::write(&tmp_path, contents)
+            .with_context(|| format!("Failed to write {}", tmp_path.display()))?;
+        fs::rename(&tmp_path, path)
+            .with_context(|| format!("Failed to replace {}", path.display()))?;
+        Ok(())
+    }
+}
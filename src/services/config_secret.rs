@@ -0,0 +1,145 @@
+use crate::config::Config;
+use anyhow::{Context, Result};
+use base64::Engine;
+use openssl::hash::MessageDigest;
+use openssl::pkey::PKey;
+use openssl::sign::Signer;
+use openssl::symm::{decrypt_aead, encrypt_aead, Cipher};
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use tracing::info;
+
+const KEY_LEN: usize = 32;
+const NONCE_LEN: usize = 12;
+const TAG_LEN: usize = 16;
+
+/// Encrypts and decrypts the secret values of a configuration file, such as
+/// tokens and webhook URLs, under a key kept beside the file and readable only by
+/// its owner. Secrets are stored as `enc:v1:<base64>` and only ever decrypted in
+/// memory, so the file can be shared or backed up without them.
+pub struct ConfigSecret {
+    key: [u8; KEY_LEN],
+}
+
+impl ConfigSecret {
+    /// Whether a stored value is a secret
+    pub fn is_encrypted(value: &str) -> bool {
+        value.starts_with(Config::SECRET_PREFIX)
+    }
+
+    /// The device key of a configuration file
+    pub fn key_path(config_file: &Path) -> PathBuf {
+        config_file
+            .parent()
+            .unwrap_or(Path::new("."))
+            .join(Config::SECRET_KEY_FILE)
+    }
+
+    /// The key of a configuration file, failing if there is none
+    pub fn open(config_file: &Path) -> Result<Self> {
+        let path = Self::key_path(config_file);
+        let bytes = fs::read(&path)
+            .with_context(|| format!("Failed to read the secret key {}", path.display()))?;
+        let key = bytes
+            .try_into()
+            .map_err(|_| anyhow::anyhow!("{} is not a {}-byte key", path.display(), KEY_LEN))?;
+        Ok(Self { key })
+    }
+
+    /// The key of a configuration file, generated on first use
+    pub fn open_or_create(config_file: &Path) -> Result<Self> {
+        let path = Self::key_path(config_file);
+        if path.exists() {
+            return Self::open(config_file);
+        }
+        let mut key = [0u8; KEY_LEN];
+        openssl::rand::rand_bytes(&mut key)?;
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)
+                .with_context(|| format!("Failed to create {}", parent.display()))?;
+        }
+        // Created with owner-only permissions, and never over a key that another
+        // process wrote in the meantime
+        let mut options = fs::OpenOptions::new();
+        options.write(true).create_new(true);
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::OpenOptionsExt;
+            options.mode(0o600);
+        }
+        match options.open(&path) {
+            Ok(mut file) => {
+                file.write_all(&key)
+                    .with_context(|| format!("Failed to write {}", path.display()))?;
+                info!("Generated the secret key {}", path.display());
+                Ok(Self { key })
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists => Self::open(config_file),
+            Err(e) => Err(e).with_context(|| format!("Failed to create {}", path.display())),
+        }
+    }
+
+    /// Encrypts the value of a key. The key's name is authenticated along with it,
+    /// so a secret copied to another key fails to decrypt.
+    pub fn encrypt(&self, name: &str, plaintext: &str) -> Result<String> {
+        let mut nonce = [0u8; NONCE_LEN];
+        openssl::rand::rand_bytes(&mut nonce)?;
+        let mut tag = [0u8; TAG_LEN];
+        let ciphertext = encrypt_aead(
+            Cipher::aes_256_gcm(),
+            &self.key,
+            Some(&nonce),
+            name.as_bytes(),
+            plaintext.as_bytes(),
+            &mut tag,
+        )?;
+        let sealed = [&nonce[..], &ciphertext, &tag].concat();
+        Ok(format!(
+            "{}{}",
+            Config::SECRET_PREFIX,
+            base64::engine::general_purpose::STANDARD.encode(sealed)
+        ))
+    }
+
+    /// Hex HMAC-SHA256 of `data` under the key. `name` says what the data is, so a
+    /// MAC of one kind of record does not vouch for another.
+    pub fn mac(&self, name: &str, data: &[u8]) -> Result<String> {
+        let key = PKey::hmac(&self.key)?;
+        let mut signer = Signer::new(MessageDigest::sha256(), &key)?;
+        signer.update(name.as_bytes())?;
+        signer.update(&[0])?;
+        signer.update(data)?;
+        Ok(hex::encode(signer.sign_to_vec()?))
+    }
+
+    /// Decrypts a value stored by `encrypt` for the same key
+    pub fn decrypt(&self, name: &str, value: &str) -> Result<String> {
+        let encoded = value
+            .strip_prefix(Config::SECRET_PREFIX)
+            .with_context(|| format!("{} is not an encrypted value", name))?;
+        let sealed = base64::engine::general_purpose::STANDARD
+            .decode(encoded)
+            .with_context(|| format!("{} is not valid base64", name))?;
+        if sealed.len() < NONCE_LEN + TAG_LEN {
+            anyhow::bail!("The encrypted value of {} is truncated", name);
+        }
+        let (nonce, rest) = sealed.split_at(NONCE_LEN);
+        let (ciphertext, tag) = rest.split_at(rest.len() - TAG_LEN);
+        let plaintext = decrypt_aead(
+            Cipher::aes_256_gcm(),
+            &self.key,
+            Some(nonce),
+            name.as_bytes(),
+            ciphertext,
+            tag,
+        )
+        .map_err(|_| {
+            anyhow::anyhow!(
+                "Failed to decrypt {}: it was encrypted under another device key or altered",
+                name
+            )
+        })?;
+        String::from_utf8(plaintext).with_context(|| format!("{} is not UTF-8", name))
+    }
+}
@@ -0,0 +1,348 @@
+use crate::config::Config;
+use crate::services::{AppCredentials, FileService, HostService, ResourceLimits, SandboxManifest};
+use anyhow::{Context, Result};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use tracing::{info, warn};
+
+/// The image a version installed in container mode runs, stored in its version
+/// directory in place of the binary
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ContainerImage {
+    /// What was pulled, e.g. `ghcr.io/faust-machines/roc_camera:1.4.0`
+    pub reference: String,
+    /// The image by content, e.g. `ghcr.io/faust-machines/roc_camera@sha256:...`, so
+    /// moving the tag later does not change the installed version
+    pub pinned: String,
+    /// Runtime whose image store holds it
+    pub runtime: String,
+    pub pulled_at: DateTime<Utc>,
+}
+
+impl ContainerImage {
+    /// The registry's digest of the image, e.g. `sha256:...`; none for an image
+    /// only built locally, which is pinned by its id
+    pub fn digest(&self) -> Option<&str> {
+        self.pinned.split_once('@').map(|(_, digest)| digest)
+    }
+
+    /// The image of an installed version, if it was installed from one
+    pub fn load(version_dir: &Path) -> Option<Self> {
+        let path = version_dir.join(Config::CONTAINER_IMAGE_FILE);
+        let contents = fs::read_to_string(&path).ok()?;
+        match serde_json::from_str(&contents) {
+            Ok(image) => Some(image),
+            Err(e) => {
+                warn!("Ignoring unreadable {}: {}", path.display(), e);
+                None
+            }
+        }
+    }
+}
+
+/// Versions as OCI images, for devices that get the app through an existing image
+/// pipeline and want it isolated by a container runtime. `update` pulls the
+/// release's image with docker or podman and installs a version directory that
+/// holds the image's digest and manifest, so rollbacks, trial boots and the
+/// version commands work as for bundles; `run` and `supervise` start the
+/// container with the camera devices mapped in.
+pub struct ContainerService {
+    data_dir: PathBuf,
+}
+
+impl ContainerService {
+    pub fn new(data_dir: PathBuf) -> Self {
+        Self { data_dir }
+    }
+
+    /// The configured runtime, or else podman or docker, whichever is installed
+    pub fn runtime() -> Result<String> {
+        if let Some(runtime) = Config::container_runtime() {
+            return Ok(runtime);
+        }
+        ["podman", "docker"]
+            .into_iter()
+            .find(|runtime| HostService::find_program(runtime).is_some())
+            .map(str::to_string)
+            .context("Container mode needs docker or podman, and neither is installed")
+    }
+
+    /// The image reference of a version: the configured repository, tagged with
+    /// the version without its `v`
+    pub fn reference(version: &str) -> Result<String> {
+        let image = Config::container_image()
+            .context("No container image is configured; set GEIST_CONTAINER_IMAGE")?;
+        Ok(format!("{}:{}", image, Config::normalize_version(version)))
+    }
+
+    /// Pulls the image of `version` and installs it as that version, with the
+    /// manifest the image carries. `verify` checks the pulled image before anything
+    /// is taken from it and returns its signer.
+    pub fn install(
+        &self,
+        version: &str,
+        verify: impl FnOnce(&ContainerImage) -> Result<Option<String>>,
+    ) -> Result<ContainerImage> {
+        let runtime = Self::runtime()?;
+        let reference = Self::reference(version)?;
+        info!("Pulling {} with {}", reference, runtime);
+        let status = Command::new(&runtime)
+            .args(["pull", &reference])
+            .status()
+            .with_context(|| format!("Failed to run {}", runtime))?;
+        if !status.success() {
+            anyhow::bail!("{} pull {} failed with {}", runtime, reference, status);
+        }
+
+        let image = ContainerImage {
+            pinned: Self::pinned(&runtime, &reference)?,
+            reference,
+            runtime,
+            pulled_at: Utc::now(),
+        };
+        let signed_by = verify(&image)?;
+        let manifest = match self.manifest(&image)? {
+            Some(manifest) => manifest,
+            None => {
+                warn!(
+                    "{} carries no {}; the version runs without manifest settings",
+                    image.reference,
+                    Config::CONTAINER_MANIFEST_PATH
+                );
+                format!("version: {}\n", Config::normalize_version(version))
+            }
+        };
+        FileService::new(self.data_dir.clone())
+            .install_image(version, &image, &manifest, signed_by)?;
+        Ok(image)
+    }
+
+    // The digest the runtime recorded for a pulled tag; an image only built
+    // locally has none and is pinned by its id
+    fn pinned(runtime: &str, reference: &str) -> Result<String> {
+        let repository = reference
+            .rsplit_once(':')
+            .map_or(reference, |(repository, _)| repository);
+        let output = Self::output(
+            runtime,
+            &[
+                "image",
+                "inspect",
+                "--format",
+                "{{range .RepoDigests}}{{println .}}{{end}}{{.Id}}",
+                reference,
+            ],
+        )?;
+        let digests: Vec<&str> = output
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty())
+            .collect();
+        digests
+            .iter()
+            .find(|digest| digest.starts_with(&format!("{}@", repository)))
+            .or(digests.last())
+            .map(|digest| digest.to_string())
+            .with_context(|| format!("{} inspect gave no digest for {}", runtime, reference))
+    }
+
+    // Copies the manifest out of a container created from the image, without
+    // starting it
+    fn manifest(&self, image: &ContainerImage) -> Result<Option<String>> {
+        let container = Self::output(&image.runtime, &["create", &image.pinned])?;
+        let container = container.trim();
+        let temp_dir = tempfile::tempdir()?;
+        let path = temp_dir.path().join("manifest.yaml");
+        let copied = Command::new(&image.runtime)
+            .arg("cp")
+            .arg(format!("{}:{}", container, Config::CONTAINER_MANIFEST_PATH))
+            .arg(&path)
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .status()
+            .is_ok_and(|status| status.success());
+        if let Err(e) = Self::output(&image.runtime, &["rm", container]) {
+            warn!("Failed to remove the container {}: {:#}", container, e);
+        }
+        match copied {
+            true => Ok(Some(fs::read_to_string(&path).with_context(|| {
+                format!("Failed to read the manifest of {}", image.reference)
+            })?)),
+            false => Ok(None),
+        }
+    }
+
+    /// Whether the runtime still holds an installed version's image
+    pub fn present(image: &ContainerImage) -> bool {
+        Command::new(&image.runtime)
+            .args(["image", "inspect", &image.pinned])
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .status()
+            .is_ok_and(|status| status.success())
+    }
+
+    /// A command that runs the image of the version in `version_dir` in the
+    /// foreground, so the supervisor watches the container through it: signals
+    /// are passed on to the app and it exits the way the app did. The app sees the
+    /// devices its manifest declares, the shared app data at `/data` and the
+    /// supervisor's run directory; arguments added to the command go to the app.
+    pub fn command(
+        &self,
+        version_dir: &Path,
+        image: &ContainerImage,
+        replay_bag: Option<&Path>,
+    ) -> Result<Command> {
+        let version = version_dir
+            .file_name()
+            .unwrap_or_default()
+            .to_string_lossy();
+        let name = format!("{}{}", Config::CONTAINER_NAME_PREFIX, version);
+        // A container a killed supervisor left behind would hold on to the name
+        let _ = Command::new(&image.runtime)
+            .args(["rm", "--force", &name])
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .status();
+
+        let manifest = SandboxManifest::load(version_dir);
+        let devices: Vec<PathBuf> = match manifest.devices.is_empty() {
+            true => Config::CONTAINER_DEFAULT_DEVICES
+                .iter()
+                .map(PathBuf::from)
+                .filter(|device| device.exists())
+                .collect(),
+            false => manifest.devices,
+        };
+        for device in &devices {
+            if !device.exists() {
+                anyhow::bail!(
+                    "Device {} required by the version's manifest does not exist",
+                    device.display()
+                );
+            }
+        }
+
+        let app_data_dir = self.data_dir.join(Config::APP_DATA_DIR);
+        let run_dir = self.data_dir.join(Config::APP_RUN_DIR);
+        for dir in [&app_data_dir, &run_dir] {
+            fs::create_dir_all(dir)
+                .with_context(|| format!("Failed to create {}", dir.display()))?;
+        }
+        // The image's own files are the runtime's, so only the mounts change hands
+        let credentials = AppCredentials::resolve(version_dir)?;
+        if let Some(credentials) = &credentials {
+            for dir in [&app_data_dir, &run_dir] {
+                credentials.own(dir)?;
+            }
+            credentials.announce();
+        }
+
+        let mut command = Command::new(&image.runtime);
+        command.args([
+            "run",
+            "--rm",
+            "--init",
+            "--name",
+            &name,
+            "--network",
+            "host",
+        ]);
+        for device in &devices {
+            command.arg("--device").arg(device);
+        }
+        if let Some(credentials) = &credentials {
+            command
+                .arg("--user")
+                .arg(format!("{}:{}", credentials.uid, credentials.gid));
+            for group in &credentials.groups[1..] {
+                command.arg("--group-add").arg(group.to_string());
+            }
+        }
+        let mut volume = |host: &Path, container: &Path, read_only: bool| {
+            command.arg("--volume").arg(format!(
+                "{}:{}{}",
+                host.display(),
+                container.display(),
+                if read_only { ":ro" } else { "" }
+            ));
+        };
+        volume(&app_data_dir, Path::new(Config::CONTAINER_DATA_PATH), false);
+        // At the same path, so the socket and files the supervisor names are found
+        volume(&run_dir, &run_dir, false);
+        for path in manifest.paths.iter().filter(|path| path.exists()) {
+            volume(path, path, true);
+        }
+        if let Some(bag) = replay_bag {
+            volume(bag, bag, true);
+        }
+
+        // Keep the supervisor's memory reserve out of the app's reach, and the app
+        // within the limits of the version
+        let limits = ResourceLimits::load(version_dir);
+        let memory_limit = limits.memory_mb.map(|mb| mb * 1024 * 1024);
+        let memory = match HostService::total_memory() {
+            Ok(total) => {
+                let limit = total.saturating_sub(Config::reserved_memory_bytes());
+                Some(memory_limit.map_or(limit, |memory_limit| memory_limit.min(limit)))
+            }
+            Err(e) => {
+                warn!("Not applying memory limit: {:#}", e);
+                memory_limit
+            }
+        };
+        if let Some(memory) = memory {
+            command.arg("--memory").arg(memory.to_string());
+            // The same as the memory limit, i.e. no swap, as outside containers
+            if memory_limit.is_some() {
+                command.arg("--memory-swap").arg(memory.to_string());
+            }
+        }
+        if let Some(percent) = limits.cpu_percent {
+            command
+                .arg("--cpus")
+                .arg(format!("{}.{:02}", percent / 100, percent % 100));
+        }
+
+        command.arg("--env").arg(format!(
+            "GEIST_APP_DATA_DIR={}",
+            Config::CONTAINER_DATA_PATH
+        ));
+        // Passed on from the environment the supervisor gives the command
+        for name in ["GEIST_IPC_SOCKET", "GEIST_STOP_ACK_FILE"] {
+            command.arg("--env").arg(name);
+        }
+        if let Some(bag) = replay_bag {
+            command
+                .arg("--env")
+                .arg(format!("GEIST_REPLAY_BAG={}", bag.display()));
+        }
+        command.arg(&image.pinned);
+        if let Some(bag) = replay_bag {
+            info!("Replaying session bag: {}", bag.display());
+            command.arg("--replay").arg(bag);
+        }
+        command.current_dir(version_dir);
+        Ok(command)
+    }
+
+    // Runs a runtime command to completion and returns what it printed
+    fn output(runtime: &str, args: &[&str]) -> Result<String> {
+        let output = Command::new(runtime)
+            .args(args)
+            .output()
+            .with_context(|| format!("Failed to run {}", runtime))?;
+        if !output.status.success() {
+            anyhow::bail!(
+                "{} {} failed: {}",
+                runtime,
+                args.join(" "),
+                String::from_utf8_lossy(&output.stderr).trim()
+            );
+        }
+        Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+    }
+}
@@ -0,0 +1,615 @@
+use crate::config::Config;
+use crate::services::der;
+use anyhow::{Context, Result};
+use base64::Engine;
+use openssl::hash::MessageDigest;
+use openssl::pkey::{PKey, Public};
+use openssl::pkey_ctx::PkeyCtx;
+use openssl::sign::Verifier;
+use openssl::stack::Stack;
+use openssl::x509::store::X509StoreBuilder;
+use openssl::x509::verify::X509VerifyParam;
+use openssl::x509::{X509StoreContext, X509};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::PathBuf;
+use std::process::Command;
+
+// Fulcio's OIDC issuer extensions: the raw issuer string (1.3.6.1.4.1.57264.1.1)
+// and its DER-encoded successor (1.3.6.1.4.1.57264.1.8)
+const ISSUER_V1_OID: &[u8] = &[0x2b, 0x06, 0x01, 0x04, 0x01, 0x83, 0xbf, 0x30, 0x01, 0x01];
+const ISSUER_V2_OID: &[u8] = &[0x2b, 0x06, 0x01, 0x04, 0x01, 0x83, 0xbf, 0x30, 0x01, 0x08];
+
+// A bundle as written by `cosign sign-blob --bundle`
+#[derive(Deserialize)]
+struct CosignBundle {
+    #[serde(rename = "base64Signature")]
+    signature: String,
+    /// Base64 of the PEM signing certificate
+    cert: String,
+    #[serde(rename = "rekorBundle")]
+    rekor: RekorBundle,
+}
+
+#[derive(Deserialize)]
+struct RekorBundle {
+    #[serde(rename = "SignedEntryTimestamp")]
+    signed_entry_timestamp: String,
+    #[serde(rename = "Payload")]
+    payload: RekorPayload,
+}
+
+// Serialized in the canonical form Rekor signs: sorted keys, no whitespace
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct RekorPayload {
+    body: String,
+    integrated_time: i64,
+    #[serde(rename = "logID")]
+    log_id: String,
+    log_index: i64,
+}
+
+/// Verifies cosign keyless signatures of release bundles, as an alternative to
+/// trusting static minisign keys.
+///
+/// A keyless signature is made with a short-lived certificate that Fulcio issues
+/// to the identity of an OIDC token, e.g. a GitHub Actions workflow, and is
+/// recorded in the Rekor transparency log. A signature is accepted when Rekor's
+/// signed entry covers it, the certificate chains to the configured Fulcio roots
+/// at the time Rekor recorded it, and the certificate names the configured
+/// identity and issuer. Everything is checked offline from the cosign bundle.
+pub struct CosignPolicy {
+    identity: String,
+    issuer: String,
+    roots: Vec<X509>,
+    rekor_key: PKey<Public>,
+    // Handed to the cosign binary for container images
+    roots_path: PathBuf,
+    rekor_path: PathBuf,
+}
+
+impl CosignPolicy {
+    /// The policy configured by GEIST_COSIGN_*, or None if no identity is set
+    pub fn from_config() -> Result<Option<Self>> {
+        let Some(identity) = Config::cosign_identity() else {
+            return Ok(None);
+        };
+        let issuer = Config::cosign_issuer()
+            .context("Cosign verification needs the OIDC issuer in GEIST_COSIGN_ISSUER")?;
+        let roots_path = Config::cosign_roots()
+            .context("Cosign verification needs the Fulcio certificates in GEIST_COSIGN_ROOTS")?;
+        let rekor_path = Config::cosign_rekor_key()
+            .context("Cosign verification needs the Rekor public key in GEIST_COSIGN_REKOR_KEY")?;
+
+        let roots = X509::stack_from_pem(
+            &fs::read(&roots_path)
+                .with_context(|| format!("Failed to read {}", roots_path.display()))?,
+        )
+        .with_context(|| format!("Invalid Fulcio certificates in {}", roots_path.display()))?;
+        if roots.is_empty() {
+            anyhow::bail!("{} holds no certificates", roots_path.display());
+        }
+        let rekor_key = PKey::public_key_from_pem(
+            &fs::read(&rekor_path)
+                .with_context(|| format!("Failed to read {}", rekor_path.display()))?,
+        )
+        .with_context(|| format!("Invalid Rekor public key in {}", rekor_path.display()))?;
+
+        Ok(Some(Self {
+            identity,
+            issuer,
+            roots,
+            rekor_key,
+            roots_path,
+            rekor_path,
+        }))
+    }
+
+    /// Checks the cosign signature of a container image, given by digest, with the
+    /// cosign binary, which fetches the signature from the image's registry, and
+    /// returns the identity that signed it. The same Fulcio roots and Rekor key
+    /// are trusted as for bundles.
+    pub fn verify_image(&self, pinned: &str) -> Result<String> {
+        let mut command = Command::new("cosign");
+        command
+            .arg("verify")
+            .arg("--output")
+            .arg("json")
+            .arg("--certificate-oidc-issuer")
+            .arg(&self.issuer)
+            .env("SIGSTORE_ROOT_FILE", &self.roots_path)
+            .env("SIGSTORE_REKOR_PUBLIC_KEY", &self.rekor_path);
+        match self.identity.strip_suffix('*') {
+            Some(prefix) => command
+                .arg("--certificate-identity-regexp")
+                .arg(format!("^{}", regex_escape(prefix))),
+            None => command.arg("--certificate-identity").arg(&self.identity),
+        };
+        let output = command
+            .arg(pinned)
+            .output()
+            .context("Verifying container images needs the cosign binary")?;
+        if !output.status.success() {
+            anyhow::bail!(
+                "cosign verify {} failed: {}",
+                pinned,
+                String::from_utf8_lossy(&output.stderr).trim()
+            );
+        }
+
+        // One entry per valid signature, naming its certificate's identity
+        let signatures: Vec<serde_json::Value> = serde_json::from_slice(&output.stdout)
+            .context("Failed to parse the output of cosign verify")?;
+        Ok(signatures
+            .iter()
+            .find_map(|signature| signature["optional"]["Subject"].as_str())
+            .unwrap_or(&self.identity)
+            .to_string())
+    }
+
+    /// Checks a cosign bundle against an artifact with the given hex SHA-256 and
+    /// returns the identity that signed it
+    pub fn verify(&self, bundle: &str, sha256: &str) -> Result<String> {
+        let bundle: CosignBundle =
+            serde_json::from_str(bundle).context("Failed to parse the cosign bundle")?;
+        let base64 = &base64::engine::general_purpose::STANDARD;
+        let signature = base64
+            .decode(&bundle.signature)
+            .context("Invalid signature in the cosign bundle")?;
+        let cert_pem = base64
+            .decode(&bundle.cert)
+            .context("Invalid certificate in the cosign bundle")?;
+        let certificate = X509::from_pem(&cert_pem).context("Invalid signing certificate")?;
+
+        self.verify_rekor_entry(&bundle, &cert_pem, sha256)?;
+        self.verify_certificate(&certificate, bundle.rekor.payload.integrated_time)?;
+        let identity = self.verify_identity(&certificate)?;
+
+        // ECDSA signs the SHA-256 that was already computed during the download
+        let digest = hex::decode(sha256).context("Invalid SHA-256")?;
+        let public_key = certificate.public_key()?;
+        let mut context = PkeyCtx::new(&public_key)?;
+        context.verify_init()?;
+        if !context.verify(&digest, &signature).unwrap_or(false) {
+            anyhow::bail!("The signature does not match the release bundle");
+        }
+        Ok(identity)
+    }
+
+    // Rekor's signed entry has to cover this signature, certificate and artifact
+    fn verify_rekor_entry(
+        &self,
+        bundle: &CosignBundle,
+        cert_pem: &[u8],
+        sha256: &str,
+    ) -> Result<()> {
+        let base64 = &base64::engine::general_purpose::STANDARD;
+        let timestamp = base64
+            .decode(&bundle.rekor.signed_entry_timestamp)
+            .context("Invalid signed entry timestamp")?;
+        let mut verifier = Verifier::new(MessageDigest::sha256(), &self.rekor_key)?;
+        verifier.update(&serde_json::to_vec(&bundle.rekor.payload)?)?;
+        if !verifier.verify(&timestamp).unwrap_or(false) {
+            anyhow::bail!("The transparency log entry is not signed by the configured Rekor key");
+        }
+
+        let body: serde_json::Value = serde_json::from_slice(
+            &base64
+                .decode(&bundle.rekor.payload.body)
+                .context("Invalid transparency log entry")?,
+        )
+        .context("Failed to parse the transparency log entry")?;
+        if body["kind"] != "hashedrekord" {
+            anyhow::bail!("Unsupported transparency log entry kind {}", body["kind"]);
+        }
+        let spec = &body["spec"];
+        let logged_sha256 = spec["data"]["hash"]["value"].as_str().unwrap_or_default();
+        if !logged_sha256.eq_ignore_ascii_case(sha256) {
+            anyhow::bail!(
+                "The transparency log entry is for a different artifact ({})",
+                logged_sha256
+            );
+        }
+        let logged_signature = spec["signature"]["content"].as_str().unwrap_or_default();
+        let logged_cert = spec["signature"]["publicKey"]["content"]
+            .as_str()
+            .and_then(|cert| base64.decode(cert).ok());
+        if logged_signature != bundle.signature || logged_cert.as_deref() != Some(cert_pem) {
+            anyhow::bail!("The transparency log entry is for a different signature");
+        }
+        Ok(())
+    }
+
+    // Signing certificates live for minutes, so the chain is checked at the time
+    // Rekor recorded the signature rather than now
+    fn verify_certificate(&self, certificate: &X509, integrated_time: i64) -> Result<()> {
+        let mut store = X509StoreBuilder::new()?;
+        for root in &self.roots {
+            store.add_cert(root.clone())?;
+        }
+        let mut param = X509VerifyParam::new()?;
+        param.set_time(integrated_time as libc::time_t);
+        store.set_param(&param)?;
+        let store = store.build();
+
+        let mut context = X509StoreContext::new()?;
+        let chain = Stack::new()?;
+        let (valid, error) = context.init(&store, certificate, &chain, |context| {
+            Ok((context.verify_cert()?, context.error()))
+        })?;
+        if !valid {
+            anyhow::bail!(
+                "The signing certificate does not chain to the configured Fulcio roots: {}",
+                error.error_string()
+            );
+        }
+        Ok(())
+    }
+
+    // The certificate's subject alternative name and OIDC issuer extension
+    fn verify_identity(&self, certificate: &X509) -> Result<String> {
+        let identities: Vec<String> = certificate
+            .subject_alt_names()
+            .into_iter()
+            .flatten()
+            .filter_map(|name| name.email().or(name.uri()).map(str::to_string))
+            .collect();
+        let identity = identities
+            .iter()
+            .find(|identity| match self.identity.strip_suffix('*') {
+                Some(prefix) => identity.starts_with(prefix),
+                None => **identity == self.identity,
+            })
+            .with_context(|| {
+                format!(
+                    "The bundle was signed by {}, not {}",
+                    identities.join(", "),
+                    self.identity
+                )
+            })?;
+
+        let der = certificate.to_der()?;
+        let issuer = match der::extension(&der, ISSUER_V2_OID) {
+            // A DER UTF8String
+            Some(value) => der::element(value).map(|(_, issuer, _)| issuer),
+            None => der::extension(&der, ISSUER_V1_OID),
+        }
+        .map(String::from_utf8_lossy)
+        .context("The signing certificate names no OIDC issuer")?;
+        if issuer != self.issuer {
+            anyhow::bail!(
+                "The signing identity was issued by {}, not {}",
+                issuer,
+                self.issuer
+            );
+        }
+        Ok(identity.clone())
+    }
+}
+
+// Escapes what a regular expression would otherwise read as syntax
+fn regex_escape(text: &str) -> String {
+    text.chars()
+        .flat_map(|c| match c {
+            '\\' | '.' | '+' | '*' | '?' | '(' | ')' | '|' | '[' | ']' | '{' | '}' | '^' | '$' => {
+                vec!['\\', c]
+            }
+            _ => vec![c],
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use openssl::asn1::{Asn1Integer, Asn1Object, Asn1OctetString, Asn1Time};
+    use openssl::bn::BigNum;
+    use openssl::ec::{EcGroup, EcKey};
+    use openssl::nid::Nid;
+    use openssl::pkey::Private;
+    use openssl::sign::Signer;
+    use openssl::x509::extension::{BasicConstraints, KeyUsage, SubjectAlternativeName};
+    use openssl::x509::{X509Builder, X509Extension, X509Name};
+    use sha2::{Digest, Sha256};
+
+    const IDENTITY: &str =
+        "https://github.com/faust-machines/roc_camera/.github/workflows/release.yml@refs/tags/v1.2.0";
+    const ISSUER: &str = "https://token.actions.githubusercontent.com";
+
+    // A Fulcio CA, the Rekor log key and the key a release was signed with
+    struct Sigstore {
+        ca: X509,
+        ca_key: PKey<Private>,
+        rekor: PKey<Private>,
+        signer: PKey<Private>,
+    }
+
+    fn key() -> PKey<Private> {
+        let group = EcGroup::from_curve_name(Nid::X9_62_PRIME256V1).unwrap();
+        PKey::from_ec_key(EcKey::generate(&group).unwrap()).unwrap()
+    }
+
+    // Empty for signing certificates, which Fulcio issues without a subject
+    fn name(common_name: &str) -> X509Name {
+        let mut name = X509Name::builder().unwrap();
+        if !common_name.is_empty() {
+            name.append_entry_by_nid(Nid::COMMONNAME, common_name)
+                .unwrap();
+        }
+        name.build()
+    }
+
+    fn certificate(
+        subject: &str,
+        key: &PKey<Private>,
+        not_before: i64,
+        not_after: i64,
+    ) -> X509Builder {
+        let mut builder = X509Builder::new().unwrap();
+        builder.set_version(2).unwrap();
+        let serial = Asn1Integer::from_bn(&BigNum::from_u32(rand_serial()).unwrap()).unwrap();
+        builder.set_serial_number(&serial).unwrap();
+        builder.set_subject_name(&name(subject)).unwrap();
+        builder.set_pubkey(key).unwrap();
+        builder
+            .set_not_before(&Asn1Time::from_unix(not_before).unwrap())
+            .unwrap();
+        builder
+            .set_not_after(&Asn1Time::from_unix(not_after).unwrap())
+            .unwrap();
+        builder
+    }
+
+    fn rand_serial() -> u32 {
+        let mut serial = [0; 4];
+        openssl::rand::rand_bytes(&mut serial).unwrap();
+        u32::from_be_bytes(serial) >> 1
+    }
+
+    // A day ago, when Rekor recorded the test signatures
+    fn logged_at() -> i64 {
+        chrono::Utc::now().timestamp() - 86400
+    }
+
+    impl Sigstore {
+        fn new() -> Self {
+            let ca_key = key();
+            let now = chrono::Utc::now().timestamp();
+            let mut ca = certificate("sigstore", &ca_key, now - 30 * 86400, now + 30 * 86400);
+            ca.set_issuer_name(&name("sigstore")).unwrap();
+            ca.append_extension(BasicConstraints::new().critical().ca().build().unwrap())
+                .unwrap();
+            ca.append_extension(
+                KeyUsage::new()
+                    .critical()
+                    .key_cert_sign()
+                    .crl_sign()
+                    .build()
+                    .unwrap(),
+            )
+            .unwrap();
+            ca.sign(&ca_key, MessageDigest::sha256()).unwrap();
+            Self {
+                ca: ca.build(),
+                ca_key,
+                rekor: key(),
+                signer: key(),
+            }
+        }
+
+        // A signing certificate for `identity` valid for ten minutes from `at`, as
+        // Fulcio issues them
+        fn issue(&self, identity: &str, issuer: &str, at: i64) -> X509 {
+            let mut certificate = certificate("", &self.signer, at, at + 600);
+            certificate.set_issuer_name(self.ca.subject_name()).unwrap();
+            let san = SubjectAlternativeName::new()
+                .uri(identity)
+                .build(&certificate.x509v3_context(Some(&self.ca), None))
+                .unwrap();
+            certificate.append_extension(san).unwrap();
+            let oid = Asn1Object::from_str("1.3.6.1.4.1.57264.1.1").unwrap();
+            let value = Asn1OctetString::new_from_bytes(issuer.as_bytes()).unwrap();
+            certificate
+                .append_extension(X509Extension::new_from_der(&oid, false, &value).unwrap())
+                .unwrap();
+            certificate
+                .sign(&self.ca_key, MessageDigest::sha256())
+                .unwrap();
+            certificate.build()
+        }
+
+        // What `cosign sign-blob --bundle` writes for an artifact, recorded by Rekor
+        // at `integrated_time`
+        fn bundle(&self, certificate: &X509, sha256: &str, integrated_time: i64) -> String {
+            let base64 = &base64::engine::general_purpose::STANDARD;
+            let mut context = PkeyCtx::new(&self.signer).unwrap();
+            context.sign_init().unwrap();
+            let mut signature = Vec::new();
+            context
+                .sign_to_vec(&hex::decode(sha256).unwrap(), &mut signature)
+                .unwrap();
+            let cert_pem = certificate.to_pem().unwrap();
+
+            let body = serde_json::json!({
+                "apiVersion": "0.0.1",
+                "kind": "hashedrekord",
+                "spec": {
+                    "data": { "hash": { "algorithm": "sha256", "value": sha256 } },
+                    "signature": {
+                        "content": base64.encode(&signature),
+                        "publicKey": { "content": base64.encode(&cert_pem) },
+                    },
+                },
+            });
+            let payload = RekorPayload {
+                body: base64.encode(body.to_string()),
+                integrated_time,
+                log_id: "c0d23d6ad406973f9559f3ba2d1ca01f84147d8ffc5b8445c224f98b9591801d"
+                    .to_string(),
+                log_index: 1,
+            };
+            let mut signer = Signer::new(MessageDigest::sha256(), &self.rekor).unwrap();
+            signer
+                .update(&serde_json::to_vec(&payload).unwrap())
+                .unwrap();
+            let timestamp = signer.sign_to_vec().unwrap();
+
+            serde_json::json!({
+                "base64Signature": base64.encode(&signature),
+                "cert": base64.encode(&cert_pem),
+                "rekorBundle": {
+                    "SignedEntryTimestamp": base64.encode(timestamp),
+                    "Payload": payload,
+                },
+            })
+            .to_string()
+        }
+
+        fn policy(&self, identity: &str) -> CosignPolicy {
+            CosignPolicy {
+                identity: identity.to_string(),
+                issuer: ISSUER.to_string(),
+                roots: vec![self.ca.clone()],
+                rekor_key: PKey::public_key_from_der(&self.rekor.public_key_to_der().unwrap())
+                    .unwrap(),
+                roots_path: PathBuf::new(),
+                rekor_path: PathBuf::new(),
+            }
+        }
+    }
+
+    fn sha256(data: &str) -> String {
+        hex::encode(Sha256::digest(data))
+    }
+
+    #[test]
+    fn accepts_a_logged_signature_of_the_identity() {
+        let sigstore = Sigstore::new();
+        let digest = sha256("release bundle");
+        let certificate = sigstore.issue(IDENTITY, ISSUER, logged_at());
+        let bundle = sigstore.bundle(&certificate, &digest, logged_at() + 60);
+
+        let identity = sigstore.policy(IDENTITY).verify(&bundle, &digest).unwrap();
+        assert_eq!(identity, IDENTITY);
+    }
+
+    #[test]
+    fn accepts_an_identity_under_a_prefix() {
+        let sigstore = Sigstore::new();
+        let digest = sha256("release bundle");
+        let certificate = sigstore.issue(IDENTITY, ISSUER, logged_at());
+        let bundle = sigstore.bundle(&certificate, &digest, logged_at() + 60);
+
+        let policy = sigstore.policy("https://github.com/faust-machines/roc_camera/*");
+        assert_eq!(policy.verify(&bundle, &digest).unwrap(), IDENTITY);
+        let policy = sigstore.policy("https://github.com/faust-machines/other/*");
+        assert!(policy.verify(&bundle, &digest).is_err());
+    }
+
+    #[test]
+    fn refuses_another_identity() {
+        let sigstore = Sigstore::new();
+        let digest = sha256("release bundle");
+        let certificate = sigstore.issue(
+            "https://github.com/someone/fork/.github/workflows/release.yml@refs/heads/main",
+            ISSUER,
+            logged_at(),
+        );
+        let bundle = sigstore.bundle(&certificate, &digest, logged_at() + 60);
+
+        let error = sigstore
+            .policy(IDENTITY)
+            .verify(&bundle, &digest)
+            .unwrap_err();
+        assert!(error.to_string().contains("someone/fork"), "{:#}", error);
+    }
+
+    #[test]
+    fn refuses_another_issuer() {
+        let sigstore = Sigstore::new();
+        let digest = sha256("release bundle");
+        let certificate = sigstore.issue(IDENTITY, "https://accounts.example.com", logged_at());
+        let bundle = sigstore.bundle(&certificate, &digest, logged_at() + 60);
+
+        let error = sigstore
+            .policy(IDENTITY)
+            .verify(&bundle, &digest)
+            .unwrap_err();
+        assert!(
+            error.to_string().contains("accounts.example.com"),
+            "{:#}",
+            error
+        );
+    }
+
+    #[test]
+    fn refuses_a_certificate_of_another_ca() {
+        let sigstore = Sigstore::new();
+        let digest = sha256("release bundle");
+        let certificate = sigstore.issue(IDENTITY, ISSUER, logged_at());
+        let bundle = sigstore.bundle(&certificate, &digest, logged_at() + 60);
+
+        let mut policy = sigstore.policy(IDENTITY);
+        policy.roots = vec![Sigstore::new().ca];
+        let error = policy.verify(&bundle, &digest).unwrap_err();
+        assert!(error.to_string().contains("Fulcio roots"), "{:#}", error);
+    }
+
+    #[test]
+    fn checks_the_chain_when_the_signature_was_logged() {
+        let sigstore = Sigstore::new();
+        let digest = sha256("release bundle");
+        let certificate = sigstore.issue(IDENTITY, ISSUER, logged_at());
+
+        // Long expired now, but valid when the log recorded it
+        let bundle = sigstore.bundle(&certificate, &digest, logged_at() + 60);
+        assert!(sigstore.policy(IDENTITY).verify(&bundle, &digest).is_ok());
+
+        // Recorded after the certificate expired
+        let bundle = sigstore.bundle(&certificate, &digest, logged_at() + 3600);
+        let error = sigstore
+            .policy(IDENTITY)
+            .verify(&bundle, &digest)
+            .unwrap_err();
+        assert!(error.to_string().contains("expired"), "{:#}", error);
+    }
+
+    #[test]
+    fn refuses_an_entry_the_log_did_not_sign() {
+        let sigstore = Sigstore::new();
+        let digest = sha256("release bundle");
+        let certificate = sigstore.issue(IDENTITY, ISSUER, logged_at());
+        let bundle = sigstore.bundle(&certificate, &digest, logged_at() + 60);
+
+        let mut policy = sigstore.policy(IDENTITY);
+        policy.rekor_key = PKey::public_key_from_der(&key().public_key_to_der().unwrap()).unwrap();
+        let error = policy.verify(&bundle, &digest).unwrap_err();
+        assert!(error.to_string().contains("Rekor key"), "{:#}", error);
+    }
+
+    #[test]
+    fn refuses_a_signature_of_another_artifact() {
+        let sigstore = Sigstore::new();
+        let certificate = sigstore.issue(IDENTITY, ISSUER, logged_at());
+        let bundle = sigstore.bundle(&certificate, &sha256("old release"), logged_at() + 60);
+
+        let error = sigstore
+            .policy(IDENTITY)
+            .verify(&bundle, &sha256("release bundle"))
+            .unwrap_err();
+        assert!(
+            error.to_string().contains("different artifact"),
+            "{:#}",
+            error
+        );
+    }
+
+    #[test]
+    fn escapes_the_identity_for_cosign() {
+        assert_eq!(
+            regex_escape("https://github.com/a.b/c+d/"),
+            r"https://github\.com/a\.b/c\+d/"
+        );
+    }
+}
@@ -0,0 +1,159 @@
+use crate::config::Config;
+use crate::services::{EventKind, EventService, FileService};
+use anyhow::{Context, Result};
+use chrono::{DateTime, Utc};
+use fs2::FileExt;
+use serde::{Deserialize, Serialize};
+use std::fs::{self, File};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use tracing::warn;
+
+/// Which version is current, and how many times that has been changed
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CurrentPointer {
+    pub version: String,
+    /// Incremented by every write, so a writer can tell whether the pointer moved
+    /// since it read it
+    pub generation: u64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub updated_at: Option<DateTime<Utc>>,
+}
+
+/// The current-version pointer shared by the CLI, the supervisor and anything
+/// else that switches versions.
+///
+/// The pointer lives in `current.json` and is only ever replaced through a
+/// temporary file and a rename, so readers never see it torn. Writers hold a lock
+/// while they compare and swap, so a change made after they read the pointer is
+/// never silently overwritten. The plain `current_version` file is kept as a copy
+/// for scripts and older geist releases, and is read as generation 0 on devices
+/// that have no pointer yet.
+pub struct CurrentService {
+    data_dir: PathBuf,
+}
+
+impl CurrentService {
+    pub fn new(data_dir: PathBuf) -> Self {
+        Self { data_dir }
+    }
+
+    fn pointer_path(&self) -> PathBuf {
+        self.data_dir.join(Config::CURRENT_POINTER_FILE)
+    }
+
+    /// The pointer, or `None` if no version was ever made current
+    pub fn load(&self) -> Result<Option<CurrentPointer>> {
+        let path = self.pointer_path();
+        match fs::read_to_string(&path) {
+            Ok(contents) => {
+                let pointer = serde_json::from_str(&contents)
+                    .with_context(|| format!("Failed to parse {}", path.display()))?;
+                return Ok(Some(pointer));
+            }
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => {
+                return Err(e).with_context(|| format!("Failed to read {}", path.display()));
+            }
+            Err(_) => {}
+        }
+
+        let legacy = self.data_dir.join(Config::CURRENT_VERSION_FILE);
+        Ok(fs::read_to_string(legacy)
+            .ok()
+            .map(|version| version.trim().to_string())
+            .filter(|version| !version.is_empty())
+            .map(|version| CurrentPointer {
+                version,
+                generation: 0,
+                updated_at: None,
+            }))
+    }
+
+    /// The generation a writer has to pass to `compare_and_swap`, 0 if there is no pointer
+    pub fn generation(&self) -> Result<u64> {
+        Ok(self.load()?.map_or(0, |pointer| pointer.generation))
+    }
+
+    /// Points at `version` if the pointer is still at `expected_generation`, and
+    /// fails naming the version it was moved to otherwise
+    pub fn compare_and_swap(
+        &self,
+        expected_generation: u64,
+        version: &str,
+    ) -> Result<CurrentPointer> {
+        self.write(Some(expected_generation), version)
+    }
+
+    /// Points at `version` whatever the pointer was. Still atomic, and the new
+    /// generation is derived under the lock, so concurrent writes are never lost.
+    pub fn set(&self, version: &str) -> Result<CurrentPointer> {
+        self.write(None, version)
+    }
+
+    fn write(&self, expected_generation: Option<u64>, version: &str) -> Result<CurrentPointer> {
+        // Held from reading the generation until the new pointer is in place
+        let lock = File::create(self.data_dir.join(Config::CURRENT_LOCK_FILE))
+            .context("Failed to open the current version lock")?;
+        lock.lock_exclusive()
+            .context("Failed to lock the current version")?;
+
+        let current = self.load()?;
+        let generation = current.as_ref().map_or(0, |pointer| pointer.generation);
+        if let Some(expected) = expected_generation {
+            if generation != expected {
+                anyhow::bail!(
+                    "The current version was changed to {} by another process (generation {}, expected {})",
+                    current.map_or("nothing".to_string(), |pointer| pointer.version),
+                    generation,
+                    expected
+                );
+            }
+        }
+
+        // Rewriting the same version would only make concurrent writers fail
+        if let Some(current) = current.as_ref().filter(|current| current.generation > 0) {
+            if current.version == version {
+                lock.unlock().ok();
+                return Ok(current.clone());
+            }
+        }
+
+        let pointer = CurrentPointer {
+            version: version.to_string(),
+            generation: generation + 1,
+            updated_at: Some(Utc::now()),
+        };
+        replace(&self.pointer_path(), &serde_json::to_vec_pretty(&pointer)?)?;
+        // The copy for scripts is best effort; the pointer is what geist reads
+        if let Err(e) = replace(
+            &self.data_dir.join(Config::CURRENT_VERSION_FILE),
+            version.as_bytes(),
+        ) {
+            warn!("Failed to update {}: {:#}", Config::CURRENT_VERSION_FILE, e);
+        }
+        lock.unlock().ok();
+
+        if current.is_none_or(|previous| previous.version != version) {
+            EventService::new(self.data_dir.clone()).record(
+                EventKind::CurrentChanged,
+                Some(version),
+                None,
+            );
+        }
+        Ok(pointer)
+    }
+}
+
+// Writes through a temporary file and a rename, so readers see the old or the new
+// contents but never a mix. Both are flushed to disk before it returns, so a power
+// cut cannot leave an empty file or bring the old contents back.
+fn replace(path: &Path, contents: &[u8]) -> Result<()> {
+    let tmp_path = path.with_extension("tmp");
+    let mut file = fs::File::create(&tmp_path)
+        .with_context(|| format!("Failed to write {}", tmp_path.display()))?;
+    file.write_all(contents)
+        .and_then(|_| file.sync_all())
+        .with_context(|| format!("Failed to write {}", tmp_path.display()))?;
+    fs::rename(&tmp_path, path).with_context(|| format!("Failed to replace {}", path.display()))?;
+    FileService::sync_dir(path.parent().unwrap_or(Path::new(".")))
+}
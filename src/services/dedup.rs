@@ -0,0 +1,116 @@
+use crate::services::FileService;
+use anyhow::{Context, Result};
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use tracing::{debug, warn};
+
+/// The files of an installed version, indexed so that a version being installed
+/// next to it can hard-link the files whose content has not changed instead of
+/// storing another copy. Files are only hashed once their size matches a file
+/// being installed, so the index is cheap to build.
+pub struct LinkSource {
+    version: String,
+    by_size: HashMap<u64, Vec<PathBuf>>,
+    hashes: HashMap<PathBuf, String>,
+    // Cleared on the first failed link, e.g. on a filesystem without hard links
+    enabled: bool,
+    /// Files linked rather than copied
+    pub linked_files: usize,
+    /// Bytes those files would have taken as copies
+    pub linked_bytes: u64,
+}
+
+impl LinkSource {
+    /// Indexes the regular files of an installed version
+    pub fn open(data_dir: &Path, version: &str) -> Result<Self> {
+        let dir = data_dir.join(version);
+        let mut by_size: HashMap<u64, Vec<PathBuf>> = HashMap::new();
+        for path in FileService::relative_files(&dir)? {
+            let path = dir.join(path);
+            let metadata = fs::symlink_metadata(&path)
+                .with_context(|| format!("Failed to read {}", path.display()))?;
+            // Empty files take no space to begin with
+            if metadata.is_file() && metadata.len() > 0 {
+                by_size.entry(metadata.len()).or_default().push(path);
+            }
+        }
+        Ok(Self {
+            version: version.to_string(),
+            by_size,
+            hashes: HashMap::new(),
+            enabled: true,
+            linked_files: 0,
+            linked_bytes: 0,
+        })
+    }
+
+    /// The version files are linked from
+    pub fn version(&self) -> &str {
+        &self.version
+    }
+
+    /// Hard-links `dst` to an indexed file with the same content and permissions as
+    /// `src`, or copies `src` when there is none
+    pub fn link_or_copy(&mut self, src: &Path, dst: &Path) -> Result<()> {
+        if let Some(existing) = self.find(src)? {
+            match fs::hard_link(&existing, dst) {
+                Ok(()) => {
+                    debug!("Linked {} to {}", dst.display(), existing.display());
+                    self.linked_files += 1;
+                    self.linked_bytes += fs::metadata(&existing)?.len();
+                    return Ok(());
+                }
+                Err(e) => {
+                    warn!(
+                        "Cannot hard-link {} ({}); copying files instead",
+                        existing.display(),
+                        e
+                    );
+                    self.enabled = false;
+                }
+            }
+        }
+        FileService::copy_file(src, dst)
+    }
+
+    // An indexed file identical to `src`, hashing candidates only as needed
+    fn find(&mut self, src: &Path) -> Result<Option<PathBuf>> {
+        if !self.enabled {
+            return Ok(None);
+        }
+        let metadata =
+            fs::metadata(src).with_context(|| format!("Failed to read {}", src.display()))?;
+        let Some(candidates) = self.by_size.get(&metadata.len()) else {
+            return Ok(None);
+        };
+        // A link shares its permissions with the file it points at
+        let candidates: Vec<PathBuf> = candidates
+            .iter()
+            .filter(|path| {
+                fs::metadata(path)
+                    .is_ok_and(|existing| existing.permissions() == metadata.permissions())
+            })
+            .cloned()
+            .collect();
+        if candidates.is_empty() {
+            return Ok(None);
+        }
+
+        let sha256 = FileService::sha256_file(src)?;
+        for candidate in candidates {
+            let existing = match self.hashes.get(&candidate) {
+                Some(existing) => existing.clone(),
+                None => {
+                    let existing = FileService::sha256_file(&candidate)?;
+                    self.hashes.insert(candidate.clone(), existing.clone());
+                    existing
+                }
+            };
+            if existing == sha256 {
+                return Ok(Some(candidate));
+            }
+        }
+        Ok(None)
+    }
+}
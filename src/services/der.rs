@@ -0,0 +1,73 @@
+/// Splits the DER element at the start of `der` into its tag, its contents and what follows
+pub fn element(der: &[u8]) -> Option<(u8, &[u8], &[u8])> {
+    let (&tag, rest) = der.split_first()?;
+    let (&first, mut rest) = rest.split_first()?;
+    let len = if first < 0x80 {
+        first as usize
+    } else {
+        let octets = (first & 0x7f) as usize;
+        if octets == 0 || octets > 4 || rest.len() < octets {
+            return None;
+        }
+        let len = rest[..octets]
+            .iter()
+            .fold(0usize, |len, byte| len << 8 | *byte as usize);
+        rest = &rest[octets..];
+        len
+    };
+    (rest.len() >= len).then(|| (tag, &rest[..len], &rest[len..]))
+}
+
+// The fields of a certificate's TBSCertificate that follow its optional version
+fn tbs_fields(certificate: &[u8]) -> Option<&[u8]> {
+    let (_, certificate, _) = element(certificate)?;
+    let (_, tbs, _) = element(certificate)?;
+    // An explicit version comes first in v2 and v3 certificates
+    if tbs.first() == Some(&0xa0) {
+        return Some(element(tbs)?.2);
+    }
+    Some(tbs)
+}
+
+/// The encoded SubjectPublicKeyInfo of an X.509 certificate, header included
+pub fn subject_public_key_info(certificate: &[u8]) -> Option<&[u8]> {
+    let mut tbs = tbs_fields(certificate)?;
+    // serialNumber, signature, issuer, validity and subject precede the key
+    for _ in 0..5 {
+        tbs = element(tbs)?.2;
+    }
+    let (_, _, after) = element(tbs)?;
+    Some(&tbs[..tbs.len() - after.len()])
+}
+
+/// The value of the extension with the given encoded object identifier (without
+/// its tag and length), i.e. the contents of its `extnValue` octet string
+pub fn extension<'a>(certificate: &'a [u8], oid: &[u8]) -> Option<&'a [u8]> {
+    let mut tbs = tbs_fields(certificate)?;
+    // The extensions are the [3] element after the key and the optional unique ids
+    let extensions = loop {
+        let (tag, contents, rest) = element(tbs)?;
+        if tag == 0xa3 {
+            break element(contents)?.1;
+        }
+        tbs = rest;
+    };
+
+    let mut extensions = extensions;
+    while !extensions.is_empty() {
+        let (_, extension, rest) = element(extensions)?;
+        let (_, id, mut fields) = element(extension)?;
+        if id == oid {
+            // An optional critical flag comes before the value
+            loop {
+                let (tag, contents, rest) = element(fields)?;
+                if tag == 0x04 {
+                    return Some(contents);
+                }
+                fields = rest;
+            }
+        }
+        extensions = rest;
+    }
+    None
+}
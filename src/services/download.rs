@@ -0,0 +1,272 @@
+use crate::config::Config;
+use crate::services::fs::HashingWriter;
+use crate::services::http;
+use anyhow::{Context, Result};
+use reqwest::blocking::{Client, RequestBuilder, Response};
+use reqwest::header::{HeaderMap, CONTENT_RANGE, RANGE};
+use reqwest::{StatusCode, Url};
+use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Condvar, Mutex};
+use std::thread;
+use std::time::Duration;
+use tracing::{debug, info, warn};
+
+/// Downloads the file a GET request points at into `output_path`. When the
+/// server accepts range requests, a file larger than one chunk is fetched as
+/// `Config::download_chunk_bytes` chunks over `Config::download_concurrency`
+/// connections and reassembled in place; otherwise it is fetched in one piece.
+///
+/// Returns the hex SHA-256 of the file, computed from the bytes as they arrive so
+/// the file never has to be read back to be verified.
+pub fn download_to_file(
+    client: &Client,
+    request: RequestBuilder,
+    output_path: &Path,
+) -> Result<String> {
+    let request = request.build().context("Invalid download request")?;
+    let source = Source {
+        client,
+        url: request.url().clone(),
+        headers: request.headers().clone(),
+        timeout: request.timeout().copied(),
+    };
+
+    let concurrency = Config::download_concurrency();
+    if concurrency <= 1 {
+        let response = source.get(None)?;
+        return save(response, output_path);
+    }
+
+    // The first chunk tells whether ranges work and how large the file is
+    let chunk_size = Config::download_chunk_bytes();
+    let response = source.get(Some((0, chunk_size - 1)))?;
+    let total = match response.status() {
+        StatusCode::PARTIAL_CONTENT => total_size(&response)
+            .context("The server answered a range request without the file size")?,
+        StatusCode::RANGE_NOT_SATISFIABLE => return save(source.get(None)?, output_path),
+        _ => return save(response, output_path),
+    };
+
+    let file = File::create(output_path)
+        .with_context(|| format!("Failed to create {}", output_path.display()))?;
+    file.set_len(total)?;
+    let first = read_chunk(response, chunk_size.min(total))?;
+    write_chunk(output_path, 0, &first)?;
+
+    let chunks = total.div_ceil(chunk_size);
+    if chunks <= 1 {
+        return Ok(hex::encode(Sha256::digest(&first)));
+    }
+    let workers = concurrency.min(chunks as usize - 1);
+    let hasher = OrderedHasher::new(workers as u64);
+    hasher.add(0, first);
+    info!(
+        "Downloading {} bytes in {} chunks over {} connections",
+        total, chunks, workers
+    );
+
+    // Workers take the next chunk until all are done or one fails for good
+    let next = AtomicU64::new(1);
+    let failed = AtomicBool::new(false);
+    let error = Mutex::new(None);
+    thread::scope(|scope| {
+        for _ in 0..workers {
+            scope.spawn(|| {
+                while !failed.load(Ordering::SeqCst) {
+                    let index = next.fetch_add(1, Ordering::SeqCst);
+                    if index >= chunks || !hasher.wait_for_turn(index) {
+                        break;
+                    }
+                    let start = index * chunk_size;
+                    let end = (start + chunk_size).min(total) - 1;
+                    match source.fetch_chunk(output_path, start, end) {
+                        Ok(data) => hasher.add(index, data),
+                        Err(e) => {
+                            failed.store(true, Ordering::SeqCst);
+                            error.lock().unwrap().get_or_insert(e);
+                            hasher.stop();
+                        }
+                    }
+                }
+            });
+        }
+    });
+
+    match error.into_inner().unwrap() {
+        Some(e) => {
+            fs::remove_file(output_path).ok();
+            Err(e)
+        }
+        None => Ok(hasher.finish()),
+    }
+}
+
+// Feeds the chunks of a parallel download to one SHA-256 in file order. Chunks
+// that complete ahead of an earlier one are held in memory until it arrives, and
+// no chunk more than `window` ahead of the first missing one is started, which
+// bounds that memory to `window` chunks.
+struct OrderedHasher {
+    state: Mutex<Hashing>,
+    advanced: Condvar,
+    window: u64,
+}
+
+struct Hashing {
+    next: u64,
+    early: BTreeMap<u64, Vec<u8>>,
+    hasher: Sha256,
+    stopped: bool,
+}
+
+impl OrderedHasher {
+    fn new(window: u64) -> Self {
+        Self {
+            state: Mutex::new(Hashing {
+                next: 0,
+                early: BTreeMap::new(),
+                hasher: Sha256::new(),
+                stopped: false,
+            }),
+            advanced: Condvar::new(),
+            window,
+        }
+    }
+
+    fn add(&self, index: u64, data: Vec<u8>) {
+        let mut state = self.state.lock().unwrap();
+        state.early.insert(index, data);
+        loop {
+            let next = state.next;
+            let Some(data) = state.early.remove(&next) else {
+                break;
+            };
+            state.hasher.update(&data);
+            state.next += 1;
+        }
+        self.advanced.notify_all();
+    }
+
+    // Blocks until chunk `index` may be fetched; false once the download failed
+    fn wait_for_turn(&self, index: u64) -> bool {
+        let mut state = self.state.lock().unwrap();
+        while !state.stopped && index >= state.next + self.window {
+            state = self.advanced.wait(state).unwrap();
+        }
+        !state.stopped
+    }
+
+    fn stop(&self) {
+        self.state.lock().unwrap().stopped = true;
+        self.advanced.notify_all();
+    }
+
+    fn finish(self) -> String {
+        let state = self.state.into_inner().unwrap();
+        hex::encode(state.hasher.finalize())
+    }
+}
+
+// The download request, taken apart so every chunk request can be built from it
+struct Source<'a> {
+    client: &'a Client,
+    url: Url,
+    headers: HeaderMap,
+    timeout: Option<Duration>,
+}
+
+impl Source<'_> {
+    fn get(&self, range: Option<(u64, u64)>) -> Result<Response> {
+        let mut request = self
+            .client
+            .get(self.url.clone())
+            .headers(self.headers.clone());
+        if let Some(timeout) = self.timeout {
+            request = request.timeout(timeout);
+        }
+        if let Some((start, end)) = range {
+            request = request.header(RANGE, format!("bytes={}-{}", start, end));
+        }
+        let response =
+            http::send(request).with_context(|| format!("Failed to download {}", self.url))?;
+        // An empty file cannot satisfy any range; the caller falls back to a plain GET
+        if !response.status().is_success() && response.status() != StatusCode::RANGE_NOT_SATISFIABLE
+        {
+            anyhow::bail!("HTTP {}", response.status());
+        }
+        Ok(response)
+    }
+
+    fn fetch_chunk(&self, output_path: &Path, start: u64, end: u64) -> Result<Vec<u8>> {
+        let mut attempt = 1;
+        loop {
+            let result = self.get(Some((start, end))).and_then(|response| {
+                if response.status() != StatusCode::PARTIAL_CONTENT {
+                    anyhow::bail!(
+                        "The server stopped answering range requests ({})",
+                        response.status()
+                    );
+                }
+                let data = read_chunk(response, end - start + 1)?;
+                write_chunk(output_path, start, &data)?;
+                Ok(data)
+            });
+            match result {
+                Ok(data) => {
+                    debug!("Downloaded bytes {}-{}", start, end);
+                    return Ok(data);
+                }
+                Err(e) if attempt >= Config::DOWNLOAD_CHUNK_ATTEMPTS => {
+                    return Err(e)
+                        .with_context(|| format!("Failed to download bytes {}-{}", start, end))
+                }
+                Err(e) => {
+                    warn!(
+                        "Bytes {}-{} failed (attempt {}): {:#}",
+                        start, end, attempt, e
+                    );
+                    attempt += 1;
+                }
+            }
+        }
+    }
+}
+
+// `Content-Range: bytes 0-8388607/734003200`
+fn total_size(response: &Response) -> Option<u64> {
+    let range = response.headers().get(CONTENT_RANGE)?.to_str().ok()?;
+    range.rsplit_once('/')?.1.trim().parse().ok()
+}
+
+// Chunks are kept in memory until they are hashed, so they are only written once
+fn read_chunk(response: Response, length: u64) -> Result<Vec<u8>> {
+    let mut data = Vec::with_capacity(length as usize);
+    response.take(length).read_to_end(&mut data)?;
+    if data.len() as u64 != length {
+        anyhow::bail!("Connection closed after {} of {} bytes", data.len(), length);
+    }
+    Ok(data)
+}
+
+fn write_chunk(output_path: &Path, offset: u64, data: &[u8]) -> Result<()> {
+    let mut file = OpenOptions::new()
+        .write(true)
+        .open(output_path)
+        .with_context(|| format!("Failed to open {}", output_path.display()))?;
+    file.seek(SeekFrom::Start(offset))?;
+    file.write_all(data)
+        .with_context(|| format!("Failed to save {}", output_path.display()))
+}
+
+fn save(mut response: Response, output_path: &Path) -> Result<String> {
+    let file = File::create(output_path)
+        .with_context(|| format!("Failed to create {}", output_path.display()))?;
+    let mut writer = HashingWriter::new(file);
+    io::copy(&mut response, &mut writer)
+        .with_context(|| format!("Failed to save {}", output_path.display()))?;
+    Ok(writer.finish())
+}
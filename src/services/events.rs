@@ -0,0 +1,164 @@
+use crate::config::Config;
+use anyhow::{Context, Result};
+use chrono::{DateTime, Utc};
+use fs2::FileExt;
+use serde::{Deserialize, Serialize};
+use std::fs::{self, File, OpenOptions};
+use std::io::Write;
+use std::path::PathBuf;
+use tracing::warn;
+
+/// Kind of state change
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum EventKind {
+    AppStarted,
+    AppExited,
+    CurrentChanged,
+    Installed,
+    Staged,
+    RolledBack,
+    MarkedBad,
+    /// `geist mark-good` vouched for a version
+    MarkedGood,
+    SafeModeEntered,
+    SafeModeLeft,
+    /// An unattended update did not start, e.g. for lack of power
+    UpdateDeferred,
+    /// Installed files changed since install time, found by the tamper check
+    Tampered,
+    /// `geist pin` froze the device on a version
+    Pinned,
+    /// `geist unpin` released the pin
+    Unpinned,
+    /// Data migrations of a version ran
+    Migrated,
+    /// A data migration failed and the app data was restored from its backup
+    MigrationFailed,
+    /// `geist backup restore` put back a backup
+    Restored,
+    /// Written by a newer geist
+    #[serde(other)]
+    Unknown,
+}
+
+/// A state change, numbered in the order it happened
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Event {
+    pub seq: u64,
+    pub at: DateTime<Utc>,
+    pub kind: EventKind,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub version: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub detail: Option<String>,
+}
+
+/// Events after a given sequence number, as printed by `geist events`
+#[derive(Debug, Serialize)]
+pub struct EventPage {
+    /// Sequence number of the newest event; pass it as `--after-seq` next time
+    pub last_seq: u64,
+    /// True if events after the requested number are no longer available, either
+    /// because they were pruned or because the log was reset
+    pub gap: bool,
+    pub events: Vec<Event>,
+}
+
+/// Append-only log of state changes with monotonically increasing sequence
+/// numbers, so pollers can fetch only what changed since they last looked
+pub struct EventService {
+    path: PathBuf,
+    lock_path: PathBuf,
+}
+
+impl EventService {
+    pub fn new(data_dir: PathBuf) -> Self {
+        Self {
+            path: data_dir.join(Config::EVENTS_FILE),
+            lock_path: data_dir.join(Config::EVENTS_LOCK_FILE),
+        }
+    }
+
+    /// Appends an event. Events are informational, so failures are only logged.
+    pub fn record(&self, kind: EventKind, version: Option<&str>, detail: Option<String>) {
+        if let Err(e) = self.append(kind, version, detail) {
+            warn!("Failed to record {:?} event: {:#}", kind, e);
+        }
+    }
+
+    fn append(&self, kind: EventKind, version: Option<&str>, detail: Option<String>) -> Result<()> {
+        // Held while numbering and writing so concurrent geist processes never
+        // hand out the same sequence number
+        let lock = File::create(&self.lock_path).context("Failed to open event lock")?;
+        lock.lock_exclusive().context("Failed to lock event log")?;
+
+        let mut events = self.load()?;
+        let event = Event {
+            seq: events.last().map_or(1, |last| last.seq + 1),
+            at: Utc::now(),
+            kind,
+            version: version.map(str::to_string),
+            detail,
+        };
+
+        if events.len() < Config::MAX_EVENTS {
+            let mut file = OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(&self.path)
+                .with_context(|| format!("Failed to open {}", self.path.display()))?;
+            writeln!(file, "{}", serde_json::to_string(&event)?)?;
+        } else {
+            // Drop the oldest events through a temporary file so readers never see a partial log
+            events.push(event);
+            let keep = &events[events.len() - Config::MAX_EVENTS..];
+            let mut contents = String::new();
+            for event in keep {
+                contents.push_str(&serde_json::to_string(event)?);
+                contents.push('\n');
+            }
+            let tmp_path = self.path.with_extension("jsonl.tmp");
+            fs::write(&tmp_path, contents).context("Failed to write event log")?;
+            fs::rename(&tmp_path, &self.path).context("Failed to replace event log")?;
+        }
+
+        lock.unlock().ok();
+        Ok(())
+    }
+
+    /// All retained events, oldest first
+    pub fn load(&self) -> Result<Vec<Event>> {
+        if !self.path.exists() {
+            return Ok(Vec::new());
+        }
+        let contents = fs::read_to_string(&self.path)
+            .with_context(|| format!("Failed to read {}", self.path.display()))?;
+        // A line cut short by a crash mid-write is skipped rather than poisoning the log
+        Ok(contents
+            .lines()
+            .filter_map(|line| serde_json::from_str(line).ok())
+            .collect())
+    }
+
+    /// Sequence number of the newest event, 0 if there is none
+    pub fn last_seq(&self) -> Result<u64> {
+        Ok(self.load()?.last().map_or(0, |event| event.seq))
+    }
+
+    /// The events newer than `after_seq`
+    pub fn after(&self, after_seq: u64) -> Result<EventPage> {
+        let events = self.load()?;
+        let last_seq = events.last().map_or(0, |event| event.seq);
+        let oldest_seq = events.first().map_or(last_seq + 1, |event| event.seq);
+
+        Ok(EventPage {
+            last_seq,
+            gap: after_seq + 1 < oldest_seq || after_seq > last_seq,
+            events: events
+                .into_iter()
+                .filter(|event| event.seq > after_seq)
+                .collect(),
+        })
+    }
+}
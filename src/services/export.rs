@@ -0,0 +1,231 @@
+use crate::config::Config;
+use crate::services::{
+    AppArtifacts, BinaryArch, BundleCache, BundleFormat, ContainerImage, FileService, HashRecords,
+    HashingWriter,
+};
+use anyhow::{Context, Result};
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use tracing::{info, warn};
+
+// The app binary in a version directory
+const APP_BINARY: &str = "roc_camera";
+
+/// A release bundle written by `ExportService::export`
+pub struct ExportedBundle {
+    pub path: PathBuf,
+    pub sha256: String,
+    /// Whether it is the bundle the version was installed from, taken from the
+    /// bundle cache, rather than one packed from the installed files
+    pub original: bool,
+}
+
+/// Turns an installed version back into a release bundle, e.g. to carry a working
+/// install to an offline device on a USB stick. The bundle the version was
+/// installed from is used when the bundle cache still holds it, so the export
+/// matches the published checksum; otherwise the installed files are packed again,
+/// after checking them against the hashes recorded at install time.
+pub struct ExportService {
+    fs_service: FileService,
+}
+
+impl ExportService {
+    pub fn new(data_dir: PathBuf) -> Self {
+        Self {
+            fs_service: FileService::new(data_dir),
+        }
+    }
+
+    /// Writes the bundle of `version` to `output`, compressed as its extension
+    /// says (gzip unless it ends in `.tar.zst` or `.tar.xz`), and lists it in the
+    /// checksum file next to it
+    pub fn export(&self, version: &str, output: &Path) -> Result<ExportedBundle> {
+        let file_name = output
+            .file_name()
+            .with_context(|| format!("{} is not a file name", output.display()))?
+            .to_string_lossy()
+            .into_owned();
+        let format = BundleFormat::split_file_name(&file_name)
+            .map(|(_, format)| format)
+            .unwrap_or(BundleFormat::Gzip);
+        let partial = output.with_file_name(format!(".{}.partial", file_name));
+
+        if let Some(image) = ContainerImage::load(&self.fs_service.data_dir.join(version)) {
+            anyhow::bail!(
+                "Version {} runs the image {}, which has no bundle to export; save the image with '{} save' instead",
+                version,
+                image.pinned,
+                image.runtime
+            );
+        }
+        let record = self.fs_service.install_record(version);
+        let cached = record
+            .as_ref()
+            .and_then(|record| {
+                BundleCache::new(self.fs_service.data_dir.clone()).lookup(&record.bundle_sha256)
+            })
+            .filter(|path| BundleFormat::detect(path).is_ok_and(|cached| cached == format));
+
+        if cached.is_none() {
+            self.check_intact(version)?;
+        }
+        let written = match &cached {
+            Some(cached) => {
+                info!("Exporting the cached bundle {}", cached.display());
+                FileService::copy_with_sha256(cached, &partial)
+            }
+            None => self.pack(version, format, &partial),
+        };
+        let sha256 = written.inspect_err(|_| {
+            let _ = fs::remove_file(&partial);
+        })?;
+        let original = cached.is_some();
+        fs::rename(&partial, output)
+            .with_context(|| format!("Failed to write {}", output.display()))?;
+        Self::list_checksum(output, &file_name, &sha256)?;
+
+        Ok(ExportedBundle {
+            path: output.to_path_buf(),
+            sha256,
+            original,
+        })
+    }
+
+    // Only an install that matches what was installed is packed again, so a
+    // damaged or tampered copy is not spread to other devices
+    fn check_intact(&self, version: &str) -> Result<()> {
+        let problems = self.fs_service.verify_installed(version)?;
+        if !problems.is_empty() {
+            anyhow::bail!(
+                "Installed copy of {} is damaged: {}",
+                version,
+                problems.join("; ")
+            );
+        }
+        if !HashRecords::new(self.fs_service.data_dir.clone()).exists(version) {
+            warn!(
+                "Version {} was installed without recorded file hashes; exporting its files unchecked",
+                version
+            );
+            return Ok(());
+        }
+        let changes = self.fs_service.verify_hashes(version)?;
+        if !changes.is_empty() {
+            anyhow::bail!(
+                "{} file(s) of {} changed since it was installed ({}); reinstall it before exporting",
+                changes.len(),
+                version,
+                changes.join("; ")
+            );
+        }
+        Ok(())
+    }
+
+    // Packs the version directory as a bundle, leaving out what geist itself adds
+    // to it: the install record and the link to the app data
+    fn pack(&self, version: &str, format: BundleFormat, path: &Path) -> Result<String> {
+        let version_dir = self.fs_service.data_dir.join(version);
+        info!(
+            "Packing {} as a {} bundle",
+            version_dir.display(),
+            format.extension()
+        );
+        let binary_name = Self::binary_name(&version_dir)?;
+        let writer = HashingWriter::new(
+            fs::File::create(path)
+                .with_context(|| format!("Failed to create {}", path.display()))?,
+        );
+        let writer = match format {
+            BundleFormat::Gzip => Self::append_version(
+                &version_dir,
+                &binary_name,
+                flate2::write::GzEncoder::new(writer, flate2::Compression::default()),
+            )?
+            .finish()?,
+            BundleFormat::Zstd => Self::append_version(
+                &version_dir,
+                &binary_name,
+                zstd::Encoder::new(writer, 0).context("Failed to start zstd compression")?,
+            )?
+            .finish()?,
+            BundleFormat::Xz => Self::append_version(
+                &version_dir,
+                &binary_name,
+                xz2::write::XzEncoder::new(writer, 6),
+            )?
+            .finish()?,
+        };
+        Ok(writer.finish())
+    }
+
+    // The name the app binary goes by in the bundle: `roc_camera`, or for a release
+    // that ships a build per architecture, the name of the one that was installed,
+    // so the manifest's artifacts still point at it
+    fn binary_name(version_dir: &Path) -> Result<String> {
+        let Some(artifacts) = AppArtifacts::load(version_dir)? else {
+            return Ok(APP_BINARY.to_string());
+        };
+        // A script standing in for the app is taken to be the host's build
+        let arch = BinaryArch::of_binary(&version_dir.join(APP_BINARY))?
+            .or_else(BinaryArch::host)
+            .context("Cannot tell which architecture the installed roc_camera is built for")?;
+        Ok(artifacts.select(arch)?.to_string())
+    }
+
+    // Writes the tarball of a version directory and returns the compressor
+    fn append_version<W: Write>(version_dir: &Path, binary_name: &str, writer: W) -> Result<W> {
+        let mut builder = tar::Builder::new(writer);
+        // Links are part of the release and stay links
+        builder.follow_symlinks(false);
+        let mut entries: Vec<PathBuf> = fs::read_dir(version_dir)
+            .with_context(|| format!("Failed to read {}", version_dir.display()))?
+            .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+            .filter(|path| {
+                let name = path.file_name().unwrap_or_default();
+                name != Config::INSTALL_RECORD_FILE && name != Config::APP_DATA_DIR
+            })
+            .collect();
+        entries.sort();
+        for path in entries {
+            let name = match path.file_name().unwrap_or_default() {
+                name if name == APP_BINARY => binary_name.as_ref(),
+                name => name,
+            };
+            let metadata = fs::symlink_metadata(&path)?;
+            match metadata.is_dir() {
+                true => builder.append_dir_all(name, &path),
+                false => builder.append_path_with_name(&path, name),
+            }
+            .with_context(|| format!("Failed to pack {}", path.display()))?;
+        }
+        Ok(builder.into_inner()?)
+    }
+
+    // Adds or replaces the bundle's line in the checksum file beside it, so the
+    // directory can be copied to `geist/bundles` on a USB stick as it is
+    fn list_checksum(output: &Path, file_name: &str, sha256: &str) -> Result<()> {
+        let path = output.with_file_name(Config::checksum_file_name());
+        let existing = match fs::read_to_string(&path) {
+            Ok(contents) => contents,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => String::new(),
+            Err(e) => return Err(e).with_context(|| format!("Failed to read {}", path.display())),
+        };
+        let mut contents: String = existing
+            .lines()
+            .filter(|line| {
+                line.split_once(char::is_whitespace)
+                    .is_none_or(|(_, name)| name.trim().trim_start_matches('*') != file_name)
+            })
+            .map(|line| format!("{}\n", line))
+            .collect();
+        contents.push_str(&format!("{}  {}\n", sha256, file_name));
+
+        let tmp_path = output.with_file_name(format!("{}.tmp", Config::checksum_file_name()));
+        fs::write(&tmp_path, contents)
+            .with_context(|| format!("Failed to write {}", tmp_path.display()))?;
+        fs::rename(&tmp_path, &path)
+            .with_context(|| format!("Failed to replace {}", path.display()))?;
+        Ok(())
+    }
+}
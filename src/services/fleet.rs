@@ -0,0 +1,135 @@
+use anyhow::{Context, Result};
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+use std::process::Command;
+use std::thread;
+use tracing::{error, info};
+
+/// A camera listed in the fleet inventory
+#[derive(Debug, Clone, Deserialize)]
+pub struct Device {
+    pub name: String,
+    /// ssh destination, e.g. `pi@cam-01.local`
+    pub host: String,
+    /// Free-form labels such as `site` and `zone` that describe failure domains
+    #[serde(default)]
+    pub tags: BTreeMap<String, String>,
+}
+
+/// Devices managed together, read from a JSON inventory file
+#[derive(Debug, Deserialize)]
+pub struct Inventory {
+    pub devices: Vec<Device>,
+}
+
+impl Inventory {
+    pub fn load(path: &Path) -> Result<Self> {
+        let contents = fs::read_to_string(path)
+            .with_context(|| format!("Failed to read inventory {}", path.display()))?;
+        let inventory: Inventory = serde_json::from_str(&contents)
+            .with_context(|| format!("Failed to parse inventory {}", path.display()))?;
+        if inventory.devices.is_empty() {
+            anyhow::bail!("Inventory {} lists no devices", path.display());
+        }
+        Ok(inventory)
+    }
+}
+
+/// Orders and runs operations across the devices of an inventory
+pub struct FleetService {
+    inventory: Inventory,
+}
+
+impl FleetService {
+    pub fn new(inventory: Inventory) -> Self {
+        Self { inventory }
+    }
+
+    /// Name of the failure domain a device belongs to, built from the given tags
+    pub fn failure_domain(device: &Device, domain_tags: &[String]) -> String {
+        domain_tags
+            .iter()
+            .map(|tag| {
+                device
+                    .tags
+                    .get(tag)
+                    .map(String::as_str)
+                    .unwrap_or("unassigned")
+            })
+            .collect::<Vec<_>>()
+            .join("/")
+    }
+
+    /// Splits the devices into waves so that no wave takes down more than
+    /// `max_down_fraction` of any failure domain (but always at least one device).
+    pub fn plan_waves(&self, domain_tags: &[String], max_down_fraction: f64) -> Vec<Vec<&Device>> {
+        let mut domains: BTreeMap<String, Vec<&Device>> = BTreeMap::new();
+        for device in &self.inventory.devices {
+            domains
+                .entry(Self::failure_domain(device, domain_tags))
+                .or_default()
+                .push(device);
+        }
+
+        let mut waves: Vec<Vec<&Device>> = Vec::new();
+        for devices in domains.values() {
+            let per_wave = ((devices.len() as f64 * max_down_fraction).floor() as usize).max(1);
+            for (index, chunk) in devices.chunks(per_wave).enumerate() {
+                if waves.len() <= index {
+                    waves.push(Vec::new());
+                }
+                waves[index].extend_from_slice(chunk);
+            }
+        }
+        waves
+    }
+
+    /// Runs `geist <args>` on every device of a wave in parallel over ssh.
+    /// Returns the names of the devices where it failed.
+    pub fn run_wave(wave: &[&Device], args: &[String]) -> Vec<String> {
+        thread::scope(|scope| {
+            let handles: Vec<_> = wave
+                .iter()
+                .map(|device| {
+                    scope.spawn(move || {
+                        let _span = tracing::info_span!("device", name = %device.name).entered();
+                        let result = Self::run_remote(device, args);
+                        if let Err(e) = &result {
+                            error!("{} failed: {:#}", device.name, e);
+                        }
+                        (device.name.clone(), result.is_ok())
+                    })
+                })
+                .collect();
+
+            handles
+                .into_iter()
+                .filter_map(|handle| match handle.join() {
+                    Ok((_, true)) => None,
+                    Ok((name, false)) => Some(name),
+                    Err(_) => Some("<panicked>".to_string()),
+                })
+                .collect()
+        })
+    }
+
+    fn run_remote(device: &Device, args: &[String]) -> Result<()> {
+        info!("Running 'geist {}' on {}", args.join(" "), device.host);
+        let output = Command::new("ssh")
+            .args(["-o", "BatchMode=yes", &device.host, "geist"])
+            .args(args)
+            .output()
+            .context("Failed to execute ssh")?;
+
+        if !output.status.success() {
+            anyhow::bail!(
+                "geist exited with {}: {}",
+                output.status,
+                String::from_utf8_lossy(&output.stderr).trim()
+            );
+        }
+        Ok(())
+    }
+}
@@ -1,40 +1,218 @@
+use crate::config::Config;
+use crate::services::{
+    AppArtifacts, BinaryArch, BundleFormat, ContainerImage, ContainerService, CurrentService,
+    EventKind, EventService, HashRecords, HostService, KeyService, LinkSource, MacProfile,
+    SmokeTest, StateService, VersionMark,
+};
 use anyhow::{Context, Result};
+use chrono::{DateTime, Local, Utc};
 use fs_extra::dir::copy as copy_dir;
 use fs_extra::dir::CopyOptions;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::{BTreeMap, HashSet};
 use std::fs;
+use std::io::Write;
 use std::path::{Path, PathBuf};
-use std::process::Command;
+use std::time::Duration;
 use tempfile;
-use tracing::info;
+use tracing::{info, warn};
 
 pub struct FileService {
     pub data_dir: PathBuf,
 }
 
+/// Summary of a version installed under the data directory
+#[derive(Serialize)]
+pub struct InstalledVersion {
+    pub version: String,
+    pub path: PathBuf,
+    pub installed_at: Option<DateTime<Local>>,
+    pub size_bytes: u64,
+    pub has_manifest: bool,
+    /// Missing for versions installed before records were written
+    pub install_record: Option<InstallRecord>,
+    /// Whether the version is marked good or bad
+    pub mark: Option<VersionMark>,
+}
+
+/// Disk space an update takes while it runs, in bytes, estimated before its
+/// bundle is downloaded
+#[derive(Debug, Clone, Copy, Serialize)]
+pub struct SpaceEstimate {
+    /// The downloaded release bundle
+    pub bundle: u64,
+    /// The bundle extracted next to it
+    pub extracted: u64,
+    /// The copy installed into the data directory
+    pub install: u64,
+}
+
+impl SpaceEstimate {
+    pub fn total(&self) -> u64 {
+        self.bundle + self.extracted + self.install
+    }
+}
+
+/// Where a bundle being installed came from
+pub struct BundleOrigin {
+    /// How the bundle was obtained, e.g. `registry` or `usb`
+    pub backend: String,
+    /// URL or path the bundle was read from
+    pub source: String,
+    /// Id of the trusted key whose signature covered the bundle's checksum
+    pub signed_by: Option<String>,
+    /// Id of the trusted key whose detached signature covered the bundle itself
+    pub bundle_signed_by: Option<String>,
+}
+
+/// Provenance of an installed version, written once as `install.json` into its
+/// directory and never changed afterwards
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct InstallRecord {
+    pub backend: String,
+    pub source: String,
+    pub bundle_sha256: String,
+    pub signed_by: Option<String>,
+    /// Missing when the bundle had no detached signature or its check was skipped
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub bundle_signed_by: Option<String>,
+    /// Version of geist that installed it
+    pub installed_by: String,
+    pub installed_at: DateTime<Utc>,
+}
+
+/// One of the two install slots that `run` can boot from
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Slot {
+    #[default]
+    A,
+    B,
+}
+
+impl Slot {
+    pub fn other(self) -> Slot {
+        match self {
+            Slot::A => Slot::B,
+            Slot::B => Slot::A,
+        }
+    }
+}
+
+impl std::fmt::Display for Slot {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Slot::A => write!(f, "A"),
+            Slot::B => write!(f, "B"),
+        }
+    }
+}
+
+/// Boot marker recording which version each slot holds and which one to boot
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct BootMarker {
+    /// Slot that last reached a healthy state
+    pub active: Slot,
+    /// Slot holding a freshly installed version that has not proven itself yet
+    pub trial: Option<Slot>,
+    pub a: Option<String>,
+    pub b: Option<String>,
+}
+
+impl BootMarker {
+    pub fn version(&self, slot: Slot) -> Option<&String> {
+        match slot {
+            Slot::A => self.a.as_ref(),
+            Slot::B => self.b.as_ref(),
+        }
+    }
+
+    fn set_version(&mut self, slot: Slot, version: &str) {
+        match slot {
+            Slot::A => self.a = Some(version.to_string()),
+            Slot::B => self.b = Some(version.to_string()),
+        }
+    }
+
+    /// The slot `run` should launch: the trial slot if one is pending
+    pub fn boot_slot(&self) -> Slot {
+        self.trial.unwrap_or(self.active)
+    }
+
+    pub fn boot_version(&self) -> Option<&String> {
+        self.version(self.boot_slot())
+    }
+
+    /// Versions referenced by either slot
+    pub fn versions(&self) -> Vec<String> {
+        self.a.iter().chain(self.b.iter()).cloned().collect()
+    }
+}
+
+/// Writes through to a file while computing the SHA-256 of everything written
+pub struct HashingWriter<W> {
+    inner: W,
+    hasher: Sha256,
+}
+
+impl<W: Write> HashingWriter<W> {
+    pub fn new(inner: W) -> Self {
+        Self {
+            inner,
+            hasher: Sha256::new(),
+        }
+    }
+
+    /// Hex encoded SHA-256 of what was written
+    pub fn finish(self) -> String {
+        hex::encode(self.hasher.finalize())
+    }
+}
+
+impl<W: Write> Write for HashingWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let written = self.inner.write(buf)?;
+        self.hasher.update(&buf[..written]);
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.inner.flush()
+    }
+}
+
 impl FileService {
     pub fn new(data_dir: PathBuf) -> Self {
         Self { data_dir }
     }
 
+    /// The current version: GEIST_CURRENT_VERSION if set, otherwise the one the
+    /// current pointer names, otherwise this geist's own version
+    pub fn current_version(&self) -> String {
+        if let Ok(version) = std::env::var("GEIST_CURRENT_VERSION") {
+            return version;
+        }
+
+        match CurrentService::new(self.data_dir.clone()).load() {
+            Ok(Some(pointer)) => pointer.version,
+            Ok(None) => format!("v{}", Config::PKG_VERSION),
+            Err(e) => {
+                warn!("{:#}", e);
+                format!("v{}", Config::PKG_VERSION)
+            }
+        }
+    }
+
     pub fn extract_bundle(&self, bundle_path: &Path, temp_dir: &Path) -> Result<PathBuf> {
         info!("Extracting release bundle...");
-        let status = Command::new("tar")
-            .args(["xzf", bundle_path.to_str().unwrap()])
-            .current_dir(temp_dir)
-            .output()
-            .context("Failed to execute tar command")?;
-
-        if !status.status.success() {
-            let error = String::from_utf8_lossy(&status.stderr);
-            return Err(anyhow::anyhow!(
-                "Failed to extract release bundle: {}",
-                error
-            ));
-        }
+        Self::unpack_tarball(bundle_path, temp_dir)
+            .map_err(|e| anyhow::anyhow!("Failed to extract release bundle: {:#}", e))?;
 
         Ok(temp_dir.join("release_bundle"))
     }
 
+    #[tracing::instrument(skip_all)]
     pub fn extract_bundle_with_details(
         &self,
         bundle_path: &Path,
@@ -59,29 +237,15 @@ impl FileService {
 
         // List the contents of the tarball before extraction
         info!("Listing contents of the tarball:");
-        let list_output = Command::new("tar").arg("-tvf").arg(bundle_path).output()?;
-
-        if list_output.status.success() {
-            let stdout = String::from_utf8_lossy(&list_output.stdout);
-            info!("Tarball contents:\n{}", stdout);
-        } else {
-            let stderr = String::from_utf8_lossy(&list_output.stderr);
-            info!("Failed to list tarball contents: {}", stderr);
+        match Self::list_tarball(bundle_path) {
+            Ok(listing) => info!("Tarball contents:\n{}", listing),
+            Err(e) => info!("Failed to list tarball contents: {:#}", e),
         }
 
         // Extract the tarball directly to the release_bundle_dir
         info!("Extracting tarball to: {}", release_bundle_dir.display());
-        let output = Command::new("tar")
-            .arg("-xzf")
-            .arg(bundle_path)
-            .arg("-C")
-            .arg(&release_bundle_dir)
-            .output()?;
-
-        if !output.status.success() {
-            let stderr = String::from_utf8_lossy(&output.stderr);
-            return Err(anyhow::anyhow!("Failed to extract tarball: {}", stderr));
-        }
+        Self::unpack_tarball(bundle_path, &release_bundle_dir)
+            .map_err(|e| anyhow::anyhow!("Failed to extract tarball: {:#}", e))?;
 
         // List the contents of the extracted directory for debugging
         info!("Contents of release_bundle_dir:");
@@ -90,6 +254,33 @@ impl FileService {
         Ok(release_bundle_dir)
     }
 
+    // Tarballs are read in-process rather than with the tar binary, which not every
+    // developer machine has, whichever compression the bundle uses
+    fn unpack_tarball(bundle_path: &Path, dest: &Path) -> Result<()> {
+        let mut archive = tar::Archive::new(BundleFormat::open(bundle_path)?);
+        archive.set_preserve_permissions(true);
+        archive.unpack(dest)?;
+        Ok(())
+    }
+
+    // Mode, size and path of every entry, like `tar -tv`
+    fn list_tarball(bundle_path: &Path) -> Result<String> {
+        let mut archive = tar::Archive::new(BundleFormat::open(bundle_path)?);
+
+        let mut listing = String::new();
+        for entry in archive.entries()? {
+            let entry = entry?;
+            let header = entry.header();
+            listing.push_str(&format!(
+                "{:o} {:>10} {}\n",
+                header.mode().unwrap_or(0),
+                header.size().unwrap_or(0),
+                entry.path()?.display()
+            ));
+        }
+        Ok(listing)
+    }
+
     // Helper function to walk directories and log contents
     fn walk_directory(&self, dir: &Path) -> Result<Vec<PathBuf>> {
         let mut files = Vec::new();
@@ -128,43 +319,130 @@ impl FileService {
         Ok(files)
     }
 
-    // Helper function to recursively copy directories
-    pub fn copy_dir_all(&self, src: &Path, dst: &Path) -> Result<()> {
-        fs::create_dir_all(dst)?;
-        for entry in fs::read_dir(src)? {
+    /// Copies a directory tree, keeping the permissions and modification times of
+    /// its files and directories. Symlinks are recreated rather than followed: a
+    /// relative link is copied as it is, and an absolute link into the tree is
+    /// rewritten to point at the same entry of the copy.
+    pub fn copy_dir_all(src: &Path, dst: &Path) -> Result<()> {
+        Self::copy_tree(src, src, dst, None)
+    }
+
+    // Copies `src` below `src_root` to `dst`, linking unchanged files to `links`
+    // when given
+    fn copy_tree(
+        src_root: &Path,
+        src: &Path,
+        dst: &Path,
+        mut links: Option<&mut LinkSource>,
+    ) -> Result<()> {
+        fs::create_dir_all(dst).with_context(|| format!("Failed to create {}", dst.display()))?;
+        for entry in
+            fs::read_dir(src).with_context(|| format!("Failed to read {}", src.display()))?
+        {
             let entry = entry?;
+            // The entry's own type, so links are never followed
             let ty = entry.file_type()?;
             let src_path = entry.path();
             let dst_path = dst.join(entry.file_name());
 
-            if ty.is_dir() {
-                FileService::copy_dir_all_static(&src_path, &dst_path)?;
+            if ty.is_symlink() {
+                Self::copy_symlink(src_root, &src_path, &dst_path)?;
+            } else if ty.is_dir() {
+                Self::copy_tree(src_root, &src_path, &dst_path, links.as_deref_mut())?;
             } else {
-                fs::copy(&src_path, &dst_path)?;
+                Self::link_or_copy(links.as_deref_mut(), &src_path, &dst_path)?;
             }
         }
-        Ok(())
+        // Last, since adding the entries bumped the directory's modification time
+        Self::copy_metadata(src, dst)
     }
 
-    // Static version of copy_dir_all to avoid self parameter
-    fn copy_dir_all_static(src: &Path, dst: &Path) -> Result<()> {
-        fs::create_dir_all(dst)?;
-        for entry in fs::read_dir(src)? {
-            let entry = entry?;
-            let ty = entry.file_type()?;
-            let src_path = entry.path();
-            let dst_path = dst.join(entry.file_name());
+    /// Copies a file along with its permissions and modification time
+    pub fn copy_file(src: &Path, dst: &Path) -> Result<()> {
+        fs::copy(src, dst)
+            .with_context(|| format!("Failed to copy {} to {}", src.display(), dst.display()))?;
+        Self::copy_metadata(src, dst)
+    }
 
-            if ty.is_dir() {
-                FileService::copy_dir_all_static(&src_path, &dst_path)?;
-            } else {
-                fs::copy(&src_path, &dst_path)?;
+    fn link_or_copy(links: Option<&mut LinkSource>, src: &Path, dst: &Path) -> Result<()> {
+        match links {
+            Some(links) => links.link_or_copy(src, dst),
+            None => Self::copy_file(src, dst),
+        }
+    }
+
+    // Recreates a link found at `src` below `src_root` at `dst`
+    #[cfg(unix)]
+    fn copy_symlink(src_root: &Path, src: &Path, dst: &Path) -> Result<()> {
+        let target = fs::read_link(src)
+            .with_context(|| format!("Failed to read the link {}", src.display()))?;
+        // An absolute link into the tree would point back into the source once
+        // copied, so it becomes a relative one that moves with the copy
+        let target = match target.strip_prefix(src_root) {
+            Ok(inside) if target.is_absolute() => {
+                let depth = src
+                    .parent()
+                    .and_then(|parent| parent.strip_prefix(src_root).ok())
+                    .map_or(0, |parent| parent.components().count());
+                std::iter::repeat_n(Path::new(".."), depth)
+                    .collect::<PathBuf>()
+                    .join(inside)
             }
+            _ => target,
+        };
+        std::os::unix::fs::symlink(&target, dst)
+            .with_context(|| format!("Failed to create the link {}", dst.display()))
+    }
+
+    // Without unix symlinks, whatever the link points at is copied in its place
+    #[cfg(not(unix))]
+    fn copy_symlink(_src_root: &Path, src: &Path, dst: &Path) -> Result<()> {
+        if src.is_dir() {
+            Self::copy_dir_all(src, dst)
+        } else {
+            Self::copy_file(src, dst)
         }
-        Ok(())
     }
 
+    // Gives `dst` the modification time and permissions of `src`, the permissions
+    // last so a read-only mode cannot get in the way
+    fn copy_metadata(src: &Path, dst: &Path) -> Result<()> {
+        let metadata =
+            fs::metadata(src).with_context(|| format!("Failed to read {}", src.display()))?;
+        let times = fs::FileTimes::new().set_modified(metadata.modified()?);
+        // Directories can only be opened to set their times on unix
+        if cfg!(unix) || metadata.is_file() {
+            fs::File::open(dst)
+                .and_then(|file| file.set_times(times))
+                .with_context(|| format!("Failed to set the times of {}", dst.display()))?;
+        }
+        fs::set_permissions(dst, metadata.permissions())
+            .with_context(|| format!("Failed to set the permissions of {}", dst.display()))
+    }
+
+    /// Installs the extracted release as `version`, all at once: a failure midway
+    /// leaves an existing copy of the version as it was
     pub fn install_version(&self, release_bundle_dir: &Path, version: &str) -> Result<()> {
+        let staging_dir = self.stage_version(release_bundle_dir, version)?;
+        self.commit_staged(&staging_dir, version)
+    }
+
+    // Where a version is assembled before it is renamed into place
+    fn staging_dir(&self, version: &str) -> PathBuf {
+        self.data_dir
+            .join(format!("{}{}", version, Config::STAGING_SUFFIX))
+    }
+
+    // Where the copy a reinstall replaces waits until the new one is in place
+    fn replaced_dir(&self, version: &str) -> PathBuf {
+        self.data_dir
+            .join(format!("{}{}", version, Config::REPLACED_SUFFIX))
+    }
+
+    // Copies the extracted release into the version's staging directory, leaving
+    // the installed copy, if any, untouched
+    #[tracing::instrument(skip(self, release_bundle_dir))]
+    fn stage_version(&self, release_bundle_dir: &Path, version: &str) -> Result<PathBuf> {
         // Find the binary and other required files in the extracted contents
         let found_files = self.walk_directory(release_bundle_dir)?;
 
@@ -184,9 +462,12 @@ impl FileService {
         }
 
         // Use the found paths or default to the expected locations
-        let binary_path = binary_path.unwrap_or_else(|| release_bundle_dir.join("roc_camera"));
         let manifest_path =
             manifest_path.unwrap_or_else(|| release_bundle_dir.join("manifest.yaml"));
+        let binary_path = match Self::select_artifact(&manifest_path)? {
+            Some(artifact) => artifact,
+            None => binary_path.unwrap_or_else(|| release_bundle_dir.join("roc_camera")),
+        };
         let assets_dir = assets_dir.unwrap_or_else(|| release_bundle_dir.join("roc_camera_app"));
 
         info!("Using binary path: {}", binary_path.display());
@@ -215,41 +496,491 @@ impl FileService {
             ));
         }
 
-        // Create version directory in data_dir
-        let version_dir = self.data_dir.join(version);
-        info!("Installing to version directory: {}", version_dir.display());
+        self.recover_replaced(version)?;
 
-        // Remove existing directory if it exists to avoid "Directory not empty" error
-        if version_dir.exists() {
-            info!(
-                "Removing existing version directory: {}",
-                version_dir.display()
-            );
-            fs::remove_dir_all(&version_dir)?;
+        // Whatever an interrupted install left behind is started over
+        let staging_dir = self.staging_dir(version);
+        info!("Staging version in: {}", staging_dir.display());
+        if staging_dir.exists() {
+            fs::remove_dir_all(&staging_dir).with_context(|| {
+                format!("Failed to remove the leftover {}", staging_dir.display())
+            })?;
         }
+        fs::create_dir_all(&staging_dir)
+            .with_context(|| format!("Failed to create {}", staging_dir.display()))?;
 
-        // Create the version directory
-        fs::create_dir_all(&version_dir)?;
+        // Copy files to the staging directory
+        let dest_binary = staging_dir.join("roc_camera");
+        let dest_manifest = staging_dir.join("manifest.yaml");
+        let dest_assets = staging_dir.join("roc_camera_app");
 
-        // Copy files to the version directory
-        let dest_binary = version_dir.join("roc_camera");
-        let dest_manifest = version_dir.join("manifest.yaml");
-        let dest_assets = version_dir.join("roc_camera_app");
+        // Files unchanged since the previous version are hard-linked to its copies
+        let mut links = self.link_source(version);
 
         info!("Copying binary to: {}", dest_binary.display());
-        fs::copy(&binary_path, &dest_binary)?;
+        Self::link_or_copy(links.as_mut(), &binary_path, &dest_binary)?;
 
         info!("Copying manifest to: {}", dest_manifest.display());
-        fs::copy(&manifest_path, &dest_manifest)?;
+        Self::link_or_copy(links.as_mut(), &manifest_path, &dest_manifest)?;
 
         info!("Copying assets to: {}", dest_assets.display());
-        self.copy_dir_all(&assets_dir, &dest_assets)?;
+        Self::copy_tree(&assets_dir, &assets_dir, &dest_assets, links.as_mut())?;
+
+        // Data migrations ship next to the manifest and run from the version directory
+        if let Some(migrations_dir) = manifest_path
+            .parent()
+            .map(|dir| dir.join(Config::MIGRATIONS_DIR))
+            .filter(|dir| dir.is_dir())
+        {
+            let dest_migrations = staging_dir.join(Config::MIGRATIONS_DIR);
+            info!("Copying migrations to: {}", dest_migrations.display());
+            Self::copy_tree(
+                &migrations_dir,
+                &migrations_dir,
+                &dest_migrations,
+                links.as_mut(),
+            )?;
+        }
+
+        if let Some(links) = links.filter(|links| links.linked_files > 0) {
+            info!(
+                "Linked {} unchanged files ({} bytes) to version {}",
+                links.linked_files,
+                links.linked_bytes,
+                links.version()
+            );
+        }
+
+        // The SBOM ships next to the manifest and stays with the version for `geist sbom`
+        if let Some(manifest_dir) = manifest_path.parent() {
+            for file_name in Config::SBOM_FILE_NAMES {
+                let sbom = manifest_dir.join(file_name);
+                if sbom.is_file() {
+                    Self::copy_file(&sbom, &staging_dir.join(file_name))?;
+                }
+            }
+
+            // Loaded only when the version is launched
+            if let Some(profile) = MacProfile::load(&staging_dir) {
+                profile.copy_files(manifest_dir, &staging_dir)?;
+            }
+        }
+
+        Ok(staging_dir)
+    }
+
+    // The build of the app for this host, when the release ships one per
+    // architecture; it is installed as `roc_camera` like a single build
+    fn select_artifact(manifest_path: &Path) -> Result<Option<PathBuf>> {
+        let Some(manifest_dir) = manifest_path.parent() else {
+            return Ok(None);
+        };
+        let Some(artifacts) = AppArtifacts::load(manifest_dir)? else {
+            return Ok(None);
+        };
+        let arch = BinaryArch::host().with_context(|| {
+            format!(
+                "Cannot pick a build of roc_camera for the unknown architecture {}",
+                HostService::arch()
+            )
+        })?;
+        let file = artifacts.select(arch)?;
+        let path = manifest_dir.join(file);
+        if !path.is_file() {
+            anyhow::bail!(
+                "The release lists {} as its {} build, but the bundle does not contain it",
+                file,
+                arch
+            );
+        }
+        info!("Installing the {} build {}", arch, file);
+        Ok(Some(path))
+    }
+
+    // The version that `version` shares unchanged files with: the current one, or
+    // else the newest other installed version. Linking only saves space, so a
+    // version that cannot be indexed is copied from scratch instead.
+    fn link_source(&self, version: &str) -> Option<LinkSource> {
+        let current = self.current_version();
+        let previous = if current != version && self.data_dir.join(&current).is_dir() {
+            Some(current)
+        } else {
+            self.installed_versions()
+                .ok()?
+                .into_iter()
+                .rev()
+                .find(|installed| installed != version)
+        }?;
+        match LinkSource::open(&self.data_dir, &previous) {
+            Ok(links) => Some(links),
+            Err(e) => {
+                warn!("Not linking unchanged files to {}: {:#}", previous, e);
+                None
+            }
+        }
+    }
+
+    // Flushes a staged version to disk and renames it into place. A copy of the
+    // version installed before is moved aside first, since a directory can only be
+    // renamed over an empty one, and removed once the new copy is in place.
+    fn commit_staged(&self, staging_dir: &Path, version: &str) -> Result<()> {
+        Self::sync_tree(staging_dir)?;
+
+        let version_dir = self.data_dir.join(version);
+        let replaced_dir = self.replaced_dir(version);
+        if version_dir.exists() {
+            if replaced_dir.exists() {
+                fs::remove_dir_all(&replaced_dir)?;
+            }
+            fs::rename(&version_dir, &replaced_dir)
+                .with_context(|| format!("Failed to move {} aside", version_dir.display()))?;
+        }
+        if let Err(e) = fs::rename(staging_dir, &version_dir) {
+            if replaced_dir.exists() {
+                fs::rename(&replaced_dir, &version_dir).ok();
+            }
+            return Err(e)
+                .with_context(|| format!("Failed to move {} into place", staging_dir.display()));
+        }
+        Self::sync_dir(&self.data_dir)?;
 
+        if replaced_dir.exists() {
+            if let Err(e) = fs::remove_dir_all(&replaced_dir) {
+                warn!("Failed to remove {}: {}", replaced_dir.display(), e);
+            }
+        }
         info!("Successfully installed version: {}", version);
+        Ok(())
+    }
+
+    // Puts back a copy that an install interrupted between its two renames left
+    // aside, so the version is never lost
+    fn recover_replaced(&self, version: &str) -> Result<()> {
+        let version_dir = self.data_dir.join(version);
+        let replaced_dir = self.replaced_dir(version);
+        if !replaced_dir.exists() {
+            return Ok(());
+        }
+        if version_dir.exists() {
+            fs::remove_dir_all(&replaced_dir)?;
+        } else {
+            warn!(
+                "Restoring {} left aside by an interrupted install",
+                version_dir.display()
+            );
+            fs::rename(&replaced_dir, &version_dir)?;
+        }
+        Ok(())
+    }
+
+    // Flushes every file and directory below `dir` to disk
+    fn sync_tree(dir: &Path) -> Result<()> {
+        for entry in
+            fs::read_dir(dir).with_context(|| format!("Failed to read {}", dir.display()))?
+        {
+            let entry = entry?;
+            let file_type = entry.file_type()?;
+            if file_type.is_dir() {
+                Self::sync_tree(&entry.path())?;
+            } else if file_type.is_file() {
+                fs::File::open(entry.path())
+                    .and_then(|file| file.sync_all())
+                    .with_context(|| format!("Failed to flush {}", entry.path().display()))?;
+            }
+        }
+        Self::sync_dir(dir)
+    }
+
+    /// Flushes a directory's entries, so renames into it survive a power cut
+    pub fn sync_dir(dir: &Path) -> Result<()> {
+        #[cfg(unix)]
+        fs::File::open(dir)
+            .and_then(|dir| dir.sync_all())
+            .with_context(|| format!("Failed to flush {}", dir.display()))?;
+        #[cfg(not(unix))]
+        let _ = dir;
+        Ok(())
+    }
+
+    /// Checks that an installed version has everything `run` needs.
+    /// Returns the problems found, empty when the installation is intact.
+    pub fn verify_installed(&self, version: &str) -> Result<Vec<String>> {
+        let version_dir = self.data_dir.join(version);
+        if !version_dir.is_dir() {
+            anyhow::bail!("Version {} is not installed", version);
+        }
+
+        let mut problems = Vec::new();
+
+        // A version run as a container needs its image rather than any files
+        if let Some(image) = ContainerImage::load(&version_dir) {
+            if !version_dir.join("manifest.yaml").is_file() {
+                problems.push(format!(
+                    "Manifest missing: {}",
+                    version_dir.join("manifest.yaml").display()
+                ));
+            }
+            if !ContainerService::present(&image) {
+                problems.push(format!(
+                    "Image {} is no longer held by {}",
+                    image.pinned, image.runtime
+                ));
+            }
+            return Ok(problems);
+        }
+
+        let binary = version_dir.join("roc_camera");
+        match fs::metadata(&binary) {
+            Ok(metadata) if !metadata.is_file() => {
+                problems.push(format!("{} is not a file", binary.display()))
+            }
+            Ok(metadata) if metadata.len() == 0 => {
+                problems.push(format!("{} is empty", binary.display()))
+            }
+            Ok(_) => {}
+            Err(_) => problems.push(format!("Binary missing: {}", binary.display())),
+        }
+
+        let manifest = version_dir.join("manifest.yaml");
+        if !manifest.is_file() {
+            problems.push(format!("Manifest missing: {}", manifest.display()));
+        }
+
+        let assets = version_dir.join("roc_camera_app");
+        if !assets.is_dir() {
+            problems.push(format!("Assets directory missing: {}", assets.display()));
+        } else if fs::read_dir(&assets)?.next().is_none() {
+            problems.push(format!("Assets directory is empty: {}", assets.display()));
+        }
+
+        if let Some(profile) = MacProfile::load(&version_dir) {
+            for file in profile.files() {
+                let path = version_dir.join(file);
+                if !path.is_file() {
+                    problems.push(format!("MAC profile missing: {}", path.display()));
+                }
+            }
+        }
+
+        Ok(problems)
+    }
+
+    /// Recomputes the hashes of an installed version's files and compares them
+    /// with the ones recorded at install time. Returns the files that were
+    /// modified, are missing or were added, empty when nothing changed. Fails when
+    /// the version has no record, or one the device key does not vouch for.
+    pub fn verify_hashes(&self, version: &str) -> Result<Vec<String>> {
+        let version_dir = self.data_dir.join(version);
+        if !version_dir.is_dir() {
+            anyhow::bail!("Version {} is not installed", version);
+        }
+        let recorded = HashRecords::new(self.data_dir.clone()).load(version)?;
+
+        let mut problems = Vec::new();
+        let present = Self::relative_files(&version_dir)?;
+        for (path, expected) in &recorded {
+            if !present.contains(path) {
+                problems.push(format!("Missing: {}", path));
+            } else if Self::sha256_entry(&version_dir.join(path))? != *expected {
+                problems.push(format!("Modified: {}", path));
+            }
+        }
+        for path in present {
+            if !recorded.contains_key(&path) {
+                problems.push(format!("Extra: {}", path));
+            }
+        }
+        Ok(problems)
+    }
+
+    /// Checks the SHA-256 a release bundle was found to have while it was
+    /// downloaded or copied against the expected one, then extracts it into
+    /// `work_dir`, applies any key rotations it carries and installs it as
+    /// `version` along with a record of its origin
+    #[tracing::instrument(skip(
+        self,
+        bundle_path,
+        work_dir,
+        expected_sha256,
+        actual_sha256,
+        origin
+    ))]
+    pub fn install_bundle(
+        &self,
+        bundle_path: &Path,
+        work_dir: &Path,
+        expected_sha256: &str,
+        actual_sha256: &str,
+        version: &str,
+        origin: BundleOrigin,
+    ) -> Result<()> {
+        if expected_sha256 != actual_sha256 {
+            anyhow::bail!(
+                "Checksum mismatch for release bundle: expected {}, got {}",
+                expected_sha256,
+                actual_sha256
+            );
+        }
+
+        info!("Extracting release bundle from: {}", bundle_path.display());
+        let release_bundle_dir = self.extract_bundle_with_details(bundle_path, work_dir)?;
+
+        // Installing copies the extracted files, so they have to fit next to the reserve
+        self.ensure_disk_reserve(
+            Self::size_of(&release_bundle_dir)?,
+            "installing the release",
+        )?;
 
+        // Releases can carry a successor signing key, signed by a trusted key
+        let rotated = KeyService::new(self.data_dir.clone()).import_rotations(
+            &release_bundle_dir,
+            Duration::from_secs(Config::KEY_ROTATION_OVERLAP_DAYS * 24 * 60 * 60),
+        )?;
+        for key in rotated {
+            info!("Release rotated in signing key {}", key.key_id);
+        }
+
+        // Everything up to the rename happens in the staging directory, so the
+        // version only appears once it is complete
+        let staging_dir = self.stage_version(&release_bundle_dir, version)?;
+        let discard = |e: anyhow::Error| {
+            if let Err(re) = fs::remove_dir_all(&staging_dir) {
+                warn!("Failed to remove {}: {}", staging_dir.display(), re);
+            }
+            e
+        };
+
+        // A build that fails its own smoke test must never become bootable
+        if let Some(smoke_test) = SmokeTest::load(&staging_dir) {
+            smoke_test.run(&staging_dir).map_err(|e| {
+                discard(e.context(format!("Version {} failed its smoke test", version)))
+            })?;
+        }
+
+        // Hashed after the smoke test, which runs in the version directory
+        let files = Self::file_hashes(&staging_dir).map_err(discard)?;
+        Self::write_install_record(
+            &staging_dir,
+            &InstallRecord {
+                backend: origin.backend,
+                source: origin.source.clone(),
+                bundle_sha256: actual_sha256.to_string(),
+                signed_by: origin.signed_by,
+                bundle_signed_by: origin.bundle_signed_by,
+                installed_by: Config::PKG_VERSION.to_string(),
+                installed_at: Utc::now(),
+            },
+        )
+        .map_err(discard)?;
+        self.commit_staged(&staging_dir, version).map_err(discard)?;
+        self.record_hashes(version, files)?;
+
+        EventService::new(self.data_dir.clone()).record(
+            EventKind::Installed,
+            Some(version),
+            Some(origin.source),
+        );
+        Ok(())
+    }
+
+    /// Installs a version pulled as a container image: its directory holds the
+    /// description of the image and the manifest it carries instead of the app.
+    /// `signed_by` is the identity that signed the image, if it was checked.
+    pub fn install_image(
+        &self,
+        version: &str,
+        image: &ContainerImage,
+        manifest: &str,
+        signed_by: Option<String>,
+    ) -> Result<()> {
+        self.recover_replaced(version)?;
+        let staging_dir = self.staging_dir(version);
+        if staging_dir.exists() {
+            fs::remove_dir_all(&staging_dir).with_context(|| {
+                format!("Failed to remove the leftover {}", staging_dir.display())
+            })?;
+        }
+        fs::create_dir_all(&staging_dir)
+            .with_context(|| format!("Failed to create {}", staging_dir.display()))?;
+        let discard = |e: anyhow::Error| {
+            if let Err(re) = fs::remove_dir_all(&staging_dir) {
+                warn!("Failed to remove {}: {}", staging_dir.display(), re);
+            }
+            e
+        };
+
+        let files = (|| -> Result<BTreeMap<String, String>> {
+            fs::write(
+                staging_dir.join(Config::CONTAINER_IMAGE_FILE),
+                serde_json::to_string_pretty(image)?,
+            )?;
+            fs::write(staging_dir.join("manifest.yaml"), manifest)?;
+            Self::write_install_record(
+                &staging_dir,
+                &InstallRecord {
+                    backend: "container".to_string(),
+                    source: image.reference.clone(),
+                    bundle_sha256: image
+                        .pinned
+                        .rsplit_once(':')
+                        .map_or(image.pinned.as_str(), |(_, digest)| digest)
+                        .to_string(),
+                    signed_by: None,
+                    bundle_signed_by: signed_by,
+                    installed_by: Config::PKG_VERSION.to_string(),
+                    installed_at: Utc::now(),
+                },
+            )?;
+            Self::file_hashes(&staging_dir)
+        })()
+        .map_err(discard)?;
+        self.commit_staged(&staging_dir, version).map_err(discard)?;
+        self.record_hashes(version, files)
+    }
+
+    // Without its record, an installed version counts as tampered with wherever
+    // launches are refused for it, so a failure here fails the install
+    fn record_hashes(&self, version: &str, files: BTreeMap<String, String>) -> Result<()> {
+        HashRecords::new(self.data_dir.clone())
+            .write(version, files)
+            .with_context(|| {
+                format!(
+                    "Version {} installed, but its file hashes could not be recorded",
+                    version
+                )
+            })
+    }
+
+    fn write_install_record(version_dir: &Path, record: &InstallRecord) -> Result<()> {
+        let path = version_dir.join(Config::INSTALL_RECORD_FILE);
+        fs::write(&path, serde_json::to_string_pretty(record)?)
+            .with_context(|| format!("Failed to write {}", path.display()))?;
+
+        // Read-only so nothing on the device rewrites it by accident
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            fs::set_permissions(&path, fs::Permissions::from_mode(0o444))?;
+        }
         Ok(())
     }
 
+    /// The provenance record of an installed version, if it has one
+    pub fn install_record(&self, version: &str) -> Option<InstallRecord> {
+        let path = self
+            .data_dir
+            .join(version)
+            .join(Config::INSTALL_RECORD_FILE);
+        let contents = fs::read_to_string(&path).ok()?;
+        match serde_json::from_str(&contents) {
+            Ok(record) => Some(record),
+            Err(e) => {
+                tracing::warn!("Ignoring unreadable {}: {}", path.display(), e);
+                None
+            }
+        }
+    }
+
     pub fn update_files(&self, bundle_path: &Path) -> Result<()> {
         info!("Updating application files...");
 
@@ -277,6 +1008,405 @@ impl FileService {
         Ok(())
     }
 
+    /// Names of all version directories in the data directory, oldest first by
+    /// semver. Directories whose names are not versions are skipped.
+    pub fn installed_versions(&self) -> Result<Vec<String>> {
+        let mut versions = Vec::new();
+        if !self.data_dir.exists() {
+            return Ok(versions);
+        }
+
+        for entry in fs::read_dir(&self.data_dir)? {
+            let entry = entry?;
+            if entry.file_type()?.is_dir() {
+                if let Some(name) = entry.file_name().to_str() {
+                    // Installs in progress or interrupted are not versions yet
+                    if name.ends_with(Config::STAGING_SUFFIX)
+                        || name.ends_with(Config::REPLACED_SUFFIX)
+                    {
+                        continue;
+                    }
+                    // Installed with or without the 'v' prefix, as it was given
+                    if name.starts_with(|c: char| c == 'v' || c.is_ascii_digit()) {
+                        match Config::parse_version(name) {
+                            Ok(_) => versions.push(name.to_string()),
+                            Err(e) => warn!("Ignoring {}: {:#}", entry.path().display(), e),
+                        }
+                    }
+                }
+            }
+        }
+
+        Config::sort_versions(&mut versions);
+        Ok(versions)
+    }
+
+    /// Collects install details for every version in the data directory
+    pub fn list_versions(&self) -> Result<Vec<InstalledVersion>> {
+        let state = StateService::new(self.data_dir.clone()).load()?;
+        let mut details = Vec::new();
+        for version in self.installed_versions()? {
+            let path = self.data_dir.join(&version);
+            let installed_at = fs::metadata(&path)
+                .and_then(|m| m.modified())
+                .ok()
+                .map(DateTime::<Local>::from);
+            let size_bytes = Self::dir_size(&path)?;
+            let has_manifest = path.join("manifest.yaml").is_file();
+            let install_record = self.install_record(&version);
+            let mark = state.mark(&version);
+
+            details.push(InstalledVersion {
+                mark,
+                version,
+                path,
+                installed_at,
+                size_bytes,
+                has_manifest,
+                install_record,
+            });
+        }
+        Ok(details)
+    }
+
+    pub fn boot_marker(&self) -> Result<BootMarker> {
+        let path = self.data_dir.join(Config::BOOT_MARKER_FILE);
+        if !path.exists() {
+            return Ok(BootMarker::default());
+        }
+
+        let contents = fs::read_to_string(&path).context("Failed to read boot marker")?;
+        serde_json::from_str(&contents).context("Failed to parse boot marker")
+    }
+
+    pub fn save_boot_marker(&self, marker: &BootMarker) -> Result<()> {
+        let path = self.data_dir.join(Config::BOOT_MARKER_FILE);
+        let tmp_path = path.with_extension("json.tmp");
+        fs::write(&tmp_path, serde_json::to_string_pretty(marker)?)
+            .context("Failed to write boot marker")?;
+        fs::rename(&tmp_path, &path).context("Failed to replace boot marker")?;
+        Ok(())
+    }
+
+    /// Points the inactive slot at an installed version and marks it for a trial boot.
+    ///
+    /// When no slot holds a version yet there is nothing to fall back to, so the
+    /// version becomes active straight away. Returns whether a trial is pending.
+    #[tracing::instrument(skip(self))]
+    pub fn stage_in_inactive_slot(&self, version: &str) -> Result<bool> {
+        if !self.data_dir.join(version).is_dir() {
+            anyhow::bail!("Version {} is not installed", version);
+        }
+
+        let mut marker = self.boot_marker()?;
+        let trial = if marker.version(marker.active).is_none() {
+            marker.set_version(marker.active, version);
+            marker.trial = None;
+            info!("Installed {} into slot {}", version, marker.active);
+            false
+        } else if marker.version(marker.active).map(String::as_str) == Some(version) {
+            // Reinstalling the active version does not need a trial
+            marker.trial = None;
+            false
+        } else {
+            let inactive = marker.active.other();
+            marker.set_version(inactive, version);
+            marker.trial = Some(inactive);
+            info!("Staged {} in slot {} for a trial boot", version, inactive);
+            EventService::new(self.data_dir.clone()).record(
+                EventKind::Staged,
+                Some(version),
+                Some(format!("slot {}", inactive)),
+            );
+            true
+        };
+
+        self.save_boot_marker(&marker)?;
+        Ok(trial)
+    }
+
+    /// Makes the trial slot the active one once it reached a healthy state
+    pub fn commit_trial_slot(&self) -> Result<Option<String>> {
+        let mut marker = self.boot_marker()?;
+        let Some(trial) = marker.trial.take() else {
+            return Ok(None);
+        };
+
+        marker.active = trial;
+        self.save_boot_marker(&marker)?;
+        info!("Committed slot {} as active", trial);
+        Ok(marker.version(trial).cloned())
+    }
+
+    /// Makes an installed version the active slot without a trial, used for rollbacks.
+    /// A version already held by a slot is reused; otherwise it replaces the inactive slot.
+    pub fn activate_version(&self, version: &str) -> Result<()> {
+        if !self.data_dir.join(version).is_dir() {
+            anyhow::bail!("Version {} is not installed", version);
+        }
+
+        let mut marker = self.boot_marker()?;
+        let slot = [marker.active, marker.active.other()]
+            .into_iter()
+            .find(|slot| marker.version(*slot).map(String::as_str) == Some(version))
+            .unwrap_or_else(|| marker.active.other());
+
+        marker.set_version(slot, version);
+        marker.active = slot;
+        marker.trial = None;
+        self.save_boot_marker(&marker)?;
+        info!("Activated {} in slot {}", version, slot);
+        Ok(())
+    }
+
+    /// Abandons the trial slot and returns the version of the slot to fall back to
+    pub fn fail_trial_slot(&self) -> Result<Option<String>> {
+        let mut marker = self.boot_marker()?;
+        if let Some(trial) = marker.trial.take() {
+            tracing::warn!(
+                "Slot {} failed its trial boot, falling back to slot {}",
+                trial,
+                marker.active
+            );
+            self.save_boot_marker(&marker)?;
+        }
+        Ok(marker.version(marker.active).cloned())
+    }
+
+    /// Versions that `prune` would remove: everything but the newest `keep`
+    /// versions and the explicitly protected ones
+    pub fn prune_candidates(&self, keep: usize, protected: &[String]) -> Result<Vec<String>> {
+        let versions = self.installed_versions()?;
+        let retained = versions.len().saturating_sub(keep);
+
+        Ok(versions
+            .into_iter()
+            .take(retained)
+            .filter(|v| !protected.contains(v))
+            .collect())
+    }
+
+    /// Deletes an installed version directory
+    pub fn remove_version(&self, version: &str) -> Result<()> {
+        let version_dir = self.data_dir.join(version);
+        if !version_dir.is_dir() {
+            anyhow::bail!("Version {} is not installed", version);
+        }
+
+        info!("Removing version directory: {}", version_dir.display());
+        fs::remove_dir_all(&version_dir)
+            .with_context(|| format!("Failed to remove {}", version_dir.display()))?;
+        HashRecords::new(self.data_dir.clone()).remove(version);
+        Ok(())
+    }
+
+    /// Fails if writing `needed` more bytes would eat into the supervisor's disk reserve
+    pub fn ensure_disk_reserve(&self, needed: u64, what: &str) -> Result<()> {
+        let available = HostService::available_space(&self.data_dir)?;
+        let reserve = Config::reserved_disk_bytes();
+        if available < needed.saturating_add(reserve) {
+            anyhow::bail!(
+                "Not enough disk space for {}: need {} bytes plus a {} byte reserve, {} available. Run 'geist prune' to remove old versions and cached bundles, then try again",
+                what,
+                needed,
+                reserve,
+                available
+            );
+        }
+        Ok(())
+    }
+
+    /// Estimates the space an update to a release whose bundle is `bundle_size`
+    /// bytes needs. Releases are close in size, so the current install stands in
+    /// for both the extracted bundle and the new install, or the bundle itself
+    /// when there is none.
+    pub fn estimate_update_space(&self, bundle_size: u64) -> SpaceEstimate {
+        let current_dir = self.data_dir.join(self.current_version());
+        let unpacked = if current_dir.is_dir() {
+            Self::size_of(&current_dir).unwrap_or(bundle_size)
+        } else {
+            bundle_size
+        };
+        SpaceEstimate {
+            bundle: bundle_size,
+            extracted: unpacked,
+            install: unpacked,
+        }
+    }
+
+    /// Fails before anything is downloaded if an update to `version` would not fit:
+    /// the bundle and its extracted files go to `work_dir` and the installed copy to
+    /// the data directory, which also keeps its reserve free
+    pub fn ensure_update_space(
+        &self,
+        estimate: &SpaceEstimate,
+        work_dir: &Path,
+        version: &str,
+    ) -> Result<()> {
+        let reserve = Config::reserved_disk_bytes();
+        let mib = |bytes: u64| bytes.div_ceil(1024 * 1024);
+        let check = |dir: &Path, needed: u64, reserve: u64, parts: String| -> Result<()> {
+            let available = HostService::available_space(dir)?;
+            if available < needed.saturating_add(reserve) {
+                anyhow::bail!(
+                    "Not enough disk space to update to {}: it needs about {} MiB ({}) plus a {} MiB reserve on {}, where {} MiB is free. Run 'geist prune' to remove old versions and cached bundles, then try again",
+                    version,
+                    mib(needed),
+                    parts,
+                    mib(reserve),
+                    dir.display(),
+                    mib(available)
+                );
+            }
+            Ok(())
+        };
+
+        let download = format!(
+            "{} MiB bundle, {} MiB extracted",
+            mib(estimate.bundle),
+            mib(estimate.extracted)
+        );
+        let install = format!("{} MiB installed copy", mib(estimate.install));
+        if Self::same_filesystem(work_dir, &self.data_dir) {
+            return check(
+                &self.data_dir,
+                estimate.total(),
+                reserve,
+                format!("{}, {}", download, install),
+            );
+        }
+        check(work_dir, estimate.bundle + estimate.extracted, 0, download)?;
+        check(&self.data_dir, estimate.install, reserve, install)
+    }
+
+    // Whether two directories are on the same filesystem; assumed when unknown, as
+    // then all the space has to fit on one
+    fn same_filesystem(a: &Path, b: &Path) -> bool {
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::MetadataExt;
+            if let (Ok(a), Ok(b)) = (fs::metadata(a), fs::metadata(b)) {
+                return a.dev() == b.dev();
+            }
+        }
+        let _ = (a, b);
+        true
+    }
+
+    /// Total size in bytes of all files below a directory. Files hard-linked into
+    /// several versions count once.
+    pub fn size_of(dir: &Path) -> Result<u64> {
+        Self::dir_size(dir)
+    }
+
+    /// Files and symlinks below a directory, relative to it with `/` separators and
+    /// sorted, leaving out the install record geist writes into version directories
+    /// and the link to the shared app data directory.
+    /// Symlinks to directories are listed rather than followed.
+    pub fn relative_files(dir: &Path) -> Result<Vec<String>> {
+        fn collect(root: &Path, dir: &Path, files: &mut Vec<String>) -> Result<()> {
+            for entry in
+                fs::read_dir(dir).with_context(|| format!("Failed to read {}", dir.display()))?
+            {
+                let entry = entry?;
+                let path = entry.path();
+                if entry.file_type()?.is_dir() {
+                    collect(root, &path, files)?;
+                    continue;
+                }
+                files.push(
+                    path.strip_prefix(root)?
+                        .components()
+                        .map(|part| part.as_os_str().to_string_lossy())
+                        .collect::<Vec<_>>()
+                        .join("/"),
+                );
+            }
+            Ok(())
+        }
+
+        let mut files = Vec::new();
+        collect(dir, dir, &mut files)?;
+        files.retain(|path| path != Config::INSTALL_RECORD_FILE && path != Config::APP_DATA_DIR);
+        files.sort();
+        Ok(files)
+    }
+
+    /// SHA-256 of every file of an installed version, keyed by its relative path
+    pub fn file_hashes(dir: &Path) -> Result<BTreeMap<String, String>> {
+        Self::relative_files(dir)?
+            .into_iter()
+            .map(|path| {
+                let sha256 = Self::sha256_entry(&dir.join(&path))?;
+                Ok((path, sha256))
+            })
+            .collect()
+    }
+
+    // Hashes a file's contents, or for a symlink the path it points at, so links are
+    // tracked as links and a dangling one does not fail the hashing
+    fn sha256_entry(path: &Path) -> Result<String> {
+        let metadata = fs::symlink_metadata(path)
+            .with_context(|| format!("Failed to read {}", path.display()))?;
+        if !metadata.file_type().is_symlink() {
+            return Self::sha256_file(path);
+        }
+        let target = fs::read_link(path)
+            .with_context(|| format!("Failed to read the link {}", path.display()))?;
+        let mut hasher = Sha256::new();
+        hasher.update(target.to_string_lossy().as_bytes());
+        Ok(hex::encode(hasher.finalize()))
+    }
+
+    /// Hex encoded SHA-256 digest of a file
+    pub fn sha256_file(path: &Path) -> Result<String> {
+        let mut file =
+            fs::File::open(path).with_context(|| format!("Failed to open {}", path.display()))?;
+        let mut hasher = Sha256::new();
+        std::io::copy(&mut file, &mut hasher)?;
+        Ok(hex::encode(hasher.finalize()))
+    }
+
+    /// Copies a file and returns the hex SHA-256 of what was copied, without a
+    /// second read of either file
+    pub fn copy_with_sha256(from: &Path, to: &Path) -> Result<String> {
+        let mut source =
+            fs::File::open(from).with_context(|| format!("Failed to open {}", from.display()))?;
+        let mut writer = HashingWriter::new(
+            fs::File::create(to).with_context(|| format!("Failed to create {}", to.display()))?,
+        );
+        std::io::copy(&mut source, &mut writer)
+            .with_context(|| format!("Failed to copy {}", from.display()))?;
+        Ok(writer.finish())
+    }
+
+    // Total size of all files below a directory, without following symlinks and
+    // counting a file hard-linked more than once below it only once
+    fn dir_size(dir: &Path) -> Result<u64> {
+        fn walk(dir: &Path, seen: &mut HashSet<(u64, u64)>) -> Result<u64> {
+            let mut total = 0;
+            for entry in fs::read_dir(dir)? {
+                let entry = entry?;
+                let metadata = fs::symlink_metadata(entry.path())?;
+                if metadata.is_dir() {
+                    total += walk(&entry.path(), seen)?;
+                    continue;
+                }
+                #[cfg(unix)]
+                {
+                    use std::os::unix::fs::MetadataExt;
+                    if metadata.nlink() > 1 && !seen.insert((metadata.dev(), metadata.ino())) {
+                        continue;
+                    }
+                }
+                total += metadata.len();
+            }
+            Ok(total)
+        }
+
+        walk(dir, &mut HashSet::new())
+    }
+
     pub fn verify_permissions(&self) -> Result<()> {
         // Check if we have write permissions to data directory
         if !self.data_dir.exists() {
@@ -291,3 +1421,437 @@ impl FileService {
         Ok(())
     }
 }
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use std::os::unix::fs::{symlink, PermissionsExt};
+    use std::time::SystemTime;
+
+    fn mode(path: &Path) -> u32 {
+        fs::symlink_metadata(path).unwrap().permissions().mode() & 0o777
+    }
+
+    fn modified(path: &Path) -> SystemTime {
+        fs::metadata(path).unwrap().modified().unwrap()
+    }
+
+    fn set_modified(path: &Path, secs_ago: u64) {
+        let time = SystemTime::now() - Duration::from_secs(secs_ago);
+        fs::File::open(path)
+            .unwrap()
+            .set_times(fs::FileTimes::new().set_modified(time))
+            .unwrap();
+    }
+
+    #[test]
+    fn copy_dir_all_keeps_permissions() {
+        let src = tempfile::tempdir().unwrap();
+        let dst = tempfile::tempdir().unwrap();
+        fs::create_dir(src.path().join("bin")).unwrap();
+        fs::write(src.path().join("bin/tool"), "#!/bin/sh\n").unwrap();
+        fs::set_permissions(
+            src.path().join("bin/tool"),
+            fs::Permissions::from_mode(0o750),
+        )
+        .unwrap();
+        fs::write(src.path().join("config"), "key: value\n").unwrap();
+        fs::set_permissions(src.path().join("config"), fs::Permissions::from_mode(0o640)).unwrap();
+        fs::set_permissions(src.path().join("bin"), fs::Permissions::from_mode(0o710)).unwrap();
+
+        let copy = dst.path().join("app");
+        FileService::copy_dir_all(src.path(), &copy).unwrap();
+
+        assert_eq!(mode(&copy.join("bin/tool")), 0o750);
+        assert_eq!(mode(&copy.join("config")), 0o640);
+        assert_eq!(mode(&copy.join("bin")), 0o710);
+    }
+
+    #[test]
+    fn copy_dir_all_keeps_modification_times() {
+        let src = tempfile::tempdir().unwrap();
+        let dst = tempfile::tempdir().unwrap();
+        fs::create_dir_all(src.path().join("a/b")).unwrap();
+        fs::write(src.path().join("a/b/data"), "data").unwrap();
+        set_modified(&src.path().join("a/b/data"), 3600);
+        set_modified(&src.path().join("a/b"), 7200);
+        set_modified(&src.path().join("a"), 10800);
+
+        let copy = dst.path().join("app");
+        FileService::copy_dir_all(src.path(), &copy).unwrap();
+
+        for path in ["a/b/data", "a/b", "a"] {
+            assert_eq!(
+                modified(&copy.join(path)),
+                modified(&src.path().join(path)),
+                "{}",
+                path
+            );
+        }
+    }
+
+    #[test]
+    fn copy_dir_all_recreates_relative_symlinks() {
+        let src = tempfile::tempdir().unwrap();
+        let dst = tempfile::tempdir().unwrap();
+        fs::create_dir(src.path().join("lib")).unwrap();
+        fs::write(src.path().join("lib/libapp.so.1"), "elf").unwrap();
+        symlink("libapp.so.1", src.path().join("lib/libapp.so")).unwrap();
+
+        let copy = dst.path().join("app");
+        FileService::copy_dir_all(src.path(), &copy).unwrap();
+
+        let link = copy.join("lib/libapp.so");
+        assert!(fs::symlink_metadata(&link)
+            .unwrap()
+            .file_type()
+            .is_symlink());
+        assert_eq!(fs::read_link(&link).unwrap(), Path::new("libapp.so.1"));
+        assert_eq!(fs::read_to_string(&link).unwrap(), "elf");
+    }
+
+    #[test]
+    fn copy_dir_all_rewrites_absolute_symlinks_into_the_tree() {
+        let src = tempfile::tempdir().unwrap();
+        let dst = tempfile::tempdir().unwrap();
+        fs::create_dir_all(src.path().join("data/fonts")).unwrap();
+        fs::write(src.path().join("data/fonts/font.ttf"), "font").unwrap();
+        fs::create_dir(src.path().join("assets")).unwrap();
+        symlink(
+            src.path().join("data/fonts/font.ttf"),
+            src.path().join("assets/font.ttf"),
+        )
+        .unwrap();
+        symlink("/etc/hostname", src.path().join("hostname")).unwrap();
+
+        let copy = dst.path().join("app");
+        FileService::copy_dir_all(src.path(), &copy).unwrap();
+
+        assert_eq!(
+            fs::read_link(copy.join("assets/font.ttf")).unwrap(),
+            Path::new("../data/fonts/font.ttf")
+        );
+        assert_eq!(
+            fs::read_to_string(copy.join("assets/font.ttf")).unwrap(),
+            "font"
+        );
+        // Links out of the tree are left pointing where they did
+        assert_eq!(
+            fs::read_link(copy.join("hostname")).unwrap(),
+            Path::new("/etc/hostname")
+        );
+    }
+
+    #[test]
+    fn copy_dir_all_copies_dangling_symlinks() {
+        let src = tempfile::tempdir().unwrap();
+        let dst = tempfile::tempdir().unwrap();
+        symlink("missing", src.path().join("dangling")).unwrap();
+
+        let copy = dst.path().join("app");
+        FileService::copy_dir_all(src.path(), &copy).unwrap();
+
+        assert_eq!(
+            fs::read_link(copy.join("dangling")).unwrap(),
+            Path::new("missing")
+        );
+        assert!(!copy.join("dangling").exists());
+    }
+
+    #[test]
+    fn copy_dir_all_does_not_follow_directory_symlinks() {
+        let src = tempfile::tempdir().unwrap();
+        let dst = tempfile::tempdir().unwrap();
+        fs::create_dir(src.path().join("real")).unwrap();
+        fs::write(src.path().join("real/file"), "file").unwrap();
+        symlink("real", src.path().join("alias")).unwrap();
+        // A link back up the tree would make a following copy recurse forever
+        symlink("..", src.path().join("real/parent")).unwrap();
+
+        let copy = dst.path().join("app");
+        FileService::copy_dir_all(src.path(), &copy).unwrap();
+
+        let alias = fs::symlink_metadata(copy.join("alias")).unwrap();
+        assert!(alias.file_type().is_symlink());
+        assert_eq!(fs::read_to_string(copy.join("alias/file")).unwrap(), "file");
+        assert!(fs::symlink_metadata(copy.join("real/parent"))
+            .unwrap()
+            .file_type()
+            .is_symlink());
+    }
+
+    #[test]
+    fn file_hashes_track_symlinks_as_links() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::create_dir(dir.path().join("real")).unwrap();
+        fs::write(dir.path().join("real/file"), "file").unwrap();
+        symlink("real", dir.path().join("alias")).unwrap();
+        symlink("missing", dir.path().join("dangling")).unwrap();
+
+        let hashes = FileService::file_hashes(dir.path()).unwrap();
+
+        assert_eq!(
+            hashes.keys().collect::<Vec<_>>(),
+            ["alias", "dangling", "real/file"]
+        );
+        fs::remove_file(dir.path().join("alias")).unwrap();
+        symlink("elsewhere", dir.path().join("alias")).unwrap();
+        assert_ne!(
+            FileService::file_hashes(dir.path()).unwrap()["alias"],
+            hashes["alias"]
+        );
+    }
+
+    #[test]
+    fn install_version_keeps_file_metadata() {
+        let release = tempfile::tempdir().unwrap();
+        let data = tempfile::tempdir().unwrap();
+        fs::write(release.path().join("roc_camera"), "binary").unwrap();
+        fs::set_permissions(
+            release.path().join("roc_camera"),
+            fs::Permissions::from_mode(0o755),
+        )
+        .unwrap();
+        set_modified(&release.path().join("roc_camera"), 3600);
+        fs::write(release.path().join("manifest.yaml"), "version: 1.0.0\n").unwrap();
+        fs::create_dir(release.path().join("roc_camera_app")).unwrap();
+        fs::write(release.path().join("roc_camera_app/run.sh"), "#!/bin/sh\n").unwrap();
+        fs::set_permissions(
+            release.path().join("roc_camera_app/run.sh"),
+            fs::Permissions::from_mode(0o700),
+        )
+        .unwrap();
+        symlink("run.sh", release.path().join("roc_camera_app/start")).unwrap();
+
+        let service = FileService::new(data.path().to_path_buf());
+        service.install_version(release.path(), "1.0.0").unwrap();
+
+        let installed = data.path().join("1.0.0");
+        assert_eq!(mode(&installed.join("roc_camera")), 0o755);
+        assert_eq!(
+            modified(&installed.join("roc_camera")),
+            modified(&release.path().join("roc_camera"))
+        );
+        assert_eq!(mode(&installed.join("roc_camera_app/run.sh")), 0o700);
+        assert_eq!(
+            fs::read_link(installed.join("roc_camera_app/start")).unwrap(),
+            Path::new("run.sh")
+        );
+    }
+
+    // A release with an app tree whose files are laid out by `build`
+    fn app_release(build: impl FnOnce(&Path)) -> tempfile::TempDir {
+        let release = tempfile::tempdir().unwrap();
+        fs::write(release.path().join("roc_camera"), "binary").unwrap();
+        fs::write(release.path().join("manifest.yaml"), "version: 1.0.0\n").unwrap();
+        fs::create_dir(release.path().join("roc_camera_app")).unwrap();
+        build(&release.path().join("roc_camera_app"));
+        release
+    }
+
+    #[test]
+    fn install_version_keeps_executable_bits_in_the_app_tree() {
+        let release = app_release(|app| {
+            fs::create_dir(app.join("bin")).unwrap();
+            fs::write(app.join("bin/helper"), "#!/bin/sh\n").unwrap();
+            fs::set_permissions(app.join("bin/helper"), fs::Permissions::from_mode(0o755)).unwrap();
+            fs::write(app.join("bin/owner_only"), "#!/bin/sh\n").unwrap();
+            fs::set_permissions(
+                app.join("bin/owner_only"),
+                fs::Permissions::from_mode(0o700),
+            )
+            .unwrap();
+            fs::write(app.join("settings.yaml"), "key: value\n").unwrap();
+            fs::set_permissions(app.join("settings.yaml"), fs::Permissions::from_mode(0o644))
+                .unwrap();
+        });
+        let data = tempfile::tempdir().unwrap();
+
+        let service = FileService::new(data.path().to_path_buf());
+        service.install_version(release.path(), "1.0.0").unwrap();
+
+        let app = data.path().join("1.0.0/roc_camera_app");
+        assert_eq!(mode(&app.join("bin/helper")), 0o755);
+        assert_eq!(mode(&app.join("bin/owner_only")), 0o700);
+        assert_eq!(mode(&app.join("settings.yaml")), 0o644);
+    }
+
+    #[test]
+    fn install_version_recreates_symlinks_in_the_app_tree() {
+        let release = app_release(|app| {
+            fs::create_dir_all(app.join("lib/models")).unwrap();
+            fs::write(app.join("lib/libvision.so.2"), "elf").unwrap();
+            fs::write(app.join("lib/models/detector.onnx"), "model").unwrap();
+            symlink("libvision.so.2", app.join("lib/libvision.so")).unwrap();
+            symlink("lib/models", app.join("models")).unwrap();
+            symlink(
+                app.join("lib/models/detector.onnx"),
+                app.join("detector.onnx"),
+            )
+            .unwrap();
+        });
+        let data = tempfile::tempdir().unwrap();
+
+        let service = FileService::new(data.path().to_path_buf());
+        service.install_version(release.path(), "1.0.0").unwrap();
+        // The links must resolve inside the installed version, not the release
+        drop(release);
+
+        let app = data.path().join("1.0.0/roc_camera_app");
+        let is_symlink = |path: &str| {
+            fs::symlink_metadata(app.join(path))
+                .unwrap()
+                .file_type()
+                .is_symlink()
+        };
+        assert!(is_symlink("lib/libvision.so"));
+        assert!(is_symlink("models"));
+        assert!(is_symlink("detector.onnx"));
+        assert_eq!(
+            fs::read_link(app.join("lib/libvision.so")).unwrap(),
+            Path::new("libvision.so.2")
+        );
+        assert_eq!(
+            fs::read_link(app.join("detector.onnx")).unwrap(),
+            Path::new("lib/models/detector.onnx")
+        );
+        assert_eq!(
+            fs::read_to_string(app.join("lib/libvision.so")).unwrap(),
+            "elf"
+        );
+        assert_eq!(
+            fs::read_to_string(app.join("models/detector.onnx")).unwrap(),
+            "model"
+        );
+        assert_eq!(
+            fs::read_to_string(app.join("detector.onnx")).unwrap(),
+            "model"
+        );
+    }
+
+    #[test]
+    fn install_version_keeps_modification_times_in_the_app_tree() {
+        let release = app_release(|app| {
+            fs::create_dir(app.join("fonts")).unwrap();
+            fs::write(app.join("fonts/font.ttf"), "font").unwrap();
+            set_modified(&app.join("fonts/font.ttf"), 86400);
+            set_modified(&app.join("fonts"), 7200);
+            set_modified(app, 3600);
+        });
+        set_modified(&release.path().join("roc_camera"), 600);
+        let data = tempfile::tempdir().unwrap();
+
+        let service = FileService::new(data.path().to_path_buf());
+        service.install_version(release.path(), "1.0.0").unwrap();
+
+        let installed = data.path().join("1.0.0");
+        for path in [
+            "roc_camera",
+            "roc_camera_app",
+            "roc_camera_app/fonts",
+            "roc_camera_app/fonts/font.ttf",
+        ] {
+            assert_eq!(
+                modified(&installed.join(path)),
+                modified(&release.path().join(path)),
+                "{}",
+                path
+            );
+        }
+    }
+
+    #[test]
+    fn install_version_installs_the_build_for_the_host() {
+        let release = tempfile::tempdir().unwrap();
+        let data = tempfile::tempdir().unwrap();
+        let host = BinaryArch::host().unwrap();
+        let other = match host {
+            BinaryArch::Riscv64 => BinaryArch::X86_64,
+            _ => BinaryArch::Riscv64,
+        };
+        for arch in [host, other] {
+            fs::write(
+                release.path().join(format!("roc_camera-{}", arch)),
+                arch.name(),
+            )
+            .unwrap();
+        }
+        fs::write(
+            release.path().join("manifest.yaml"),
+            format!(
+                "version: 1.0.0\nartifacts:\n  {0}: roc_camera-{0}\n  {1}: roc_camera-{1}\n",
+                host, other
+            ),
+        )
+        .unwrap();
+        fs::create_dir(release.path().join("roc_camera_app")).unwrap();
+
+        let service = FileService::new(data.path().to_path_buf());
+        service.install_version(release.path(), "1.0.0").unwrap();
+        assert_eq!(
+            fs::read_to_string(data.path().join("1.0.0/roc_camera")).unwrap(),
+            host.name()
+        );
+        assert!(!data
+            .path()
+            .join(format!("1.0.0/roc_camera-{}", other))
+            .exists());
+
+        fs::write(
+            release.path().join("manifest.yaml"),
+            format!("version: 1.1.0\nartifacts:\n  {0}: roc_camera-{0}\n", other),
+        )
+        .unwrap();
+        let error = service
+            .install_version(release.path(), "1.1.0")
+            .unwrap_err();
+        assert!(error.to_string().contains("no build of roc_camera"));
+        assert!(!data.path().join("1.1.0").exists());
+    }
+
+    #[test]
+    fn install_version_links_unchanged_files() {
+        use std::os::unix::fs::MetadataExt;
+
+        let data = tempfile::tempdir().unwrap();
+        let service = FileService::new(data.path().to_path_buf());
+        let release = |binary: &str, asset_mode: u32| {
+            let dir = tempfile::tempdir().unwrap();
+            fs::write(dir.path().join("roc_camera"), binary).unwrap();
+            fs::write(dir.path().join("manifest.yaml"), "name: app\n").unwrap();
+            fs::create_dir_all(dir.path().join("roc_camera_app/fonts")).unwrap();
+            fs::write(dir.path().join("roc_camera_app/fonts/font.ttf"), "font").unwrap();
+            fs::write(dir.path().join("roc_camera_app/config"), "config").unwrap();
+            fs::set_permissions(
+                dir.path().join("roc_camera_app/config"),
+                fs::Permissions::from_mode(asset_mode),
+            )
+            .unwrap();
+            dir
+        };
+        let first = release("binary 1", 0o644);
+        service.install_version(first.path(), "v1.0.0").unwrap();
+        let second = release("binary 2", 0o600);
+        service.install_version(second.path(), "v1.1.0").unwrap();
+
+        let inode = |version: &str, path: &str| {
+            fs::metadata(data.path().join(version).join(path))
+                .unwrap()
+                .ino()
+        };
+        let shared = |path: &str| inode("v1.0.0", path) == inode("v1.1.0", path);
+        assert!(shared("roc_camera_app/fonts/font.ttf"));
+        assert!(shared("manifest.yaml"));
+        assert!(!shared("roc_camera"));
+        // Same content, but a link would give both versions the same permissions
+        assert!(!shared("roc_camera_app/config"));
+        assert_eq!(
+            fs::read_to_string(data.path().join("v1.1.0/roc_camera")).unwrap(),
+            "binary 2"
+        );
+
+        let one = FileService::size_of(&data.path().join("v1.0.0")).unwrap();
+        let both = FileService::size_of(data.path()).unwrap();
+        assert_eq!(both, one + "binary 2".len() as u64 + "config".len() as u64);
+    }
+}
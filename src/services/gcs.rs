@@ -1,156 +1,340 @@
 use crate::config::Config;
+use crate::services::registry::ReleaseInfo;
+use crate::services::{
+    download, http, BundleFormat, ConditionalCache, GcsAuth, GcsCredentials, RegistryBackend,
+};
 use anyhow::{Context, Result};
+use chrono::{DateTime, Utc};
 use reqwest::{
-    blocking::Client,
+    blocking::{Client, RequestBuilder},
     header::{HeaderMap, HeaderValue, AUTHORIZATION},
+    Method,
 };
-use std::fs;
-use std::path::Path;
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
 
+/// Releases stored as `releases/<version>/<file>` in a bucket served over HTTP
 pub struct GcsService {
     client: Client,
-    token: String,
+    auth: GcsAuth,
     registry_path: String,
+    answers: ConditionalCache,
 }
 
 impl GcsService {
-    pub fn new(token: String, registry_path: String) -> Self {
-        Self {
-            client: Client::new(),
-            token,
+    /// `data_dir` keeps the answers revalidated on the next request
+    pub fn new(
+        credentials: GcsCredentials,
+        registry_path: String,
+        data_dir: PathBuf,
+    ) -> Result<Self> {
+        Ok(Self {
+            client: http::client()?,
+            auth: GcsAuth::new(credentials)?,
             registry_path,
-        }
+            answers: ConditionalCache::new(data_dir),
+        })
     }
 
-    pub fn download_binary(&self, version: &str, output_path: &Path) -> Result<()> {
-        let normalized_version = Config::normalize_version(version);
-        let url = format!(
-            "{}/releases/{}/{}",
-            self.registry_path,
-            normalized_version,
-            Config::RELEASE_BUNDLE_NAME
-        );
-
-        let mut request = self.client.get(&url);
+    fn request(&self, method: Method, url: &str) -> Result<RequestBuilder> {
+        let mut request = http::bounded(self.client.request(method, url));
 
-        // Only add authorization if token is not empty
-        if !self.token.is_empty() {
+        // Public buckets are read without a token
+        if let Some(token) = self.auth.token()? {
             let mut headers = HeaderMap::new();
             headers.insert(
                 AUTHORIZATION,
-                HeaderValue::from_str(&format!("Bearer {}", self.token))?,
+                HeaderValue::from_str(&format!("Bearer {}", token))?,
             );
             request = request.headers(headers);
         }
+        Ok(request)
+    }
 
-        let response = request.send().context("Failed to download binary")?;
+    fn url(&self, version: &str, file_name: &str) -> String {
+        format!(
+            "{}/releases/{}/{}",
+            self.registry_path,
+            Config::normalize_version(version),
+            file_name
+        )
+    }
 
+    // GETs a small text file, None on 404
+    fn fetch_text(&self, url: &str, file_name: &str) -> Result<Option<String>> {
+        let response = http::send(self.request(Method::GET, url)?)?;
+
+        if response.status() == reqwest::StatusCode::NOT_FOUND {
+            return Ok(None);
+        }
         if !response.status().is_success() {
-            anyhow::bail!("Failed to download binary: HTTP {}", response.status());
+            anyhow::bail!("HTTP {}", response.status());
         }
 
-        let content = response
-            .bytes()
-            .context("Failed to read response content")?;
-
-        fs::write(output_path, content).context("Failed to save binary")?;
-
-        Ok(())
+        let text = response
+            .text()
+            .with_context(|| format!("Failed to read {} from response", file_name))?;
+        Ok(Some(text))
     }
 
-    pub fn verify_version(&self, version: &str) -> Result<bool> {
-        let normalized_version = Config::normalize_version(version);
-        let url = format!(
-            "{}/releases/{}/{}",
-            self.registry_path,
-            normalized_version,
-            Config::CHECKSUM_FILE_NAME
-        );
+    /// Versions with files under `releases/` in the bucket, oldest first by semver
+    pub fn list_versions(&self) -> Result<Vec<String>> {
+        let mut versions: Vec<String> = self
+            .list_release_objects()?
+            .into_iter()
+            .map(|object| object.version)
+            .collect();
+        Config::sort_versions(&mut versions);
+        versions.dedup();
+        Ok(versions)
+    }
 
-        let mut request = self.client.head(&url);
+    // Lists `releases/<version>/<file>` objects through the XML API, following the
+    // listing across pages
+    fn list_release_objects(&self) -> Result<Vec<ReleaseObject>> {
+        let mut objects = Vec::new();
+        let mut marker: Option<String> = None;
+        loop {
+            let mut query = vec![("prefix", "releases/".to_string())];
+            if let Some(marker) = &marker {
+                query.push(("marker", marker.clone()));
+            }
+            let response = http::send(
+                self.request(Method::GET, &self.registry_path)?
+                    .query(&query),
+            )
+            .context("Failed to list the bucket")?;
+            if !response.status().is_success() {
+                anyhow::bail!("Failed to list the bucket: HTTP {}", response.status());
+            }
+            let body = response
+                .text()
+                .context("Failed to read the bucket listing")?;
+            let page = ListingPage::parse(&body)?;
+
+            objects.extend(page.objects.iter().filter_map(ReleaseObject::from_listing));
+            match page.next_marker {
+                Some(next) if page.truncated => marker = Some(next),
+                _ => break,
+            }
+        }
+        Ok(objects)
+    }
 
-        // Only add authorization if token is not empty
-        if !self.token.is_empty() {
-            let mut headers = HeaderMap::new();
-            headers.insert(
-                AUTHORIZATION,
-                HeaderValue::from_str(&format!("Bearer {}", self.token))?,
-            );
-            request = request.headers(headers);
+    /// Uploads a file as `object` below the bucket URL
+    pub fn upload_object(&self, object: &str, path: &Path) -> Result<()> {
+        let url = format!("{}/{}", self.registry_path, object);
+        let file = std::fs::File::open(path)
+            .with_context(|| format!("Failed to open {}", path.display()))?;
+        let response = http::send(
+            self.request(Method::PUT, &url)?
+                .header(reqwest::header::CONTENT_TYPE, "application/octet-stream")
+                .body(file),
+        )
+        .with_context(|| format!("Failed to upload {}", object))?;
+        if !response.status().is_success() {
+            anyhow::bail!("Failed to upload {}: HTTP {}", object, response.status());
         }
+        Ok(())
+    }
 
-        let response = request.send().context("Failed to verify version")?;
+    /// Downloads `object` below the bucket URL, returning the hex SHA-256 of what
+    /// was written
+    pub fn download_object(&self, object: &str, output_path: &Path) -> Result<String> {
+        let url = format!("{}/{}", self.registry_path, object);
+        download::download_to_file(&self.client, self.request(Method::GET, &url)?, output_path)
+            .with_context(|| format!("Failed to download {}", object))
+    }
 
-        Ok(response.status().is_success())
+    pub fn download_binary(&self, version: &str, output_path: &Path) -> Result<()> {
+        self.download(version, Config::RELEASE_BUNDLE_NAME, output_path)
+            .context("Failed to download binary")?;
+        Ok(())
     }
+}
 
-    pub fn get_latest_version(&self) -> Result<String> {
-        let url = format!("{}/releases/latest", self.registry_path);
+// An object of a release as listed by the bucket
+struct ReleaseObject {
+    version: String,
+    file_name: String,
+    size: Option<u64>,
+    updated: Option<DateTime<Utc>>,
+}
 
-        let mut request = self.client.get(&url);
+impl ReleaseObject {
+    fn from_listing(entry: &ListedObject) -> Option<Self> {
+        let mut parts = entry.key.strip_prefix("releases/")?.splitn(2, '/');
+        let version = parts.next().filter(|v| !v.is_empty())?;
+        let file_name = parts.next().filter(|f| !f.is_empty())?;
+        Some(Self {
+            version: version.to_string(),
+            file_name: file_name.to_string(),
+            size: entry.size,
+            updated: entry.updated,
+        })
+    }
+}
 
-        // Only add authorization if token is not empty
-        if !self.token.is_empty() {
-            let mut headers = HeaderMap::new();
-            headers.insert(
-                AUTHORIZATION,
-                HeaderValue::from_str(&format!("Bearer {}", self.token))?,
-            );
-            request = request.headers(headers);
-        }
+struct ListedObject {
+    key: String,
+    size: Option<u64>,
+    updated: Option<DateTime<Utc>>,
+}
 
-        let response = request.send().context("Failed to fetch latest version")?;
+// One page of a ListBucketResult
+struct ListingPage {
+    objects: Vec<ListedObject>,
+    truncated: bool,
+    next_marker: Option<String>,
+}
 
-        if !response.status().is_success() {
-            anyhow::bail!("Failed to fetch latest version: HTTP {}", response.status());
+impl ListingPage {
+    fn parse(xml: &str) -> Result<Self> {
+        let document = roxmltree::Document::parse(xml).context("Malformed bucket listing")?;
+        let root = document.root_element();
+        let text = |node: roxmltree::Node, name: &str| {
+            node.children()
+                .find(|child| child.has_tag_name(name))
+                .and_then(|child| child.text())
+                .map(str::to_string)
+        };
+
+        let mut objects = Vec::new();
+        for contents in root.children().filter(|c| c.has_tag_name("Contents")) {
+            let Some(key) = text(contents, "Key") else {
+                continue;
+            };
+            objects.push(ListedObject {
+                size: text(contents, "Size").and_then(|s| s.parse().ok()),
+                updated: text(contents, "LastModified")
+                    .and_then(|at| DateTime::parse_from_rfc3339(&at).ok())
+                    .map(|at| at.with_timezone(&Utc)),
+                key,
+            });
         }
 
-        let version = response
-            .text()
-            .context("Failed to read latest version from response")?;
+        let truncated = text(root, "IsTruncated").as_deref() == Some("true");
+        // Without a NextMarker the last key of a page is where the next page starts
+        let next_marker =
+            text(root, "NextMarker").or_else(|| objects.last().map(|o| o.key.clone()));
+        Ok(Self {
+            objects,
+            truncated,
+            next_marker,
+        })
+    }
+}
 
-        Ok(version.trim().to_string())
+impl RegistryBackend for GcsService {
+    fn name(&self) -> &'static str {
+        "gcs"
     }
 
-    pub fn download_release_bundle(&self, version: &str, output_path: &Path) -> Result<()> {
-        let normalized_version = Config::normalize_version(version);
+    #[tracing::instrument(skip(self))]
+    fn verify_version(&self, version: &str) -> Result<bool> {
+        let url = self.url(version, &Config::checksum_file_name());
+        let response =
+            http::send(self.request(Method::HEAD, &url)?).context("Failed to verify version")?;
 
-        let url = format!(
-            "{}/releases/{}/release_bundle-{}.tar.gz",
-            self.registry_path, normalized_version, version
-        );
+        // Only a 404 says the version does not exist; other errors say nothing about it
+        let status = response.status();
+        if status == reqwest::StatusCode::NOT_FOUND {
+            return Ok(false);
+        }
+        if !status.is_success() {
+            anyhow::bail!("Failed to verify version: HTTP {}", status);
+        }
+        Ok(true)
+    }
 
-        tracing::debug!("Attempting to download from URL: {}", url);
+    #[tracing::instrument(skip(self))]
+    fn latest(&self) -> Result<String> {
+        let url = format!("{}/releases/latest", self.registry_path);
+        let answer = self
+            .answers
+            .get(self.request(Method::GET, &url)?)
+            .context("Failed to fetch latest version")?;
 
-        let mut request = self.client.get(&url);
+        if !answer.status.is_success() {
+            anyhow::bail!("Failed to fetch latest version: HTTP {}", answer.status);
+        }
 
-        // Only add authorization if token is not empty
-        if !self.token.is_empty() {
-            let mut headers = HeaderMap::new();
-            headers.insert(
-                AUTHORIZATION,
-                HeaderValue::from_str(&format!("Bearer {}", self.token))?,
-            );
-            request = request.headers(headers);
+        let version = answer.body.trim();
+        if version.is_empty() || version == Config::DEFAULT_VERSION {
+            anyhow::bail!("{}/releases/latest names no version", self.registry_path);
         }
+        Ok(version.to_string())
+    }
+
+    fn list(&self) -> Result<Vec<ReleaseInfo>> {
+        let mut releases: BTreeMap<String, ReleaseInfo> = BTreeMap::new();
+        let mut formats: BTreeMap<String, BundleFormat> = BTreeMap::new();
+        for object in self.list_release_objects()? {
+            let release = releases
+                .entry(object.version.clone())
+                .or_insert_with(|| ReleaseInfo {
+                    version: object.version.clone(),
+                    published_at: None,
+                    bundle_size: None,
+                });
+            // The bundle is uploaded last, so it dates the release; of several
+            // formats the one `update` would download is reported
+            let Some(format) = BundleFormat::PREFERRED
+                .into_iter()
+                .find(|format| object.file_name == format.file_name(&object.version))
+            else {
+                continue;
+            };
+            if formats
+                .get(&object.version)
+                .is_none_or(|known| format < *known)
+            {
+                formats.insert(object.version.clone(), format);
+                release.published_at = object.updated;
+                release.bundle_size = object.size;
+            }
+        }
+        Ok(releases.into_values().collect())
+    }
+
+    fn artifact_url(&self, version: &str, file_name: &str) -> Result<String> {
+        Ok(self.url(version, file_name))
+    }
 
-        let response = request
-            .send()
-            .context("Failed to download release bundle")?;
+    #[tracing::instrument(skip(self))]
+    fn artifact_size(&self, version: &str, file_name: &str) -> Result<Option<u64>> {
+        let response = http::send(self.request(Method::HEAD, &self.url(version, file_name))?)?;
 
         if !response.status().is_success() {
-            anyhow::bail!(
-                "Failed to download release bundle: HTTP {}",
-                response.status()
-            );
+            anyhow::bail!("HTTP {}", response.status());
         }
 
-        let content = response
-            .bytes()
-            .context("Failed to read response content")?;
+        Ok(response
+            .headers()
+            .get(reqwest::header::CONTENT_LENGTH)
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| v.parse().ok()))
+    }
 
-        fs::write(output_path, content).context("Failed to save release bundle")?;
+    #[tracing::instrument(skip(self, output_path))]
+    fn download(&self, version: &str, file_name: &str, output_path: &Path) -> Result<String> {
+        let url = self.url(version, file_name);
+        tracing::debug!("Attempting to download from URL: {}", url);
 
-        Ok(())
+        download::download_to_file(&self.client, self.request(Method::GET, &url)?, output_path)
+            .with_context(|| format!("Failed to download {}", file_name))
+    }
+
+    #[tracing::instrument(skip(self))]
+    fn fetch(&self, version: &str, file_name: &str) -> Result<Option<String>> {
+        self.fetch_text(&self.url(version, file_name), file_name)
+    }
+
+    #[tracing::instrument(skip(self))]
+    fn fetch_metadata(&self, file_name: &str) -> Result<Option<String>> {
+        self.fetch_text(
+            &format!("{}/releases/{}", self.registry_path, file_name),
+            file_name,
+        )
     }
 }
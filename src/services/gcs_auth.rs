@@ -0,0 +1,228 @@
+use crate::config::Config;
+use crate::services::{http, TokenStore};
+use anyhow::{Context, Result};
+use jsonwebtoken::{Algorithm, EncodingKey, Header};
+use reqwest::blocking::Client;
+use serde::{Deserialize, Serialize};
+use std::cell::RefCell;
+use std::fs;
+use std::path::Path;
+use std::time::{Duration, Instant};
+use tracing::{debug, info};
+
+// Tokens are renewed this long before they expire so a request never carries a stale one
+const REFRESH_MARGIN: Duration = Duration::from_secs(60);
+
+// Lifetime requested for service-account assertions; Google allows at most an hour
+const ASSERTION_LIFETIME_SECS: i64 = 3600;
+
+/// How requests to a GCS bucket authenticate
+#[derive(Debug, Clone, Default)]
+pub enum GcsCredentials {
+    /// Public bucket; requests carry no token
+    #[default]
+    Anonymous,
+    /// A token obtained elsewhere, used as is
+    Token(String),
+    /// JSON key of a service account, exchanged for tokens with a signed assertion
+    ServiceAccount(ServiceAccountKey),
+    /// User credentials from `gcloud auth application-default login`
+    AuthorizedUser(AuthorizedUser),
+}
+
+/// The fields of a service-account key file the token exchange needs
+#[derive(Debug, Clone, Deserialize)]
+pub struct ServiceAccountKey {
+    pub client_email: String,
+    pub private_key: String,
+    #[serde(default)]
+    pub private_key_id: Option<String>,
+    #[serde(default = "default_token_uri")]
+    pub token_uri: String,
+}
+
+/// The fields of an application default credentials file written by gcloud
+#[derive(Debug, Clone, Deserialize)]
+pub struct AuthorizedUser {
+    pub client_id: String,
+    pub client_secret: String,
+    pub refresh_token: String,
+    #[serde(default = "default_token_uri")]
+    pub token_uri: String,
+}
+
+fn default_token_uri() -> String {
+    Config::GCS_TOKEN_URI.to_string()
+}
+
+// Credential files carry their kind in a `type` field
+#[derive(Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum CredentialsFile {
+    ServiceAccount(ServiceAccountKey),
+    AuthorizedUser(AuthorizedUser),
+}
+
+#[derive(Serialize)]
+struct Claims<'a> {
+    iss: &'a str,
+    scope: &'a str,
+    aud: &'a str,
+    iat: i64,
+    exp: i64,
+}
+
+#[derive(Deserialize)]
+struct TokenResponse {
+    access_token: String,
+    expires_in: u64,
+}
+
+impl GcsCredentials {
+    /// Application default credentials: the key file named by
+    /// GOOGLE_APPLICATION_CREDENTIALS, otherwise the credentials saved by
+    /// `geist auth login gcs`, otherwise gcloud's application default credentials
+    /// if present, otherwise anonymous access
+    pub fn application_default(data_dir: &Path) -> Result<Self> {
+        if let Some(path) = Config::gcs_credentials_file() {
+            return Self::from_file(&path);
+        }
+        if let Some(saved) = TokenStore::new(data_dir.to_path_buf()).token("gcs")? {
+            return Ok(Self::from_saved(saved));
+        }
+        match Config::gcloud_adc_file() {
+            Some(path) if path.is_file() => Self::from_file(&path),
+            _ => Ok(Self::Anonymous),
+        }
+    }
+
+    // `auth login gcs` takes the contents of a credentials file or a bare access token
+    fn from_saved(saved: String) -> Self {
+        info!("Using the GCS credentials saved by 'geist auth login'");
+        match serde_json::from_str(&saved) {
+            Ok(CredentialsFile::ServiceAccount(key)) => Self::ServiceAccount(key),
+            Ok(CredentialsFile::AuthorizedUser(user)) => Self::AuthorizedUser(user),
+            Err(_) => Self::Token(saved.trim().to_string()),
+        }
+    }
+
+    /// Reads a service-account key or authorized-user credentials file
+    pub fn from_file(path: &Path) -> Result<Self> {
+        let contents = fs::read_to_string(path)
+            .with_context(|| format!("Failed to read GCS credentials {}", path.display()))?;
+        let file: CredentialsFile = serde_json::from_str(&contents).with_context(|| {
+            format!(
+                "{} is neither a service-account key nor authorized-user credentials",
+                path.display()
+            )
+        })?;
+        info!("Using GCS credentials from {}", path.display());
+        Ok(match file {
+            CredentialsFile::ServiceAccount(key) => Self::ServiceAccount(key),
+            CredentialsFile::AuthorizedUser(user) => Self::AuthorizedUser(user),
+        })
+    }
+}
+
+/// Hands out OAuth access tokens for GCS requests, fetching a new one when the
+/// current one is about to expire
+pub struct GcsAuth {
+    client: Client,
+    credentials: GcsCredentials,
+    cached: RefCell<Option<(String, Instant)>>,
+}
+
+impl GcsAuth {
+    pub fn new(credentials: GcsCredentials) -> Result<Self> {
+        Ok(Self {
+            client: http::client()?,
+            credentials,
+            cached: RefCell::new(None),
+        })
+    }
+
+    /// The bearer token for the next request, None for anonymous access
+    pub fn token(&self) -> Result<Option<String>> {
+        let response = match &self.credentials {
+            GcsCredentials::Anonymous => return Ok(None),
+            GcsCredentials::Token(token) => {
+                return Ok(Some(token.clone()).filter(|token| !token.is_empty()))
+            }
+            _ if self.cached_token().is_some() => return Ok(self.cached_token()),
+            GcsCredentials::ServiceAccount(key) => self.exchange_assertion(key)?,
+            GcsCredentials::AuthorizedUser(user) => self.refresh_user_token(user)?,
+        };
+        debug!(
+            "Obtained GCS access token valid for {}s",
+            response.expires_in
+        );
+        let expires = Instant::now() + Duration::from_secs(response.expires_in);
+        *self.cached.borrow_mut() = Some((response.access_token.clone(), expires));
+        Ok(Some(response.access_token))
+    }
+
+    fn cached_token(&self) -> Option<String> {
+        match &*self.cached.borrow() {
+            Some((token, expires)) if Instant::now() + REFRESH_MARGIN < *expires => {
+                Some(token.clone())
+            }
+            _ => None,
+        }
+    }
+
+    // JWT bearer grant: a short-lived assertion signed with the account's key
+    fn exchange_assertion(&self, key: &ServiceAccountKey) -> Result<TokenResponse> {
+        let now = chrono::Utc::now().timestamp();
+        let claims = Claims {
+            iss: &key.client_email,
+            scope: Config::GCS_OAUTH_SCOPE,
+            aud: &key.token_uri,
+            iat: now,
+            exp: now + ASSERTION_LIFETIME_SECS,
+        };
+        let mut header = Header::new(Algorithm::RS256);
+        header.kid = key.private_key_id.clone();
+        let signing_key = EncodingKey::from_rsa_pem(key.private_key.as_bytes())
+            .context("Invalid private key in the service-account key")?;
+        let assertion = jsonwebtoken::encode(&header, &claims, &signing_key)
+            .context("Failed to sign the token request")?;
+
+        self.request_token(
+            &key.token_uri,
+            &[
+                ("grant_type", "urn:ietf:params:oauth:grant-type:jwt-bearer"),
+                ("assertion", &assertion),
+            ],
+        )
+    }
+
+    fn refresh_user_token(&self, user: &AuthorizedUser) -> Result<TokenResponse> {
+        self.request_token(
+            &user.token_uri,
+            &[
+                ("grant_type", "refresh_token"),
+                ("client_id", &user.client_id),
+                ("client_secret", &user.client_secret),
+                ("refresh_token", &user.refresh_token),
+            ],
+        )
+    }
+
+    fn request_token(&self, token_uri: &str, form: &[(&str, &str)]) -> Result<TokenResponse> {
+        let response = http::send(http::bounded(self.client.post(token_uri)).form(form))
+            .context("Failed to request a GCS access token")?;
+
+        if !response.status().is_success() {
+            let status = response.status();
+            let body = response.text().unwrap_or_default();
+            anyhow::bail!(
+                "Failed to request a GCS access token: HTTP {} {}",
+                status,
+                body.trim()
+            );
+        }
+        response
+            .json()
+            .context("Failed to parse the GCS token response")
+    }
+}
@@ -0,0 +1,251 @@
+use crate::config::Config;
+use crate::services::registry::ReleaseInfo;
+use crate::services::{download, http, BundleFormat, ConditionalCache, RegistryBackend};
+use anyhow::{Context, Result};
+use chrono::{DateTime, Utc};
+use reqwest::{
+    blocking::{Client, RequestBuilder},
+    header::{HeaderValue, ACCEPT, AUTHORIZATION, USER_AGENT},
+    StatusCode,
+};
+use serde::Deserialize;
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+/// A GitHub release; only the fields the supervisor uses
+#[derive(Debug, Clone, Deserialize)]
+pub struct Release {
+    pub tag_name: String,
+    /// Release notes, in Markdown
+    #[serde(default)]
+    pub body: Option<String>,
+    #[serde(default)]
+    pub published_at: Option<DateTime<Utc>>,
+    #[serde(default)]
+    pub assets: Vec<ReleaseAsset>,
+}
+
+/// A file attached to a GitHub release
+#[derive(Debug, Clone, Deserialize)]
+pub struct ReleaseAsset {
+    pub name: String,
+    pub size: u64,
+    /// API URL of the asset; also works for private repositories
+    pub url: String,
+    pub browser_download_url: String,
+}
+
+/// Releases published as GitHub releases tagged `v<version>`, with the release
+/// files attached as assets named after `asset_pattern`
+pub struct GitHubService {
+    client: Client,
+    token: String,
+    api_url: String,
+    repository: String,
+    asset_pattern: String,
+    // Release lookups by tag, so one update only asks the API once per release
+    releases: RefCell<BTreeMap<String, Option<Release>>>,
+    answers: ConditionalCache,
+}
+
+impl GitHubService {
+    /// `api_url` is `https://api.github.com` or a GitHub Enterprise API root, and
+    /// `asset_pattern` maps release file names to asset names, see `Config::GITHUB_ASSET_PATTERN`
+    pub fn new(
+        token: String,
+        api_url: String,
+        repository: String,
+        asset_pattern: String,
+        data_dir: PathBuf,
+    ) -> Result<Self> {
+        Ok(Self {
+            client: http::client()?,
+            token,
+            api_url: api_url.trim_end_matches('/').to_string(),
+            repository: repository.trim_matches('/').to_string(),
+            asset_pattern,
+            releases: RefCell::new(BTreeMap::new()),
+            answers: ConditionalCache::new(data_dir),
+        })
+    }
+
+    fn request(&self, url: &str, accept: &str) -> Result<RequestBuilder> {
+        // The API rejects requests without a user agent
+        let mut request = http::bounded(self.client.get(url))
+            .header(USER_AGENT, concat!("geist/", env!("CARGO_PKG_VERSION")))
+            .header(ACCEPT, accept);
+
+        // Only add authorization if token is not empty
+        if !self.token.is_empty() {
+            request = request.header(
+                AUTHORIZATION,
+                HeaderValue::from_str(&format!("Bearer {}", self.token))?,
+            );
+        }
+        Ok(request)
+    }
+
+    fn api_url(&self, path: &str) -> String {
+        format!("{}/repos/{}/{}", self.api_url, self.repository, path)
+    }
+
+    fn api_get<T: for<'de> Deserialize<'de>>(&self, path: &str) -> Result<Option<T>> {
+        let url = self.api_url(path);
+        let response = http::send(self.request(&url, "application/vnd.github+json")?)
+            .with_context(|| format!("Failed to query {}", url))?;
+
+        if response.status() == StatusCode::NOT_FOUND {
+            return Ok(None);
+        }
+        if !response.status().is_success() {
+            anyhow::bail!("Failed to query {}: HTTP {}", url, response.status());
+        }
+        Ok(Some(response.json().with_context(|| {
+            format!("Failed to parse the answer of {}", url)
+        })?))
+    }
+
+    fn tag(version: &str) -> String {
+        format!("v{}", Config::normalize_version(version))
+    }
+
+    /// The release of a version, if it was published
+    pub fn release(&self, version: &str) -> Result<Option<Release>> {
+        let tag = Self::tag(version);
+        if let Some(release) = self.releases.borrow().get(&tag) {
+            return Ok(release.clone());
+        }
+        let release: Option<Release> = self.api_get(&format!("releases/tags/{}", tag))?;
+        self.releases.borrow_mut().insert(tag, release.clone());
+        Ok(release)
+    }
+
+    /// The published releases, newest first
+    pub fn releases(&self) -> Result<Vec<Release>> {
+        Ok(self.api_get("releases?per_page=100")?.unwrap_or_default())
+    }
+
+    fn asset(&self, version: &str, file_name: &str) -> Result<Option<ReleaseAsset>> {
+        let release = self
+            .release(version)?
+            .with_context(|| format!("Release {} not found", Self::tag(version)))?;
+        let name = self.published_name(version, file_name);
+        Ok(release.assets.into_iter().find(|a| a.name == name))
+    }
+
+    fn require_asset(&self, version: &str, file_name: &str) -> Result<ReleaseAsset> {
+        self.asset(version, file_name)?.with_context(|| {
+            format!(
+                "Release {} has no asset {}",
+                Self::tag(version),
+                self.published_name(version, file_name)
+            )
+        })
+    }
+
+    fn download_asset(&self, asset: &ReleaseAsset) -> Result<reqwest::blocking::Response> {
+        let response = http::send(self.request(&asset.url, "application/octet-stream")?)
+            .with_context(|| format!("Failed to download {}", asset.name))?;
+        if !response.status().is_success() {
+            anyhow::bail!(
+                "Failed to download {}: HTTP {}",
+                asset.name,
+                response.status()
+            );
+        }
+        Ok(response)
+    }
+}
+
+impl RegistryBackend for GitHubService {
+    fn name(&self) -> &'static str {
+        "github"
+    }
+
+    fn published_name(&self, version: &str, file_name: &str) -> String {
+        let version = Config::normalize_version(version);
+        self.asset_pattern
+            .replace("{file}", file_name)
+            .replace("{version}", &version)
+            .replace("{tag}", &format!("v{}", version))
+    }
+
+    #[tracing::instrument(skip(self))]
+    fn verify_version(&self, version: &str) -> Result<bool> {
+        Ok(self.release(version)?.is_some())
+    }
+
+    #[tracing::instrument(skip(self))]
+    fn latest(&self) -> Result<String> {
+        // Polled often, so asked conditionally; a 304 costs no rate limit
+        let url = self.api_url("releases/latest");
+        let answer = self
+            .answers
+            .get(self.request(&url, "application/vnd.github+json")?)?;
+        if answer.status == StatusCode::NOT_FOUND {
+            anyhow::bail!("The repository has no published release");
+        }
+        if !answer.status.is_success() {
+            anyhow::bail!("Failed to query {}: HTTP {}", url, answer.status);
+        }
+        let release: Release = serde_json::from_str(&answer.body)
+            .with_context(|| format!("Failed to parse the answer of {}", url))?;
+        Ok(release.tag_name)
+    }
+
+    fn list(&self) -> Result<Vec<ReleaseInfo>> {
+        Ok(self
+            .releases()?
+            .into_iter()
+            .map(|release| {
+                let version = Config::normalize_version(&release.tag_name);
+                // The size of the bundle in the format `update` would download
+                let bundle_size = BundleFormat::PREFERRED.into_iter().find_map(|format| {
+                    let bundle_name = self.published_bundle_name(&version, format);
+                    release
+                        .assets
+                        .iter()
+                        .find(|asset| asset.name == bundle_name)
+                        .map(|asset| asset.size)
+                });
+                ReleaseInfo {
+                    bundle_size,
+                    published_at: release.published_at,
+                    version: release.tag_name,
+                }
+            })
+            .collect())
+    }
+
+    fn artifact_url(&self, version: &str, file_name: &str) -> Result<String> {
+        Ok(self.require_asset(version, file_name)?.browser_download_url)
+    }
+
+    fn artifact_size(&self, version: &str, file_name: &str) -> Result<Option<u64>> {
+        Ok(Some(self.require_asset(version, file_name)?.size))
+    }
+
+    #[tracing::instrument(skip(self, output_path))]
+    fn download(&self, version: &str, file_name: &str, output_path: &Path) -> Result<String> {
+        let asset = self.require_asset(version, file_name)?;
+        download::download_to_file(
+            &self.client,
+            self.request(&asset.url, "application/octet-stream")?,
+            output_path,
+        )
+        .with_context(|| format!("Failed to download {}", asset.name))
+    }
+
+    #[tracing::instrument(skip(self))]
+    fn fetch(&self, version: &str, file_name: &str) -> Result<Option<String>> {
+        let Some(asset) = self.asset(version, file_name)? else {
+            return Ok(None);
+        };
+        let text = self
+            .download_asset(&asset)?
+            .text()
+            .with_context(|| format!("Failed to read {} from response", file_name))?;
+        Ok(Some(text))
+    }
+}
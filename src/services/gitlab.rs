@@ -0,0 +1,260 @@
+use crate::config::Config;
+use crate::services::registry::ReleaseInfo;
+use crate::services::{download, http, ConditionalCache, RegistryBackend};
+use anyhow::{Context, Result};
+use chrono::{DateTime, Utc};
+use reqwest::{
+    blocking::{Client, RequestBuilder, Response},
+    header::CONTENT_LENGTH,
+    Method, StatusCode,
+};
+use serde::Deserialize;
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+/// A GitLab release; only the fields the supervisor uses
+#[derive(Debug, Clone, Deserialize)]
+pub struct GitLabRelease {
+    pub tag_name: String,
+    #[serde(default)]
+    pub released_at: Option<DateTime<Utc>>,
+    #[serde(default)]
+    pub assets: GitLabAssets,
+}
+
+/// The files attached to a GitLab release
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct GitLabAssets {
+    #[serde(default)]
+    pub links: Vec<GitLabAssetLink>,
+}
+
+/// A file linked to a GitLab release, usually stored in the project's package registry
+#[derive(Debug, Clone, Deserialize)]
+pub struct GitLabAssetLink {
+    pub name: String,
+    pub url: String,
+    /// Permanent link to the file; missing on old GitLab versions
+    #[serde(default)]
+    pub direct_asset_url: Option<String>,
+}
+
+impl GitLabAssetLink {
+    pub fn download_url(&self) -> &str {
+        self.direct_asset_url.as_deref().unwrap_or(&self.url)
+    }
+}
+
+/// Releases published as GitLab releases tagged `v<version>`, with the release
+/// files attached as asset links
+pub struct GitLabService {
+    client: Client,
+    token: String,
+    instance_url: String,
+    project: String,
+    // Release lookups by tag, so one update only asks the API once per release
+    releases: RefCell<BTreeMap<String, Option<GitLabRelease>>>,
+    answers: ConditionalCache,
+}
+
+impl GitLabService {
+    /// `project` is the numeric project ID or the full path, e.g. `faust/roc_camera`
+    pub fn new(
+        token: String,
+        instance_url: String,
+        project: String,
+        data_dir: PathBuf,
+    ) -> Result<Self> {
+        Ok(Self {
+            client: http::client()?,
+            token,
+            instance_url: instance_url.trim_end_matches('/').to_string(),
+            project,
+            releases: RefCell::new(BTreeMap::new()),
+            answers: ConditionalCache::new(data_dir),
+        })
+    }
+
+    fn request(&self, method: Method, url: &str) -> RequestBuilder {
+        let mut request = http::bounded(self.client.request(method, url));
+
+        // Asset links may point anywhere, and the token is only the instance's to
+        // see. As a bearer token it is also dropped on redirects to other hosts,
+        // such as the object storage behind package downloads.
+        if !self.token.is_empty() && self.is_instance(url) {
+            request = request.bearer_auth(&self.token);
+        }
+        request
+    }
+
+    // Whether a URL has the scheme, host and port of the instance
+    fn is_instance(&self, url: &str) -> bool {
+        match (
+            reqwest::Url::parse(url),
+            reqwest::Url::parse(&self.instance_url),
+        ) {
+            (Ok(url), Ok(instance)) => url.origin() == instance.origin(),
+            _ => false,
+        }
+    }
+
+    fn api_url(&self, path: &str) -> String {
+        // Project paths have to be passed with their slashes encoded
+        format!(
+            "{}/api/v4/projects/{}/{}",
+            self.instance_url,
+            self.project.replace('/', "%2F"),
+            path
+        )
+    }
+
+    fn api_get<T: for<'de> Deserialize<'de>>(&self, path: &str) -> Result<Option<T>> {
+        let url = self.api_url(path);
+        let response = http::send(self.request(Method::GET, &url))
+            .with_context(|| format!("Failed to query {}", url))?;
+
+        if response.status() == StatusCode::NOT_FOUND {
+            return Ok(None);
+        }
+        if !response.status().is_success() {
+            anyhow::bail!("Failed to query {}: HTTP {}", url, response.status());
+        }
+        Ok(Some(response.json().with_context(|| {
+            format!("Failed to parse the answer of {}", url)
+        })?))
+    }
+
+    fn tag(version: &str) -> String {
+        format!("v{}", Config::normalize_version(version))
+    }
+
+    /// The release of a version, if it was published
+    pub fn release(&self, version: &str) -> Result<Option<GitLabRelease>> {
+        let tag = Self::tag(version);
+        if let Some(release) = self.releases.borrow().get(&tag) {
+            return Ok(release.clone());
+        }
+        let release: Option<GitLabRelease> = self.api_get(&format!("releases/{}", tag))?;
+        self.releases.borrow_mut().insert(tag, release.clone());
+        Ok(release)
+    }
+
+    fn asset(&self, version: &str, file_name: &str) -> Result<Option<GitLabAssetLink>> {
+        let release = self
+            .release(version)?
+            .with_context(|| format!("Release {} not found", Self::tag(version)))?;
+        Ok(release
+            .assets
+            .links
+            .into_iter()
+            .find(|link| link.name == file_name))
+    }
+
+    fn require_asset(&self, version: &str, file_name: &str) -> Result<GitLabAssetLink> {
+        self.asset(version, file_name)?
+            .with_context(|| format!("Release {} has no asset {}", Self::tag(version), file_name))
+    }
+
+    fn download_asset(&self, link: &GitLabAssetLink) -> Result<Response> {
+        let response = http::send(self.request(Method::GET, link.download_url()))
+            .with_context(|| format!("Failed to download {}", link.name))?;
+        if !response.status().is_success() {
+            anyhow::bail!(
+                "Failed to download {}: HTTP {}",
+                link.name,
+                response.status()
+            );
+        }
+        Ok(response)
+    }
+}
+
+impl RegistryBackend for GitLabService {
+    fn name(&self) -> &'static str {
+        "gitlab"
+    }
+
+    #[tracing::instrument(skip(self))]
+    fn verify_version(&self, version: &str) -> Result<bool> {
+        Ok(self.release(version)?.is_some())
+    }
+
+    #[tracing::instrument(skip(self))]
+    fn latest(&self) -> Result<String> {
+        // Releases are listed newest first by release date. Polled often, so asked
+        // conditionally.
+        let url = self.api_url("releases?per_page=1&order_by=released_at&sort=desc");
+        let answer = self.answers.get(self.request(Method::GET, &url))?;
+        let releases: Vec<GitLabRelease> = match answer.status {
+            StatusCode::NOT_FOUND => Vec::new(),
+            status if !status.is_success() => {
+                anyhow::bail!("Failed to query {}: HTTP {}", url, status)
+            }
+            _ => serde_json::from_str(&answer.body)
+                .with_context(|| format!("Failed to parse the answer of {}", url))?,
+        };
+        releases
+            .into_iter()
+            .next()
+            .map(|release| release.tag_name)
+            .context("The project has no published release")
+    }
+
+    fn list(&self) -> Result<Vec<ReleaseInfo>> {
+        let releases: Vec<GitLabRelease> =
+            self.api_get("releases?per_page=100")?.unwrap_or_default();
+        Ok(releases
+            .into_iter()
+            .map(|release| ReleaseInfo {
+                version: release.tag_name,
+                published_at: release.released_at,
+                // Asset links carry no size, and asking for each would make listing slow
+                bundle_size: None,
+            })
+            .collect())
+    }
+
+    fn artifact_url(&self, version: &str, file_name: &str) -> Result<String> {
+        Ok(self
+            .require_asset(version, file_name)?
+            .download_url()
+            .to_string())
+    }
+
+    fn artifact_size(&self, version: &str, file_name: &str) -> Result<Option<u64>> {
+        let link = self.require_asset(version, file_name)?;
+        let response = http::send(self.request(Method::HEAD, link.download_url()))?;
+        if !response.status().is_success() {
+            anyhow::bail!("HTTP {}", response.status());
+        }
+        Ok(response
+            .headers()
+            .get(CONTENT_LENGTH)
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| v.parse().ok()))
+    }
+
+    #[tracing::instrument(skip(self, output_path))]
+    fn download(&self, version: &str, file_name: &str, output_path: &Path) -> Result<String> {
+        let link = self.require_asset(version, file_name)?;
+        download::download_to_file(
+            &self.client,
+            self.request(Method::GET, link.download_url()),
+            output_path,
+        )
+        .with_context(|| format!("Failed to download {}", link.name))
+    }
+
+    #[tracing::instrument(skip(self))]
+    fn fetch(&self, version: &str, file_name: &str) -> Result<Option<String>> {
+        let Some(link) = self.asset(version, file_name)? else {
+            return Ok(None);
+        };
+        let text = self
+            .download_asset(&link)?
+            .text()
+            .with_context(|| format!("Failed to read {} from response", file_name))?;
+        Ok(Some(text))
+    }
+}
@@ -0,0 +1,126 @@
+use crate::config::Config;
+use anyhow::{Context, Result};
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::fs;
+use std::net::TcpListener;
+use std::path::Path;
+use std::process::Command;
+use std::time::Duration;
+use tracing::{info, warn};
+
+// Sockets passed to one launch; bounded so the child needs no allocation after fork
+const MAX_SOCKETS: usize = 16;
+
+/// Listening sockets a version serves its API on. The supervisor binds them and
+/// passes them to every launch, so a restart can start the new instance next to
+/// the old one without either binding the port itself.
+///
+/// Declared in the `handoff` section of a version's `manifest.yaml`:
+///
+/// ```yaml
+/// handoff:
+///   listen: ["0.0.0.0:8080"]
+///   ready_timeout_secs: 30
+/// ```
+#[derive(Debug, Clone, Deserialize)]
+pub struct Handoff {
+    pub listen: Vec<String>,
+    /// How long a new instance gets to become ready before the handoff is abandoned
+    #[serde(default = "Handoff::default_ready_timeout_secs")]
+    pub ready_timeout_secs: u64,
+}
+
+// Only the part of the manifest the handoff cares about
+#[derive(Deserialize)]
+struct Manifest {
+    #[serde(default)]
+    handoff: Option<Handoff>,
+}
+
+impl Handoff {
+    fn default_ready_timeout_secs() -> u64 {
+        Config::HANDOFF_READY_TIMEOUT_SECS
+    }
+
+    /// The handoff settings of an installed version, if it serves sockets
+    pub fn load(version_dir: &Path) -> Option<Self> {
+        let path = version_dir.join("manifest.yaml");
+        let contents = fs::read_to_string(&path).ok()?;
+        match serde_yaml::from_str::<Manifest>(&contents) {
+            Ok(manifest) => manifest
+                .handoff
+                .filter(|handoff| !handoff.listen.is_empty()),
+            Err(e) => {
+                warn!("Ignoring handoff settings in {}: {}", path.display(), e);
+                None
+            }
+        }
+    }
+
+    pub fn ready_timeout(&self) -> Duration {
+        Duration::from_secs(self.ready_timeout_secs)
+    }
+}
+
+/// Sockets bound by the supervisor, kept open for as long as it runs so that
+/// connections queue up instead of being refused while the app restarts
+#[derive(Default)]
+pub struct ListenSockets {
+    listeners: BTreeMap<String, TcpListener>,
+}
+
+impl ListenSockets {
+    /// Makes the given addresses available to `command` as inherited file
+    /// descriptors 3, 4, ... in order, announced through GEIST_LISTEN_FDS and
+    /// GEIST_LISTEN_ADDRS. Addresses are bound on first use.
+    pub fn pass_to(&mut self, command: &mut Command, addresses: &[String]) -> Result<()> {
+        if addresses.len() > MAX_SOCKETS {
+            anyhow::bail!("At most {} listening sockets can be passed", MAX_SOCKETS);
+        }
+        for address in addresses {
+            if !self.listeners.contains_key(address) {
+                let listener = TcpListener::bind(address)
+                    .with_context(|| format!("Failed to listen on {}", address))?;
+                info!("Listening on {} for the app", address);
+                self.listeners.insert(address.clone(), listener);
+            }
+        }
+
+        #[cfg(unix)]
+        {
+            use std::os::unix::io::AsRawFd;
+            use std::os::unix::process::CommandExt;
+
+            command.env("GEIST_LISTEN_FDS", addresses.len().to_string());
+            command.env("GEIST_LISTEN_ADDRS", addresses.join(","));
+
+            let fds: Vec<libc::c_int> = addresses
+                .iter()
+                .map(|address| self.listeners[address].as_raw_fd())
+                .collect();
+            unsafe {
+                command.pre_exec(move || {
+                    // Move the sockets out of the way first so placing one at its
+                    // target descriptor cannot close another that still sits there
+                    let mut moved = [0 as libc::c_int; MAX_SOCKETS];
+                    for (index, fd) in fds.iter().enumerate() {
+                        moved[index] = libc::fcntl(*fd, libc::F_DUPFD, 100);
+                        if moved[index] < 0 {
+                            return Err(std::io::Error::last_os_error());
+                        }
+                    }
+                    // dup2 leaves the close-on-exec flag off on the new descriptor
+                    for (index, fd) in moved[..fds.len()].iter().enumerate() {
+                        if libc::dup2(*fd, 3 + index as libc::c_int) < 0 {
+                            return Err(std::io::Error::last_os_error());
+                        }
+                        libc::close(*fd);
+                    }
+                    Ok(())
+                });
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,143 @@
+use crate::config::Config;
+use crate::services::ConfigSecret;
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::PathBuf;
+use tracing::warn;
+
+// What the MAC covers, serialized in this field order
+#[derive(Serialize, Deserialize)]
+struct Signed {
+    version: String,
+    files: BTreeMap<String, String>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct HashRecord {
+    #[serde(flatten)]
+    signed: Signed,
+    /// HMAC-SHA256 under the device key; missing when there was none to sign with
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    mac: Option<String>,
+}
+
+/// The SHA-256 of every file of each installed version, taken at install time and
+/// kept apart from the versions in `hash_records/` under the data directory, so
+/// that whoever can write a version directory cannot rewrite the hashes along
+/// with the files. Each record is signed with the device key beside the user's
+/// configuration file, generated on first use.
+pub struct HashRecords {
+    dir: PathBuf,
+}
+
+impl HashRecords {
+    pub fn new(data_dir: PathBuf) -> Self {
+        Self {
+            dir: data_dir.join(Config::HASH_RECORDS_DIR),
+        }
+    }
+
+    fn path(&self, version: &str) -> PathBuf {
+        self.dir.join(format!("{}.json", version))
+    }
+
+    /// Records the hashes of a version's files, replacing any earlier record.
+    /// Readable by everyone and writable by no one, like the install record.
+    pub fn write(&self, version: &str, files: BTreeMap<String, String>) -> Result<()> {
+        fs::create_dir_all(&self.dir)
+            .with_context(|| format!("Failed to create {}", self.dir.display()))?;
+        let signed = Signed {
+            version: version.to_string(),
+            files,
+        };
+        let mac = match Self::device_key(true) {
+            Some(key) => Some(key.mac(Config::HASH_RECORDS_DIR, &serde_json::to_vec(&signed)?)?),
+            None => {
+                warn!("No device key to sign the file hashes of {} with", version);
+                None
+            }
+        };
+
+        let path = self.path(version);
+        let tmp_path = path.with_extension("json.tmp");
+        fs::remove_file(&tmp_path).ok();
+        fs::write(
+            &tmp_path,
+            serde_json::to_string_pretty(&HashRecord { signed, mac })?,
+        )
+        .with_context(|| format!("Failed to write {}", tmp_path.display()))?;
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            fs::set_permissions(&tmp_path, fs::Permissions::from_mode(0o444))?;
+        }
+        fs::rename(&tmp_path, &path).with_context(|| format!("Failed to write {}", path.display()))
+    }
+
+    /// The recorded hashes of a version's files. Fails when there is no record, or
+    /// it is not signed by the device key while there is one.
+    pub fn load(&self, version: &str) -> Result<BTreeMap<String, String>> {
+        let path = self.path(version);
+        let contents = fs::read_to_string(&path)
+            .with_context(|| format!("Version {} has no record of its file hashes", version))?;
+        let record: HashRecord = serde_json::from_str(&contents)
+            .with_context(|| format!("Failed to parse {}", path.display()))?;
+        if record.signed.version != version {
+            anyhow::bail!(
+                "{} is the record of {}",
+                path.display(),
+                record.signed.version
+            );
+        }
+
+        if let Some(key) = Self::device_key(false) {
+            let expected = key.mac(
+                Config::HASH_RECORDS_DIR,
+                &serde_json::to_vec(&record.signed)?,
+            )?;
+            let valid = record.mac.as_ref().is_some_and(|mac| {
+                mac.len() == expected.len()
+                    && openssl::memcmp::eq(mac.as_bytes(), expected.as_bytes())
+            });
+            if !valid {
+                anyhow::bail!(
+                    "The file hashes of {} are not signed by this device's key",
+                    version
+                );
+            }
+        }
+        Ok(record.signed.files)
+    }
+
+    /// Whether a version's hashes were recorded, whatever the record holds
+    pub fn exists(&self, version: &str) -> bool {
+        self.path(version).is_file()
+    }
+
+    /// Drops the record of a removed version
+    pub fn remove(&self, version: &str) {
+        let path = self.path(version);
+        if let Err(e) = fs::remove_file(&path) {
+            if e.kind() != std::io::ErrorKind::NotFound {
+                warn!("Failed to remove {}: {}", path.display(), e);
+            }
+        }
+    }
+
+    // The device key of the user's configuration file, created if asked to
+    fn device_key(create: bool) -> Option<ConfigSecret> {
+        let config_file = Config::user_config_file()?;
+        if create {
+            return ConfigSecret::open_or_create(&config_file)
+                .inspect_err(|e| warn!("{:#}", e))
+                .ok();
+        }
+        ConfigSecret::key_path(&config_file)
+            .exists()
+            .then(|| ConfigSecret::open(&config_file))?
+            .inspect_err(|e| warn!("{:#}", e))
+            .ok()
+    }
+}
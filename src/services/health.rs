@@ -0,0 +1,155 @@
+use crate::config::Config;
+use crate::services::AppService;
+use anyhow::{Context, Result};
+use serde::Deserialize;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::time::{Duration, SystemTime};
+use tracing::warn;
+
+// Upper bound for a single HTTP request or probe script
+const CHECK_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Checks a trial version has to pass before it becomes current. Every configured
+/// check must pass.
+///
+/// Declared in the `health_probe` section of a version's `manifest.yaml`:
+///
+/// ```yaml
+/// health_probe:
+///   http: http://127.0.0.1:8080/healthz
+///   exec: ./healthcheck.sh
+///   heartbeat: /run/roc_camera/heartbeat
+///   timeout_secs: 60
+/// ```
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct HealthProbe {
+    /// URL that has to answer with a success status
+    pub http: Option<String>,
+    /// Shell command, run in the version directory, that has to exit successfully
+    pub exec: Option<String>,
+    /// File the app touches regularly; it has to be younger than
+    /// `HEALTH_HEARTBEAT_MAX_AGE_SECS`
+    pub heartbeat: Option<PathBuf>,
+    /// How long the version gets to pass, after its trial window
+    #[serde(default = "HealthProbe::default_timeout_secs")]
+    pub timeout_secs: u64,
+}
+
+// Only the part of the manifest the probe cares about
+#[derive(Deserialize)]
+struct Manifest {
+    #[serde(default)]
+    health_probe: Option<HealthProbe>,
+}
+
+impl HealthProbe {
+    fn default_timeout_secs() -> u64 {
+        Config::HEALTH_PROBE_TIMEOUT_SECS
+    }
+
+    /// The probe for an installed version, if it declares one. GEIST_HEALTH_PROBE
+    /// replaces the manifest's checks.
+    pub fn load(version_dir: &Path) -> Option<Self> {
+        if let Some(spec) = Config::health_probe() {
+            return match Self::parse(&spec) {
+                Some(probe) => Some(probe),
+                None => {
+                    warn!("Ignoring unknown health probe: {}", spec);
+                    None
+                }
+            };
+        }
+
+        let path = version_dir.join("manifest.yaml");
+        let contents = fs::read_to_string(&path).ok()?;
+        match serde_yaml::from_str::<Manifest>(&contents) {
+            Ok(manifest) => manifest.health_probe,
+            Err(e) => {
+                warn!("Ignoring health probe in {}: {}", path.display(), e);
+                None
+            }
+        }
+    }
+
+    /// `http://...`, `https://...`, `exec:<command>` or `heartbeat:<path>`
+    pub fn parse(spec: &str) -> Option<Self> {
+        let mut probe = Self {
+            timeout_secs: Self::default_timeout_secs(),
+            ..Self::default()
+        };
+        if spec.starts_with("http://") || spec.starts_with("https://") {
+            probe.http = Some(spec.to_string());
+        } else if let Some(command) = spec.strip_prefix("exec:") {
+            probe.exec = Some(command.to_string());
+        } else if let Some(path) = spec.strip_prefix("heartbeat:") {
+            probe.heartbeat = Some(PathBuf::from(path));
+        } else {
+            return None;
+        }
+        Some(probe)
+    }
+
+    pub fn timeout(&self) -> Duration {
+        Duration::from_secs(self.timeout_secs)
+    }
+
+    /// Runs every configured check once
+    pub fn check(&self, version_dir: &Path) -> Result<()> {
+        if let Some(url) = &self.http {
+            Self::check_http(url)?;
+        }
+        if let Some(command) = &self.exec {
+            Self::check_exec(command, version_dir)?;
+        }
+        if let Some(path) = &self.heartbeat {
+            Self::check_heartbeat(path)?;
+        }
+        Ok(())
+    }
+
+    fn check_http(url: &str) -> Result<()> {
+        let response = reqwest::blocking::Client::builder()
+            .timeout(CHECK_TIMEOUT)
+            .build()?
+            .get(url)
+            .send()
+            .with_context(|| format!("Health endpoint {} is unreachable", url))?;
+        if !response.status().is_success() {
+            anyhow::bail!("Health endpoint {} answered {}", url, response.status());
+        }
+        Ok(())
+    }
+
+    fn check_exec(command: &str, version_dir: &Path) -> Result<()> {
+        let mut child = Command::new("sh")
+            .args(["-c", command])
+            .current_dir(version_dir)
+            .spawn()
+            .with_context(|| format!("Failed to run health check '{}'", command))?;
+
+        match AppService::wait_timeout(&mut child, CHECK_TIMEOUT)? {
+            Some(status) if status.success() => Ok(()),
+            Some(status) => anyhow::bail!("Health check '{}' exited with {}", command, status),
+            None => {
+                child.kill().ok();
+                child.wait().ok();
+                anyhow::bail!("Health check '{}' timed out", command)
+            }
+        }
+    }
+
+    fn check_heartbeat(path: &Path) -> Result<()> {
+        let modified = fs::metadata(path)
+            .and_then(|m| m.modified())
+            .with_context(|| format!("No heartbeat at {}", path.display()))?;
+        let age = SystemTime::now()
+            .duration_since(modified)
+            .unwrap_or_default();
+        if age > Duration::from_secs(Config::HEALTH_HEARTBEAT_MAX_AGE_SECS) {
+            anyhow::bail!("Last heartbeat at {} is {:?} old", path.display(), age);
+        }
+        Ok(())
+    }
+}
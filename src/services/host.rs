@@ -0,0 +1,356 @@
+use crate::config::Config;
+use crate::services::BinaryArch;
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::ffi::OsString;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+
+/// Device facts reported by `--fake-device` instead of reading the hardware, so the
+/// update, run, health and rollback flows can be exercised in containers.
+///
+/// Loaded from a YAML fixture; missing fields fall back to a Raspberry Pi at rest:
+///
+/// ```yaml
+/// arch: armv7l
+/// cpu_temperature_c: 47.5
+/// power:
+///   battery_percent: 20
+///   external_power: false
+/// ```
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct DeviceFixture {
+    pub arch: String,
+    pub cpu_temperature_c: Option<f64>,
+    pub power: PowerState,
+}
+
+impl Default for DeviceFixture {
+    fn default() -> Self {
+        Self {
+            arch: "armv7l".to_string(),
+            cpu_temperature_c: Some(45.0),
+            power: PowerState::default(),
+        }
+    }
+}
+
+/// How the device is powered, for deciding whether an update can safely write to
+/// disk. Everything is unknown on devices that expose no power state.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct PowerState {
+    /// Charge of the battery or UPS HAT
+    pub battery_percent: Option<u8>,
+    /// Whether mains, PoE or USB power is present
+    pub external_power: Option<bool>,
+    /// Whether the supply voltage is too low right now, as the Raspberry Pi
+    /// firmware reports it
+    pub under_voltage: bool,
+}
+
+impl PowerState {
+    /// Why an update that nobody watches must not start now, if it must not
+    pub fn update_blocker(&self, min_battery_percent: u8) -> Option<String> {
+        if self.under_voltage {
+            return Some("the supply voltage is too low (under-voltage)".to_string());
+        }
+        match self.battery_percent {
+            // A battery only backs up a device that is still on external power
+            Some(percent) if percent < min_battery_percent && self.external_power != Some(true) => {
+                Some(format!(
+                    "the battery is at {}%, below the {}% unattended updates need, and not charging",
+                    percent, min_battery_percent
+                ))
+            }
+            _ => None,
+        }
+    }
+}
+
+impl DeviceFixture {
+    /// The fixture named by `--fake-device`: `default` for the built-in one,
+    /// otherwise the path of a YAML file
+    pub fn load(spec: &str) -> Result<Self> {
+        if spec == "default" {
+            return Ok(Self::default());
+        }
+        let contents = fs::read_to_string(spec)
+            .with_context(|| format!("Failed to read device fixture {}", spec))?;
+        serde_yaml::from_str(&contents)
+            .with_context(|| format!("Failed to parse device fixture {}", spec))
+    }
+}
+
+/// Facts about the device the supervisor is running on
+pub struct HostService;
+
+impl HostService {
+    /// The simulated device, when running with `--fake-device`
+    pub fn fake_device() -> Option<DeviceFixture> {
+        // The fixture was checked when the flag was parsed
+        Config::fake_device().and_then(|spec| DeviceFixture::load(&spec).ok())
+    }
+
+    /// CPU architecture as `uname -m` reports it, e.g. `armv7l` or `x86_64`
+    pub fn arch() -> String {
+        match Self::fake_device() {
+            Some(fixture) => fixture.arch,
+            None => Self::machine().unwrap_or_else(|| std::env::consts::ARCH.to_string()),
+        }
+    }
+
+    // The machine the running kernel reports, which is what the app binary has to
+    // match, rather than the architecture geist itself was built for
+    #[cfg(unix)]
+    fn machine() -> Option<String> {
+        // SAFETY: uname only writes into the zeroed struct, whose fields it leaves
+        // NUL-terminated
+        let name = unsafe {
+            let mut name: libc::utsname = std::mem::zeroed();
+            if libc::uname(&mut name) != 0 {
+                return None;
+            }
+            name
+        };
+        let machine = unsafe { std::ffi::CStr::from_ptr(name.machine.as_ptr()) };
+        machine
+            .to_str()
+            .ok()
+            .map(str::to_string)
+            .filter(|machine| !machine.is_empty())
+    }
+
+    #[cfg(not(unix))]
+    fn machine() -> Option<String> {
+        None
+    }
+
+    /// Whether the `roc_camera` binary can be executed here: on a 32- or 64-bit ARM
+    /// host such as the Raspberry Pi, on a fake device whose binary is a stand-in,
+    /// anywhere with `--force-run`, e.g. a workstation with a build of the app for
+    /// its own architecture, with `run --emulate`, which runs an ARM build under
+    /// QEMU, or in container mode, where the runtime picks the image's build for
+    /// the host
+    pub fn can_run_app() -> bool {
+        Config::fake_device().is_some()
+            || Config::force_run()
+            || Config::emulate()
+            || Config::container_image().is_some()
+            || BinaryArch::host().is_some_and(BinaryArch::is_device)
+    }
+
+    /// SoC temperature in degrees Celsius, from the first thermal zone
+    pub fn cpu_temperature() -> Option<f64> {
+        if let Some(fixture) = Self::fake_device() {
+            return fixture.cpu_temperature_c;
+        }
+        let millidegrees: f64 = fs::read_to_string("/sys/class/thermal/thermal_zone0/temp")
+            .ok()?
+            .trim()
+            .parse()
+            .ok()?;
+        Some(millidegrees / 1000.0)
+    }
+
+    /// Power state from the power supplies in sysfs (batteries, UPS HATs with a
+    /// kernel driver, mains and USB supplies) and the firmware's under-voltage flag
+    pub fn power_state() -> PowerState {
+        if let Some(fixture) = Self::fake_device() {
+            return fixture.power;
+        }
+
+        let mut power = PowerState::default();
+        let supplies = fs::read_dir(Config::power_supply_dir())
+            .into_iter()
+            .flatten()
+            .filter_map(|entry| entry.ok().map(|entry| entry.path()));
+        for supply in supplies {
+            let read = |name: &str| {
+                fs::read_to_string(supply.join(name))
+                    .ok()
+                    .map(|value| value.trim().to_string())
+            };
+            match read("type").as_deref() {
+                Some("Battery") => {
+                    if let Some(capacity) = read("capacity").and_then(|c| c.parse().ok()) {
+                        power.battery_percent = Some(capacity);
+                    }
+                }
+                Some(_) => {
+                    if let Some(online) = read("online") {
+                        let online = online == "1";
+                        power.external_power = Some(power.external_power == Some(true) || online);
+                    }
+                }
+                None => {}
+            }
+        }
+
+        // Hex bit mask; bit 0 is set while the voltage is too low
+        power.under_voltage = fs::read_to_string(Config::THROTTLED_FILE)
+            .ok()
+            .and_then(|flags| {
+                let flags = flags.trim();
+                let flags = flags.strip_prefix("0x").unwrap_or(flags);
+                u32::from_str_radix(flags, 16).ok()
+            })
+            .is_some_and(|flags| flags & 1 != 0);
+        power
+    }
+
+    /// Name of the machine, from the kernel
+    pub fn hostname() -> Option<String> {
+        fs::read_to_string("/proc/sys/kernel/hostname")
+            .ok()
+            .map(|name| name.trim().to_string())
+            .filter(|name| !name.is_empty())
+    }
+
+    /// Bytes available to unprivileged users on the filesystem holding `path`
+    pub fn available_space(path: &Path) -> Result<u64> {
+        fs2::available_space(path)
+            .with_context(|| format!("Failed to stat filesystem of {}", path.display()))
+    }
+
+    /// Total physical memory in bytes, from /proc/meminfo
+    pub fn total_memory() -> Result<u64> {
+        let meminfo =
+            fs::read_to_string("/proc/meminfo").context("Failed to read /proc/meminfo")?;
+        meminfo
+            .lines()
+            .find_map(|line| line.strip_prefix("MemTotal:"))
+            .and_then(|rest| {
+                rest.trim()
+                    .trim_end_matches("kB")
+                    .trim()
+                    .parse::<u64>()
+                    .ok()
+            })
+            .map(|kib| kib * 1024)
+            .context("MemTotal missing from /proc/meminfo")
+    }
+
+    /// Scheduler state of a process (`R`, `S`, `T`, ...), from /proc/<pid>/stat
+    pub fn process_state(pid: u32) -> Option<char> {
+        let stat = fs::read_to_string(format!("/proc/{}/stat", pid)).ok()?;
+        // The command name is parenthesized and may itself contain spaces or parens
+        let (_, rest) = stat.rsplit_once(')')?;
+        rest.trim_start().chars().next()
+    }
+
+    /// Reads a pid file, returning the pid only if that process is still alive
+    pub fn running_pid(pid_file: &Path) -> Option<u32> {
+        let pid: u32 = fs::read_to_string(pid_file).ok()?.trim().parse().ok()?;
+        Self::is_running(pid).then_some(pid)
+    }
+
+    #[cfg(unix)]
+    pub fn is_running(pid: u32) -> bool {
+        // Signal 0 only checks that the process exists and may be signalled
+        unsafe { libc::kill(pid as libc::pid_t, 0) == 0 }
+    }
+
+    // Apps are only launched on Linux devices, so there is never one to find elsewhere
+    #[cfg(not(unix))]
+    pub fn is_running(_pid: u32) -> bool {
+        false
+    }
+
+    /// Sends a signal, named like `SIGUSR1` or `USR1` or given by number, to a process
+    #[cfg(unix)]
+    pub fn send_signal(pid: u32, signal: &str) -> Result<()> {
+        let number =
+            Self::parse_signal(signal).with_context(|| format!("Unknown signal '{}'", signal))?;
+        if unsafe { libc::kill(pid as libc::pid_t, number) } != 0 {
+            return Err(std::io::Error::last_os_error())
+                .with_context(|| format!("Failed to send {} to process {}", signal, pid));
+        }
+        Ok(())
+    }
+
+    #[cfg(unix)]
+    fn parse_signal(signal: &str) -> Option<i32> {
+        if let Ok(number) = signal.parse() {
+            return Some(number);
+        }
+        let name = signal.trim().to_uppercase();
+        match name.strip_prefix("SIG").unwrap_or(&name) {
+            "HUP" => Some(libc::SIGHUP),
+            "INT" => Some(libc::SIGINT),
+            "QUIT" => Some(libc::SIGQUIT),
+            "USR1" => Some(libc::SIGUSR1),
+            "USR2" => Some(libc::SIGUSR2),
+            "TERM" => Some(libc::SIGTERM),
+            "WINCH" => Some(libc::SIGWINCH),
+            _ => None,
+        }
+    }
+
+    // Nor, as with `is_running`, one to signal
+    #[cfg(not(unix))]
+    pub fn send_signal(_pid: u32, _signal: &str) -> Result<()> {
+        Ok(())
+    }
+
+    /// Where a program is installed, looked up in PATH
+    pub fn find_program(name: &str) -> Option<PathBuf> {
+        let path = std::env::var_os("PATH")?;
+        std::env::split_paths(&path)
+            .map(|dir| dir.join(name))
+            .find(|program| program.is_file())
+    }
+
+    /// Fails with a clear message when a device-only feature is used on another platform
+    pub fn require_linux(feature: &str) -> Result<()> {
+        if !cfg!(target_os = "linux") {
+            anyhow::bail!(
+                "{} is only available on Linux devices, not on {}",
+                feature,
+                std::env::consts::OS
+            );
+        }
+        Ok(())
+    }
+
+    /// Relaunches geist with `args` in a new session, detached from the terminal,
+    /// with stdout and stderr appended to `log_file`. Returns the new process id.
+    pub fn spawn_detached(args: &[OsString], log_file: &Path) -> Result<u32> {
+        Self::require_linux("Running as a daemon")?;
+        if let Some(parent) = log_file.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let log = fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(log_file)
+            .with_context(|| format!("Failed to open {}", log_file.display()))?;
+
+        let exe = std::env::current_exe().context("Failed to locate the geist executable")?;
+        let mut command = Command::new(exe);
+        command
+            .args(args)
+            .stdin(Stdio::null())
+            .stdout(log.try_clone()?)
+            .stderr(log);
+
+        // A new session drops the controlling terminal, so closing SSH doesn't send SIGHUP
+        #[cfg(unix)]
+        unsafe {
+            use std::os::unix::process::CommandExt;
+            command.pre_exec(|| {
+                if libc::setsid() == -1 {
+                    return Err(std::io::Error::last_os_error());
+                }
+                Ok(())
+            });
+        }
+
+        let child = command
+            .spawn()
+            .context("Failed to start detached process")?;
+        Ok(child.id())
+    }
+}
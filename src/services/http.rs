@@ -0,0 +1,537 @@
+use crate::config::Config;
+use crate::services::der;
+use anyhow::{Context, Result};
+use base64::Engine;
+use reqwest::blocking::{Client, RequestBuilder, Response};
+use reqwest::tls::TlsInfo;
+use reqwest::Certificate;
+use rustls::client::danger::{HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier};
+use rustls::client::WebPkiServerVerifier;
+use rustls::pki_types::pem::PemObject;
+use rustls::pki_types::{CertificateDer, ServerName, UnixTime};
+use rustls::{DigitallySignedStruct, SignatureScheme};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use tracing::debug;
+
+/// A client for registry and authentication requests, with the connect and read
+/// timeouts of `Config::http_connect_timeout` and `Config::http_read_timeout`, and
+/// trusting the CAs of `Config::ca_bundle` in addition to the system ones. The
+/// pins of `Config::tls_pins` are checked during the TLS handshake, before any
+/// request goes out; send with `send` to also refuse plain HTTP to a pinned host.
+pub fn client() -> Result<Client> {
+    // Malformed pins fail every request rather than leave a host unpinned
+    let mut pins = HashMap::new();
+    for (host, specs) in Config::tls_pins() {
+        let parsed = specs
+            .iter()
+            .map(|spec| Pin::parse(spec))
+            .collect::<Result<Vec<_>>>()
+            .with_context(|| format!("Invalid TLS pin for {}", host))?;
+        pins.insert(host.to_ascii_lowercase(), parsed);
+    }
+
+    let mut builder = Client::builder()
+        .connect_timeout(Config::http_connect_timeout())
+        .timeout(Config::http_read_timeout())
+        .tls_info(true);
+    if pins.is_empty() {
+        if let Some(path) = Config::ca_bundle() {
+            for certificate in load_certificates(&path)? {
+                builder = builder.add_root_certificate(certificate);
+            }
+        }
+    } else {
+        let hosts: Vec<String> = pins.keys().cloned().collect();
+        builder = builder.use_preconfigured_tls(pinned_tls(pins)?).redirect(
+            reqwest::redirect::Policy::custom(move |attempt| {
+                // Every TLS hop is pinned in the handshake; plain HTTP has no pin
+                let downgraded = attempt.url().scheme() != "https"
+                    && attempt
+                        .url()
+                        .host_str()
+                        .is_some_and(|host| hosts.contains(&host.to_ascii_lowercase()));
+                match downgraded {
+                    true => {
+                        let message = format!(
+                            "refusing the redirect to {}: its host is pinned, which needs https",
+                            attempt.url()
+                        );
+                        attempt.error(message)
+                    }
+                    false if attempt.previous().len() >= 10 => attempt.stop(),
+                    false => attempt.follow(),
+                }
+            }),
+        );
+    }
+    builder.build().context("Failed to set up the HTTP client")
+}
+
+// TLS that trusts the system CAs and those of `Config::ca_bundle`, and refuses a
+// pinned host whose certificate matches none of its pins
+fn pinned_tls(pins: HashMap<String, Vec<Pin>>) -> Result<rustls::ClientConfig> {
+    let mut roots = rustls::RootCertStore::empty();
+    let probe = openssl_probe::probe();
+    let system = probe.cert_file.into_iter().chain(probe.cert_dir);
+    let extra = Config::ca_bundle();
+    for path in system.chain(extra.clone()) {
+        let required = Some(&path) == extra.as_ref();
+        let files = match bundle_files(&path) {
+            Ok(files) => files,
+            Err(e) if required => return Err(e),
+            Err(_) => continue,
+        };
+        for file in files {
+            let pem = match read_bundle(&file) {
+                Ok(pem) => pem,
+                Err(e) if required => return Err(e),
+                Err(_) => continue,
+            };
+            let certificates: Vec<CertificateDer> = CertificateDer::pem_slice_iter(&pem)
+                .filter_map(|certificate| certificate.ok())
+                .collect();
+            let (added, _) = roots.add_parsable_certificates(certificates);
+            debug!(
+                "Trusting {} CA certificate(s) from {}",
+                added,
+                file.display()
+            );
+        }
+    }
+    if roots.is_empty() {
+        anyhow::bail!("Found no CA certificates to verify pinned hosts with");
+    }
+
+    let provider = Arc::new(rustls::crypto::ring::default_provider());
+    let webpki = WebPkiServerVerifier::builder_with_provider(Arc::new(roots), provider.clone())
+        .build()
+        .context("Failed to set up certificate verification")?;
+    let config = rustls::ClientConfig::builder_with_provider(provider)
+        .with_safe_default_protocol_versions()
+        .context("Failed to set up TLS")?
+        .dangerous()
+        .with_custom_certificate_verifier(Arc::new(PinnedVerifier { webpki, pins }))
+        .with_no_client_auth();
+    Ok(config)
+}
+
+// Verifies certificates as usual, then against the pins of their host
+#[derive(Debug)]
+struct PinnedVerifier {
+    webpki: Arc<WebPkiServerVerifier>,
+    pins: HashMap<String, Vec<Pin>>,
+}
+
+impl ServerCertVerifier for PinnedVerifier {
+    fn verify_server_cert(
+        &self,
+        end_entity: &CertificateDer<'_>,
+        intermediates: &[CertificateDer<'_>],
+        server_name: &ServerName<'_>,
+        ocsp_response: &[u8],
+        now: UnixTime,
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        let verified = self.webpki.verify_server_cert(
+            end_entity,
+            intermediates,
+            server_name,
+            ocsp_response,
+            now,
+        )?;
+        let host = match server_name {
+            ServerName::DnsName(name) => name.as_ref().to_ascii_lowercase(),
+            ServerName::IpAddress(ip) => std::net::IpAddr::from(*ip).to_string(),
+            _ => return Ok(verified),
+        };
+        let Some(pins) = self.pins.get(&host) else {
+            return Ok(verified);
+        };
+        let presented = Pin::of_certificate(end_entity.as_ref());
+        if pins.iter().any(|pin| presented.contains(pin)) {
+            return Ok(verified);
+        }
+        Err(rustls::Error::General(format!(
+            "the certificate of {} matches none of its pins; it presented {}",
+            host,
+            presented
+                .iter()
+                .map(Pin::to_string)
+                .collect::<Vec<_>>()
+                .join(" and ")
+        )))
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        self.webpki.verify_tls12_signature(message, cert, dss)
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        self.webpki.verify_tls13_signature(message, cert, dss)
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.webpki.supported_verify_schemes()
+    }
+}
+
+/// Bounds a request, from connecting until its body is read, by `Config::http_timeout`
+pub fn bounded(request: RequestBuilder) -> RequestBuilder {
+    match Config::http_timeout() {
+        Some(timeout) => request.timeout(timeout),
+        None => request,
+    }
+}
+
+/// Sends a request, refusing plain HTTP to a pinned host before anything goes out,
+/// and checks the answer against the TLS pins of its host
+pub fn send(request: RequestBuilder) -> Result<Response> {
+    let (client, request) = request.build_split();
+    let request = request?;
+    if let Some(host) = request.url().host_str() {
+        let pinned = Config::tls_pins()
+            .keys()
+            .any(|pinned| pinned.eq_ignore_ascii_case(host));
+        if request.url().scheme() != "https" && pinned {
+            anyhow::bail!(
+                "{} is pinned, so {} has to use https; refusing to send the request",
+                host,
+                request.url()
+            );
+        }
+    }
+    check_pins(client.execute(request)?)
+}
+
+/// Fails unless a pinned host answered with a certificate matching one of its pins.
+/// Checked before anything reads the body, so a certificate issued by a
+/// compromised CA cannot deliver a bundle.
+pub fn check_pins(response: Response) -> Result<Response> {
+    let url = response.url();
+    let Some(host) = url.host_str() else {
+        return Ok(response);
+    };
+    let Some(pins) = Config::tls_pins().remove(host) else {
+        return Ok(response);
+    };
+
+    let certificate = response
+        .extensions()
+        .get::<TlsInfo>()
+        .and_then(TlsInfo::peer_certificate)
+        .with_context(|| {
+            format!(
+                "{} is pinned but answered {} without TLS; refusing the answer",
+                host, url
+            )
+        })?;
+    let presented = Pin::of_certificate(certificate);
+    for pin in &pins {
+        if presented.contains(&Pin::parse(pin)?) {
+            return Ok(response);
+        }
+    }
+    anyhow::bail!(
+        "The TLS certificate of {} matches none of its pins; refusing the answer. It presented {}",
+        host,
+        presented
+            .iter()
+            .map(Pin::to_string)
+            .collect::<Vec<_>>()
+            .join(" and ")
+    )
+}
+
+// A pinned public key or certificate, by SHA-256
+#[derive(Debug, PartialEq, Eq)]
+enum Pin {
+    /// `sha256/<base64>` of the DER SubjectPublicKeyInfo, as used by HPKP and curl;
+    /// stays valid when the certificate is renewed with the same key
+    PublicKey(Vec<u8>),
+    /// `cert-sha256/<hex>` fingerprint of the whole certificate, colons allowed
+    Certificate(Vec<u8>),
+}
+
+impl Pin {
+    fn parse(pin: &str) -> Result<Self> {
+        let pin = pin.trim();
+        let (kind, digest) = if let Some(encoded) = pin.strip_prefix("sha256/") {
+            let digest = base64::engine::general_purpose::STANDARD
+                .decode(encoded)
+                .with_context(|| format!("'{}' is not valid base64", encoded))?;
+            (Pin::PublicKey as fn(Vec<u8>) -> Pin, digest)
+        } else if let Some(encoded) = pin.strip_prefix("cert-sha256/") {
+            let digest = hex::decode(encoded.replace(':', ""))
+                .with_context(|| format!("'{}' is not valid hex", encoded))?;
+            (Pin::Certificate as fn(Vec<u8>) -> Pin, digest)
+        } else {
+            anyhow::bail!("'{}' is neither sha256/<base64> nor cert-sha256/<hex>", pin);
+        };
+        if digest.len() != 32 {
+            anyhow::bail!("'{}' is not a SHA-256 digest", pin);
+        }
+        Ok(kind(digest))
+    }
+
+    // The pins a DER certificate matches
+    fn of_certificate(der: &[u8]) -> Vec<Pin> {
+        let mut pins = vec![Pin::Certificate(Sha256::digest(der).to_vec())];
+        if let Some(spki) = der::subject_public_key_info(der) {
+            pins.insert(0, Pin::PublicKey(Sha256::digest(spki).to_vec()));
+        }
+        pins
+    }
+}
+
+impl std::fmt::Display for Pin {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Pin::PublicKey(digest) => write!(
+                f,
+                "sha256/{}",
+                base64::engine::general_purpose::STANDARD.encode(digest)
+            ),
+            Pin::Certificate(digest) => write!(f, "cert-sha256/{}", hex::encode(digest)),
+        }
+    }
+}
+
+// The certificates of a PEM bundle, or of the `.pem` and `.crt` files in a directory
+fn load_certificates(path: &Path) -> Result<Vec<Certificate>> {
+    let mut certificates = Vec::new();
+    for file in bundle_files(path)? {
+        let pem = read_bundle(&file)?;
+        let bundle = Certificate::from_pem_bundle(&pem)
+            .with_context(|| format!("Invalid CA bundle {}", file.display()))?;
+        debug!(
+            "Trusting {} CA certificate(s) from {}",
+            bundle.len(),
+            file.display()
+        );
+        certificates.extend(bundle);
+    }
+    if certificates.is_empty() {
+        anyhow::bail!("No CA certificates found in {}", path.display());
+    }
+    Ok(certificates)
+}
+
+// A PEM bundle, or the `.pem` and `.crt` files in a directory
+fn bundle_files(path: &Path) -> Result<Vec<PathBuf>> {
+    let files = if path.is_dir() {
+        let mut files: Vec<_> = fs::read_dir(path)
+            .with_context(|| format!("Failed to read CA directory {}", path.display()))?
+            .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+            .filter(|file| {
+                file.extension()
+                    .is_some_and(|ext| ext == "pem" || ext == "crt")
+            })
+            .collect();
+        files.sort();
+        files
+    } else {
+        vec![path.to_path_buf()]
+    };
+    Ok(files)
+}
+
+fn read_bundle(file: &Path) -> Result<Vec<u8>> {
+    fs::read(file).with_context(|| format!("Failed to read CA bundle {}", file.display()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use openssl::asn1::Asn1Time;
+    use openssl::ec::{EcGroup, EcKey};
+    use openssl::hash::MessageDigest;
+    use openssl::nid::Nid;
+    use openssl::pkey::{PKey, Private};
+    use openssl::x509::extension::{
+        BasicConstraints, ExtendedKeyUsage, KeyUsage, SubjectAlternativeName,
+    };
+    use openssl::x509::{X509Builder, X509Name, X509};
+
+    const HOST: &str = "registry.example.com";
+
+    fn key() -> PKey<Private> {
+        let group = EcGroup::from_curve_name(Nid::X9_62_PRIME256V1).unwrap();
+        PKey::from_ec_key(EcKey::generate(&group).unwrap()).unwrap()
+    }
+
+    fn name(common_name: &str) -> X509Name {
+        let mut name = X509Name::builder().unwrap();
+        name.append_entry_by_nid(Nid::COMMONNAME, common_name)
+            .unwrap();
+        name.build()
+    }
+
+    fn certificate(subject: &str, issuer: &str, key: &PKey<Private>) -> X509Builder {
+        let mut builder = X509Builder::new().unwrap();
+        builder.set_version(2).unwrap();
+        builder.set_subject_name(&name(subject)).unwrap();
+        builder.set_issuer_name(&name(issuer)).unwrap();
+        builder.set_pubkey(key).unwrap();
+        builder
+            .set_not_before(&Asn1Time::days_from_now(0).unwrap())
+            .unwrap();
+        builder
+            .set_not_after(&Asn1Time::days_from_now(30).unwrap())
+            .unwrap();
+        builder
+    }
+
+    // A CA and a server certificate for `HOST` it issued
+    fn issue() -> (X509, X509) {
+        let ca_key = key();
+        let mut ca = certificate("Test CA", "Test CA", &ca_key);
+        ca.append_extension(BasicConstraints::new().critical().ca().build().unwrap())
+            .unwrap();
+        ca.append_extension(KeyUsage::new().critical().key_cert_sign().build().unwrap())
+            .unwrap();
+        ca.sign(&ca_key, MessageDigest::sha256()).unwrap();
+        let ca = ca.build();
+
+        let mut server = certificate(HOST, "Test CA", &key());
+        let san = SubjectAlternativeName::new()
+            .dns(HOST)
+            .build(&server.x509v3_context(Some(&ca), None))
+            .unwrap();
+        server.append_extension(san).unwrap();
+        server
+            .append_extension(ExtendedKeyUsage::new().server_auth().build().unwrap())
+            .unwrap();
+        server.sign(&ca_key, MessageDigest::sha256()).unwrap();
+        (ca, server.build())
+    }
+
+    // Trusts `ca` and pins `HOST`
+    fn verifier(ca: &X509, pins: &[&str]) -> PinnedVerifier {
+        let mut roots = rustls::RootCertStore::empty();
+        roots
+            .add(CertificateDer::from(ca.to_der().unwrap()))
+            .unwrap();
+        let provider = Arc::new(rustls::crypto::ring::default_provider());
+        let webpki = WebPkiServerVerifier::builder_with_provider(Arc::new(roots), provider)
+            .build()
+            .unwrap();
+        let pins = pins.iter().map(|pin| Pin::parse(pin).unwrap()).collect();
+        PinnedVerifier {
+            webpki,
+            pins: HashMap::from([(HOST.to_string(), pins)]),
+        }
+    }
+
+    fn verify(verifier: &PinnedVerifier, server: &X509, host: &str) -> Result<(), rustls::Error> {
+        let name = ServerName::try_from(host.to_string()).unwrap();
+        verifier
+            .verify_server_cert(
+                &CertificateDer::from(server.to_der().unwrap()),
+                &[],
+                &name,
+                &[],
+                UnixTime::now(),
+            )
+            .map(|_| ())
+    }
+
+    fn key_pin(certificate: &X509) -> String {
+        let spki = certificate
+            .public_key()
+            .unwrap()
+            .public_key_to_der()
+            .unwrap();
+        format!(
+            "sha256/{}",
+            base64::engine::general_purpose::STANDARD.encode(Sha256::digest(spki))
+        )
+    }
+
+    fn certificate_pin(certificate: &X509) -> String {
+        let digest = Sha256::digest(certificate.to_der().unwrap());
+        let hex: Vec<String> = digest.iter().map(|byte| format!("{:02X}", byte)).collect();
+        format!("cert-sha256/{}", hex.join(":"))
+    }
+
+    #[test]
+    fn parses_key_and_certificate_pins() {
+        let digest = [7u8; 32];
+        let key = format!(
+            "sha256/{}",
+            base64::engine::general_purpose::STANDARD.encode(digest)
+        );
+        assert_eq!(Pin::parse(&key).unwrap(), Pin::PublicKey(digest.to_vec()));
+        let certificate = format!("cert-sha256/{}", hex::encode(digest));
+        assert_eq!(
+            Pin::parse(&certificate).unwrap(),
+            Pin::Certificate(digest.to_vec())
+        );
+        assert_eq!(Pin::parse(&key).unwrap().to_string(), key);
+
+        assert!(Pin::parse("sha256/AAAA").is_err());
+        assert!(Pin::parse("sha256/not base64!").is_err());
+        assert!(Pin::parse("cert-sha256/xyz").is_err());
+        assert!(Pin::parse(&format!("sha1/{}", hex::encode(digest))).is_err());
+    }
+
+    #[test]
+    fn pins_the_key_and_the_certificate() {
+        let (_, server) = issue();
+        let presented = Pin::of_certificate(&server.to_der().unwrap());
+        assert_eq!(
+            presented,
+            vec![
+                Pin::parse(&key_pin(&server)).unwrap(),
+                Pin::parse(&certificate_pin(&server)).unwrap(),
+            ]
+        );
+    }
+
+    #[test]
+    fn accepts_a_certificate_matching_a_pin() {
+        let (ca, server) = issue();
+        let other = format!("cert-sha256/{}", hex::encode([0u8; 32]));
+        assert!(verify(&verifier(&ca, &[&other, &key_pin(&server)]), &server, HOST).is_ok());
+        assert!(verify(&verifier(&ca, &[&certificate_pin(&server)]), &server, HOST).is_ok());
+    }
+
+    #[test]
+    fn refuses_a_certificate_matching_no_pin() {
+        let (ca, server) = issue();
+        // Issued by the same trusted CA, but not the pinned key
+        let (_, other) = issue();
+        let error = verify(&verifier(&ca, &[&key_pin(&other)]), &server, HOST).unwrap_err();
+        let message = error.to_string();
+        assert!(message.contains("matches none of its pins"), "{}", message);
+        assert!(message.contains(&key_pin(&server)), "{}", message);
+    }
+
+    #[test]
+    fn refuses_an_untrusted_certificate_even_when_pinned() {
+        let (ca, _) = issue();
+        let (_, server) = issue();
+        assert!(verify(&verifier(&ca, &[&key_pin(&server)]), &server, HOST).is_err());
+    }
+
+    #[test]
+    fn leaves_unpinned_hosts_to_the_usual_checks() {
+        let (ca, server) = issue();
+        let mut verifier = verifier(&ca, &[]);
+        let pins = verifier.pins.remove(HOST).unwrap();
+        verifier.pins.insert("mirror.example.com".to_string(), pins);
+        assert!(verify(&verifier, &server, HOST).is_ok());
+    }
+}
@@ -0,0 +1,144 @@
+use crate::config::Config;
+use crate::services::http;
+use anyhow::{Context, Result};
+use chrono::{DateTime, Utc};
+use reqwest::blocking::RequestBuilder;
+use reqwest::header::{HeaderValue, ETAG, IF_MODIFIED_SINCE, IF_NONE_MATCH, LAST_MODIFIED};
+use reqwest::StatusCode;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::fs;
+use std::path::PathBuf;
+use tracing::{debug, warn};
+
+/// The answer to a GET sent through `ConditionalCache`
+#[derive(Debug)]
+pub struct CachedAnswer {
+    /// 200 when the server answered 304 and the body is the cached one
+    pub status: StatusCode,
+    pub body: String,
+    /// The server confirmed the cached body is still current
+    pub not_modified: bool,
+}
+
+// A body remembered with the validators the server sent for it
+#[derive(Serialize, Deserialize)]
+struct Entry {
+    url: String,
+    etag: Option<String>,
+    last_modified: Option<String>,
+    body: String,
+    fetched_at: DateTime<Utc>,
+}
+
+/// Small registry answers that are polled often, such as the latest version,
+/// remembered with their `ETag` and `Last-Modified` headers under the data
+/// directory. Later requests send `If-None-Match`/`If-Modified-Since`, so an
+/// unchanged answer costs a bodiless 304, which GitHub also does not count
+/// against the rate limit.
+pub struct ConditionalCache {
+    dir: PathBuf,
+}
+
+impl ConditionalCache {
+    pub fn new(data_dir: PathBuf) -> Self {
+        Self {
+            dir: data_dir.join(Config::HTTP_CACHE_DIR),
+        }
+    }
+
+    fn entry_path(&self, url: &str) -> PathBuf {
+        self.dir.join(format!(
+            "{}.json",
+            hex::encode(Sha256::digest(url.as_bytes()))
+        ))
+    }
+
+    fn load(&self, url: &str) -> Option<Entry> {
+        let contents = fs::read_to_string(self.entry_path(url)).ok()?;
+        serde_json::from_str::<Entry>(&contents)
+            .ok()
+            .filter(|entry| entry.url == url)
+    }
+
+    fn store(&self, entry: &Entry) -> Result<()> {
+        fs::create_dir_all(&self.dir).context("Failed to create the HTTP cache")?;
+        let path = self.entry_path(&entry.url);
+        let tmp_path = path.with_extension("tmp");
+        fs::write(&tmp_path, serde_json::to_vec(entry)?)?;
+        fs::rename(&tmp_path, &path)?;
+        Ok(())
+    }
+
+    /// Sends a GET, revalidating the answer remembered for its URL if there is one
+    pub fn get(&self, request: RequestBuilder) -> Result<CachedAnswer> {
+        let (client, request) = request.build_split();
+        let mut request = request.context("Invalid request")?;
+        let url = request.url().to_string();
+
+        let cached = self.load(&url);
+        if let Some(entry) = &cached {
+            let headers = request.headers_mut();
+            if let Some(etag) = entry.etag.as_deref().and_then(|v| v.parse().ok()) {
+                headers.insert(IF_NONE_MATCH, etag);
+            }
+            if let Some(at) = entry.last_modified.as_deref().and_then(|v| v.parse().ok()) {
+                headers.insert(IF_MODIFIED_SINCE, at);
+            }
+        }
+
+        let response = client
+            .execute(request)
+            .map_err(anyhow::Error::from)
+            .and_then(http::check_pins)
+            .with_context(|| format!("Failed to query {}", url))?;
+        let status = response.status();
+        if status == StatusCode::NOT_MODIFIED {
+            if let Some(entry) = cached {
+                debug!("{} not modified since {}", url, entry.fetched_at);
+                return Ok(CachedAnswer {
+                    status: StatusCode::OK,
+                    body: entry.body,
+                    not_modified: true,
+                });
+            }
+        }
+
+        let header = |name| {
+            response
+                .headers()
+                .get(name)
+                .and_then(|v: &HeaderValue| v.to_str().ok())
+                .map(str::to_string)
+        };
+        let etag = header(ETAG);
+        let last_modified = header(LAST_MODIFIED);
+        let body = response
+            .text()
+            .with_context(|| format!("Failed to read the answer of {}", url))?;
+
+        // Only answers the server can revalidate are worth keeping
+        if status == StatusCode::OK && (etag.is_some() || last_modified.is_some()) {
+            let entry = Entry {
+                url,
+                etag,
+                last_modified,
+                body,
+                fetched_at: Utc::now(),
+            };
+            if let Err(e) = self.store(&entry) {
+                warn!("Failed to cache the answer of {}: {:#}", entry.url, e);
+            }
+            return Ok(CachedAnswer {
+                status,
+                body: entry.body,
+                not_modified: false,
+            });
+        }
+        Ok(CachedAnswer {
+            status,
+            body,
+            not_modified: false,
+        })
+    }
+}
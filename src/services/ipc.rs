@@ -0,0 +1,105 @@
+use crate::config::Config;
+use crate::services::{HostService, StateService};
+use anyhow::{Context, Result};
+use std::io::{BufRead, BufReader, ErrorKind, Read, Write};
+use std::path::PathBuf;
+use std::time::Duration;
+
+/// Talks to the running app over its control socket, which the supervisor passes
+/// to it in GEIST_IPC_SOCKET. A request is a single line; the app answers with any
+/// number of lines and closes the connection.
+pub struct IpcService {
+    data_dir: PathBuf,
+}
+
+impl IpcService {
+    pub fn new(data_dir: PathBuf) -> Self {
+        Self { data_dir }
+    }
+
+    pub fn socket_path(&self) -> PathBuf {
+        self.data_dir
+            .join(Config::APP_RUN_DIR)
+            .join(Config::APP_SOCKET_FILE)
+    }
+
+    /// Sends a request and returns the complete answer, failing if the app takes
+    /// longer than `IPC_TIMEOUT_SECS` to respond
+    pub fn request(&self, request: &str) -> Result<String> {
+        self.request_within(request, Duration::from_secs(Config::IPC_TIMEOUT_SECS))
+    }
+
+    /// Like `request`, with a custom time limit for the answer
+    pub fn request_within(&self, request: &str, timeout: Duration) -> Result<String> {
+        let mut stream = self.send(request, Some(timeout))?;
+
+        let mut reply = String::new();
+        match stream.read_to_string(&mut reply) {
+            Ok(_) => Ok(reply),
+            Err(e) if matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => {
+                anyhow::bail!("Application did not answer within {:?}", timeout)
+            }
+            Err(e) => Err(e).context("Failed to read the application's answer"),
+        }
+    }
+
+    /// Sends a request and passes each line of the answer on as it arrives, for as
+    /// long as the app keeps the connection open
+    pub fn stream(&self, request: &str, mut on_line: impl FnMut(&str)) -> Result<()> {
+        let stream = self.send(request, None)?;
+        for line in BufReader::new(stream).lines() {
+            on_line(&line.context("Failed to read from the application")?);
+        }
+        Ok(())
+    }
+
+    /// Whether the app accepts connections on its control socket
+    pub fn is_listening(&self) -> bool {
+        self.connect().is_ok()
+    }
+
+    fn send(&self, request: &str, read_timeout: Option<Duration>) -> Result<impl Read> {
+        let mut stream = self.connect()?;
+        stream.set_read_timeout(read_timeout)?;
+        stream.set_write_timeout(Some(Duration::from_secs(Config::IPC_TIMEOUT_SECS)))?;
+        writeln!(stream, "{}", request).context("Failed to send request to the application")?;
+        Ok(stream)
+    }
+
+    // Connecting to a socket nobody listens on fails immediately, so an offline app
+    // is reported right away instead of after a timeout
+    #[cfg(unix)]
+    fn connect(&self) -> Result<std::os::unix::net::UnixStream> {
+        std::os::unix::net::UnixStream::connect(self.socket_path())
+            .map_err(|_| anyhow::anyhow!("{}", self.offline_reason()))
+    }
+
+    // Apps only run on Linux devices; the stream type just keeps callers uniform
+    #[cfg(not(unix))]
+    fn connect(&self) -> Result<std::net::TcpStream> {
+        anyhow::bail!("Node and topic commands are only available on Linux devices")
+    }
+
+    /// Why the app cannot be reached, from the pid file and the supervisor state
+    pub fn offline_reason(&self) -> String {
+        if let Some(pid) = HostService::running_pid(&self.data_dir.join(Config::APP_PID_FILE)) {
+            return format!(
+                "Application is running (pid {}) but not accepting commands on {}",
+                pid,
+                self.socket_path().display()
+            );
+        }
+
+        let last_exit = StateService::new(self.data_dir.clone())
+            .load()
+            .ok()
+            .and_then(|state| state.last_exit);
+        match last_exit {
+            Some(exit) => format!(
+                "Application is not running (last exit: {})",
+                exit.describe()
+            ),
+            None => "Application is not running".to_string(),
+        }
+    }
+}
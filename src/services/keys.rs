@@ -0,0 +1,310 @@
+use crate::config::Config;
+use anyhow::{Context, Result};
+use base64::Engine;
+use chrono::{DateTime, Utc};
+use minisign_verify::{PublicKey, Signature};
+use serde::{Deserialize, Serialize};
+use std::fs::{self, OpenOptions};
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use tracing::info;
+
+/// A public key trusted to sign releases, optionally limited to a validity window
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TrustedKey {
+    /// Minisign key id, as printed by `minisign -G`
+    pub key_id: String,
+    /// Base64 encoded minisign public key
+    pub public_key: String,
+    pub added_at: DateTime<Utc>,
+    #[serde(default)]
+    pub not_before: Option<DateTime<Utc>>,
+    #[serde(default)]
+    pub not_after: Option<DateTime<Utc>>,
+    /// Key that signed this key into the trust store, if it arrived by rotation
+    #[serde(default)]
+    pub signed_by: Option<String>,
+}
+
+impl TrustedKey {
+    pub fn is_valid_at(&self, at: DateTime<Utc>) -> bool {
+        self.not_before.is_none_or(|t| at >= t) && self.not_after.is_none_or(|t| at < t)
+    }
+}
+
+/// What happened to a trusted key
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum KeyAction {
+    Added,
+    Rotated,
+    Removed,
+}
+
+/// A change to the trusted keys, as kept in the audit trail
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct KeyAuditEntry {
+    pub at: DateTime<Utc>,
+    pub action: KeyAction,
+    pub key_id: String,
+    /// For rotations, the trusted key that signed the new one
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub signed_by: Option<String>,
+    /// The local user who made the change, or the release bundle a rotation came from
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub by: Option<String>,
+}
+
+/// Trust anchors for verifying update signatures, stored under the data directory
+pub struct KeyService {
+    path: PathBuf,
+    audit_path: PathBuf,
+}
+
+impl KeyService {
+    pub fn new(data_dir: PathBuf) -> Self {
+        Self {
+            path: data_dir.join(Config::TRUSTED_KEYS_FILE),
+            audit_path: data_dir.join(Config::KEY_AUDIT_FILE),
+        }
+    }
+
+    pub fn load(&self) -> Result<Vec<TrustedKey>> {
+        if !self.path.exists() {
+            return Ok(Vec::new());
+        }
+
+        let contents = fs::read_to_string(&self.path).context("Failed to read trusted keys")?;
+        serde_json::from_str(&contents).context("Failed to parse trusted keys")
+    }
+
+    fn save(&self, keys: &[TrustedKey]) -> Result<()> {
+        let tmp_path = self.path.with_extension("json.tmp");
+        fs::write(&tmp_path, serde_json::to_string_pretty(keys)?)
+            .context("Failed to write trusted keys")?;
+        fs::rename(&tmp_path, &self.path).context("Failed to replace trusted keys")?;
+        Ok(())
+    }
+
+    // Appends to the audit trail; a change that cannot be audited is not made
+    fn audit(&self, action: KeyAction, key: &TrustedKey, by: Option<String>) -> Result<()> {
+        let entry = KeyAuditEntry {
+            at: Utc::now(),
+            action,
+            key_id: key.key_id.clone(),
+            signed_by: key.signed_by.clone(),
+            by: by.or_else(|| std::env::var("SUDO_USER").or(std::env::var("USER")).ok()),
+        };
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.audit_path)
+            .with_context(|| format!("Failed to open {}", self.audit_path.display()))?;
+        writeln!(file, "{}", serde_json::to_string(&entry)?)
+            .context("Failed to write the key audit trail")
+    }
+
+    /// Every recorded change to the trusted keys, oldest first
+    pub fn audit_trail(&self) -> Result<Vec<KeyAuditEntry>> {
+        if !self.audit_path.exists() {
+            return Ok(Vec::new());
+        }
+        let contents = fs::read_to_string(&self.audit_path)
+            .with_context(|| format!("Failed to read {}", self.audit_path.display()))?;
+        // A line cut short by a crash mid-write is skipped
+        Ok(contents
+            .lines()
+            .filter_map(|line| serde_json::from_str(line).ok())
+            .collect())
+    }
+
+    /// Parses a minisign public key file, or a bare base64 key, into an untrusted key record
+    pub fn parse_public_key(contents: &str) -> Result<TrustedKey> {
+        let encoded = contents
+            .lines()
+            .map(str::trim)
+            .find(|line| !line.is_empty() && !line.starts_with("untrusted comment:"))
+            .context("Public key file is empty")?;
+
+        PublicKey::from_base64(encoded)
+            .map_err(|e| anyhow::anyhow!("Invalid minisign public key: {}", e))?;
+
+        // Bytes 2..10 hold the key id, which minisign displays as a little-endian hex number
+        let raw = base64::engine::general_purpose::STANDARD.decode(encoded)?;
+        let mut key_id = [0u8; 8];
+        key_id.copy_from_slice(&raw[2..10]);
+
+        Ok(TrustedKey {
+            key_id: format!("{:016X}", u64::from_le_bytes(key_id)),
+            public_key: encoded.to_string(),
+            added_at: Utc::now(),
+            not_before: None,
+            not_after: None,
+            signed_by: None,
+        })
+    }
+
+    /// Adds a key to the trust store without requiring a signature from an existing key
+    pub fn add(&self, contents: &str) -> Result<TrustedKey> {
+        let key = Self::parse_public_key(contents)?;
+        let mut keys = self.load()?;
+        if let Some(existing) = keys.iter().find(|k| k.key_id == key.key_id) {
+            return Ok(existing.clone());
+        }
+
+        self.audit(KeyAction::Added, &key, None)?;
+        keys.push(key.clone());
+        self.save(&keys)?;
+        info!("Added trusted key {}", key.key_id);
+        Ok(key)
+    }
+
+    /// Stops trusting a key, identified by its key id
+    pub fn remove(&self, key_id: &str) -> Result<TrustedKey> {
+        let mut keys = self.load()?;
+        let index = keys
+            .iter()
+            .position(|k| k.key_id.eq_ignore_ascii_case(key_id))
+            .with_context(|| format!("Key {} is not trusted", key_id))?;
+
+        let key = keys.remove(index);
+        self.audit(KeyAction::Removed, &key, None)?;
+        self.save(&keys)?;
+        info!("Removed trusted key {}", key.key_id);
+        Ok(key)
+    }
+
+    /// Checks a minisign signature against the keys valid right now, returning the signer
+    pub fn verify(&self, data: &[u8], signature: &str) -> Result<TrustedKey> {
+        let signature = Signature::decode(signature)
+            .map_err(|e| anyhow::anyhow!("Invalid signature: {}", e))?;
+        let now = Utc::now();
+
+        for key in self.load()?.into_iter().filter(|k| k.is_valid_at(now)) {
+            let public_key = PublicKey::from_base64(&key.public_key)
+                .map_err(|e| anyhow::anyhow!("Corrupt trusted key {}: {}", key.key_id, e))?;
+            if public_key.verify(data, &signature, false).is_ok() {
+                return Ok(key);
+            }
+        }
+
+        anyhow::bail!("Signature does not match any currently valid trusted key")
+    }
+
+    /// Checks a minisign signature of a file too large to hold in memory, such as a
+    /// release bundle, reading it once. Only prehashed signatures, which minisign
+    /// makes by default, can be checked this way.
+    pub fn verify_file(&self, path: &Path, signature: &str) -> Result<TrustedKey> {
+        let signature = Signature::decode(signature)
+            .map_err(|e| anyhow::anyhow!("Invalid signature: {}", e))?;
+        let now = Utc::now();
+
+        for key in self.load()?.into_iter().filter(|k| k.is_valid_at(now)) {
+            let public_key = PublicKey::from_base64(&key.public_key)
+                .map_err(|e| anyhow::anyhow!("Corrupt trusted key {}: {}", key.key_id, e))?;
+            // Fails for every key but the one the signature names
+            let Ok(mut verifier) = public_key.verify_stream(&signature) else {
+                continue;
+            };
+
+            let mut file = fs::File::open(path)
+                .with_context(|| format!("Failed to open {}", path.display()))?;
+            let mut buffer = vec![0u8; 64 * 1024];
+            loop {
+                let read = file.read(&mut buffer)?;
+                if read == 0 {
+                    break;
+                }
+                verifier.update(&buffer[..read]);
+            }
+            if verifier.finalize().is_ok() {
+                return Ok(key);
+            }
+        }
+
+        anyhow::bail!("Signature does not match any currently valid trusted key")
+    }
+
+    /// Trusts a new key whose public key file is signed by a currently valid key.
+    ///
+    /// The signing key keeps working for `overlap` so releases signed before the
+    /// rotation still verify while the fleet picks up the new key.
+    pub fn rotate(&self, new_key: &str, signature: &str, overlap: Duration) -> Result<TrustedKey> {
+        self.rotate_by(new_key, signature, overlap, None)
+    }
+
+    fn rotate_by(
+        &self,
+        new_key: &str,
+        signature: &str,
+        overlap: Duration,
+        by: Option<String>,
+    ) -> Result<TrustedKey> {
+        let signer = self
+            .verify(new_key.as_bytes(), signature)
+            .context("New key is not signed by a trusted key")?;
+
+        let mut key = Self::parse_public_key(new_key)?;
+        key.signed_by = Some(signer.key_id.clone());
+
+        let mut keys = self.load()?;
+        if let Some(existing) = keys.iter().find(|k| k.key_id == key.key_id) {
+            return Ok(existing.clone());
+        }
+
+        let retire_at = Utc::now() + chrono::Duration::from_std(overlap)?;
+        for existing in keys.iter_mut().filter(|k| k.key_id == signer.key_id) {
+            existing.not_after = Some(existing.not_after.map_or(retire_at, |t| t.min(retire_at)));
+        }
+
+        self.audit(KeyAction::Rotated, &key, by)?;
+        keys.push(key.clone());
+        self.save(&keys)?;
+        info!(
+            "Rotated trust from key {} to key {}, old key retires at {}",
+            signer.key_id, key.key_id, retire_at
+        );
+        Ok(key)
+    }
+
+    /// Applies key rotations shipped in a release bundle as `*.pub` files with a
+    /// `.pub.minisig` signature next to them
+    pub fn import_rotations(&self, dir: &Path, overlap: Duration) -> Result<Vec<TrustedKey>> {
+        let mut rotated = Vec::new();
+        for key_path in find_public_keys(dir)? {
+            let signature_path = key_path.with_extension("pub.minisig");
+            if !signature_path.is_file() {
+                continue;
+            }
+
+            let new_key = fs::read_to_string(&key_path)?;
+            let signature = fs::read_to_string(&signature_path)?;
+            let by = format!(
+                "release bundle ({})",
+                key_path.strip_prefix(dir).unwrap_or(&key_path).display()
+            );
+            match self.rotate_by(&new_key, &signature, overlap, Some(by)) {
+                Ok(key) => rotated.push(key),
+                Err(e) => {
+                    tracing::warn!("Ignoring key rotation from {}: {:#}", key_path.display(), e)
+                }
+            }
+        }
+        Ok(rotated)
+    }
+}
+
+// Recursively collects `*.pub` files below a directory
+fn find_public_keys(dir: &Path) -> Result<Vec<PathBuf>> {
+    let mut keys = Vec::new();
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.is_dir() {
+            keys.extend(find_public_keys(&path)?);
+        } else if path.extension().is_some_and(|ext| ext == "pub") {
+            keys.push(path);
+        }
+    }
+    Ok(keys)
+}
@@ -0,0 +1,217 @@
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use tracing::{info, warn};
+
+// The profile of a version that applies on this device
+enum Active<'a> {
+    AppArmor(&'a AppArmorProfile),
+    SeLinux(&'a SeLinuxModule),
+}
+
+fn apparmor_enabled() -> bool {
+    fs::read_to_string("/sys/module/apparmor/parameters/enabled")
+        .is_ok_and(|enabled| enabled.trim() == "Y")
+}
+
+fn selinux_enabled() -> bool {
+    Path::new("/sys/fs/selinux/enforce").exists()
+}
+
+/// An AppArmor profile shipped in the bundle
+#[derive(Debug, Clone, Deserialize)]
+pub struct AppArmorProfile {
+    /// Profile file next to the manifest
+    pub file: String,
+    /// Name of the profile the file defines, which the app is launched under
+    pub name: String,
+}
+
+/// An SELinux policy module shipped in the bundle
+#[derive(Debug, Clone, Deserialize)]
+pub struct SeLinuxModule {
+    /// Module file next to the manifest, `.cil` or `.pp`
+    pub file: String,
+    /// Security context the app is launched in
+    pub context: String,
+}
+
+/// Confinement a version brings for the app, copied in when the version is
+/// installed, and loaded into the kernel and applied to the app when it is
+/// launched.
+///
+/// Declared in the `mac_profile` section of a version's `manifest.yaml`:
+///
+/// ```yaml
+/// mac_profile:
+///   apparmor:
+///     file: apparmor.profile
+///     name: roc-camera
+///   selinux:
+///     file: roc_camera.cil
+///     context: system_u:system_r:roc_camera_t:s0
+/// ```
+///
+/// Only the profile for the system the device runs is used.
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct MacProfile {
+    pub apparmor: Option<AppArmorProfile>,
+    pub selinux: Option<SeLinuxModule>,
+}
+
+/// The process attribute to write before exec so the kernel switches the app to
+/// its profile
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ExecLabel {
+    pub attr_path: PathBuf,
+    pub value: String,
+}
+
+impl ExecLabel {
+    /// Writes a label prepared as C strings. Only makes system calls, so it can run
+    /// between fork and exec.
+    #[cfg(unix)]
+    pub fn write(attr_path: &std::ffi::CStr, value: &std::ffi::CStr) -> std::io::Result<()> {
+        unsafe {
+            let fd = libc::open(attr_path.as_ptr(), libc::O_WRONLY | libc::O_CLOEXEC);
+            if fd < 0 {
+                return Err(std::io::Error::last_os_error());
+            }
+            let bytes = value.to_bytes();
+            let written = libc::write(fd, bytes.as_ptr().cast(), bytes.len());
+            let error = std::io::Error::last_os_error();
+            libc::close(fd);
+            if written != bytes.len() as isize {
+                return Err(error);
+            }
+        }
+        Ok(())
+    }
+}
+
+// Only the part of the manifest the profiles care about
+#[derive(Deserialize)]
+struct Manifest {
+    #[serde(default)]
+    mac_profile: Option<MacProfile>,
+}
+
+impl MacProfile {
+    /// The profiles declared by an installed version or an extracted bundle, if any
+    pub fn load(manifest_dir: &Path) -> Option<Self> {
+        let path = manifest_dir.join("manifest.yaml");
+        let contents = fs::read_to_string(&path).ok()?;
+        match serde_yaml::from_str::<Manifest>(&contents) {
+            Ok(manifest) => manifest
+                .mac_profile
+                .filter(|profile| profile.apparmor.is_some() || profile.selinux.is_some()),
+            Err(e) => {
+                warn!("Ignoring MAC profile in {}: {}", path.display(), e);
+                None
+            }
+        }
+    }
+
+    /// The declared profile files, relative to the manifest
+    pub fn files(&self) -> Vec<&str> {
+        self.apparmor
+            .iter()
+            .map(|profile| profile.file.as_str())
+            .chain(self.selinux.iter().map(|module| module.file.as_str()))
+            .collect()
+    }
+
+    /// Copies the profile files from beside the bundle's manifest into the version
+    /// directory. Nothing is loaded until the version is launched, so a version
+    /// that never passes its checks cannot change how the running one is confined.
+    pub fn copy_files(&self, manifest_dir: &Path, version_dir: &Path) -> Result<()> {
+        for file in self.files() {
+            if Path::new(file).components().count() != 1 {
+                anyhow::bail!("MAC profile file '{}' must be a plain file name", file);
+            }
+            let source = manifest_dir.join(file);
+            fs::copy(&source, version_dir.join(file))
+                .with_context(|| format!("Failed to copy MAC profile {}", source.display()))?;
+        }
+        Ok(())
+    }
+
+    /// Loads the profile for the system the device enforces from the version
+    /// directory, replacing an older one of the same name
+    pub fn load_into_kernel(&self, version_dir: &Path) -> Result<()> {
+        let mut command = match self.active() {
+            Some(Active::AppArmor(profile)) => {
+                info!("Loading AppArmor profile {}", profile.name);
+                let mut command = Command::new("apparmor_parser");
+                command
+                    .arg("--replace")
+                    .arg(version_dir.join(&profile.file));
+                command
+            }
+            Some(Active::SeLinux(module)) => {
+                info!("Loading SELinux module {}", module.file);
+                let mut command = Command::new("semodule");
+                command.arg("--install").arg(version_dir.join(&module.file));
+                command
+            }
+            None => {
+                warn!("This device enforces none of the access control systems the version brings profiles for; the app will run unconfined");
+                return Ok(());
+            }
+        };
+
+        let output = command
+            .output()
+            .with_context(|| format!("Failed to run {:?}", command.get_program()))?;
+        if !output.status.success() {
+            anyhow::bail!(
+                "{:?} failed: {}",
+                command.get_program(),
+                String::from_utf8_lossy(&output.stderr).trim()
+            );
+        }
+        Ok(())
+    }
+
+    fn active(&self) -> Option<Active<'_>> {
+        if let Some(profile) = self.apparmor.as_ref().filter(|_| apparmor_enabled()) {
+            return Some(Active::AppArmor(profile));
+        }
+        self.selinux
+            .as_ref()
+            .filter(|_| selinux_enabled())
+            .map(Active::SeLinux)
+    }
+
+    /// What to write before exec to launch the app confined, if the device
+    /// enforces a system the version brings a profile for
+    pub fn exec_label(&self) -> Result<Option<ExecLabel>> {
+        let label = match self.active() {
+            Some(Active::AppArmor(profile)) => {
+                // Newer kernels have an AppArmor-specific file, which stays usable
+                // when another security module owns the shared one
+                let attr_path = ["/proc/self/attr/apparmor/exec", "/proc/self/attr/exec"]
+                    .into_iter()
+                    .map(PathBuf::from)
+                    .find(|path| path.exists())
+                    .context("The kernel offers no way to switch AppArmor profiles on exec")?;
+                info!("Confining the app with AppArmor profile {}", profile.name);
+                ExecLabel {
+                    attr_path,
+                    value: format!("exec {}", profile.name),
+                }
+            }
+            Some(Active::SeLinux(module)) => {
+                info!("Confining the app in SELinux context {}", module.context);
+                ExecLabel {
+                    attr_path: PathBuf::from("/proc/self/attr/exec"),
+                    value: module.context.clone(),
+                }
+            }
+            None => return Ok(None),
+        };
+        Ok(Some(label))
+    }
+}
@@ -0,0 +1,204 @@
+use anyhow::{Context, Result};
+use serde::Serialize;
+use serde_yaml::Value;
+use std::fmt;
+
+/// How a manifest entry changed between two versions
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ChangeKind {
+    Added,
+    Removed,
+    Changed,
+}
+
+/// A single entry that differs, addressed by its path in the manifest, e.g.
+/// `health_probe.timeout_secs` or `nodes[camera].env.LOG_LEVEL`
+#[derive(Debug, Clone, Serialize)]
+pub struct ManifestChange {
+    pub path: String,
+    pub kind: ChangeKind,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub from: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub to: Option<String>,
+}
+
+impl fmt::Display for ManifestChange {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let from = self.from.as_deref().unwrap_or_default();
+        let to = self.to.as_deref().unwrap_or_default();
+        match self.kind {
+            ChangeKind::Added => write!(f, "+ {}: {}", self.path, to),
+            ChangeKind::Removed => write!(f, "- {}: {}", self.path, from),
+            ChangeKind::Changed => write!(f, "~ {}: {} -> {}", self.path, from, to),
+        }
+    }
+}
+
+/// What changes in `manifest.yaml` between the current and the target version, so
+/// operators see which behavior an update changes and not just its version number.
+/// Lists are compared by their items, and lists of mappings by the items' `name`,
+/// so reordering nodes is not reported as a change.
+#[derive(Debug, Clone, Serialize)]
+pub struct ManifestDiff {
+    pub from_version: String,
+    pub to_version: String,
+    pub changes: Vec<ManifestChange>,
+}
+
+impl ManifestDiff {
+    /// Compares two manifests. The `version` entry is left out since it always differs.
+    pub fn between(
+        from_version: &str,
+        from_manifest: &str,
+        to_version: &str,
+        to_manifest: &str,
+    ) -> Result<Self> {
+        let mut from: Value = serde_yaml::from_str(from_manifest)
+            .with_context(|| format!("Failed to parse the manifest of {}", from_version))?;
+        let mut to: Value = serde_yaml::from_str(to_manifest)
+            .with_context(|| format!("Failed to parse the manifest of {}", to_version))?;
+        for manifest in [&mut from, &mut to] {
+            if let Value::Mapping(mapping) = manifest {
+                mapping.remove("version");
+            }
+        }
+
+        let mut changes = Vec::new();
+        diff_values("", Some(&from), Some(&to), &mut changes);
+        Ok(Self {
+            from_version: from_version.to_string(),
+            to_version: to_version.to_string(),
+            changes,
+        })
+    }
+
+    /// Prints the changes for people, one line per entry
+    pub fn print(&self) {
+        if self.changes.is_empty() {
+            println!(
+                "manifest.yaml is unchanged from {} to {}",
+                self.from_version, self.to_version
+            );
+            return;
+        }
+        println!(
+            "Changes in manifest.yaml from {} to {}:",
+            self.from_version, self.to_version
+        );
+        for change in &self.changes {
+            println!("  {}", change);
+        }
+    }
+}
+
+fn diff_values(
+    path: &str,
+    from: Option<&Value>,
+    to: Option<&Value>,
+    out: &mut Vec<ManifestChange>,
+) {
+    match (from, to) {
+        (Some(Value::Mapping(from)), Some(Value::Mapping(to))) => {
+            for (key, value) in from {
+                diff_values(&join(path, key), Some(value), to.get(key), out);
+            }
+            for (key, value) in to {
+                if !from.contains_key(key) {
+                    diff_values(&join(path, key), None, Some(value), out);
+                }
+            }
+        }
+        (Some(Value::Sequence(from)), Some(Value::Sequence(to))) => {
+            match (keyed_items(from), keyed_items(to)) {
+                (Some(from), Some(to)) => {
+                    for (id, value) in &from {
+                        let other = to.iter().find(|(other, _)| other == id).map(|(_, v)| *v);
+                        diff_values(&item_path(path, id, value), Some(value), other, out);
+                    }
+                    for (id, value) in &to {
+                        if !from.iter().any(|(other, _)| other == id) {
+                            diff_values(&item_path(path, id, value), None, Some(value), out);
+                        }
+                    }
+                }
+                // Lists whose items cannot be told apart are compared as a whole
+                _ if from != to => out.push(ManifestChange {
+                    path: path.to_string(),
+                    kind: ChangeKind::Changed,
+                    from: Some(display(&Value::Sequence(from.clone()))),
+                    to: Some(display(&Value::Sequence(to.clone()))),
+                }),
+                _ => {}
+            }
+        }
+        (Some(from), Some(to)) if from != to => out.push(ManifestChange {
+            path: path.to_string(),
+            kind: ChangeKind::Changed,
+            from: Some(display(from)),
+            to: Some(display(to)),
+        }),
+        (Some(_), Some(_)) => {}
+        (Some(from), None) => out.push(ManifestChange {
+            path: path.to_string(),
+            kind: ChangeKind::Removed,
+            from: Some(display(from)),
+            to: None,
+        }),
+        (None, Some(to)) => out.push(ManifestChange {
+            path: path.to_string(),
+            kind: ChangeKind::Added,
+            from: None,
+            to: Some(display(to)),
+        }),
+        (None, None) => {}
+    }
+}
+
+// Identifies list items by their value if they are scalars, or by their `name`
+// if they are mappings. None if some item has no such identity.
+fn keyed_items(items: &[Value]) -> Option<Vec<(String, &Value)>> {
+    items
+        .iter()
+        .map(|item| match item {
+            Value::Mapping(mapping) => mapping
+                .get("name")
+                .filter(|name| !matches!(name, Value::Mapping(_) | Value::Sequence(_)))
+                .map(|name| (display(name), item)),
+            Value::Sequence(_) => None,
+            scalar => Some((display(scalar), item)),
+        })
+        .collect()
+}
+
+// Named items get their own path so changes inside them can be told apart;
+// a scalar item is its own name and is reported under the list's path
+fn item_path(path: &str, id: &str, item: &Value) -> String {
+    match item {
+        Value::Mapping(_) => format!("{}[{}]", path, id),
+        _ => path.to_string(),
+    }
+}
+
+fn join(path: &str, key: &Value) -> String {
+    if path.is_empty() {
+        display(key)
+    } else {
+        format!("{}.{}", path, display(key))
+    }
+}
+
+// Scalars as written in YAML, collections on one line
+fn display(value: &Value) -> String {
+    match value {
+        Value::Null => "null".to_string(),
+        Value::Bool(b) => b.to_string(),
+        Value::Number(n) => n.to_string(),
+        Value::String(s) => s.clone(),
+        Value::Tagged(tagged) => format!("{} {}", tagged.tag, display(&tagged.value)),
+        collection => {
+            serde_json::to_string(collection).unwrap_or_else(|_| format!("{:?}", collection))
+        }
+    }
+}
@@ -0,0 +1,102 @@
+use crate::config::Config;
+use crate::services::StateService;
+use anyhow::Result;
+use chrono::{DateTime, Datelike, NaiveDate, Utc};
+use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
+
+/// Bytes of release bundles downloaded in the current billing period
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DataUsage {
+    pub period_start: DateTime<Utc>,
+    pub bytes: u64,
+}
+
+/// Keeps large downloads off metered links: with `Config::metered` set they need
+/// `--force` (unattended updates are deferred), and with `Config::data_budget_bytes`
+/// set they may not exceed what is left of the budget for the billing period
+/// starting on `Config::billing_day`. Usage is kept in the state store.
+pub struct MeteredService {
+    state: StateService,
+}
+
+impl MeteredService {
+    pub fn new(data_dir: PathBuf) -> Self {
+        Self {
+            state: StateService::new(data_dir),
+        }
+    }
+
+    /// Usage in the current billing period
+    pub fn usage(&self) -> Result<DataUsage> {
+        let period_start = billing_period_start(Utc::now(), Config::billing_day());
+        Ok(self
+            .state
+            .load()?
+            .data_usage
+            .filter(|usage| usage.period_start == period_start)
+            .unwrap_or(DataUsage {
+                period_start,
+                bytes: 0,
+            }))
+    }
+
+    /// Counts a finished download against the current billing period
+    pub fn record(&self, bytes: u64) -> Result<DataUsage> {
+        let mut usage = self.usage()?;
+        usage.bytes += bytes;
+        self.state
+            .update(|state| state.data_usage = Some(usage.clone()))?;
+        Ok(usage)
+    }
+
+    /// Why a download of `bytes` (unknown if None) should not start without
+    /// `--force`, if it should not
+    pub fn download_blocker(&self, bytes: Option<u64>) -> Result<Option<String>> {
+        if Config::metered() {
+            return Ok(Some(
+                "the connection is metered (GEIST_METERED)".to_string(),
+            ));
+        }
+        let Some(budget) = Config::data_budget_bytes() else {
+            return Ok(None);
+        };
+
+        let usage = self.usage()?;
+        let left = budget.saturating_sub(usage.bytes);
+        let blocked = match bytes {
+            Some(bytes) => bytes > left,
+            // A download of unknown size may not start once nothing is left
+            None => left == 0,
+        };
+        Ok(blocked.then(|| {
+            format!(
+                "it would exceed the data budget: {} of {} MB used since {}",
+                usage.bytes / (1024 * 1024),
+                budget / (1024 * 1024),
+                usage.period_start.format("%Y-%m-%d")
+            )
+        }))
+    }
+}
+
+// Midnight UTC of the most recent `day` of a month, moved to the last day of
+// months that are shorter
+fn billing_period_start(now: DateTime<Utc>, day: u32) -> DateTime<Utc> {
+    let in_month = |year: i32, month: u32| {
+        (28..=day.max(28))
+            .rev()
+            .find_map(|last| NaiveDate::from_ymd_opt(year, month, day.min(last)))
+            .expect("every month has 28 days")
+    };
+
+    let mut start = in_month(now.year(), now.month());
+    if start > now.date_naive() {
+        let (year, month) = match now.month() {
+            1 => (now.year() - 1, 12),
+            month => (now.year(), month - 1),
+        };
+        start = in_month(year, month);
+    }
+    start.and_hms_opt(0, 0, 0).unwrap_or_default().and_utc()
+}
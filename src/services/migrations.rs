@@ -0,0 +1,273 @@
+use crate::config::Config;
+use crate::services::{
+    AppService, AppliedMigration, EventKind, EventService, FileService, StateService,
+};
+use anyhow::{Context, Result};
+use chrono::Utc;
+use serde::Deserialize;
+use std::fs;
+use std::path::{Component, Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::time::Duration;
+use tracing::{info, warn};
+
+/// A script a release runs against the shared app data before it is switched to,
+/// e.g. to convert settings written by an older release.
+///
+/// Declared in the `migrations` section of a version's `manifest.yaml`, in the
+/// order they run, with the scripts shipped in the bundle's `migrations/` directory:
+///
+/// ```yaml
+/// migrations:
+///   - name: 0003-split-settings
+///     script: 0003-split-settings.sh
+///     from: "<2.0.0"
+///     timeout_secs: 300
+/// ```
+#[derive(Debug, Clone, Deserialize)]
+pub struct Migration {
+    /// Identifies the migration across releases: once applied, it never runs again,
+    /// even if later releases still ship it
+    pub name: String,
+    /// Executable below the version's `migrations/` directory
+    pub script: String,
+    /// Semver range of the versions it migrates data from; without one it applies
+    /// whatever version the device comes from
+    #[serde(default)]
+    pub from: Option<String>,
+    #[serde(default = "Migration::default_timeout_secs")]
+    pub timeout_secs: u64,
+}
+
+// Only the part of the manifest migrations care about
+#[derive(Deserialize)]
+struct Manifest {
+    #[serde(default)]
+    migrations: Vec<Migration>,
+}
+
+impl Migration {
+    fn default_timeout_secs() -> u64 {
+        Config::MIGRATION_TIMEOUT_SECS
+    }
+
+    /// Whether the migration applies to data left behind by `from_version`.
+    /// Versions that are not semver only get migrations without a range.
+    pub fn applies_to(&self, from_version: &str) -> Result<bool> {
+        let Some(range) = &self.from else {
+            return Ok(true);
+        };
+        let range = semver::VersionReq::parse(&Config::normalize_version(range))
+            .with_context(|| format!("Invalid 'from' range of migration {}", self.name))?;
+        Ok(Config::parse_version(from_version).is_ok_and(|from| range.matches(&from)))
+    }
+
+    // The script inside the version's migrations directory
+    fn script_path(&self, version_dir: &Path) -> Result<PathBuf> {
+        let script = Path::new(&self.script);
+        if !script
+            .components()
+            .all(|part| matches!(part, Component::Normal(_)))
+        {
+            anyhow::bail!(
+                "Script '{}' of migration {} is not a relative path inside {}",
+                self.script,
+                self.name,
+                Config::MIGRATIONS_DIR
+            );
+        }
+        let path = version_dir.join(Config::MIGRATIONS_DIR).join(script);
+        if !path.is_file() {
+            anyhow::bail!(
+                "Script of migration {} not found at {}",
+                self.name,
+                path.display()
+            );
+        }
+        Ok(path)
+    }
+
+    // Runs the script in the app data directory, killing it if it outlives its timeout
+    fn run(&self, version_dir: &Path, app_data_dir: &Path, from: &str, to: &str) -> Result<()> {
+        let script = self.script_path(version_dir)?;
+        info!("Running migration {}: {}", self.name, script.display());
+        let mut command = Command::new(&script);
+        command
+            .current_dir(app_data_dir)
+            .env("GEIST_APP_DATA_DIR", app_data_dir)
+            .env("GEIST_MIGRATE_FROM", from)
+            .env("GEIST_MIGRATE_TO", to)
+            .stdin(Stdio::null());
+        // Its own process group, so a timeout also stops whatever the script started
+        #[cfg(unix)]
+        {
+            use std::os::unix::process::CommandExt;
+            command.process_group(0);
+        }
+        let mut child = command
+            .spawn()
+            .with_context(|| format!("Failed to run migration {}", self.name))?;
+
+        match AppService::wait_timeout(&mut child, Duration::from_secs(self.timeout_secs))? {
+            Some(status) if status.success() => Ok(()),
+            Some(status) => anyhow::bail!("Migration {} failed with {}", self.name, status),
+            None => {
+                #[cfg(unix)]
+                unsafe {
+                    libc::kill(-(child.id() as libc::pid_t), libc::SIGKILL);
+                }
+                child.kill().ok();
+                child.wait().ok();
+                anyhow::bail!(
+                    "Migration {} timed out after {}s",
+                    self.name,
+                    self.timeout_secs
+                )
+            }
+        }
+    }
+}
+
+/// Runs the data migrations a version ships against the app data directory all
+/// versions share, after backing it up, and remembers which ones were applied
+pub struct MigrationService {
+    data_dir: PathBuf,
+}
+
+impl MigrationService {
+    pub fn new(data_dir: PathBuf) -> Self {
+        Self { data_dir }
+    }
+
+    /// The migrations an installed version declares, in the order they run
+    pub fn load(version_dir: &Path) -> Result<Vec<Migration>> {
+        let path = version_dir.join("manifest.yaml");
+        if !path.is_file() {
+            return Ok(Vec::new());
+        }
+        let contents = fs::read_to_string(&path)
+            .with_context(|| format!("Failed to read {}", path.display()))?;
+        let manifest: Manifest = serde_yaml::from_str(&contents)
+            .with_context(|| format!("Failed to parse the migrations in {}", path.display()))?;
+        Ok(manifest.migrations)
+    }
+
+    /// The migrations of `version` that still have to run when coming from
+    /// `from_version`
+    pub fn pending(&self, version: &str, from_version: &str) -> Result<Vec<Migration>> {
+        let state = StateService::new(self.data_dir.clone()).load()?;
+        let mut pending = Vec::new();
+        for migration in Self::load(&self.data_dir.join(version))? {
+            if state.migration_applied(&migration.name) {
+                continue;
+            }
+            if migration.applies_to(from_version)? {
+                pending.push(migration);
+            }
+        }
+        Ok(pending)
+    }
+
+    /// Runs the pending migrations of `version`, coming from `from_version`. The app
+    /// data directory is backed up first; if a migration fails, the backup is put
+    /// back and none of them count as applied. Returns the names of those that ran.
+    pub fn migrate(&self, version: &str, from_version: &str) -> Result<Vec<String>> {
+        let pending = self.pending(version, from_version)?;
+        if pending.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        let app_data_dir = self.data_dir.join(Config::APP_DATA_DIR);
+        fs::create_dir_all(&app_data_dir)
+            .with_context(|| format!("Failed to create {}", app_data_dir.display()))?;
+        let backup = self.backup(version, &app_data_dir)?;
+
+        let version_dir = self.data_dir.join(version);
+        let events = EventService::new(self.data_dir.clone());
+        for migration in &pending {
+            if let Err(e) = migration.run(&version_dir, &app_data_dir, from_version, version) {
+                events.record(
+                    EventKind::MigrationFailed,
+                    Some(version),
+                    Some(format!("{}: {:#}", migration.name, e)),
+                );
+                Self::restore(&backup, &app_data_dir).with_context(|| {
+                    format!(
+                        "{:#}, and restoring the data from {} failed too",
+                        e,
+                        backup.display()
+                    )
+                })?;
+                return Err(e.context(format!(
+                    "The app data was restored from the backup in {}",
+                    backup.display()
+                )));
+            }
+        }
+
+        let names: Vec<String> = pending.into_iter().map(|m| m.name).collect();
+        let at = Utc::now();
+        StateService::new(self.data_dir.clone()).update(|state| {
+            state
+                .migrations
+                .extend(names.iter().map(|name| AppliedMigration {
+                    name: name.clone(),
+                    version: version.to_string(),
+                    at,
+                }));
+        })?;
+        events.record(
+            EventKind::Migrated,
+            Some(version),
+            Some(format!("{} (from {})", names.join(", "), from_version)),
+        );
+        info!("Applied migrations of {}: {}", version, names.join(", "));
+        Ok(names)
+    }
+
+    // Copies the app data directory aside, keeping the newest few copies. Named by
+    // time first, so they sort in the order they were made.
+    fn backup(&self, version: &str, app_data_dir: &Path) -> Result<PathBuf> {
+        let backups_dir = self.data_dir.join(Config::MIGRATION_BACKUPS_DIR);
+        let backup = backups_dir.join(format!(
+            "{}-{}",
+            Utc::now().format("%Y%m%dT%H%M%SZ"),
+            version
+        ));
+        FileService::new(self.data_dir.clone()).ensure_disk_reserve(
+            FileService::size_of(app_data_dir)?,
+            "the pre-migration backup",
+        )?;
+        info!(
+            "Backing up {} to {}",
+            app_data_dir.display(),
+            backup.display()
+        );
+        FileService::copy_dir_all(app_data_dir, &backup)
+            .with_context(|| format!("Failed to back up {}", app_data_dir.display()))?;
+
+        let mut backups: Vec<PathBuf> = fs::read_dir(&backups_dir)?
+            .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+            .filter(|path| path.is_dir())
+            .collect();
+        backups.sort();
+        let excess = backups.len().saturating_sub(Config::MIGRATION_BACKUPS_KEPT);
+        for old in &backups[..excess] {
+            if let Err(e) = fs::remove_dir_all(old) {
+                warn!("Failed to remove the old backup {}: {}", old.display(), e);
+            }
+        }
+        Ok(backup)
+    }
+
+    fn restore(backup: &Path, app_data_dir: &Path) -> Result<()> {
+        warn!(
+            "Restoring {} from {}",
+            app_data_dir.display(),
+            backup.display()
+        );
+        fs::remove_dir_all(app_data_dir)
+            .with_context(|| format!("Failed to clear {}", app_data_dir.display()))?;
+        FileService::copy_dir_all(backup, app_data_dir)
+    }
+}
@@ -0,0 +1,110 @@
+use crate::services::registry::ReleaseInfo;
+use crate::services::RegistryBackend;
+use anyhow::Result;
+use std::cell::Cell;
+use std::path::Path;
+use tracing::warn;
+
+/// Copies of one registry tried in order: when a request to one copy fails, e.g.
+/// because it is unreachable or times out, the next one is asked. A copy that
+/// answered is asked first from then on, so one update downloads everything from
+/// the same place and records where that was.
+pub struct MirroredRegistry {
+    mirrors: Vec<(String, Box<dyn RegistryBackend>)>,
+    preferred: Cell<usize>,
+}
+
+impl MirroredRegistry {
+    /// `mirrors` pairs a label for logs, usually the base URL, with the backend
+    /// reading that copy; the first one is the primary
+    pub fn new(mirrors: Vec<(String, Box<dyn RegistryBackend>)>) -> Self {
+        Self {
+            mirrors,
+            preferred: Cell::new(0),
+        }
+    }
+
+    // Runs `request` against the preferred copy, then the others in order
+    fn attempt<T>(
+        &self,
+        what: &str,
+        request: impl Fn(&dyn RegistryBackend) -> Result<T>,
+    ) -> Result<T> {
+        let preferred = self.preferred.get();
+        let order =
+            std::iter::once(preferred).chain((0..self.mirrors.len()).filter(|i| *i != preferred));
+
+        let mut failures = Vec::new();
+        for index in order {
+            let (label, backend) = &self.mirrors[index];
+            match request(backend.as_ref()) {
+                Ok(result) => {
+                    if index != preferred {
+                        warn!("Using registry mirror {} from now on", label);
+                        self.preferred.set(index);
+                    }
+                    return Ok(result);
+                }
+                Err(e) => {
+                    warn!("{} failed on {}: {:#}", what, label, e);
+                    failures.push(format!("{}: {:#}", label, e));
+                }
+            }
+        }
+        anyhow::bail!("{} failed on every mirror ({})", what, failures.join("; "))
+    }
+}
+
+impl RegistryBackend for MirroredRegistry {
+    fn name(&self) -> &'static str {
+        self.mirrors[self.preferred.get()].1.name()
+    }
+
+    fn verify_version(&self, version: &str) -> Result<bool> {
+        self.attempt("Version check", |backend| backend.verify_version(version))
+    }
+
+    fn latest(&self) -> Result<String> {
+        self.attempt("Latest version lookup", |backend| backend.latest())
+    }
+
+    fn list(&self) -> Result<Vec<ReleaseInfo>> {
+        self.attempt("Listing releases", |backend| backend.list())
+    }
+
+    fn published_name(&self, version: &str, file_name: &str) -> String {
+        self.mirrors[self.preferred.get()]
+            .1
+            .published_name(version, file_name)
+    }
+
+    fn artifact_url(&self, version: &str, file_name: &str) -> Result<String> {
+        self.attempt("URL lookup", |backend| {
+            backend.artifact_url(version, file_name)
+        })
+    }
+
+    fn artifact_size(&self, version: &str, file_name: &str) -> Result<Option<u64>> {
+        self.attempt("Size query", |backend| {
+            backend.artifact_size(version, file_name)
+        })
+    }
+
+    fn download(&self, version: &str, file_name: &str, output_path: &Path) -> Result<String> {
+        self.attempt(&format!("Download of {}", file_name), |backend| {
+            backend.download(version, file_name, output_path)
+        })
+    }
+
+    fn fetch(&self, version: &str, file_name: &str) -> Result<Option<String>> {
+        self.attempt(&format!("Download of {}", file_name), |backend| {
+            backend.fetch(version, file_name)
+        })
+    }
+
+    fn fetch_metadata(&self, file_name: &str) -> Result<Option<String>> {
+        self.attempt(&format!("Download of {}", file_name), |backend| {
+            backend.fetch_metadata(file_name)
+        })
+    }
+}
@@ -1,5 +1,121 @@
+pub mod app;
+pub mod artifacts;
+pub mod backup;
+pub mod bench;
+pub mod binary_arch;
+pub mod bundle_cache;
+pub mod bundle_format;
+pub mod capabilities;
+pub mod cgroup;
+pub mod config_file;
+pub mod config_secret;
+pub mod container;
+pub mod cosign;
+pub mod current;
+pub mod dedup;
+pub mod der;
+pub mod download;
+pub mod events;
+pub mod export;
+pub mod fleet;
 pub mod fs;
 pub mod gcs;
+pub mod gcs_auth;
+pub mod github;
+pub mod gitlab;
+pub mod handoff;
+pub mod hash_records;
+pub mod health;
+pub mod host;
+pub mod http;
+pub mod http_cache;
+pub mod ipc;
+pub mod keys;
+pub mod mac;
+pub mod manifest_diff;
+pub mod metered;
+pub mod migrations;
+pub mod mirrors;
+pub mod plan;
+pub mod pre_stop;
+pub mod privileges;
+pub mod quarantine;
+pub mod registry;
+pub mod replay;
+pub mod report;
+pub mod restart_policy;
+pub mod sandbox;
+pub mod sbom;
+pub mod smoke_test;
+pub mod state;
+pub mod supervisor;
+pub mod systemd;
+pub mod tamper;
+pub mod targets;
+pub mod token_store;
+pub mod upgrade_path;
+pub mod usb;
+pub mod version_diff;
 
-pub use fs::FileService;
+pub use app::AppService;
+pub use artifacts::AppArtifacts;
+pub use backup::{BackupInfo, BackupService};
+pub use bench::{BenchResult, BenchService};
+pub use binary_arch::BinaryArch;
+pub use bundle_cache::{BundleCache, FetchedBundle};
+pub use bundle_format::BundleFormat;
+pub use capabilities::{AppCapabilities, Capability};
+pub use cgroup::{AppCgroup, ResourceLimits, ResourceUsage};
+pub use config_file::{
+    BackupSettings, ConfigFile, ConfigKey, ConfigProblem, ContainerSettings, DownloadSettings,
+    GitHubSettings, GitLabSettings, HttpSettings, KeyKind, RegistrySettings, ReportSettings,
+    SuperviseSettings, UsbSettings, VerificationSettings, CONFIG_KEYS,
+};
+pub use config_secret::ConfigSecret;
+pub use container::{ContainerImage, ContainerService};
+pub use cosign::CosignPolicy;
+pub use current::{CurrentPointer, CurrentService};
+pub use dedup::LinkSource;
+pub use events::{Event, EventKind, EventPage, EventService};
+pub use export::{ExportService, ExportedBundle};
+pub use fleet::{Device, FleetService, Inventory};
+pub use fs::{
+    BootMarker, BundleOrigin, FileService, HashingWriter, InstallRecord, InstalledVersion, Slot,
+    SpaceEstimate,
+};
 pub use gcs::GcsService;
+pub use gcs_auth::{AuthorizedUser, GcsAuth, GcsCredentials, ServiceAccountKey};
+pub use github::{GitHubService, Release, ReleaseAsset};
+pub use gitlab::{GitLabAssetLink, GitLabAssets, GitLabRelease, GitLabService};
+pub use handoff::{Handoff, ListenSockets};
+pub use hash_records::HashRecords;
+pub use health::HealthProbe;
+pub use host::{DeviceFixture, HostService, PowerState};
+pub use http_cache::{CachedAnswer, ConditionalCache};
+pub use ipc::IpcService;
+pub use keys::{KeyAction, KeyAuditEntry, KeyService, TrustedKey};
+pub use mac::{AppArmorProfile, ExecLabel, MacProfile, SeLinuxModule};
+pub use manifest_diff::{ChangeKind, ManifestChange, ManifestDiff};
+pub use metered::{DataUsage, MeteredService};
+pub use migrations::{Migration, MigrationService};
+pub use mirrors::MirroredRegistry;
+pub use plan::{Cost, Plan, PlannedAction};
+pub use pre_stop::{PreStop, PreStopFallback};
+pub use privileges::{AppCredentials, PrivilegesManifest};
+pub use quarantine::{QuarantineEntry, QuarantineRecord, QuarantineService};
+pub use registry::{open_registry, RegistryBackend, ReleaseInfo};
+pub use replay::{BagMessage, ReplayBag};
+pub use report::{Report, ReportDisk, ReportHealth, ReportService, ReportVersions};
+pub use restart_policy::{ExitAction, RestartPolicy};
+pub use sandbox::{Sandbox, SandboxManifest};
+pub use sbom::{Sbom, SbomFile, SbomFormat, SbomPackage};
+pub use smoke_test::SmokeTest;
+pub use state::{AppliedMigration, ExitRecord, Hold, StateService, SupervisorState, VersionMark};
+pub use supervisor::{PidFile, RestartResult, RunOutcome, SupervisorService};
+pub use systemd::{Notifier, SystemdService};
+pub use tamper::TamperWatch;
+pub use targets::{TargetBundle, TargetFile, TargetHashes, TargetsMetadata};
+pub use token_store::{TokenLocation, TokenStore, TOKEN_SERVICES};
+pub use upgrade_path::UpgradeConstraint;
+pub use usb::{UsbPlan, UsbPolicy, UsbService};
+pub use version_diff::{FileChange, FileSide, VersionDiff};
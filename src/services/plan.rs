@@ -0,0 +1,97 @@
+use crate::config::Config;
+use crate::services::ManifestDiff;
+use anyhow::Result;
+use serde::Serialize;
+
+/// How an action's bytes count towards the totals of a plan
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Cost {
+    /// Fetched from the registry
+    Download,
+    /// Read from disk, e.g. to check a checksum
+    Read,
+    /// Newly written to the data directory
+    Write,
+    /// Removed from the data directory
+    Free,
+}
+
+/// A single step of a plan
+#[derive(Debug, Serialize)]
+pub struct PlannedAction {
+    pub action: String,
+    pub target: String,
+    pub cost: Cost,
+    /// Unknown when the registry or disk could not tell
+    pub bytes: Option<u64>,
+    pub estimated_seconds: u64,
+}
+
+/// What a command would do, printed as JSON by `--dry-run` so automation can
+/// review a change, e.g. require approval above a size, before it is made
+#[derive(Debug, Serialize)]
+pub struct Plan {
+    pub command: String,
+    pub actions: Vec<PlannedAction>,
+    pub download_bytes: u64,
+    pub disk_bytes_written: u64,
+    pub disk_bytes_freed: u64,
+    pub estimated_seconds: u64,
+    /// Conditions that would make the real run fail
+    pub warnings: Vec<String>,
+    /// How the target version's manifest differs from the current one's, for updates
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub manifest_diff: Option<ManifestDiff>,
+}
+
+impl Plan {
+    pub fn new(command: &str) -> Self {
+        Self {
+            command: command.to_string(),
+            actions: Vec::new(),
+            download_bytes: 0,
+            disk_bytes_written: 0,
+            disk_bytes_freed: 0,
+            estimated_seconds: 0,
+            warnings: Vec::new(),
+            manifest_diff: None,
+        }
+    }
+
+    /// Adds a step, estimating its duration from its size and the assumed throughput
+    pub fn add(&mut self, action: &str, target: &str, bytes: Option<u64>, cost: Cost) {
+        let rate = match cost {
+            Cost::Download => Config::PLAN_DOWNLOAD_BYTES_PER_SEC,
+            Cost::Read | Cost::Write | Cost::Free => Config::PLAN_DISK_BYTES_PER_SEC,
+        };
+        let estimated_seconds = bytes.unwrap_or(0).div_ceil(rate);
+
+        let size = bytes.unwrap_or(0);
+        match cost {
+            Cost::Download => self.download_bytes += size,
+            Cost::Read => {}
+            Cost::Write => self.disk_bytes_written += size,
+            Cost::Free => self.disk_bytes_freed += size,
+        }
+        self.estimated_seconds += estimated_seconds;
+
+        self.actions.push(PlannedAction {
+            action: action.to_string(),
+            target: target.to_string(),
+            cost,
+            bytes,
+            estimated_seconds,
+        });
+    }
+
+    pub fn warn(&mut self, warning: String) {
+        self.warnings.push(warning);
+    }
+
+    /// Writes the plan to stdout as pretty-printed JSON
+    pub fn print(&self) -> Result<()> {
+        println!("{}", serde_json::to_string_pretty(self)?);
+        Ok(())
+    }
+}
@@ -0,0 +1,123 @@
+use crate::config::Config;
+use crate::services::{HostService, IpcService};
+use anyhow::{Context, Result};
+use serde::Deserialize;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use tracing::{info, warn};
+
+/// What happens when the app does not acknowledge a pre-stop notification in time
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PreStopFallback {
+    /// Stop the app anyway
+    #[default]
+    Stop,
+    /// Leave the app running and drop the restart
+    KeepRunning,
+}
+
+/// How the app is told that it is about to be stopped for a restart, so it can
+/// finish what it is doing (e.g. close the current video segment) first. Every
+/// configured notification is sent; the app acknowledges by answering the IPC
+/// message, answering the HTTP call with a success status, creating the file named
+/// in GEIST_STOP_ACK_FILE, or exiting.
+///
+/// Declared in the `pre_stop` section of a version's `manifest.yaml`:
+///
+/// ```yaml
+/// pre_stop:
+///   signal: SIGUSR1
+///   ipc: prepare-stop
+///   http: http://127.0.0.1:8080/prepare-stop
+///   grace_secs: 10
+///   on_timeout: stop
+/// ```
+#[derive(Debug, Clone, Deserialize)]
+pub struct PreStop {
+    /// Signal sent to the app, by name (`SIGUSR1`, `USR1`) or number
+    pub signal: Option<String>,
+    /// Line sent on the control socket
+    pub ipc: Option<String>,
+    /// URL that is POSTed to
+    pub http: Option<String>,
+    /// How long the app gets to acknowledge
+    #[serde(default = "PreStop::default_grace_secs")]
+    pub grace_secs: u64,
+    #[serde(default)]
+    pub on_timeout: PreStopFallback,
+}
+
+// Only the part of the manifest the notification cares about
+#[derive(Deserialize)]
+struct Manifest {
+    #[serde(default)]
+    pre_stop: Option<PreStop>,
+}
+
+impl PreStop {
+    fn default_grace_secs() -> u64 {
+        Config::PRE_STOP_GRACE_SECS
+    }
+
+    /// The pre-stop notification of an installed version, if it declares one
+    pub fn load(version_dir: &Path) -> Option<Self> {
+        let path = version_dir.join("manifest.yaml");
+        let contents = fs::read_to_string(&path).ok()?;
+        match serde_yaml::from_str::<Manifest>(&contents) {
+            Ok(manifest) => manifest.pre_stop.filter(|pre_stop| {
+                pre_stop.signal.is_some() || pre_stop.ipc.is_some() || pre_stop.http.is_some()
+            }),
+            Err(e) => {
+                warn!("Ignoring pre-stop settings in {}: {}", path.display(), e);
+                None
+            }
+        }
+    }
+
+    pub fn grace(&self) -> Duration {
+        Duration::from_secs(self.grace_secs)
+    }
+
+    /// File the app creates to acknowledge a signal
+    pub fn ack_file(data_dir: &Path) -> PathBuf {
+        data_dir
+            .join(Config::APP_RUN_DIR)
+            .join(Config::STOP_ACK_FILE)
+    }
+
+    /// Sends the notifications to the app running as `pid`. Returns whether the
+    /// app already acknowledged, which IPC and HTTP answers do; a signal is
+    /// acknowledged later through the ack file.
+    pub fn notify(&self, pid: u32, data_dir: &Path) -> Result<bool> {
+        let mut acknowledged = false;
+
+        if let Some(signal) = &self.signal {
+            fs::remove_file(Self::ack_file(data_dir)).ok();
+            HostService::send_signal(pid, signal)?;
+        }
+        if let Some(message) = &self.ipc {
+            let reply = IpcService::new(data_dir.to_path_buf())
+                .request_within(message, self.grace())
+                .context("The app did not answer the pre-stop message")?;
+            info!("App answered the pre-stop message: {}", reply.trim());
+            acknowledged = true;
+        }
+        if let Some(url) = &self.http {
+            let response = reqwest::blocking::Client::builder()
+                .timeout(self.grace())
+                .build()?
+                .post(url)
+                .send()
+                .with_context(|| format!("Pre-stop endpoint {} is unreachable", url))?;
+            if !response.status().is_success() {
+                anyhow::bail!("Pre-stop endpoint {} answered {}", url, response.status());
+            }
+            acknowledged = true;
+        }
+
+        // With a signal declared, the ack file has the final say
+        Ok(acknowledged && self.signal.is_none())
+    }
+}
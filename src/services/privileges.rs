@@ -0,0 +1,279 @@
+use crate::config::Config;
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::Path;
+use tracing::{info, warn};
+
+/// What a version needs from the account it runs under.
+///
+/// Declared in the `privileges` section of a version's `manifest.yaml`:
+///
+/// ```yaml
+/// privileges:
+///   groups:
+///     - video
+///     - gpio
+/// ```
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct PrivilegesManifest {
+    /// Supplementary groups that grant access to the devices the app opens
+    #[serde(default)]
+    pub groups: Vec<String>,
+}
+
+// Only the part of the manifest the privileges care about
+#[derive(Deserialize)]
+struct Manifest {
+    #[serde(default)]
+    privileges: Option<PrivilegesManifest>,
+}
+
+impl PrivilegesManifest {
+    /// The privileges section of an installed version, empty if it declares none
+    pub fn load(version_dir: &Path) -> Self {
+        let path = version_dir.join("manifest.yaml");
+        let Ok(contents) = fs::read_to_string(&path) else {
+            return Self::default();
+        };
+        match serde_yaml::from_str::<Manifest>(&contents) {
+            Ok(manifest) => manifest.privileges.unwrap_or_default(),
+            Err(e) => {
+                warn!("Ignoring privileges in {}: {}", path.display(), e);
+                Self::default()
+            }
+        }
+    }
+}
+
+/// The unprivileged account a supervisor running as root launches the app under:
+/// the configured user and group, plus those supplementary groups the version
+/// declares that the operator allows, and nothing else
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AppCredentials {
+    pub user: String,
+    pub uid: u32,
+    pub gid: u32,
+    pub groups: Vec<u32>,
+}
+
+impl AppCredentials {
+    /// The account to launch the version in `version_dir` under, if an app user is
+    /// configured (GEIST_APP_USER or `supervise.app_user`) and geist can switch to
+    /// it. A group the version needs that the device lacks is an error; one that
+    /// GEIST_APP_GROUPS or `supervise.app_groups` does not allow is left out.
+    #[cfg(unix)]
+    pub fn resolve(version_dir: &Path) -> Result<Option<Self>> {
+        let Some(user) = Config::app_user() else {
+            return Ok(None);
+        };
+        let (uid, primary_gid) = lookup_user(&user)?;
+        // SAFETY: geteuid cannot fail
+        let euid = unsafe { libc::geteuid() };
+        if euid == uid {
+            return Ok(None);
+        }
+        if euid != 0 {
+            warn!(
+                "Not running as root, so the app runs as uid {} rather than {}",
+                euid, user
+            );
+            return Ok(None);
+        }
+
+        let gid = match Config::app_group() {
+            Some(group) => lookup_group(&group)?,
+            None => primary_gid.with_context(|| {
+                format!(
+                    "User {} has no entry to take its group from; set GEIST_APP_GROUP",
+                    user
+                )
+            })?,
+        };
+        if gid == 0 {
+            anyhow::bail!("Refusing to launch the app with the root group");
+        }
+        // Names the device lacks allow nothing
+        let allowed: Vec<u32> = Config::app_groups()
+            .iter()
+            .filter_map(|group| lookup_group(group).ok())
+            .collect();
+        let mut groups = vec![gid];
+        for group in PrivilegesManifest::load(version_dir).groups {
+            let id = lookup_group(&group).with_context(|| {
+                format!(
+                    "The version needs the group {}, which this device does not have",
+                    group
+                )
+            })?;
+            if id == 0 {
+                anyhow::bail!(
+                    "The version asks for the group {}, which has gid 0; refusing to launch it",
+                    group
+                );
+            }
+            if !allowed.contains(&id) {
+                warn!(
+                    "Not giving the app the group {}: it is not in supervise.app_groups",
+                    group
+                );
+                continue;
+            }
+            if !groups.contains(&id) {
+                groups.push(id);
+            }
+        }
+        Ok(Some(Self {
+            user,
+            uid,
+            gid,
+            groups,
+        }))
+    }
+
+    #[cfg(not(unix))]
+    pub fn resolve(_version_dir: &Path) -> Result<Option<Self>> {
+        Ok(None)
+    }
+
+    /// Switches the calling process to the account for good. Only makes system
+    /// calls, so it can run between fork and exec; the groups go first, since
+    /// changing them needs root.
+    #[cfg(unix)]
+    pub fn apply(&self) -> std::io::Result<()> {
+        unsafe {
+            if libc::setgroups(self.groups.len() as _, self.groups.as_ptr()) != 0
+                || libc::setgid(self.gid) != 0
+                || libc::setuid(self.uid) != 0
+            {
+                return Err(std::io::Error::last_os_error());
+            }
+        }
+        Ok(())
+    }
+
+    /// Gives the account everything below `path` that it does not own yet, without
+    /// following links, so the app can write where it did as root
+    #[cfg(unix)]
+    pub fn own(&self, path: &Path) -> Result<()> {
+        use std::os::unix::fs::MetadataExt;
+
+        let metadata = fs::symlink_metadata(path)
+            .with_context(|| format!("Failed to read {}", path.display()))?;
+        if metadata.uid() != self.uid || metadata.gid() != self.gid {
+            std::os::unix::fs::lchown(path, Some(self.uid), Some(self.gid))
+                .with_context(|| format!("Failed to give {} to {}", path.display(), self.user))?;
+        }
+        if metadata.is_dir() {
+            for entry in
+                fs::read_dir(path).with_context(|| format!("Failed to read {}", path.display()))?
+            {
+                self.own(&entry?.path())?;
+            }
+        }
+        Ok(())
+    }
+
+    #[cfg(not(unix))]
+    pub fn own(&self, _path: &Path) -> Result<()> {
+        Ok(())
+    }
+
+    /// Makes everything below `path` root's and writable by root alone, without
+    /// following links, so the app cannot change the version it runs. Also takes
+    /// back what launches before this check handed to the account.
+    #[cfg(unix)]
+    pub fn protect(&self, path: &Path) -> Result<()> {
+        use std::os::unix::fs::{MetadataExt, PermissionsExt};
+
+        let metadata = fs::symlink_metadata(path)
+            .with_context(|| format!("Failed to read {}", path.display()))?;
+        if metadata.uid() != 0 || metadata.gid() != 0 {
+            std::os::unix::fs::lchown(path, Some(0), Some(0))
+                .with_context(|| format!("Failed to give {} to root", path.display()))?;
+        }
+        if metadata.file_type().is_symlink() {
+            return Ok(());
+        }
+        // Hard links share the mode with the same file in other versions, which
+        // are protected alike
+        let mode = metadata.mode() & 0o7777;
+        if mode & 0o022 != 0 {
+            fs::set_permissions(path, fs::Permissions::from_mode(mode & !0o022))
+                .with_context(|| format!("Failed to make {} read-only", path.display()))?;
+        }
+        if metadata.is_dir() {
+            for entry in
+                fs::read_dir(path).with_context(|| format!("Failed to read {}", path.display()))?
+            {
+                self.protect(&entry?.path())?;
+            }
+        }
+        Ok(())
+    }
+
+    #[cfg(not(unix))]
+    pub fn protect(&self, _path: &Path) -> Result<()> {
+        Ok(())
+    }
+
+    /// Logs the account the app is about to be launched under
+    pub fn announce(&self) {
+        info!(
+            "Launching the app as {} (uid {}, gid {}, groups {:?})",
+            self.user, self.uid, self.gid, self.groups
+        );
+    }
+}
+
+// The uid of a user name or number, and its primary group if it has an entry
+#[cfg(unix)]
+fn lookup_user(user: &str) -> Result<(u32, Option<u32>)> {
+    let name = std::ffi::CString::new(user)?;
+    let mut entry: libc::passwd = unsafe { std::mem::zeroed() };
+    let mut buf = vec![0 as libc::c_char; 16 * 1024];
+    let mut found = std::ptr::null_mut();
+    // SAFETY: the buffer outlives the entry, whose strings point into it
+    let rc = unsafe {
+        libc::getpwnam_r(
+            name.as_ptr(),
+            &mut entry,
+            buf.as_mut_ptr(),
+            buf.len(),
+            &mut found,
+        )
+    };
+    if rc == 0 && !found.is_null() {
+        return Ok((entry.pw_uid, Some(entry.pw_gid)));
+    }
+    let uid: u32 = user
+        .parse()
+        .map_err(|_| anyhow::anyhow!("User {} does not exist", user))?;
+    let rc = unsafe { libc::getpwuid_r(uid, &mut entry, buf.as_mut_ptr(), buf.len(), &mut found) };
+    Ok((uid, (rc == 0 && !found.is_null()).then_some(entry.pw_gid)))
+}
+
+// The gid of a group name or number
+#[cfg(unix)]
+fn lookup_group(group: &str) -> Result<u32> {
+    let name = std::ffi::CString::new(group)?;
+    let mut entry: libc::group = unsafe { std::mem::zeroed() };
+    let mut buf = vec![0 as libc::c_char; 16 * 1024];
+    let mut found = std::ptr::null_mut();
+    // SAFETY: as in lookup_user
+    let rc = unsafe {
+        libc::getgrnam_r(
+            name.as_ptr(),
+            &mut entry,
+            buf.as_mut_ptr(),
+            buf.len(),
+            &mut found,
+        )
+    };
+    if rc == 0 && !found.is_null() {
+        return Ok(entry.gr_gid);
+    }
+    group
+        .parse()
+        .map_err(|_| anyhow::anyhow!("Group {} does not exist", group))
+}
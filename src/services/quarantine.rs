@@ -0,0 +1,127 @@
+use crate::config::Config;
+use anyhow::{Context, Result};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::{Path, PathBuf};
+use tracing::info;
+
+/// Forensic details kept next to a quarantined artifact
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct QuarantineRecord {
+    pub version: String,
+    pub url: String,
+    pub reason: String,
+    #[serde(default)]
+    pub expected_sha256: Option<String>,
+    #[serde(default)]
+    pub actual_sha256: Option<String>,
+    pub downloaded_at: DateTime<Utc>,
+    pub quarantined_at: DateTime<Utc>,
+}
+
+/// A quarantined artifact on disk
+pub struct QuarantineEntry {
+    pub id: String,
+    pub path: PathBuf,
+    pub record: QuarantineRecord,
+}
+
+/// Keeps bundles that failed verification or installation for later inspection
+pub struct QuarantineService {
+    dir: PathBuf,
+}
+
+impl QuarantineService {
+    // Metadata file written into each quarantine entry
+    const METADATA_FILE: &'static str = "metadata.json";
+
+    pub fn new(data_dir: PathBuf) -> Self {
+        Self {
+            dir: data_dir.join(Config::QUARANTINE_DIR),
+        }
+    }
+
+    /// Moves an artifact into the quarantine together with its metadata
+    pub fn quarantine(&self, artifact: &Path, record: &QuarantineRecord) -> Result<PathBuf> {
+        let id = format!(
+            "{}-{}",
+            record.quarantined_at.format("%Y%m%dT%H%M%SZ"),
+            record.version
+        );
+        let entry_dir = self.dir.join(&id);
+        fs::create_dir_all(&entry_dir).context("Failed to create quarantine directory")?;
+
+        let file_name = artifact
+            .file_name()
+            .context("Artifact path has no file name")?;
+        let destination = entry_dir.join(file_name);
+
+        // The artifact usually lives on a different filesystem (temp dir), so fall back to copying
+        if fs::rename(artifact, &destination).is_err() {
+            fs::copy(artifact, &destination).context("Failed to copy artifact to quarantine")?;
+            fs::remove_file(artifact).ok();
+        }
+
+        fs::write(
+            entry_dir.join(Self::METADATA_FILE),
+            serde_json::to_string_pretty(record)?,
+        )
+        .context("Failed to write quarantine metadata")?;
+
+        info!("Quarantined {} at {}", record.version, entry_dir.display());
+        Ok(entry_dir)
+    }
+
+    pub fn list(&self) -> Result<Vec<QuarantineEntry>> {
+        let mut entries = Vec::new();
+        if !self.dir.exists() {
+            return Ok(entries);
+        }
+
+        for entry in fs::read_dir(&self.dir)? {
+            let path = entry?.path();
+            let metadata_path = path.join(Self::METADATA_FILE);
+            if !metadata_path.is_file() {
+                continue;
+            }
+
+            let record = serde_json::from_str(&fs::read_to_string(&metadata_path)?)
+                .with_context(|| format!("Failed to parse {}", metadata_path.display()))?;
+            entries.push(QuarantineEntry {
+                id: path
+                    .file_name()
+                    .unwrap_or_default()
+                    .to_string_lossy()
+                    .to_string(),
+                path,
+                record,
+            });
+        }
+
+        entries.sort_by(|a, b| a.id.cmp(&b.id));
+        Ok(entries)
+    }
+
+    /// Removes a single entry, or the whole quarantine when no id is given.
+    /// Returns the number of entries removed.
+    pub fn clear(&self, id: Option<&str>) -> Result<usize> {
+        match id {
+            Some(id) => {
+                let entry_dir = self.dir.join(id);
+                if !entry_dir.join(Self::METADATA_FILE).is_file() {
+                    anyhow::bail!("No quarantine entry named {}", id);
+                }
+                fs::remove_dir_all(&entry_dir)?;
+                Ok(1)
+            }
+            None => {
+                let count = self.list()?.len();
+                if self.dir.exists() {
+                    fs::remove_dir_all(&self.dir)?;
+                }
+                Ok(count)
+            }
+        }
+    }
+}
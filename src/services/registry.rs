@@ -0,0 +1,287 @@
+use crate::config::Config;
+use crate::services::{
+    BundleFormat, GcsCredentials, GcsService, GitHubService, GitLabService, MirroredRegistry,
+    TokenStore,
+};
+use anyhow::{Context, Result};
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use std::fs;
+use std::path::Path;
+
+/// A published release as listed by a registry
+#[derive(Debug, Clone, Serialize)]
+pub struct ReleaseInfo {
+    pub version: String,
+    /// Unknown when the registry does not record it
+    pub published_at: Option<DateTime<Utc>>,
+    pub bundle_size: Option<u64>,
+}
+
+/// A place releases are published to. Every release carries the release bundle,
+/// a checksum file listing it and optionally a minisign signature of that file.
+pub trait RegistryBackend {
+    /// Short name of the backend, recorded in install records
+    fn name(&self) -> &'static str;
+
+    /// Whether a release exists; fails only when the registry could not tell
+    fn verify_version(&self, version: &str) -> Result<bool>;
+
+    /// Version string of the newest release
+    fn latest(&self) -> Result<String>;
+
+    /// The published releases
+    fn list(&self) -> Result<Vec<ReleaseInfo>>;
+
+    /// URL a file of a release is downloaded from
+    fn artifact_url(&self, version: &str, file_name: &str) -> Result<String>;
+
+    /// Size of a file of a release, if the registry reports one
+    fn artifact_size(&self, version: &str, file_name: &str) -> Result<Option<u64>>;
+
+    /// Downloads a file of a release to `output_path` and returns its hex SHA-256,
+    /// computed while it was downloaded
+    fn download(&self, version: &str, file_name: &str, output_path: &Path) -> Result<String>;
+
+    /// Fetches a small text file of a release; None if the release has no such file
+    fn fetch(&self, version: &str, file_name: &str) -> Result<Option<String>>;
+
+    /// Fetches a metadata file published for the whole registry rather than a
+    /// single release; None if the registry has no such file
+    fn fetch_metadata(&self, file_name: &str) -> Result<Option<String>> {
+        anyhow::bail!("The {} registry cannot publish {}", self.name(), file_name)
+    }
+
+    /// Name a file of a release is published under, which is also the name the
+    /// checksum file lists it by. Registries that rename release files override this.
+    fn published_name(&self, _version: &str, file_name: &str) -> String {
+        file_name.to_string()
+    }
+
+    /// Name the release bundle is published and listed in the checksum file under
+    fn published_bundle_name(&self, version: &str, format: BundleFormat) -> String {
+        self.published_name(version, &format.file_name(version))
+    }
+
+    fn release_bundle_url(&self, version: &str, format: BundleFormat) -> Result<String> {
+        self.artifact_url(version, &format.file_name(version))
+    }
+
+    /// Size of the release bundle as reported by the registry, if it reports one
+    fn release_bundle_size(&self, version: &str, format: BundleFormat) -> Result<Option<u64>> {
+        self.artifact_size(version, &format.file_name(version))
+            .context("Failed to query release bundle size")
+    }
+
+    fn download_release_bundle(
+        &self,
+        version: &str,
+        format: BundleFormat,
+        output_path: &Path,
+    ) -> Result<String> {
+        self.download(version, &format.file_name(version), output_path)
+            .context("Failed to download release bundle")
+    }
+
+    /// The most preferred format the checksum file lists a bundle in; releases
+    /// that list none of them are assumed to be gzip
+    fn bundle_format(&self, version: &str, checksums: &str) -> BundleFormat {
+        BundleFormat::PREFERRED
+            .into_iter()
+            .find(|format| {
+                find_checksum(checksums, &self.published_bundle_name(version, *format)).is_some()
+            })
+            .unwrap_or(BundleFormat::Gzip)
+    }
+
+    /// Downloads the checksum file of a release
+    fn download_checksums(&self, version: &str) -> Result<String> {
+        let name = Config::checksum_file_name();
+        self.fetch(version, &name)
+            .context("Failed to download checksums")?
+            .with_context(|| format!("Release {} has no {}", version, name))
+    }
+
+    /// Downloads the minisign signature of a release's checksum file, if it has one
+    fn download_checksums_signature(&self, version: &str) -> Result<Option<String>> {
+        self.fetch(version, &Config::checksum_signature_file_name())
+            .context("Failed to download checksum signature")
+    }
+
+    /// Downloads the detached minisign signature of a release's bundle, if it has one
+    fn download_bundle_signature(
+        &self,
+        version: &str,
+        format: BundleFormat,
+    ) -> Result<Option<String>> {
+        self.fetch(
+            version,
+            &format!(
+                "{}.{}",
+                format.file_name(version),
+                Config::SIGNATURE_EXTENSION
+            ),
+        )
+        .context("Failed to download bundle signature")
+    }
+
+    /// Downloads the cosign bundle of a release's bundle, if it has one
+    fn download_bundle_cosign(
+        &self,
+        version: &str,
+        format: BundleFormat,
+    ) -> Result<Option<String>> {
+        self.fetch(
+            version,
+            &format!(
+                "{}.{}",
+                format.file_name(version),
+                Config::COSIGN_BUNDLE_EXTENSION
+            ),
+        )
+        .context("Failed to download cosign bundle")
+    }
+
+    /// Fetches the checksum file of a release and returns the format of the bundle
+    /// to download and its SHA-256
+    fn expected_bundle_checksum(&self, version: &str) -> Result<(BundleFormat, String)> {
+        let checksums = self.download_checksums(version)?;
+
+        let format = self.bundle_format(version, &checksums);
+        let bundle_name = self.published_bundle_name(version, format);
+        let sha256 = find_checksum(&checksums, &bundle_name)
+            .with_context(|| format!("No checksum listed for {}", bundle_name))?;
+        Ok((format, sha256))
+    }
+}
+
+/// The backend named by `--source`, or by GEIST_REGISTRY_SOURCE if none was given.
+/// Saved tokens and cached answers are kept in `data_dir`.
+pub fn open_registry(source: Option<&str>, data_dir: &Path) -> Result<Box<dyn RegistryBackend>> {
+    let source = source
+        .map(str::to_string)
+        .unwrap_or_else(Config::registry_source);
+    match source.as_str() {
+        "gcs" => {
+            let credentials = GcsCredentials::application_default(data_dir)?;
+            let primary = Config::registry_base_url();
+            let mirrors = Config::registry_mirrors();
+            if mirrors.is_empty() {
+                return Ok(Box::new(GcsService::new(
+                    credentials,
+                    primary,
+                    data_dir.to_path_buf(),
+                )?));
+            }
+
+            // A mirror in the clear can serve anything its network path wants it to
+            if let Some(url) = mirrors.iter().find(|url| url.starts_with("http://")) {
+                if !Config::allow_insecure_mirrors() {
+                    anyhow::bail!(
+                        "Registry mirror {} is not served over https; set GEIST_ALLOW_INSECURE_MIRRORS=1 to use it anyway",
+                        url
+                    );
+                }
+            }
+
+            let mut registries: Vec<(String, Box<dyn RegistryBackend>)> = Vec::new();
+            for url in std::iter::once(primary).chain(mirrors) {
+                // Google credentials are only sent to Google, never to a third-party mirror
+                let credentials = if url.starts_with("https://storage.googleapis.com/") {
+                    credentials.clone()
+                } else {
+                    GcsCredentials::Anonymous
+                };
+                registries.push((
+                    url.clone(),
+                    Box::new(GcsService::new(credentials, url, data_dir.to_path_buf())?),
+                ));
+            }
+            Ok(Box::new(MirroredRegistry::new(registries)))
+        }
+        "github" => Ok(Box::new(open_github(data_dir)?)),
+        "gitlab" => Ok(Box::new(GitLabService::new(
+            saved_token(Config::gitlab_token(), "gitlab", data_dir)?,
+            Config::gitlab_url(),
+            Config::gitlab_project()
+                .context("The gitlab source needs the project in GEIST_GITLAB_PROJECT")?,
+            data_dir.to_path_buf(),
+        )?)),
+        other => anyhow::bail!(
+            "Unknown registry source '{}' (expected gcs, github or gitlab)",
+            other
+        ),
+    }
+}
+
+/// The GitHub releases of the configured repository, also where release notes
+/// come from
+pub fn open_github(data_dir: &Path) -> Result<GitHubService> {
+    let repository = Config::github_repository();
+    if repository
+        .split('/')
+        .filter(|part| !part.is_empty())
+        .count()
+        != 2
+    {
+        anyhow::bail!(
+            "GitHub repository '{}' is not of the form owner/repo",
+            repository
+        );
+    }
+    let asset_pattern = Config::github_asset_pattern();
+    if !asset_pattern.contains("{file}") {
+        anyhow::bail!(
+            "GitHub asset name pattern '{}' has no {{file}} placeholder",
+            asset_pattern
+        );
+    }
+    GitHubService::new(
+        saved_token(Config::github_token(), "github", data_dir)?,
+        Config::github_api_url(),
+        repository,
+        asset_pattern,
+        data_dir.to_path_buf(),
+    )
+}
+
+// A token from the environment wins over one saved by `auth login`
+fn saved_token(from_env: String, service: &str, data_dir: &Path) -> Result<String> {
+    if !from_env.is_empty() {
+        return Ok(from_env);
+    }
+    Ok(TokenStore::new(data_dir.to_path_buf())
+        .token(service)?
+        .unwrap_or_default())
+}
+
+/// The newest published release by semver that meets the requirement, e.g. `*`
+/// for when the registry can't say which release is the latest
+pub fn newest_release(
+    registry: &dyn RegistryBackend,
+    requirement: &semver::VersionReq,
+) -> Result<String> {
+    let versions = registry.list()?.into_iter().map(|release| release.version);
+    Config::newest_version(versions, requirement)
+        .with_context(|| format!("The registry lists no release matching {}", requirement))
+}
+
+/// Remembers the listed versions for shell completion, which must not wait for the registry
+pub fn cache_release_list(data_dir: &Path, releases: &[ReleaseInfo]) -> Result<()> {
+    let versions: Vec<&str> = releases.iter().map(|r| r.version.as_str()).collect();
+    fs::write(
+        data_dir.join(Config::RELEASES_CACHE_FILE),
+        serde_json::to_string(&versions)?,
+    )
+    .context("Failed to write the release list cache")
+}
+
+/// Looks up a file's digest in a checksum file
+pub fn find_checksum(checksums: &str, file_name: &str) -> Option<String> {
+    // sha256sum format: "<hex digest>  <file name>", optionally with a '*' binary marker
+    checksums
+        .lines()
+        .filter_map(|line| line.split_once(char::is_whitespace))
+        .find(|(_, name)| name.trim().trim_start_matches('*') == file_name)
+        .map(|(digest, _)| digest.to_lowercase())
+}
@@ -0,0 +1,223 @@
+use crate::config::Config;
+use crate::services::{HostService, IpcService};
+use anyhow::{Context, Result};
+use serde::Deserialize;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::{Duration, Instant};
+use tracing::{info, warn};
+
+/// A message recorded in a session bag
+#[derive(Debug, Clone, Deserialize)]
+pub struct BagMessage {
+    /// Seconds since the recording started
+    pub t: f64,
+    pub topic: String,
+    /// The message as the app published it
+    pub data: serde_json::Value,
+}
+
+/// A recorded session (`.geistbag`): one JSON object per line with the time a
+/// message was recorded at, its topic and its contents, e.g.
+///
+/// ```text
+/// {"t": 0.0, "topic": "/camera/frame", "data": {"seq": 1, "exposure_us": 8000}}
+/// {"t": 0.033, "topic": "/camera/frame", "data": {"seq": 2, "exposure_us": 8000}}
+/// ```
+///
+/// The app is told to read its topics from the bag instead of the live sensors,
+/// and the supervisor feeds the messages to it over the control socket.
+#[derive(Debug, Clone)]
+pub struct ReplayBag {
+    pub path: PathBuf,
+    /// Oldest first
+    pub messages: Vec<BagMessage>,
+}
+
+impl ReplayBag {
+    pub fn load(path: &Path) -> Result<Self> {
+        let contents = fs::read_to_string(path)
+            .with_context(|| format!("Failed to read replay bag {}", path.display()))?;
+        let mut messages = Vec::new();
+        for (index, line) in contents.lines().enumerate() {
+            if line.trim().is_empty() {
+                continue;
+            }
+            let message: BagMessage = serde_json::from_str(line).with_context(|| {
+                format!(
+                    "Line {} of {} is not a recorded message",
+                    index + 1,
+                    path.display()
+                )
+            })?;
+            if !message.t.is_finite() || message.t < 0.0 {
+                anyhow::bail!(
+                    "Line {} of {} has no time since the start of the recording",
+                    index + 1,
+                    path.display()
+                );
+            }
+            // The topic is a word of the request line the message is sent in
+            if message.topic.is_empty() || message.topic.contains(char::is_whitespace) {
+                anyhow::bail!(
+                    "Line {} of {} has an invalid topic '{}'",
+                    index + 1,
+                    path.display(),
+                    message.topic
+                );
+            }
+            messages.push(message);
+        }
+        // Recorders may write topics out of order within a flush
+        messages.sort_by(|a, b| a.t.total_cmp(&b.t));
+        Ok(Self {
+            path: path.to_path_buf(),
+            messages,
+        })
+    }
+
+    /// The request that hands a message to the app, e.g.
+    /// `topic publish /camera/frame {"seq":1}`
+    pub fn request(message: &BagMessage) -> String {
+        format!("topic publish {} {}", message.topic, message.data)
+    }
+
+    /// Feeds the bag to the app with process id `pid` from a background thread:
+    /// each message is sent at its recorded time, counted from when the app first
+    /// accepts a connection on its control socket. Stops when the bag ends, the
+    /// app exits, or the app does not take a message.
+    pub fn feed(self, ipc: IpcService, pid: u32) -> thread::JoinHandle<()> {
+        thread::spawn(move || match self.play(&ipc, pid) {
+            Ok(sent) => info!(
+                "Replayed {} of {} messages from {}",
+                sent,
+                self.messages.len(),
+                self.path.display()
+            ),
+            Err(e) => warn!("Stopped replaying {}: {:#}", self.path.display(), e),
+        })
+    }
+
+    // Sends the messages, returning how many the app took before the bag ended or
+    // it exited
+    fn play(&self, ipc: &IpcService, pid: u32) -> Result<usize> {
+        let deadline = Instant::now() + Duration::from_secs(Config::REPLAY_CONNECT_TIMEOUT_SECS);
+        while !ipc.is_listening() {
+            if !HostService::is_running(pid) {
+                return Ok(0);
+            }
+            if Instant::now() >= deadline {
+                anyhow::bail!(
+                    "the app did not open {} within {} seconds",
+                    ipc.socket_path().display(),
+                    Config::REPLAY_CONNECT_TIMEOUT_SECS
+                );
+            }
+            thread::sleep(Duration::from_millis(100));
+        }
+
+        let start = Instant::now();
+        for (sent, message) in self.messages.iter().enumerate() {
+            let due = start + Duration::from_secs_f64(message.t);
+            if let Some(wait) = due.checked_duration_since(Instant::now()) {
+                thread::sleep(wait);
+            }
+            if !HostService::is_running(pid) {
+                return Ok(sent);
+            }
+            ipc.request(&Self::request(message)).with_context(|| {
+                format!(
+                    "the app did not take the {} message recorded at {:.3}s",
+                    message.topic, message.t
+                )
+            })?;
+        }
+        Ok(self.messages.len())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn bag(contents: &str) -> Result<ReplayBag> {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("session.geistbag");
+        fs::write(&path, contents).unwrap();
+        ReplayBag::load(&path)
+    }
+
+    #[test]
+    fn messages_are_ordered_by_their_recorded_time() {
+        let bag = bag(concat!(
+            "{\"t\": 0.5, \"topic\": \"/imu\", \"data\": {\"z\": 9.8}}\n",
+            "\n",
+            "{\"t\": 0.0, \"topic\": \"/camera/frame\", \"data\": {\"seq\": 1}}\n",
+            "{\"t\": 0.5, \"topic\": \"/camera/frame\", \"data\": {\"seq\": 2}}\n",
+        ))
+        .unwrap();
+
+        let requests: Vec<String> = bag.messages.iter().map(ReplayBag::request).collect();
+        assert_eq!(
+            requests,
+            [
+                "topic publish /camera/frame {\"seq\":1}",
+                "topic publish /imu {\"z\":9.8}",
+                "topic publish /camera/frame {\"seq\":2}",
+            ]
+        );
+    }
+
+    #[test]
+    fn malformed_messages_are_refused() {
+        let error = bag("{\"t\": 0.0, \"topic\": \"/imu\"}\n").unwrap_err();
+        assert!(error.to_string().contains("Line 1"), "{:#}", error);
+        let error = bag("{\"t\": -1, \"topic\": \"/imu\", \"data\": 1}\n").unwrap_err();
+        assert!(error.to_string().contains("no time"), "{:#}", error);
+        let error = bag("{\"t\": 0, \"topic\": \"/camera frame\", \"data\": 1}\n").unwrap_err();
+        assert!(error.to_string().contains("invalid topic"), "{:#}", error);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn messages_are_fed_over_the_control_socket_in_time() {
+        use std::io::{BufRead, BufReader, Write};
+        use std::os::unix::net::UnixListener;
+
+        let data_dir = tempfile::tempdir().unwrap();
+        let ipc = IpcService::new(data_dir.path().to_path_buf());
+        fs::create_dir_all(ipc.socket_path().parent().unwrap()).unwrap();
+        let listener = UnixListener::bind(ipc.socket_path()).unwrap();
+        // Answers each request, and keeps those that are not connection probes
+        let app = thread::spawn(move || {
+            let mut received = Vec::new();
+            for stream in listener.incoming() {
+                let mut stream = stream.unwrap();
+                let mut line = String::new();
+                BufReader::new(&stream).read_line(&mut line).unwrap();
+                if line.is_empty() {
+                    continue;
+                }
+                writeln!(stream, "ok").unwrap();
+                received.push((Instant::now(), line.trim_end().to_string()));
+                if received.len() == 2 {
+                    return received;
+                }
+            }
+            received
+        });
+
+        let bag = bag(concat!(
+            "{\"t\": 0.0, \"topic\": \"/camera/frame\", \"data\": {\"seq\": 1}}\n",
+            "{\"t\": 0.3, \"topic\": \"/camera/frame\", \"data\": {\"seq\": 2}}\n",
+        ))
+        .unwrap();
+        assert_eq!(bag.play(&ipc, std::process::id()).unwrap(), 2);
+
+        let received = app.join().unwrap();
+        assert_eq!(received[0].1, "topic publish /camera/frame {\"seq\":1}");
+        assert_eq!(received[1].1, "topic publish /camera/frame {\"seq\":2}");
+        assert!(received[1].0 - received[0].0 >= Duration::from_millis(250));
+    }
+}
@@ -0,0 +1,286 @@
+use crate::config::Config;
+use crate::services::{
+    Event, EventKind, EventService, ExitRecord, FileService, HostService, StateService,
+    SupervisorService,
+};
+use anyhow::{Context, Result};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::PathBuf;
+use std::thread;
+use std::time::Duration;
+use tracing::{error, info, warn};
+
+/// Compact inventory of a device, uploaded by `geist report send` and daily by
+/// `geist supervise` for fleets without a remote management stack
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Report {
+    pub generated_at: DateTime<Utc>,
+    pub hostname: Option<String>,
+    pub arch: String,
+    pub geist_version: String,
+    pub versions: ReportVersions,
+    pub health: ReportHealth,
+    pub disk: ReportDisk,
+    /// Rollbacks, versions marked bad, safe mode changes and deferred updates since
+    /// the previous report
+    pub alerts: Vec<Event>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ReportVersions {
+    pub current: String,
+    pub installed: Vec<String>,
+    pub last_known_good: Option<String>,
+    pub bad: Vec<String>,
+    #[serde(default)]
+    pub good: Vec<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ReportHealth {
+    pub app_running: bool,
+    pub safe_mode: Option<String>,
+    pub last_exit: Option<ExitRecord>,
+    /// Abnormal exits of the app since the previous report
+    pub recent_crashes: usize,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ReportDisk {
+    pub available_bytes: u64,
+    /// Space taken by the data directory: installed versions, caches and logs
+    pub data_dir_bytes: u64,
+}
+
+/// Builds reports and uploads them to the endpoint in GEIST_REPORT_URL. Reports
+/// that cannot be delivered are queued under the data directory and sent, oldest
+/// first, with the next report.
+pub struct ReportService {
+    data_dir: PathBuf,
+    url: Option<String>,
+}
+
+impl ReportService {
+    pub fn new(data_dir: PathBuf, url: Option<String>) -> Self {
+        Self { data_dir, url }
+    }
+
+    fn queue_dir(&self) -> PathBuf {
+        self.data_dir.join(Config::REPORT_QUEUE_DIR)
+    }
+
+    fn interval() -> Duration {
+        Duration::from_secs(Config::REPORT_INTERVAL_SECS)
+    }
+
+    /// The inventory of this device as of now
+    pub fn build(&self) -> Result<Report> {
+        let state = StateService::new(self.data_dir.clone()).load()?;
+        let supervisor = SupervisorService::new(self.data_dir.clone(), None);
+        let since = self
+            .last_sent()
+            .unwrap_or_else(|| Utc::now() - Self::interval());
+
+        let alerts = EventService::new(self.data_dir.clone())
+            .load()?
+            .into_iter()
+            .filter(|event| event.at > since)
+            .filter(|event| {
+                matches!(
+                    event.kind,
+                    EventKind::RolledBack
+                        | EventKind::MarkedBad
+                        | EventKind::SafeModeEntered
+                        | EventKind::SafeModeLeft
+                        | EventKind::UpdateDeferred
+                        | EventKind::Tampered
+                )
+            })
+            .collect();
+
+        Ok(Report {
+            generated_at: Utc::now(),
+            hostname: HostService::hostname(),
+            arch: HostService::arch(),
+            geist_version: Config::PKG_VERSION.to_string(),
+            versions: ReportVersions {
+                current: FileService::new(self.data_dir.clone()).current_version(),
+                installed: FileService::new(self.data_dir.clone()).installed_versions()?,
+                last_known_good: state.last_known_good,
+                bad: state.bad_versions,
+                good: state.good_versions,
+            },
+            health: ReportHealth {
+                app_running: supervisor.running_app().is_some(),
+                safe_mode: supervisor.safe_mode(),
+                last_exit: state.last_exit,
+                recent_crashes: state
+                    .crash_history
+                    .iter()
+                    .filter(|crash| crash.at > since)
+                    .count(),
+            },
+            disk: ReportDisk {
+                available_bytes: HostService::available_space(&self.data_dir)?,
+                data_dir_bytes: FileService::size_of(&self.data_dir)?,
+            },
+            alerts,
+        })
+    }
+
+    /// Queues `report` and uploads the queue. Whatever fails to upload stays queued
+    /// for the next attempt. Returns the number of reports delivered.
+    pub fn send(&self, report: &Report) -> Result<usize> {
+        let url = self
+            .url
+            .as_deref()
+            .context("No report endpoint configured; set GEIST_REPORT_URL")?;
+
+        // Queue first, so an upload failure leaves the report where the next attempt finds it
+        self.enqueue(report)?;
+        fs::write(
+            self.data_dir.join(Config::REPORT_LAST_SENT_FILE),
+            report.generated_at.to_rfc3339(),
+        )
+        .context("Failed to record the report time")?;
+        self.flush(url)
+    }
+
+    // Reports waiting for delivery, oldest first
+    fn queued(&self) -> Result<Vec<PathBuf>> {
+        let Ok(entries) = fs::read_dir(self.queue_dir()) else {
+            return Ok(Vec::new());
+        };
+        let mut paths: Vec<PathBuf> = entries
+            .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+            .filter(|path| path.extension().is_some_and(|ext| ext == "json"))
+            .collect();
+        // Named by generation time, so the names sort chronologically
+        paths.sort();
+        Ok(paths)
+    }
+
+    fn enqueue(&self, report: &Report) -> Result<()> {
+        let dir = self.queue_dir();
+        fs::create_dir_all(&dir).context("Failed to create the report queue")?;
+        let path = dir.join(format!(
+            "{}.json",
+            report.generated_at.format("%Y%m%dT%H%M%S%.3fZ")
+        ));
+        fs::write(&path, serde_json::to_vec(report)?)
+            .with_context(|| format!("Failed to queue report {}", path.display()))?;
+
+        // A device that stays offline keeps only its most recent reports
+        let queued = self.queued()?;
+        let excess = queued.len().saturating_sub(Config::REPORT_QUEUE_MAX);
+        for old in &queued[..excess] {
+            warn!("Dropping undelivered report {}", old.display());
+            fs::remove_file(old).ok();
+        }
+        Ok(())
+    }
+
+    // Sends the queued reports in order, stopping at the first one that fails
+    fn flush(&self, url: &str) -> Result<usize> {
+        let queued = self.queued()?;
+        let mut delivered = 0;
+        for path in &queued {
+            let body = fs::read(path)?;
+            if let Err(e) = self.upload(url, body) {
+                return Err(e).with_context(|| {
+                    format!(
+                        "Report upload failed; {} report(s) queued",
+                        queued.len() - delivered
+                    )
+                });
+            }
+            fs::remove_file(path)?;
+            delivered += 1;
+        }
+        Ok(delivered)
+    }
+
+    // POSTs one report, retrying with a growing delay
+    fn upload(&self, url: &str, body: Vec<u8>) -> Result<()> {
+        let client = reqwest::blocking::Client::builder()
+            .timeout(Duration::from_secs(Config::REPORT_TIMEOUT_SECS))
+            .build()?;
+        let mut delay = Duration::from_secs(Config::REPORT_RETRY_DELAY_SECS);
+        let mut attempt = 1;
+        loop {
+            let mut request = client
+                .post(url)
+                .header(reqwest::header::CONTENT_TYPE, "application/json")
+                .body(body.clone());
+            if let Some(token) = Config::report_token() {
+                request = request.bearer_auth(token);
+            }
+            let result = request
+                .send()
+                .with_context(|| format!("Failed to reach {}", url))
+                .and_then(|response| {
+                    if response.status().is_success() {
+                        Ok(())
+                    } else {
+                        Err(anyhow::anyhow!("{} answered {}", url, response.status()))
+                    }
+                });
+            match result {
+                Ok(()) => return Ok(()),
+                Err(e) if attempt >= Config::REPORT_ATTEMPTS => return Err(e),
+                Err(e) => {
+                    warn!("Report upload attempt {} failed: {:#}", attempt, e);
+                    thread::sleep(delay);
+                    delay *= 2;
+                    attempt += 1;
+                }
+            }
+        }
+    }
+
+    fn last_sent(&self) -> Option<DateTime<Utc>> {
+        let contents =
+            fs::read_to_string(self.data_dir.join(Config::REPORT_LAST_SENT_FILE)).ok()?;
+        DateTime::parse_from_rfc3339(contents.trim())
+            .ok()
+            .map(|at| at.with_timezone(&Utc))
+    }
+
+    /// Sends a report once per interval, counted from the last one even across
+    /// restarts, and retries queued reports sooner. Never returns.
+    pub fn run_periodically(&self) {
+        info!("Sending a device report every {:?}", Self::interval());
+        let retry = Duration::from_secs(Config::REPORT_RETRY_INTERVAL_SECS);
+        loop {
+            let due = self
+                .last_sent()
+                .map_or(Utc::now(), |last| last + Self::interval());
+            let wait = (due - Utc::now()).to_std().unwrap_or_default();
+
+            if wait.is_zero() {
+                match self.build().and_then(|report| self.send(&report)) {
+                    Ok(delivered) => info!("Delivered {} report(s)", delivered),
+                    Err(e) => {
+                        error!("Device report not delivered: {:#}", e);
+                        thread::sleep(retry);
+                    }
+                }
+                continue;
+            }
+
+            if self.queued().unwrap_or_default().is_empty() {
+                thread::sleep(wait);
+                continue;
+            }
+            thread::sleep(wait.min(retry));
+            if let Some(url) = &self.url {
+                match self.flush(url) {
+                    Ok(delivered) => info!("Delivered {} queued report(s)", delivered),
+                    Err(e) => warn!("{:#}", e),
+                }
+            }
+        }
+    }
+}
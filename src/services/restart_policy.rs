@@ -0,0 +1,124 @@
+use crate::config::Config;
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+use std::process::ExitStatus;
+use tracing::warn;
+
+/// What the supervisor does after the app exits
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ExitAction {
+    /// Launch the app again; failures count towards crash-loop detection
+    Restart,
+    /// Stop supervising
+    Stop,
+    /// Run `geist update` and boot whatever version it stages
+    Update,
+}
+
+impl ExitAction {
+    /// `restart`, `stop` or `update`
+    pub fn parse(action: &str) -> Option<Self> {
+        match action {
+            "restart" => Some(Self::Restart),
+            "stop" => Some(Self::Stop),
+            "update" => Some(Self::Update),
+            _ => None,
+        }
+    }
+}
+
+/// Maps exit codes of the app to what happens next. Unlisted codes stop the
+/// supervisor after a successful exit and follow `on_failure` otherwise.
+///
+/// Declared in the `restart_policy` section of a version's `manifest.yaml`:
+///
+/// ```yaml
+/// restart_policy:
+///   on_failure: restart
+///   exit_codes:
+///     42: update
+/// ```
+#[derive(Debug, Clone, Deserialize)]
+pub struct RestartPolicy {
+    #[serde(default = "RestartPolicy::default_on_failure")]
+    pub on_failure: ExitAction,
+    #[serde(default)]
+    pub exit_codes: BTreeMap<i32, ExitAction>,
+}
+
+impl Default for RestartPolicy {
+    fn default() -> Self {
+        Self {
+            on_failure: Self::default_on_failure(),
+            exit_codes: BTreeMap::new(),
+        }
+    }
+}
+
+// Only the part of the manifest the supervisor cares about
+#[derive(Deserialize)]
+struct Manifest {
+    #[serde(default)]
+    restart_policy: Option<RestartPolicy>,
+}
+
+impl RestartPolicy {
+    fn default_on_failure() -> ExitAction {
+        ExitAction::Restart
+    }
+
+    /// The policy for an installed version: its manifest's policy, with the
+    /// entries from GEIST_RESTART_POLICY applied on top
+    pub fn load(version_dir: &Path) -> Self {
+        let mut policy = Self::from_manifest(&version_dir.join("manifest.yaml"));
+
+        for entry in Config::restart_policy() {
+            let parsed = entry
+                .split_once('=')
+                .and_then(|(key, action)| Some((key.trim(), ExitAction::parse(action.trim())?)));
+            match parsed {
+                Some(("on_failure", action)) => policy.on_failure = action,
+                Some((code, action)) => match code.parse() {
+                    Ok(code) => {
+                        policy.exit_codes.insert(code, action);
+                    }
+                    Err(_) => warn!("Ignoring unknown restart policy entry: {}", entry),
+                },
+                None => warn!("Ignoring unknown restart policy entry: {}", entry),
+            }
+        }
+        policy
+    }
+
+    fn from_manifest(path: &Path) -> Self {
+        let Ok(contents) = fs::read_to_string(path) else {
+            return Self::default();
+        };
+        match serde_yaml::from_str::<Manifest>(&contents) {
+            Ok(manifest) => manifest.restart_policy.unwrap_or_default(),
+            Err(e) => {
+                warn!(
+                    "Ignoring restart policy in {}, using defaults: {}",
+                    path.display(),
+                    e
+                );
+                Self::default()
+            }
+        }
+    }
+
+    /// The action for an exit; deaths by signal have no code and count as failures
+    pub fn action_for(&self, status: &ExitStatus) -> ExitAction {
+        if let Some(action) = status.code().and_then(|code| self.exit_codes.get(&code)) {
+            return *action;
+        }
+        if status.success() {
+            ExitAction::Stop
+        } else {
+            self.on_failure
+        }
+    }
+}
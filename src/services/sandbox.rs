@@ -0,0 +1,1027 @@
+use crate::config::Config;
+use crate::services::{AppCredentials, ExecLabel};
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use tracing::{info, warn};
+
+/// What a version needs from the host when it runs sandboxed.
+///
+/// Declared in the `sandbox` section of a version's `manifest.yaml`:
+///
+/// ```yaml
+/// sandbox:
+///   devices:
+///     - /dev/video0
+///     - /dev/dri
+///   paths:
+///     - /run/udev
+/// ```
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct SandboxManifest {
+    /// Device nodes, or directories of them, the app opens
+    #[serde(default)]
+    pub devices: Vec<PathBuf>,
+    /// Further host paths the app reads, made visible read-only
+    #[serde(default)]
+    pub paths: Vec<PathBuf>,
+}
+
+// Only the part of the manifest the sandbox cares about
+#[derive(Deserialize)]
+struct Manifest {
+    #[serde(default)]
+    sandbox: Option<SandboxManifest>,
+}
+
+impl SandboxManifest {
+    /// The sandbox section of an installed version, empty if it declares none
+    pub fn load(version_dir: &Path) -> Self {
+        let path = version_dir.join("manifest.yaml");
+        let Ok(contents) = fs::read_to_string(&path) else {
+            return Self::default();
+        };
+        match serde_yaml::from_str::<Manifest>(&contents) {
+            Ok(manifest) => manifest.sandbox.unwrap_or_default(),
+            Err(e) => {
+                warn!("Ignoring sandbox settings in {}: {}", path.display(), e);
+                Self::default()
+            }
+        }
+    }
+}
+
+// What the sandbox-exec helper builds the app's view of the filesystem from,
+// passed to it as JSON in GEIST_SANDBOX_SPEC
+#[derive(Debug, Serialize, Deserialize)]
+struct SandboxSpec {
+    // Empty directory the new root is mounted on
+    root: PathBuf,
+    version_dir: PathBuf,
+    read_only: Vec<PathBuf>,
+    writable: Vec<PathBuf>,
+    devices: Vec<PathBuf>,
+    // Confinement to switch to when starting the app
+    exec_label: Option<ExecLabel>,
+    // Account to switch to once the root is built
+    #[serde(default)]
+    credentials: Option<AppCredentials>,
+}
+
+const SPEC_VAR: &str = "GEIST_SANDBOX_SPEC";
+
+/// Launches the app in its own mount and PID namespaces, seeing only the system's
+/// libraries and configuration, its version directory, the directories it shares
+/// with the supervisor and with other versions and the devices its manifest
+/// declares, with system calls limited to an allowlist.
+///
+/// The namespaces are set up by `geist sandbox-exec`, a hidden helper that stays
+/// in between as the process the supervisor sees: it forwards signals to the app
+/// and exits the way the app did.
+pub struct Sandbox {
+    data_dir: PathBuf,
+}
+
+impl Sandbox {
+    pub fn new(data_dir: PathBuf) -> Self {
+        Self { data_dir }
+    }
+
+    /// A command that runs `binary` of the version in `version_dir` sandboxed;
+    /// arguments added to it are passed to the binary. `extra` paths, such as a
+    /// replay bag, are made visible read-only, `exec_label` confines the binary and
+    /// `credentials` is the account it runs under.
+    pub fn command(
+        &self,
+        version_dir: &Path,
+        binary: &Path,
+        extra: &[&Path],
+        exec_label: Option<ExecLabel>,
+        credentials: Option<AppCredentials>,
+    ) -> Result<Command> {
+        let manifest = SandboxManifest::load(version_dir);
+        for device in &manifest.devices {
+            if !device.starts_with("/dev") {
+                anyhow::bail!(
+                    "Sandbox device {} is not under /dev; declare it in paths instead",
+                    device.display()
+                );
+            }
+            if !device.exists() {
+                anyhow::bail!(
+                    "Device {} required by the version's manifest does not exist",
+                    device.display()
+                );
+            }
+        }
+
+        let mut read_only: Vec<PathBuf> = Vec::new();
+        for path in manifest
+            .paths
+            .iter()
+            .map(PathBuf::as_path)
+            .chain(extra.iter().copied())
+        {
+            if !path.is_absolute() {
+                anyhow::bail!("Sandbox path {} is not absolute", path.display());
+            }
+            if path.exists() {
+                read_only.push(path.to_path_buf());
+            } else {
+                warn!(
+                    "Not making {} visible in the sandbox: it does not exist",
+                    path.display()
+                );
+            }
+        }
+
+        let root = self.data_dir.join(Config::SANDBOX_ROOT_DIR);
+        let run_dir = self.data_dir.join(Config::APP_RUN_DIR);
+        let app_data_dir = self.data_dir.join(Config::APP_DATA_DIR);
+        fs::create_dir_all(&root)
+            .with_context(|| format!("Failed to create {}", root.display()))?;
+        for dir in [&run_dir, &app_data_dir] {
+            fs::create_dir_all(dir)
+                .with_context(|| format!("Failed to create {}", dir.display()))?;
+        }
+
+        let spec = SandboxSpec {
+            root,
+            version_dir: version_dir.to_path_buf(),
+            read_only,
+            writable: vec![run_dir, app_data_dir],
+            devices: manifest.devices,
+            exec_label,
+            credentials,
+        };
+        info!(
+            "Sandboxing the app with devices: {}",
+            if spec.devices.is_empty() {
+                "none".to_string()
+            } else {
+                spec.devices
+                    .iter()
+                    .map(|device| device.display().to_string())
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            }
+        );
+
+        let mut command =
+            Command::new(env::current_exe().context("Failed to locate the geist binary")?);
+        command
+            .arg("sandbox-exec")
+            .arg("--")
+            .arg(binary)
+            .env(SPEC_VAR, serde_json::to_string(&spec)?);
+        Ok(command)
+    }
+
+    /// Runs `program` inside the sandbox described by GEIST_SANDBOX_SPEC and exits
+    /// the way it did. Only returns on failures before the app was started.
+    #[cfg(target_os = "linux")]
+    pub fn exec(program: &std::ffi::OsStr, args: &[std::ffi::OsString]) -> Result<()> {
+        let spec: SandboxSpec = serde_json::from_str(
+            &env::var(SPEC_VAR).with_context(|| format!("{} is not set", SPEC_VAR))?,
+        )
+        .with_context(|| format!("Failed to parse {}", SPEC_VAR))?;
+        // The app has no use for it
+        env::remove_var(SPEC_VAR);
+        linux::exec(&spec, program, args)
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    pub fn exec(_program: &std::ffi::OsStr, _args: &[std::ffi::OsString]) -> Result<()> {
+        anyhow::bail!("The sandbox requires Linux")
+    }
+}
+
+// Everything that happens between forking and starting the app is prepared up
+// front, since geist may already run threads (e.g. for telemetry) and a forked
+// child of a threaded process must not allocate
+#[cfg(target_os = "linux")]
+mod linux {
+    use super::SandboxSpec;
+    use crate::config::Config;
+    use crate::services::ExecLabel;
+    use anyhow::{Context, Result};
+    use std::collections::BTreeSet;
+    use std::ffi::{CString, OsStr, OsString};
+    use std::fs;
+    use std::io;
+    use std::os::unix::ffi::OsStrExt;
+    use std::path::{Path, PathBuf};
+    use std::ptr;
+
+    // Signals the helper passes on to the app
+    const FORWARDED_SIGNALS: &[libc::c_int] = &[
+        libc::SIGHUP,
+        libc::SIGINT,
+        libc::SIGQUIT,
+        libc::SIGUSR1,
+        libc::SIGUSR2,
+        libc::SIGTERM,
+        libc::SIGWINCH,
+    ];
+
+    // Where the old root is parked during pivot_root, relative to the new one
+    const OLD_ROOT: &str = ".old_root";
+
+    // Device nodes every program expects
+    const BASIC_DEVICES: &[&str] = &[
+        "/dev/null",
+        "/dev/zero",
+        "/dev/full",
+        "/dev/random",
+        "/dev/urandom",
+        "/dev/tty",
+    ];
+
+    // One filesystem operation of building the new root
+    enum Step {
+        Mkdir(CString),
+        Touch(CString),
+        Symlink {
+            target: CString,
+            link: CString,
+        },
+        Mount {
+            source: Option<CString>,
+            target: CString,
+            fstype: Option<CString>,
+            flags: libc::c_ulong,
+            data: Option<CString>,
+        },
+        PivotRoot {
+            new_root: CString,
+            put_old: CString,
+        },
+        Chdir(CString),
+        Unmount(CString),
+        Rmdir(CString),
+    }
+
+    impl Step {
+        fn run(&self) -> io::Result<()> {
+            let result = unsafe {
+                match self {
+                    Step::Mkdir(path) => match libc::mkdir(path.as_ptr(), 0o755) {
+                        0 => 0,
+                        _ if io::Error::last_os_error().raw_os_error() == Some(libc::EEXIST) => 0,
+                        _ => -1,
+                    },
+                    Step::Touch(path) => {
+                        let fd = libc::open(
+                            path.as_ptr(),
+                            libc::O_CREAT | libc::O_WRONLY | libc::O_CLOEXEC,
+                            0o644,
+                        );
+                        if fd >= 0 {
+                            libc::close(fd);
+                        }
+                        fd.min(0)
+                    }
+                    Step::Symlink { target, link } => libc::symlink(target.as_ptr(), link.as_ptr()),
+                    Step::Mount {
+                        source,
+                        target,
+                        fstype,
+                        flags,
+                        data,
+                    } => libc::mount(
+                        source.as_ref().map_or(ptr::null(), |s| s.as_ptr()),
+                        target.as_ptr(),
+                        fstype.as_ref().map_or(ptr::null(), |s| s.as_ptr()),
+                        *flags,
+                        data.as_ref().map_or(ptr::null(), |s| s.as_ptr().cast()),
+                    ),
+                    Step::PivotRoot { new_root, put_old } => {
+                        libc::syscall(libc::SYS_pivot_root, new_root.as_ptr(), put_old.as_ptr())
+                            as libc::c_int
+                    }
+                    Step::Chdir(path) => libc::chdir(path.as_ptr()),
+                    Step::Unmount(path) => libc::umount2(path.as_ptr(), libc::MNT_DETACH),
+                    Step::Rmdir(path) => libc::rmdir(path.as_ptr()),
+                }
+            };
+            if result != 0 {
+                return Err(io::Error::last_os_error());
+            }
+            Ok(())
+        }
+    }
+
+    fn cstring(path: &Path) -> Result<CString> {
+        CString::new(path.as_os_str().as_bytes())
+            .with_context(|| format!("Invalid path {}", path.display()))
+    }
+
+    // The steps that build the new root from the spec, each with a description
+    // for errors
+    struct RootPlan {
+        root: PathBuf,
+        steps: Vec<(Step, CString)>,
+        created: BTreeSet<PathBuf>,
+    }
+
+    impl RootPlan {
+        fn build(spec: &SandboxSpec) -> Result<Self> {
+            let mut plan = Self {
+                root: spec.root.clone(),
+                steps: Vec::new(),
+                created: BTreeSet::new(),
+            };
+
+            // Keep the mounts below from propagating back to the host
+            plan.mount(
+                None,
+                Path::new("/"),
+                None,
+                libc::MS_REC | libc::MS_PRIVATE,
+                None,
+            )?;
+            plan.mount(
+                Some("tmpfs"),
+                &spec.root,
+                Some("tmpfs"),
+                libc::MS_NOSUID | libc::MS_NODEV,
+                Some("mode=0755"),
+            )?;
+
+            for path in Config::SANDBOX_SYSTEM_PATHS.iter().map(Path::new) {
+                match fs::symlink_metadata(path) {
+                    // Merged-/usr systems link /lib and friends into /usr
+                    Ok(metadata) if metadata.file_type().is_symlink() => {
+                        let target = fs::read_link(path)?;
+                        plan.symlink(&target, path)?;
+                    }
+                    Ok(_) => plan.bind(path, true)?,
+                    Err(_) => {}
+                }
+            }
+            // Fresh filesystems before the version and data paths, which may lie below
+            // /tmp in development setups and would be hidden otherwise
+            let dev = Path::new("/dev");
+            plan.mount(
+                Some("tmpfs"),
+                dev,
+                Some("tmpfs"),
+                libc::MS_NOSUID | libc::MS_NOEXEC,
+                Some("mode=0755"),
+            )?;
+            for device in BASIC_DEVICES.iter().map(Path::new) {
+                if device.exists() {
+                    plan.bind(device, false)?;
+                }
+            }
+            for device in &spec.devices {
+                plan.bind(device, false)?;
+            }
+            plan.mount(
+                Some("tmpfs"),
+                &dev.join("shm"),
+                Some("tmpfs"),
+                libc::MS_NOSUID | libc::MS_NODEV,
+                Some("mode=1777"),
+            )?;
+            for (link, target) in [
+                ("fd", "/proc/self/fd"),
+                ("stdin", "/proc/self/fd/0"),
+                ("stdout", "/proc/self/fd/1"),
+                ("stderr", "/proc/self/fd/2"),
+            ] {
+                plan.symlink(Path::new(target), &dev.join(link))?;
+            }
+
+            // A fresh /proc shows only the processes of the new PID namespace
+            plan.mount(
+                Some("proc"),
+                Path::new("/proc"),
+                Some("proc"),
+                libc::MS_NOSUID | libc::MS_NODEV | libc::MS_NOEXEC,
+                None,
+            )?;
+            plan.mount(
+                Some("tmpfs"),
+                Path::new("/tmp"),
+                Some("tmpfs"),
+                libc::MS_NOSUID | libc::MS_NODEV,
+                Some("mode=1777"),
+            )?;
+            plan.bind(&spec.version_dir, true)?;
+            for path in &spec.read_only {
+                plan.bind(path, true)?;
+            }
+            for path in &spec.writable {
+                plan.bind(path, false)?;
+            }
+
+            // Switch to the new root and drop every trace of the old one
+            let old_root = Path::new("/").join(OLD_ROOT);
+            plan.dir(&old_root)?;
+            plan.push(
+                Step::PivotRoot {
+                    new_root: cstring(&spec.root)?,
+                    put_old: cstring(&plan.inside(&old_root))?,
+                },
+                "switch to the new root",
+            )?;
+            plan.push(Step::Chdir(cstring(Path::new("/"))?), "enter the new root")?;
+            plan.push(Step::Unmount(cstring(&old_root)?), "unmount the old root")?;
+            plan.push(Step::Rmdir(cstring(&old_root)?), "remove the old root")?;
+            plan.push(
+                Step::Mount {
+                    source: None,
+                    target: cstring(Path::new("/"))?,
+                    fstype: None,
+                    flags: libc::MS_REMOUNT | libc::MS_RDONLY | libc::MS_NOSUID | libc::MS_NODEV,
+                    data: None,
+                },
+                "make the new root read-only",
+            )?;
+            plan.push(
+                Step::Chdir(cstring(&spec.version_dir)?),
+                "enter the version directory",
+            )?;
+            Ok(plan)
+        }
+
+        // Where a host path appears below the new root before switching to it
+        fn inside(&self, path: &Path) -> PathBuf {
+            self.root.join(path.strip_prefix("/").unwrap_or(path))
+        }
+
+        fn push(&mut self, step: Step, description: impl Into<String>) -> Result<()> {
+            self.steps.push((
+                step,
+                CString::new(description.into()).context("Invalid path")?,
+            ));
+            Ok(())
+        }
+
+        // Creates a directory and its parents below the new root
+        fn dir(&mut self, path: &Path) -> Result<()> {
+            let mut ancestors: Vec<&Path> = path.ancestors().collect();
+            ancestors.reverse();
+            for ancestor in ancestors.into_iter().skip(1) {
+                if self.created.insert(ancestor.to_path_buf()) {
+                    let inside = self.inside(ancestor);
+                    self.push(
+                        Step::Mkdir(cstring(&inside)?),
+                        format!("create {}", ancestor.display()),
+                    )?;
+                }
+            }
+            Ok(())
+        }
+
+        fn symlink(&mut self, target: &Path, link: &Path) -> Result<()> {
+            if let Some(parent) = link.parent() {
+                self.dir(parent)?;
+            }
+            self.created.insert(link.to_path_buf());
+            let inside = self.inside(link);
+            self.push(
+                Step::Symlink {
+                    target: cstring(target)?,
+                    link: cstring(&inside)?,
+                },
+                format!("link {}", link.display()),
+            )
+        }
+
+        // Makes a host path visible at the same place below the new root
+        fn bind(&mut self, path: &Path, read_only: bool) -> Result<()> {
+            if path.is_dir() {
+                self.dir(path)?;
+            } else {
+                if let Some(parent) = path.parent() {
+                    self.dir(parent)?;
+                }
+                if self.created.insert(path.to_path_buf()) {
+                    let inside = self.inside(path);
+                    self.push(
+                        Step::Touch(cstring(&inside)?),
+                        format!("create {}", path.display()),
+                    )?;
+                }
+            }
+
+            let target = cstring(&self.inside(path))?;
+            self.push(
+                Step::Mount {
+                    source: Some(cstring(path)?),
+                    target: target.clone(),
+                    fstype: None,
+                    flags: libc::MS_BIND | libc::MS_REC,
+                    data: None,
+                },
+                format!("bind {}", path.display()),
+            )?;
+            if read_only {
+                self.push(
+                    Step::Mount {
+                        source: None,
+                        target,
+                        fstype: None,
+                        flags: libc::MS_BIND | libc::MS_REMOUNT | libc::MS_RDONLY,
+                        data: None,
+                    },
+                    format!("make {} read-only", path.display()),
+                )?;
+            }
+            Ok(())
+        }
+
+        // Mounts a filesystem at a path below the new root, or at `/` itself
+        fn mount(
+            &mut self,
+            source: Option<&str>,
+            path: &Path,
+            fstype: Option<&str>,
+            flags: libc::c_ulong,
+            data: Option<&str>,
+        ) -> Result<()> {
+            let target = if path == Path::new("/") || path == self.root {
+                path.to_path_buf()
+            } else {
+                self.dir(path)?;
+                self.inside(path)
+            };
+            let text = |value: Option<&str>| value.map(CString::new).transpose();
+            self.push(
+                Step::Mount {
+                    source: text(source)?,
+                    target: cstring(&target)?,
+                    fstype: text(fstype)?,
+                    flags,
+                    data: text(data)?,
+                },
+                format!("mount {}", path.display()),
+            )
+        }
+    }
+
+    // Reports a failed step on stderr without allocating
+    fn fail(description: &CString, error: &io::Error) -> ! {
+        let parts: [&[u8]; 4] = [
+            b"geist sandbox: failed to ",
+            description.as_bytes(),
+            b": ",
+            b"",
+        ];
+        unsafe {
+            for part in parts {
+                libc::write(2, part.as_ptr().cast(), part.len());
+            }
+            let message = libc::strerror(error.raw_os_error().unwrap_or(0));
+            libc::write(2, message.cast(), libc::strlen(message));
+            libc::write(2, b"\n".as_ptr().cast(), 1);
+            libc::_exit(126);
+        }
+    }
+
+    pub(super) fn exec(spec: &SandboxSpec, program: &OsStr, args: &[OsString]) -> Result<()> {
+        let plan = RootPlan::build(spec)?;
+        let filter = super::seccomp::filter();
+        let program = CString::new(program.as_bytes()).context("Invalid program path")?;
+        let argv: Vec<CString> = std::iter::once(Ok(program.clone()))
+            .chain(args.iter().map(|arg| CString::new(arg.as_bytes())))
+            .collect::<Result<_, _>>()
+            .context("Invalid argument")?;
+        let mut argv_ptrs: Vec<*const libc::c_char> = argv.iter().map(|arg| arg.as_ptr()).collect();
+        argv_ptrs.push(ptr::null());
+        let exec_failed = CString::new(format!("start {}", program.to_string_lossy()))?;
+        let no_new_privs = CString::new("forbid gaining privileges")?;
+        let switch_user = CString::new("switch to the app user")?;
+        let install_filter = CString::new("install the system call filter")?;
+        let label = match &spec.exec_label {
+            Some(label) => Some((
+                CString::new(label.attr_path.as_os_str().as_bytes())?,
+                CString::new(label.value.as_str())?,
+                CString::new(format!("switch to {}", label.value))?,
+            )),
+            None => None,
+        };
+
+        unsafe {
+            // Blocked before forking so no signal slips through before the loop below
+            let mut signals: libc::sigset_t = std::mem::zeroed();
+            let mut previous: libc::sigset_t = std::mem::zeroed();
+            libc::sigemptyset(&mut signals);
+            for signal in FORWARDED_SIGNALS.iter().chain([&libc::SIGCHLD]) {
+                libc::sigaddset(&mut signals, *signal);
+            }
+            libc::sigprocmask(libc::SIG_BLOCK, &signals, &mut previous);
+
+            if libc::unshare(libc::CLONE_NEWNS | libc::CLONE_NEWPID) != 0 {
+                let error = io::Error::last_os_error();
+                if error.raw_os_error() == Some(libc::EPERM) {
+                    anyhow::bail!("The sandbox needs to run as root (CAP_SYS_ADMIN)");
+                }
+                return Err(error).context("Failed to create the sandbox namespaces");
+            }
+
+            // The first child is PID 1 of the new namespace
+            let pid = libc::fork();
+            if pid < 0 {
+                return Err(io::Error::last_os_error()).context("Failed to fork the sandbox");
+            }
+            if pid == 0 {
+                libc::sigprocmask(libc::SIG_SETMASK, &previous, ptr::null_mut());
+                // Without the helper nothing would stop the app any more
+                libc::prctl(libc::PR_SET_PDEATHSIG, libc::SIGKILL);
+                for (step, description) in &plan.steps {
+                    if let Err(e) = step.run() {
+                        fail(description, &e);
+                    }
+                }
+                if let Some((attr_path, value, description)) = &label {
+                    if let Err(e) = ExecLabel::write(attr_path, value) {
+                        fail(description, &e);
+                    }
+                }
+                if let Some(credentials) = &spec.credentials {
+                    if let Err(e) = credentials.apply() {
+                        fail(&switch_user, &e);
+                    }
+                }
+                if libc::prctl(libc::PR_SET_NO_NEW_PRIVS, 1, 0, 0, 0) != 0 {
+                    fail(&no_new_privs, &io::Error::last_os_error());
+                }
+                let program = libc::sock_fprog {
+                    len: filter.len() as libc::c_ushort,
+                    filter: filter.as_ptr() as *mut libc::sock_filter,
+                };
+                if libc::prctl(
+                    libc::PR_SET_SECCOMP,
+                    libc::SECCOMP_MODE_FILTER,
+                    &program as *const libc::sock_fprog,
+                ) != 0
+                {
+                    fail(&install_filter, &io::Error::last_os_error());
+                }
+                libc::execv(argv[0].as_ptr(), argv_ptrs.as_ptr());
+                fail(&exec_failed, &io::Error::last_os_error());
+            }
+
+            loop {
+                let mut signal = 0;
+                if libc::sigwait(&signals, &mut signal) != 0 {
+                    continue;
+                }
+                if signal != libc::SIGCHLD {
+                    libc::kill(pid, signal);
+                    continue;
+                }
+                let mut status = 0;
+                if libc::waitpid(pid, &mut status, libc::WNOHANG) != pid {
+                    continue;
+                }
+                if libc::WIFSIGNALED(status) {
+                    // Die the same way, so the supervisor sees a crash as a crash
+                    let signal = libc::WTERMSIG(status);
+                    let no_core = libc::rlimit {
+                        rlim_cur: 0,
+                        rlim_max: 0,
+                    };
+                    libc::setrlimit(libc::RLIMIT_CORE, &no_core);
+                    libc::signal(signal, libc::SIG_DFL);
+                    libc::sigprocmask(libc::SIG_SETMASK, &previous, ptr::null_mut());
+                    libc::raise(signal);
+                    std::process::exit(128 + signal);
+                }
+                std::process::exit(libc::WEXITSTATUS(status));
+            }
+        }
+    }
+}
+
+// The system calls a sandboxed app may make; anything else fails with EPERM.
+// Left out are the ones that change the system rather than the process: mounts,
+// namespaces, modules, tracing other processes, rebooting, setting the clock.
+#[cfg(target_os = "linux")]
+mod seccomp {
+    use libc::c_long;
+
+    // struct seccomp_data: the syscall number comes first, then the architecture
+    const NR_OFFSET: u32 = 0;
+    const ARCH_OFFSET: u32 = 4;
+
+    #[cfg(target_arch = "x86_64")]
+    const AUDIT_ARCH: u32 = 0xc000_003e;
+    #[cfg(target_arch = "aarch64")]
+    const AUDIT_ARCH: u32 = 0xc000_00b7;
+    #[cfg(target_arch = "arm")]
+    const AUDIT_ARCH: u32 = 0x4000_0028;
+
+    const COMMON: &[c_long] = &[
+        libc::SYS_read,
+        libc::SYS_write,
+        libc::SYS_readv,
+        libc::SYS_writev,
+        libc::SYS_pread64,
+        libc::SYS_pwrite64,
+        libc::SYS_preadv,
+        libc::SYS_pwritev,
+        libc::SYS_openat,
+        libc::SYS_close,
+        libc::SYS_close_range,
+        libc::SYS_lseek,
+        libc::SYS_fstat,
+        libc::SYS_statx,
+        libc::SYS_faccessat,
+        libc::SYS_faccessat2,
+        libc::SYS_readlinkat,
+        libc::SYS_getdents64,
+        libc::SYS_mkdirat,
+        libc::SYS_unlinkat,
+        libc::SYS_renameat2,
+        libc::SYS_linkat,
+        libc::SYS_symlinkat,
+        libc::SYS_fchmod,
+        libc::SYS_fchmodat,
+        libc::SYS_fchownat,
+        libc::SYS_ftruncate,
+        libc::SYS_fallocate,
+        libc::SYS_fsync,
+        libc::SYS_fdatasync,
+        libc::SYS_flock,
+        libc::SYS_fcntl,
+        libc::SYS_ioctl,
+        libc::SYS_dup,
+        libc::SYS_dup3,
+        libc::SYS_pipe2,
+        libc::SYS_copy_file_range,
+        libc::SYS_splice,
+        libc::SYS_tee,
+        libc::SYS_statfs,
+        libc::SYS_fstatfs,
+        libc::SYS_getcwd,
+        libc::SYS_chdir,
+        libc::SYS_fchdir,
+        libc::SYS_umask,
+        libc::SYS_mprotect,
+        libc::SYS_munmap,
+        libc::SYS_mremap,
+        libc::SYS_madvise,
+        libc::SYS_mincore,
+        libc::SYS_msync,
+        libc::SYS_mlock,
+        libc::SYS_munlock,
+        libc::SYS_brk,
+        libc::SYS_membarrier,
+        libc::SYS_memfd_create,
+        libc::SYS_futex,
+        libc::SYS_set_robust_list,
+        libc::SYS_get_robust_list,
+        libc::SYS_set_tid_address,
+        libc::SYS_rseq,
+        libc::SYS_clone,
+        libc::SYS_clone3,
+        libc::SYS_execve,
+        libc::SYS_exit,
+        libc::SYS_exit_group,
+        libc::SYS_wait4,
+        libc::SYS_waitid,
+        libc::SYS_kill,
+        libc::SYS_tkill,
+        libc::SYS_tgkill,
+        libc::SYS_getpid,
+        libc::SYS_gettid,
+        libc::SYS_getppid,
+        libc::SYS_getpgid,
+        libc::SYS_setpgid,
+        libc::SYS_getsid,
+        libc::SYS_setsid,
+        libc::SYS_prctl,
+        libc::SYS_rt_sigaction,
+        libc::SYS_rt_sigprocmask,
+        libc::SYS_rt_sigreturn,
+        libc::SYS_rt_sigtimedwait,
+        libc::SYS_rt_sigsuspend,
+        libc::SYS_rt_sigpending,
+        libc::SYS_rt_sigqueueinfo,
+        libc::SYS_sigaltstack,
+        libc::SYS_restart_syscall,
+        libc::SYS_sched_yield,
+        libc::SYS_sched_getaffinity,
+        libc::SYS_sched_setaffinity,
+        libc::SYS_sched_getparam,
+        libc::SYS_sched_setparam,
+        libc::SYS_sched_getscheduler,
+        libc::SYS_sched_setscheduler,
+        libc::SYS_sched_get_priority_max,
+        libc::SYS_sched_get_priority_min,
+        libc::SYS_getpriority,
+        libc::SYS_setpriority,
+        libc::SYS_nanosleep,
+        libc::SYS_clock_gettime,
+        libc::SYS_clock_getres,
+        libc::SYS_clock_nanosleep,
+        libc::SYS_gettimeofday,
+        libc::SYS_times,
+        libc::SYS_getrusage,
+        libc::SYS_prlimit64,
+        libc::SYS_sysinfo,
+        libc::SYS_uname,
+        libc::SYS_getrandom,
+        libc::SYS_capget,
+        libc::SYS_eventfd2,
+        libc::SYS_epoll_create1,
+        libc::SYS_epoll_ctl,
+        libc::SYS_epoll_pwait,
+        libc::SYS_ppoll,
+        libc::SYS_pselect6,
+        libc::SYS_timerfd_create,
+        libc::SYS_timerfd_settime,
+        libc::SYS_timerfd_gettime,
+        libc::SYS_signalfd4,
+        libc::SYS_inotify_init1,
+        libc::SYS_inotify_add_watch,
+        libc::SYS_inotify_rm_watch,
+        libc::SYS_socket,
+        libc::SYS_socketpair,
+        libc::SYS_bind,
+        libc::SYS_listen,
+        libc::SYS_accept4,
+        libc::SYS_connect,
+        libc::SYS_getsockname,
+        libc::SYS_getpeername,
+        libc::SYS_sendto,
+        libc::SYS_recvfrom,
+        libc::SYS_sendmsg,
+        libc::SYS_recvmsg,
+        libc::SYS_sendmmsg,
+        libc::SYS_recvmmsg,
+        libc::SYS_setsockopt,
+        libc::SYS_getsockopt,
+        libc::SYS_shutdown,
+        libc::SYS_getuid,
+        libc::SYS_geteuid,
+        libc::SYS_getgid,
+        libc::SYS_getegid,
+        libc::SYS_getgroups,
+        libc::SYS_getresuid,
+        libc::SYS_getresgid,
+        libc::SYS_utimensat,
+    ];
+
+    #[cfg(target_arch = "x86_64")]
+    const ARCH: &[c_long] = &[
+        libc::SYS_arch_prctl,
+        libc::SYS_open,
+        libc::SYS_stat,
+        libc::SYS_lstat,
+        libc::SYS_newfstatat,
+        libc::SYS_access,
+        libc::SYS_readlink,
+        libc::SYS_getdents,
+        libc::SYS_mkdir,
+        libc::SYS_rmdir,
+        libc::SYS_unlink,
+        libc::SYS_rename,
+        libc::SYS_renameat,
+        libc::SYS_chmod,
+        libc::SYS_mmap,
+        libc::SYS_pipe,
+        libc::SYS_dup2,
+        libc::SYS_poll,
+        libc::SYS_select,
+        libc::SYS_epoll_create,
+        libc::SYS_epoll_wait,
+        libc::SYS_eventfd,
+        libc::SYS_signalfd,
+        libc::SYS_inotify_init,
+        libc::SYS_accept,
+        libc::SYS_fadvise64,
+        libc::SYS_sendfile,
+        libc::SYS_getrlimit,
+        libc::SYS_vfork,
+        libc::SYS_fork,
+        libc::SYS_time,
+        libc::SYS_alarm,
+        libc::SYS_getpgrp,
+    ];
+
+    #[cfg(target_arch = "aarch64")]
+    const ARCH: &[c_long] = &[
+        libc::SYS_newfstatat,
+        libc::SYS_mmap,
+        libc::SYS_fadvise64,
+        libc::SYS_sendfile,
+        libc::SYS_accept,
+    ];
+
+    // 32-bit ARM also needs the 64-bit time and file offset variants, and the
+    // ARM-private calls glibc makes to set up thread-local storage
+    #[cfg(target_arch = "arm")]
+    const ARCH: &[c_long] = &[
+        libc::SYS_open,
+        libc::SYS_stat64,
+        libc::SYS_lstat64,
+        libc::SYS_fstat64,
+        libc::SYS_fstatat64,
+        libc::SYS_statfs64,
+        libc::SYS_fstatfs64,
+        libc::SYS_access,
+        libc::SYS_readlink,
+        libc::SYS_getdents,
+        libc::SYS_mkdir,
+        libc::SYS_rmdir,
+        libc::SYS_unlink,
+        libc::SYS_rename,
+        libc::SYS_renameat,
+        libc::SYS_chmod,
+        libc::SYS_mmap2,
+        libc::SYS__llseek,
+        libc::SYS_fcntl64,
+        libc::SYS_ftruncate64,
+        libc::SYS_pipe,
+        libc::SYS_dup2,
+        libc::SYS_poll,
+        libc::SYS__newselect,
+        libc::SYS_epoll_create,
+        libc::SYS_epoll_wait,
+        libc::SYS_eventfd,
+        libc::SYS_signalfd,
+        libc::SYS_inotify_init,
+        libc::SYS_accept,
+        libc::SYS_send,
+        libc::SYS_recv,
+        libc::SYS_arm_fadvise64_64,
+        libc::SYS_sendfile64,
+        libc::SYS_ugetrlimit,
+        libc::SYS_vfork,
+        libc::SYS_sigreturn,
+        libc::SYS_getuid32,
+        libc::SYS_geteuid32,
+        libc::SYS_getgid32,
+        libc::SYS_getegid32,
+        libc::SYS_getgroups32,
+        libc::SYS_getresuid32,
+        libc::SYS_getresgid32,
+        // The *_time64 calls, which libc has no names for yet: clock_gettime64,
+        // clock_getres_time64, clock_nanosleep_time64, timerfd_gettime64,
+        // timerfd_settime64, utimensat_time64, pselect6_time64, ppoll_time64,
+        // recvmmsg_time64, rt_sigtimedwait_time64 and futex_time64
+        403,
+        406,
+        407,
+        410,
+        411,
+        412,
+        413,
+        414,
+        417,
+        421,
+        422,
+        // __ARM_NR_cacheflush and __ARM_NR_set_tls
+        0x0f_0002,
+        0x0f_0005,
+    ];
+
+    #[cfg(not(any(target_arch = "x86_64", target_arch = "aarch64", target_arch = "arm")))]
+    compile_error!("No system call allowlist for this architecture");
+
+    fn statement(code: u32, k: u32) -> libc::sock_filter {
+        libc::sock_filter {
+            code: code as u16,
+            jt: 0,
+            jf: 0,
+            k,
+        }
+    }
+
+    /// The filter program: kill on a foreign architecture, allow the listed
+    /// calls, refuse the rest with EPERM
+    pub fn filter() -> Vec<libc::sock_filter> {
+        let load = libc::BPF_LD | libc::BPF_W | libc::BPF_ABS;
+        let ret = libc::BPF_RET | libc::BPF_K;
+        let mut program = vec![
+            statement(load, ARCH_OFFSET),
+            libc::sock_filter {
+                code: (libc::BPF_JMP | libc::BPF_JEQ | libc::BPF_K) as u16,
+                jt: 1,
+                jf: 0,
+                k: AUDIT_ARCH,
+            },
+            statement(ret, libc::SECCOMP_RET_KILL_PROCESS),
+            statement(load, NR_OFFSET),
+        ];
+        for nr in COMMON.iter().chain(ARCH) {
+            program.push(libc::sock_filter {
+                code: (libc::BPF_JMP | libc::BPF_JEQ | libc::BPF_K) as u16,
+                jt: 0,
+                jf: 1,
+                k: *nr as u32,
+            });
+            program.push(statement(ret, libc::SECCOMP_RET_ALLOW));
+        }
+        program.push(statement(ret, libc::SECCOMP_RET_ERRNO | libc::EPERM as u32));
+        program
+    }
+}
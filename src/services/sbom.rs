@@ -0,0 +1,245 @@
+use crate::config::Config;
+use crate::services::FileService;
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+
+/// The SBOM standards a release bundle may ship its inventory in
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SbomFormat {
+    Spdx,
+    CycloneDx,
+}
+
+/// A software package the release is built from
+#[derive(Debug, Clone, Serialize)]
+pub struct SbomPackage {
+    pub name: String,
+    pub version: Option<String>,
+    pub license: Option<String>,
+}
+
+/// A file the release declares, relative to the bundle root
+#[derive(Debug, Clone, Serialize)]
+pub struct SbomFile {
+    pub path: String,
+    pub sha256: Option<String>,
+}
+
+/// Software bill of materials shipped in a release bundle as `sbom.spdx.json`
+/// (SPDX 2.x JSON) or `sbom.cdx.json` (CycloneDX JSON) next to the manifest
+#[derive(Debug, Clone, Serialize)]
+pub struct Sbom {
+    pub format: SbomFormat,
+    pub packages: Vec<SbomPackage>,
+    pub files: Vec<SbomFile>,
+}
+
+// The parts of an SPDX document that are read
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct SpdxDocument {
+    #[serde(default)]
+    packages: Vec<SpdxPackage>,
+    #[serde(default)]
+    files: Vec<SpdxFile>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct SpdxPackage {
+    name: String,
+    version_info: Option<String>,
+    license_concluded: Option<String>,
+    license_declared: Option<String>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct SpdxFile {
+    file_name: String,
+    #[serde(default)]
+    checksums: Vec<SpdxChecksum>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct SpdxChecksum {
+    algorithm: String,
+    checksum_value: String,
+}
+
+// The parts of a CycloneDX document that are read; files are components of type `file`
+#[derive(Deserialize)]
+struct CycloneDxDocument {
+    #[serde(default)]
+    components: Vec<CycloneDxComponent>,
+}
+
+#[derive(Deserialize)]
+struct CycloneDxComponent {
+    #[serde(rename = "type")]
+    kind: String,
+    name: String,
+    version: Option<String>,
+    #[serde(default)]
+    hashes: Vec<CycloneDxHash>,
+    #[serde(default)]
+    licenses: Vec<CycloneDxLicenseChoice>,
+}
+
+#[derive(Deserialize)]
+struct CycloneDxHash {
+    alg: String,
+    content: String,
+}
+
+#[derive(Deserialize)]
+struct CycloneDxLicenseChoice {
+    license: Option<CycloneDxLicense>,
+    expression: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct CycloneDxLicense {
+    id: Option<String>,
+    name: Option<String>,
+}
+
+// SBOMs list files as `./roc_camera` or `/roc_camera` as often as `roc_camera`
+fn normalize_path(path: &str) -> String {
+    path.trim_start_matches("./")
+        .trim_start_matches('/')
+        .to_string()
+}
+
+impl Sbom {
+    /// The SBOM in an installed version's or an extracted bundle's directory, if
+    /// it ships one
+    pub fn load(dir: &Path) -> Result<Option<Self>> {
+        for file_name in Config::SBOM_FILE_NAMES {
+            let path = dir.join(file_name);
+            if !path.is_file() {
+                continue;
+            }
+            let contents = fs::read_to_string(&path)
+                .with_context(|| format!("Failed to read {}", path.display()))?;
+            let sbom = if *file_name == Config::SBOM_FILE_NAMES[0] {
+                Self::parse_spdx(&contents)
+            } else {
+                Self::parse_cyclonedx(&contents)
+            }
+            .with_context(|| format!("Failed to parse {}", path.display()))?;
+            return Ok(Some(sbom));
+        }
+        Ok(None)
+    }
+
+    fn parse_spdx(contents: &str) -> Result<Self> {
+        let document: SpdxDocument = serde_json::from_str(contents)?;
+        Ok(Self {
+            format: SbomFormat::Spdx,
+            packages: document
+                .packages
+                .into_iter()
+                .map(|package| SbomPackage {
+                    name: package.name,
+                    version: package.version_info,
+                    // NOASSERTION is SPDX for "not stated"
+                    license: package
+                        .license_concluded
+                        .filter(|license| license != "NOASSERTION")
+                        .or(package.license_declared)
+                        .filter(|license| license != "NOASSERTION"),
+                })
+                .collect(),
+            files: document
+                .files
+                .into_iter()
+                .map(|file| SbomFile {
+                    path: normalize_path(&file.file_name),
+                    sha256: file
+                        .checksums
+                        .into_iter()
+                        .find(|checksum| checksum.algorithm == "SHA256")
+                        .map(|checksum| checksum.checksum_value.to_lowercase()),
+                })
+                .collect(),
+        })
+    }
+
+    fn parse_cyclonedx(contents: &str) -> Result<Self> {
+        let document: CycloneDxDocument = serde_json::from_str(contents)?;
+        let (files, packages): (Vec<_>, Vec<_>) = document
+            .components
+            .into_iter()
+            .partition(|component| component.kind == "file");
+        Ok(Self {
+            format: SbomFormat::CycloneDx,
+            packages: packages
+                .into_iter()
+                .map(|component| SbomPackage {
+                    license: component.licenses.into_iter().find_map(|choice| {
+                        choice.expression.or(choice
+                            .license
+                            .and_then(|license| license.id.or(license.name)))
+                    }),
+                    name: component.name,
+                    version: component.version,
+                })
+                .collect(),
+            files: files
+                .into_iter()
+                .map(|component| SbomFile {
+                    path: normalize_path(&component.name),
+                    sha256: component
+                        .hashes
+                        .into_iter()
+                        .find(|hash| hash.alg == "SHA-256")
+                        .map(|hash| hash.content.to_lowercase()),
+                })
+                .collect(),
+        })
+    }
+
+    /// Compares the files in `dir` with the declared ones. Returns the files that
+    /// are not declared or whose SHA-256 differs from the declared one, empty when
+    /// the SBOM covers them all. Declared files that are absent are not reported,
+    /// since installing drops the parts of a bundle that `run` does not use.
+    pub fn check(&self, dir: &Path) -> Result<Vec<String>> {
+        let declared: BTreeMap<&str, &SbomFile> = self
+            .files
+            .iter()
+            .map(|file| (file.path.as_str(), file))
+            .collect();
+        // The SBOM cannot declare its own hash
+        let present: Vec<String> = FileService::relative_files(dir)?
+            .into_iter()
+            .filter(|path| !Config::SBOM_FILE_NAMES.contains(&path.as_str()))
+            .collect();
+
+        let mut problems = Vec::new();
+        for path in &present {
+            match declared.get(path.as_str()) {
+                None => problems.push(format!("Not declared in the SBOM: {}", path)),
+                Some(SbomFile {
+                    sha256: Some(expected),
+                    ..
+                }) => {
+                    let actual = FileService::sha256_file(&dir.join(path))?;
+                    if actual != *expected {
+                        problems.push(format!(
+                            "Differs from the SBOM: {} (expected SHA-256 {}, got {})",
+                            path, expected, actual
+                        ));
+                    }
+                }
+                Some(_) => {}
+            }
+        }
+        Ok(problems)
+    }
+}
@@ -0,0 +1,130 @@
+use crate::config::Config;
+use crate::services::AppService;
+use anyhow::{Context, Result};
+use serde::Deserialize;
+use std::fs;
+use std::io::Read;
+use std::path::Path;
+use std::process::{Command, Stdio};
+use std::thread;
+use std::time::Duration;
+use tracing::{info, warn};
+
+/// A command a freshly installed version has to pass before it can be staged.
+///
+/// Declared in the `smoke_test` section of a version's `manifest.yaml`:
+///
+/// ```yaml
+/// smoke_test:
+///   command: ./roc_camera --self-test
+///   timeout_secs: 120
+/// ```
+#[derive(Debug, Clone, Deserialize)]
+pub struct SmokeTest {
+    /// Shell command, run in the version directory, that has to exit successfully
+    pub command: String,
+    #[serde(default = "SmokeTest::default_timeout_secs")]
+    pub timeout_secs: u64,
+}
+
+// Only the part of the manifest the smoke test cares about
+#[derive(Deserialize)]
+struct Manifest {
+    #[serde(default)]
+    smoke_test: Option<SmokeTest>,
+}
+
+impl SmokeTest {
+    fn default_timeout_secs() -> u64 {
+        Config::SMOKE_TEST_TIMEOUT_SECS
+    }
+
+    /// The smoke test of an installed version, if it declares one
+    pub fn load(version_dir: &Path) -> Option<Self> {
+        let path = version_dir.join("manifest.yaml");
+        let contents = fs::read_to_string(&path).ok()?;
+        match serde_yaml::from_str::<Manifest>(&contents) {
+            Ok(manifest) => manifest.smoke_test,
+            Err(e) => {
+                warn!("Ignoring smoke test in {}: {}", path.display(), e);
+                None
+            }
+        }
+    }
+
+    /// Runs the command in the version directory. Fails with the tail of its
+    /// output if it exits unsuccessfully or outlives its timeout.
+    pub fn run(&self, version_dir: &Path) -> Result<()> {
+        info!("Running smoke test: {}", self.command);
+        let mut command = Command::new("sh");
+        command
+            .args(["-c", &self.command])
+            .current_dir(version_dir)
+            .env("GEIST_SMOKE_TEST", "1")
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped());
+        // Its own process group, so a timeout also stops whatever the shell started
+        #[cfg(unix)]
+        {
+            use std::os::unix::process::CommandExt;
+            command.process_group(0);
+        }
+        let mut child = command
+            .spawn()
+            .with_context(|| format!("Failed to run smoke test '{}'", self.command))?;
+
+        // Drained on their own threads so a chatty test never blocks on a full pipe
+        let stdout = child.stdout.take().map(Self::collect);
+        let stderr = child.stderr.take().map(Self::collect);
+
+        let status = AppService::wait_timeout(&mut child, Duration::from_secs(self.timeout_secs))?;
+        if status.is_none() {
+            #[cfg(unix)]
+            unsafe {
+                libc::kill(-(child.id() as libc::pid_t), libc::SIGKILL);
+            }
+            child.kill().ok();
+            child.wait().ok();
+        }
+
+        let mut output = String::new();
+        for reader in [stdout, stderr].into_iter().flatten() {
+            output.push_str(&reader.join().unwrap_or_default());
+        }
+
+        let failure = match status {
+            Some(status) if status.success() => {
+                info!("Smoke test passed");
+                return Ok(());
+            }
+            Some(status) => format!("Smoke test '{}' failed with {}", self.command, status),
+            None => format!(
+                "Smoke test '{}' timed out after {}s",
+                self.command, self.timeout_secs
+            ),
+        };
+        match Self::tail(&output) {
+            Some(tail) => anyhow::bail!("{}. Output:\n{}", failure, tail),
+            None => anyhow::bail!("{} without output", failure),
+        }
+    }
+
+    fn collect(mut pipe: impl Read + Send + 'static) -> thread::JoinHandle<String> {
+        thread::spawn(move || {
+            let mut buf = Vec::new();
+            pipe.read_to_end(&mut buf).ok();
+            String::from_utf8_lossy(&buf).into_owned()
+        })
+    }
+
+    // The last lines are where a failing test explains itself
+    fn tail(output: &str) -> Option<String> {
+        let lines: Vec<&str> = output.lines().collect();
+        if lines.is_empty() {
+            return None;
+        }
+        let start = lines.len().saturating_sub(Config::SMOKE_TEST_OUTPUT_LINES);
+        Some(lines[start..].join("\n"))
+    }
+}
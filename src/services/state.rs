@@ -0,0 +1,284 @@
+use crate::config::Config;
+use crate::services::DataUsage;
+use anyhow::{Context, Result};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::PathBuf;
+use std::process::ExitStatus;
+use std::time::Duration;
+
+/// Supervisor bookkeeping persisted across invocations
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct SupervisorState {
+    /// Most recent version known to have run correctly
+    #[serde(default)]
+    pub last_known_good: Option<String>,
+    /// Recent abnormal exits of the app, oldest first
+    #[serde(default)]
+    pub crash_history: Vec<CrashRecord>,
+    /// Versions that must not be selected automatically
+    #[serde(default)]
+    pub bad_versions: Vec<String>,
+    /// Versions known to work, which a rollback may fall back to when the last
+    /// known-good version is gone
+    #[serde(default)]
+    pub good_versions: Vec<String>,
+    /// How the app exited most recently, clean or not
+    #[serde(default)]
+    pub last_exit: Option<ExitRecord>,
+    /// Release downloads in the current billing period, see `MeteredService`
+    #[serde(default)]
+    pub data_usage: Option<DataUsage>,
+    /// Highest version of the signed targets metadata seen, so an older copy is
+    /// refused
+    #[serde(default)]
+    pub targets_version: Option<u64>,
+    /// Version the device is pinned to by `geist pin`
+    #[serde(default)]
+    pub hold: Option<Hold>,
+    /// Data migrations that ran, oldest first
+    #[serde(default)]
+    pub migrations: Vec<AppliedMigration>,
+}
+
+/// What is known about an installed version
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum VersionMark {
+    /// Ran correctly, or was vouched for with `mark-good`; a rollback target
+    Good,
+    /// Crash-looped, or was marked with `mark-bad`; never selected automatically
+    Bad,
+}
+
+/// A pin set by `geist pin`: updates refuse to move off the version without
+/// `--force`, and automatic ones don't start at all
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Hold {
+    pub version: String,
+    pub at: DateTime<Utc>,
+    pub reason: Option<String>,
+}
+
+impl Hold {
+    /// e.g. "pinned to v1.4.0 since 2026-03-02 14:10 (recording until Friday)", in
+    /// local time
+    pub fn describe(&self) -> String {
+        let at = self
+            .at
+            .with_timezone(&chrono::Local)
+            .format("%Y-%m-%d %H:%M");
+        match &self.reason {
+            Some(reason) => format!("pinned to {} since {} ({})", self.version, at, reason),
+            None => format!("pinned to {} since {}", self.version, at),
+        }
+    }
+}
+
+/// A data migration that ran against the app data directory
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AppliedMigration {
+    pub name: String,
+    /// The version that shipped it
+    pub version: String,
+    pub at: DateTime<Utc>,
+}
+
+/// A single abnormal exit of the app
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CrashRecord {
+    pub version: String,
+    pub at: DateTime<Utc>,
+    pub code: Option<i32>,
+}
+
+/// The most recent exit of the app
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ExitRecord {
+    pub version: String,
+    pub at: DateTime<Utc>,
+    pub success: bool,
+    /// Exit code as a shell reports it, 128 + signal number for deaths by signal
+    pub code: Option<i32>,
+}
+
+impl ExitRecord {
+    pub fn new(version: &str, status: &ExitStatus) -> Self {
+        #[cfg(unix)]
+        let signal = std::os::unix::process::ExitStatusExt::signal(status);
+        #[cfg(not(unix))]
+        let signal: Option<i32> = None;
+
+        Self {
+            version: version.to_string(),
+            at: Utc::now(),
+            success: status.success(),
+            code: status.code().or(signal.map(|signal| 128 + signal)),
+        }
+    }
+
+    /// e.g. "crash at 12:03, code 139", in local time
+    pub fn describe(&self) -> String {
+        let kind = if self.success { "clean" } else { "crash" };
+        let at = self.at.with_timezone(&chrono::Local).format("%H:%M");
+        match self.code {
+            Some(code) => format!("{} at {}, code {}", kind, at, code),
+            None => format!("{} at {}", kind, at),
+        }
+    }
+}
+
+impl SupervisorState {
+    // Upper bound on retained crash records so the state file stays small
+    const MAX_CRASH_RECORDS: usize = 50;
+
+    /// The pin that keeps the device from moving to `version`, if any
+    pub fn hold_against(&self, version: &str) -> Option<&Hold> {
+        self.hold.as_ref().filter(|hold| {
+            Config::normalize_version(&hold.version) != Config::normalize_version(version)
+        })
+    }
+
+    pub fn migration_applied(&self, name: &str) -> bool {
+        self.migrations
+            .iter()
+            .any(|migration| migration.name == name)
+    }
+
+    pub fn is_bad(&self, version: &str) -> bool {
+        self.bad_versions.iter().any(|v| v == version)
+    }
+
+    pub fn mark_bad(&mut self, version: &str) {
+        if !self.is_bad(version) {
+            self.bad_versions.push(version.to_string());
+        }
+        self.good_versions.retain(|v| v != version);
+        if self.last_known_good.as_deref() == Some(version) {
+            self.last_known_good = None;
+        }
+    }
+
+    pub fn is_good(&self, version: &str) -> bool {
+        self.good_versions.iter().any(|v| v == version)
+    }
+
+    pub fn mark_good(&mut self, version: &str) {
+        if !self.is_good(version) {
+            self.good_versions.push(version.to_string());
+        }
+        self.bad_versions.retain(|v| v != version);
+    }
+
+    /// Whether a version is marked good or bad, if either
+    pub fn mark(&self, version: &str) -> Option<VersionMark> {
+        if self.is_bad(version) {
+            Some(VersionMark::Bad)
+        } else if self.is_good(version) {
+            Some(VersionMark::Good)
+        } else {
+            None
+        }
+    }
+
+    /// The version to roll back to from a failing one: the last known-good version,
+    /// or else the newest installed version marked good
+    pub fn rollback_target(&self, from: &str, installed: impl Fn(&str) -> bool) -> Option<String> {
+        let eligible = |v: &String| v != from && !self.is_bad(v) && installed(v);
+        if let Some(last) = self.last_known_good.as_ref().filter(|v| eligible(v)) {
+            return Some(last.clone());
+        }
+        let mut good = self.good_versions.clone();
+        Config::sort_versions(&mut good);
+        good.into_iter().rev().find(eligible)
+    }
+
+    /// Number of crashes of a version within the given window
+    pub fn crashes_within(&self, version: &str, window: Duration) -> usize {
+        let cutoff = Utc::now() - chrono::Duration::from_std(window).unwrap_or_default();
+        self.crash_history
+            .iter()
+            .filter(|c| c.version == version && c.at >= cutoff)
+            .count()
+    }
+}
+
+pub struct StateService {
+    path: PathBuf,
+}
+
+impl StateService {
+    pub fn new(data_dir: PathBuf) -> Self {
+        Self {
+            path: data_dir.join(Config::STATE_FILE),
+        }
+    }
+
+    /// Loads the persisted state, falling back to defaults if none exists yet
+    pub fn load(&self) -> Result<SupervisorState> {
+        if !self.path.exists() {
+            return Ok(SupervisorState::default());
+        }
+
+        let contents = fs::read_to_string(&self.path)
+            .with_context(|| format!("Failed to read state file {}", self.path.display()))?;
+        serde_json::from_str(&contents)
+            .with_context(|| format!("Failed to parse state file {}", self.path.display()))
+    }
+
+    /// Writes the state through a temporary file so readers never see a partial file
+    pub fn save(&self, state: &SupervisorState) -> Result<()> {
+        let tmp_path = self.path.with_extension("json.tmp");
+        fs::write(&tmp_path, serde_json::to_string_pretty(state)?)
+            .context("Failed to write state file")?;
+        fs::rename(&tmp_path, &self.path).context("Failed to replace state file")?;
+        Ok(())
+    }
+
+    /// Records an abnormal exit and reports whether the version is now crash-looping
+    pub fn record_crash(&self, version: &str, code: Option<i32>) -> Result<bool> {
+        let threshold = Config::crash_loop_threshold();
+        let window = Config::crash_loop_window();
+
+        let state = self.update(|state| {
+            state.crash_history.push(CrashRecord {
+                version: version.to_string(),
+                at: Utc::now(),
+                code,
+            });
+            let excess = state
+                .crash_history
+                .len()
+                .saturating_sub(SupervisorState::MAX_CRASH_RECORDS);
+            state.crash_history.drain(..excess);
+        })?;
+
+        let crashes = state.crashes_within(version, window);
+        tracing::warn!(
+            "Version {} crashed {} time(s) within the last {:?}",
+            version,
+            crashes,
+            window
+        );
+        Ok(crashes >= threshold)
+    }
+
+    /// Remembers how the app exited so offline commands can explain why
+    pub fn record_exit(&self, version: &str, status: &ExitStatus) -> Result<ExitRecord> {
+        let record = ExitRecord::new(version, status);
+        self.update(|state| state.last_exit = Some(record.clone()))?;
+        Ok(record)
+    }
+
+    /// Loads the state, applies a change, and saves it back
+    pub fn update<F>(&self, change: F) -> Result<SupervisorState>
+    where
+        F: FnOnce(&mut SupervisorState),
+    {
+        let mut state = self.load()?;
+        change(&mut state);
+        self.save(&state)?;
+        Ok(state)
+    }
+}
@@ -0,0 +1,1178 @@
+use crate::config::Config;
+use crate::services::{
+    AppCgroup, AppService, CurrentService, EventKind, EventService, ExitAction, ExitRecord,
+    FileService, Handoff, HealthProbe, HostService, IpcService, ListenSockets, Notifier, PreStop,
+    PreStopFallback, ReplayBag, ResourceLimits, RestartPolicy, Sandbox, StateService, TamperWatch,
+};
+use crate::utils::signals;
+use anyhow::{Context, Result};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::cell::{Cell, RefCell};
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+use std::process::{Child, Command, ExitStatus};
+use std::thread;
+use std::time::{Duration, Instant};
+use tracing::{error, info, warn};
+
+// How often a running app is checked for exit, stop signals and watchdog pings
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+// How often a running app is checked for restart requests
+const RESTART_CHECK_INTERVAL: Duration = Duration::from_secs(1);
+
+/// How a single launch of the app ended
+pub enum RunOutcome {
+    /// The app exited successfully
+    Clean,
+    /// The app exited successfully and the restart policy asks to launch it again
+    Relaunch,
+    /// The app exited with a code the restart policy maps to an update
+    UpdateRequested,
+    /// The app failed with a code the restart policy maps to stopping
+    Halted { status: ExitStatus },
+    /// The app crashed and the version stays selected
+    Crashed {
+        status: ExitStatus,
+        uptime: Duration,
+    },
+    /// The version failed its trial or crash-looped and another version was activated
+    RolledBack { to: String },
+    /// `restart` stopped the app and asked for the boot version to be relaunched
+    Restarted,
+    /// `restart` started `to` next to the app on the same sockets, and stopped the
+    /// app once `to` was ready; `child` is the new instance
+    HandedOff {
+        to: String,
+        on_trial: bool,
+        child: Child,
+    },
+    /// geist received SIGINT or SIGTERM and stopped the app
+    Stopped,
+}
+
+/// What a running supervisor did with a request from `restart`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "result", rename_all = "snake_case")]
+pub enum RestartResult {
+    /// The app was stopped and the boot version launched
+    Relaunched,
+    /// `to` took over the app's sockets and the old instance was stopped
+    HandedOff { to: String },
+    /// `to` never became ready, or the app refused to stop, so the old instance
+    /// keeps running
+    Kept { to: String },
+    /// The app did not acknowledge the pre-stop notification and keeps running
+    Declined,
+    /// The supervisor did not pick the request up in time, so `restart` stopped
+    /// the app itself and the supervisor relaunches it
+    Stopped,
+}
+
+/// Removes a pid file when the process that wrote it is done
+pub struct PidFile {
+    path: PathBuf,
+}
+
+impl Drop for PidFile {
+    fn drop(&mut self) {
+        fs::remove_file(&self.path).ok();
+    }
+}
+
+// How a zero-downtime restart attempt ended
+enum HandoffResult {
+    /// The running version serves no sockets, so it has to be stopped first
+    Unsupported,
+    /// The new instance never became ready; the running one keeps serving
+    Kept,
+    Done {
+        to: String,
+        on_trial: bool,
+        child: Child,
+    },
+}
+
+/// Launches installed versions and applies the slot, crash-loop and rollback rules
+pub struct SupervisorService {
+    data_dir: PathBuf,
+    fs_service: FileService,
+    state: StateService,
+    events: EventService,
+    current: CurrentService,
+    // Generation of the current-version pointer when the running version was launched
+    seen_generation: Cell<Option<u64>>,
+    replay_bag: Option<PathBuf>,
+    notifier: Option<Notifier>,
+    sockets: RefCell<ListenSockets>,
+    // When the systemd watchdog is due its next ping; kept across the waits it is
+    // checked in, which are often shorter than the interval
+    next_ping: Cell<Option<Instant>>,
+}
+
+impl SupervisorService {
+    pub fn new(data_dir: PathBuf, replay_bag: Option<PathBuf>) -> Self {
+        Self {
+            fs_service: FileService::new(data_dir.clone()),
+            state: StateService::new(data_dir.clone()),
+            events: EventService::new(data_dir.clone()),
+            current: CurrentService::new(data_dir.clone()),
+            seen_generation: Cell::new(None),
+            data_dir,
+            replay_bag,
+            notifier: Notifier::from_env(),
+            sockets: RefCell::new(ListenSockets::default()),
+            next_ping: Cell::new(None),
+        }
+    }
+
+    /// Picks the version to launch and whether it is on a trial boot.
+    ///
+    /// Without an explicit version the boot marker decides, falling back to the
+    /// latest installed version that is not marked bad.
+    pub fn boot_target(&self, version: Option<String>) -> Result<(String, bool)> {
+        let marker = self.fs_service.boot_marker()?;
+
+        let (target_version, on_trial) = match version {
+            Some(v) => (v, false),
+            None => match marker.boot_version() {
+                Some(v) => (v.clone(), marker.trial.is_some()),
+                None => {
+                    let state = self.state.load()?;
+                    let versions = self.fs_service.installed_versions()?;
+
+                    match versions.iter().rev().find(|v| !state.is_bad(v)) {
+                        Some(latest) => (latest.clone(), false),
+                        None => anyhow::bail!("No versions found. Please run 'update' first."),
+                    }
+                }
+            },
+        };
+
+        // Check if the version exists
+        let version_dir = self.data_dir.join(&target_version);
+        if !version_dir.exists() {
+            anyhow::bail!(
+                "Version {} not found. Please run 'update {}' first.",
+                target_version,
+                target_version
+            );
+        }
+
+        // Find the binary, or the image it runs as
+        if !version_dir.join("roc_camera").exists()
+            && !version_dir.join(Config::CONTAINER_IMAGE_FILE).exists()
+        {
+            anyhow::bail!("Binary not found for version {}", target_version);
+        }
+
+        Ok((target_version, on_trial))
+    }
+
+    /// Records a version as current, logging rather than failing on errors. A switch
+    /// made by another process since the running version was launched wins.
+    pub fn make_current(&self, version: &str) {
+        let result = match self.seen_generation.get() {
+            Some(generation) => self.current.compare_and_swap(generation, version),
+            None => self.current.set(version),
+        };
+        match result {
+            Ok(pointer) => self.seen_generation.set(Some(pointer.generation)),
+            Err(e) => warn!("Not making {} current: {:#}", version, e),
+        }
+    }
+
+    /// Launches a version and waits for it to exit.
+    ///
+    /// A trial boot has to stay up for `SLOT_HEALTHY_AFTER_SECS` and pass its health
+    /// probe, if it declares one, before its slot is committed and it becomes current;
+    /// failing earlier falls back to the active slot. Later exits follow
+    /// the version's restart policy; repeated crashes mark the version bad and roll
+    /// back to the last known-good version.
+    pub fn run_version(&self, version: &str, on_trial: bool) -> Result<RunOutcome> {
+        let child = self.spawn_app(version)?;
+        self.watch_app(version, on_trial, child)
+    }
+
+    // Starts an installed version, passing it the listening sockets it declares
+    fn spawn_app(&self, version: &str) -> Result<Child> {
+        if Config::tamper_refuse_launch() {
+            self.refuse_if_tampered(version)?;
+        }
+
+        let mut app = AppService::new(self.data_dir.join(version));
+        if Config::sandbox() {
+            app = app.sandboxed(Sandbox::new(self.data_dir.clone()));
+        }
+        // A container is limited by its runtime
+        let limits = ResourceLimits::load(&app.version_dir);
+        if !limits.is_empty() && app.image().is_none() {
+            match AppCgroup::prepare(&limits) {
+                Ok(cgroup) => {
+                    info!("Limiting the app to {}", limits.describe());
+                    app = app.limited(cgroup);
+                }
+                Err(e) => warn!("Not applying resource limits: {:#}", e),
+            }
+        }
+        // Read before the launch, so a bag that cannot be played stops it
+        let replay = self
+            .replay_bag
+            .as_deref()
+            .map(ReplayBag::load)
+            .transpose()?;
+        let mut command = app.command(self.replay_bag.as_deref())?;
+        let run_dir = self.data_dir.join(Config::APP_RUN_DIR);
+        fs::create_dir_all(&run_dir)
+            .with_context(|| format!("Failed to create {}", run_dir.display()))?;
+        command.env(
+            "GEIST_IPC_SOCKET",
+            IpcService::new(self.data_dir.clone()).socket_path(),
+        );
+        command.env("GEIST_STOP_ACK_FILE", PreStop::ack_file(&self.data_dir));
+        // Sockets cannot be passed into a container, which binds its own
+        let image = app.image();
+        if let Some(handoff) = Handoff::load(&app.version_dir).filter(|_| image.is_none()) {
+            self.sockets
+                .borrow_mut()
+                .pass_to(&mut command, &handoff.listen)?;
+        }
+
+        // Run the binary
+        match &image {
+            Some(image) => info!("Starting container of {}", image.pinned),
+            None => info!("Executing binary: {}", app.binary_path().display()),
+        }
+        let child = command.spawn()?;
+        self.events.record(
+            EventKind::AppStarted,
+            Some(version),
+            Some(format!("pid {}", child.id())),
+        );
+        // Every launch, relaunches included, is fed the bag from its start
+        if let Some(bag) = replay {
+            bag.feed(IpcService::new(self.data_dir.clone()), child.id());
+        }
+        Ok(child)
+    }
+
+    // Files modified outside the supervisor may no longer be what was verified at
+    // install time. Every version geist installs gets a record of its hashes, so
+    // one with an install record but no usable hash record is refused too; only
+    // versions put in place by other means launch unchecked.
+    fn refuse_if_tampered(&self, version: &str) -> Result<()> {
+        let changes = match TamperWatch::new(self.data_dir.clone()).check(version) {
+            Ok(changes) => changes,
+            Err(e) if self.fs_service.install_record(version).is_some() => {
+                let reason = format!("{:#}", e);
+                self.events
+                    .record(EventKind::Tampered, Some(version), Some(reason.clone()));
+                anyhow::bail!(
+                    "Refusing to launch {}: its files cannot be checked ({}). Reinstall it",
+                    version,
+                    reason
+                )
+            }
+            Err(e) => {
+                warn!("Could not check {} for tampering: {:#}", version, e);
+                return Ok(());
+            }
+        };
+        if changes.is_empty() {
+            return Ok(());
+        }
+        self.events
+            .record(EventKind::Tampered, Some(version), Some(changes.join(", ")));
+        anyhow::bail!(
+            "Refusing to launch {}: its files were modified outside the supervisor ({}). Reinstall it, or see 'geist verify --local {}'",
+            version,
+            changes.join(", "),
+            version
+        )
+    }
+
+    /// Supervises a launched instance of a version until it exits, as described for
+    /// `run_version`. Also takes over instances started by a handoff.
+    pub fn watch_app(&self, version: &str, on_trial: bool, mut child: Child) -> Result<RunOutcome> {
+        let app = AppService::new(self.data_dir.join(version));
+        let policy = RestartPolicy::load(&app.version_dir);
+        let started = Instant::now();
+        match self.current.generation() {
+            Ok(generation) => self.seen_generation.set(Some(generation)),
+            Err(e) => {
+                warn!("{:#}", e);
+                self.seen_generation.set(None);
+            }
+        }
+        let _app_pid = self.write_pid_file(Config::APP_PID_FILE, child.id())?;
+
+        if on_trial {
+            let healthy_after = Duration::from_secs(Config::SLOT_HEALTHY_AFTER_SECS);
+            if let Some(notifier) = &self.notifier {
+                notifier.status(&format!("Trial boot of {}", version));
+            }
+            let health_check = tracing::info_span!("health_check", version = %version).entered();
+            let trial_status = self.wait_child(&mut child, Some(healthy_after))?;
+            drop(health_check);
+            // A clean exit is recorded once the main loop below sees it
+            if let Some(status) = trial_status.filter(|status| {
+                !status.success() || signals::pending().is_some() || self.restart_requested()
+            }) {
+                self.note_exit(version, &status);
+            }
+
+            match trial_status {
+                Some(_) if signals::pending().is_some() => return Ok(RunOutcome::Stopped),
+                Some(_) if self.restart_requested() => {
+                    info!("Version {} stopped for a requested restart", version);
+                    return Ok(RunOutcome::Restarted);
+                }
+                Some(status) if !status.success() => {
+                    error!("Version {} failed its trial boot: {}", version, status);
+                    return self.fail_trial(version, status, started);
+                }
+                Some(_) => {}
+                None => {
+                    // Still up after the trial window; a declared probe has the final say
+                    if let Some(probe) = HealthProbe::load(&app.version_dir) {
+                        if let Some(outcome) =
+                            self.await_probe(&mut child, &probe, version, started)?
+                        {
+                            return Ok(outcome);
+                        }
+                    }
+                }
+            }
+
+            self.fs_service.commit_trial_slot()?;
+            self.make_current(version);
+            // Passing its trial makes it a rollback target for later versions
+            if let Err(e) = self.state.update(|s| s.mark_good(version)) {
+                warn!("Failed to mark {} as good: {}", version, e);
+            }
+        }
+
+        // A trial boot only counts as running once it passed the health window
+        if let Some(notifier) = &self.notifier {
+            notifier.status(&format!("Running {}", version));
+        }
+
+        // Restart requests are handled here so versions serving sockets can hand off
+        let status = loop {
+            if let Some(status) = self.wait_child(&mut child, Some(RESTART_CHECK_INTERVAL))? {
+                break status;
+            }
+            if !self.restart_requested() {
+                continue;
+            }
+            match self.hand_off(version, &mut child)? {
+                HandoffResult::Done {
+                    to,
+                    on_trial,
+                    child,
+                } => {
+                    return Ok(RunOutcome::HandedOff {
+                        to,
+                        on_trial,
+                        child,
+                    })
+                }
+                HandoffResult::Kept => {}
+                HandoffResult::Unsupported => {
+                    if !self.prepare_stop(version, &mut child)? {
+                        self.answer_restart(RestartResult::Declined);
+                        continue;
+                    }
+                    AppService::terminate(
+                        &mut child,
+                        Duration::from_secs(Config::RESTART_GRACE_SECS),
+                    )?;
+                }
+            }
+        };
+        let uptime = started.elapsed();
+        self.note_exit(version, &status);
+
+        if signals::pending().is_some() {
+            info!("Version {} stopped on request after {:?}", version, uptime);
+            return Ok(RunOutcome::Stopped);
+        }
+
+        // An exit caused by `restart` is neither a crash nor a clean shutdown
+        if self.restart_requested() {
+            info!("Version {} stopped for a requested restart", version);
+            return Ok(RunOutcome::Restarted);
+        }
+
+        if status.success() {
+            // A clean exit means this version is a safe rollback target
+            if let Err(e) = self.state.update(|s| {
+                s.last_known_good = Some(version.to_string());
+                s.mark_good(version);
+            }) {
+                warn!("Failed to record last known-good version: {}", e);
+            }
+        }
+
+        match policy.action_for(&status) {
+            ExitAction::Stop if status.success() => return Ok(RunOutcome::Clean),
+            ExitAction::Stop => {
+                error!(
+                    "Version {} exited with {}, which the restart policy maps to stop",
+                    version, status
+                );
+                return Ok(RunOutcome::Halted { status });
+            }
+            ExitAction::Update => {
+                info!(
+                    "Version {} exited with {}, which the restart policy maps to an update",
+                    version, status
+                );
+                return Ok(RunOutcome::UpdateRequested);
+            }
+            ExitAction::Restart if status.success() => return Ok(RunOutcome::Relaunch),
+            ExitAction::Restart => {}
+        }
+
+        // Repeated crashes of a version roll back to the last known-good one
+        if self.state.record_crash(version, status.code())? {
+            let updated = self.state.update(|s| s.mark_bad(version))?;
+            error!("Version {} is crash-looping, marked as bad", version);
+            self.events.record(
+                EventKind::MarkedBad,
+                Some(version),
+                Some("crash loop".to_string()),
+            );
+
+            if let Some(previous) =
+                updated.rollback_target(version, |v| self.data_dir.join(v).is_dir())
+            {
+                warn!(
+                    restart = true,
+                    "Rolling back to known-good version: {}", previous
+                );
+                self.fs_service.activate_version(&previous)?;
+                self.events.record(
+                    EventKind::RolledBack,
+                    Some(&previous),
+                    Some(format!("from {}", version)),
+                );
+                self.make_current(&previous);
+                return Ok(RunOutcome::RolledBack { to: previous });
+            }
+            error!("No known-good version to roll back to");
+        }
+
+        Ok(RunOutcome::Crashed { status, uptime })
+    }
+
+    // Starts the boot version next to the running one on the same sockets and stops
+    // the running one once the new instance is ready
+    fn hand_off(&self, version: &str, child: &mut Child) -> Result<HandoffResult> {
+        let grace = Duration::from_secs(Config::RESTART_GRACE_SECS);
+        if Handoff::load(&self.data_dir.join(version)).is_none() {
+            return Ok(HandoffResult::Unsupported);
+        }
+        let (target, on_trial) = self.boot_target(None)?;
+        // Without sockets to inherit the new instance would have to bind the ports itself
+        let Some(handoff) = Handoff::load(&self.data_dir.join(&target)) else {
+            warn!("{} serves no sockets, restarting without a handoff", target);
+            return Ok(HandoffResult::Unsupported);
+        };
+        self.take_restart_request();
+
+        let _span = tracing::info_span!("handoff", from = %version, to = %target).entered();
+        info!("Handing off from {} to {}", version, target);
+        let mut next = self.spawn_app(&target)?;
+
+        if self.await_ready(&mut next, &target, handoff.ready_timeout())? {
+            info!("{} is ready, stopping {}", target, version);
+            if !self.prepare_stop(version, child)? {
+                AppService::terminate(&mut next, grace)?;
+                self.answer_restart(RestartResult::Kept { to: target });
+                return Ok(HandoffResult::Kept);
+            }
+            AppService::terminate(child, grace)?;
+            self.answer_restart(RestartResult::HandedOff { to: target.clone() });
+            return Ok(HandoffResult::Done {
+                to: target,
+                on_trial,
+                child: next,
+            });
+        }
+
+        warn!("{} did not become ready, {} keeps running", target, version);
+        AppService::terminate(&mut next, grace)?;
+        self.answer_restart(RestartResult::Kept { to: target });
+        Ok(HandoffResult::Kept)
+    }
+
+    // Tells the app it is about to be stopped, if its version asks for that, and waits
+    // for it to acknowledge. Returns false if it should keep running instead.
+    fn prepare_stop(&self, version: &str, child: &mut Child) -> Result<bool> {
+        let Some(pre_stop) = PreStop::load(&self.data_dir.join(version)) else {
+            return Ok(true);
+        };
+        let deadline = Instant::now() + pre_stop.grace();
+        let ack_file = PreStop::ack_file(&self.data_dir);
+
+        info!("Notifying {} that it is about to be stopped", version);
+        let acknowledged = match pre_stop.notify(child.id(), &self.data_dir) {
+            Ok(true) => true,
+            Ok(false) => loop {
+                // Exiting on its own is as good as an acknowledgment
+                if self.wait_child(child, Some(POLL_INTERVAL))?.is_some() || ack_file.exists() {
+                    break true;
+                }
+                if Instant::now() >= deadline {
+                    break false;
+                }
+            },
+            Err(e) => {
+                warn!("Failed to notify {}: {:#}", version, e);
+                false
+            }
+        };
+        fs::remove_file(&ack_file).ok();
+
+        if acknowledged {
+            info!("{} is ready to be stopped", version);
+            return Ok(true);
+        }
+        match pre_stop.on_timeout {
+            PreStopFallback::Stop => {
+                warn!(
+                    "{} did not acknowledge the stop, stopping it anyway",
+                    version
+                );
+                Ok(true)
+            }
+            PreStopFallback::KeepRunning => {
+                warn!(
+                    "{} did not acknowledge the stop and keeps running, restart dropped",
+                    version
+                );
+                Ok(false)
+            }
+        }
+    }
+
+    // Waits for a new instance to pass its health probe or, without one, to stay up
+    // for `HANDOFF_SETTLE_SECS`
+    fn await_ready(&self, child: &mut Child, version: &str, timeout: Duration) -> Result<bool> {
+        let version_dir = self.data_dir.join(version);
+        let probe = HealthProbe::load(&version_dir);
+        let settle = Duration::from_secs(Config::HANDOFF_SETTLE_SECS);
+        let started = Instant::now();
+
+        loop {
+            if let Some(status) = self.wait_child(child, Some(RESTART_CHECK_INTERVAL))? {
+                warn!("{} exited with {} during the handoff", version, status);
+                return Ok(false);
+            }
+            let ready = match &probe {
+                Some(probe) => probe.check(&version_dir).is_ok(),
+                None => started.elapsed() >= settle,
+            };
+            if ready {
+                return Ok(true);
+            }
+            if started.elapsed() >= timeout || signals::pending().is_some() {
+                return Ok(false);
+            }
+        }
+    }
+
+    // Retries a trial version's health probe until it passes or runs out of time.
+    // Returns how the run ended when the trial fails instead of being committed.
+    fn await_probe(
+        &self,
+        child: &mut Child,
+        probe: &HealthProbe,
+        version: &str,
+        started: Instant,
+    ) -> Result<Option<RunOutcome>> {
+        let _span = tracing::info_span!("health_probe", version = %version).entered();
+        let version_dir = self.data_dir.join(version);
+        let interval = Duration::from_secs(Config::HEALTH_PROBE_INTERVAL_SECS);
+        let deadline = Instant::now() + probe.timeout();
+
+        let failure = loop {
+            let error = match probe.check(&version_dir) {
+                Ok(()) => {
+                    info!("Version {} passed its health probe", version);
+                    return Ok(None);
+                }
+                Err(e) => e,
+            };
+            if Instant::now() >= deadline {
+                break error;
+            }
+            info!("Health probe not passing yet: {:#}", error);
+
+            if let Some(status) = self.wait_child(child, Some(interval))? {
+                self.note_exit(version, &status);
+                if signals::pending().is_some() {
+                    return Ok(Some(RunOutcome::Stopped));
+                }
+                if self.restart_requested() {
+                    info!("Version {} stopped for a requested restart", version);
+                    return Ok(Some(RunOutcome::Restarted));
+                }
+                error!(
+                    "Version {} exited with {} before passing its health probe",
+                    version, status
+                );
+                return self.fail_trial(version, status, started).map(Some);
+            }
+        };
+
+        error!("Version {} failed its health probe: {:#}", version, failure);
+        let status = AppService::terminate(child, Duration::from_secs(Config::RESTART_GRACE_SECS))?;
+        self.note_exit(version, &status);
+        self.fail_trial(version, status, started).map(Some)
+    }
+
+    // Keeps the last exit for commands that find the app offline; losing it is harmless
+    fn note_exit(&self, version: &str, status: &ExitStatus) {
+        let detail = match self.state.record_exit(version, status) {
+            Ok(record) => record.describe(),
+            Err(e) => {
+                warn!("Failed to record exit of {}: {}", version, e);
+                ExitRecord::new(version, status).describe()
+            }
+        };
+        self.events
+            .record(EventKind::AppExited, Some(version), Some(detail));
+    }
+
+    // Counts a failed trial boot and falls back to the version in the active slot
+    fn fail_trial(
+        &self,
+        version: &str,
+        status: ExitStatus,
+        started: Instant,
+    ) -> Result<RunOutcome> {
+        self.state.record_crash(version, status.code())?;
+
+        match self.fs_service.fail_trial_slot()? {
+            Some(previous) => {
+                warn!(
+                    restart = true,
+                    "Booting previous slot version: {}", previous
+                );
+                self.events.record(
+                    EventKind::RolledBack,
+                    Some(&previous),
+                    Some(format!("from {}", version)),
+                );
+                self.make_current(&previous);
+                Ok(RunOutcome::RolledBack { to: previous })
+            }
+            None => Ok(RunOutcome::Crashed {
+                status,
+                uptime: started.elapsed(),
+            }),
+        }
+    }
+
+    /// Keeps the boot version running, restarting it with exponential backoff when it
+    /// crashes. Returns once the restart policy says to stop.
+    pub fn supervise(&self, initial_backoff: Duration, max_backoff: Duration) -> Result<()> {
+        let _pid_file = self.claim_supervisor()?;
+        if let Some(reason) = self.safe_mode() {
+            if !self.idle_in_safe_mode(&reason) {
+                return Ok(());
+            }
+        }
+
+        // The service is up once it supervises, however long the app takes to pass
+        // a trial boot or keeps crashing; how the app fares goes into its status
+        if let Some(notifier) = &self.notifier {
+            notifier.ready("Supervising");
+        }
+
+        let (mut version, mut on_trial) = self.boot_target(None)?;
+        if !on_trial {
+            self.make_current(&version);
+        }
+
+        let mut backoff = initial_backoff;
+        let mut adopted = None;
+        loop {
+            if signals::pending().is_some() {
+                info!("Stopping supervision on request");
+                self.clear_daemon_starts();
+                return Ok(());
+            }
+
+            if self.take_restart_request() {
+                self.answer_restart(RestartResult::Relaunched);
+                (version, on_trial) = self.boot_target(None)?;
+                backoff = initial_backoff;
+            }
+
+            let _span = tracing::info_span!("supervise", version = %version).entered();
+
+            let outcome = match adopted.take() {
+                Some(child) => self.watch_app(&version, on_trial, child)?,
+                None => self.run_version(&version, on_trial)?,
+            };
+            match outcome {
+                RunOutcome::Clean => {
+                    info!("Version {} exited cleanly, stopping supervision", version);
+                    self.clear_daemon_starts();
+                    return Ok(());
+                }
+                RunOutcome::Halted { status } => {
+                    warn!(
+                        "Stopping supervision after {} exited with {}",
+                        version, status
+                    );
+                    self.clear_daemon_starts();
+                    return Ok(());
+                }
+                RunOutcome::RolledBack { to } => {
+                    if let Some(notifier) = &self.notifier {
+                        notifier.status(&format!("Rolled back from {} to {}", version, to));
+                    }
+                    version = to;
+                    on_trial = false;
+                    backoff = initial_backoff;
+                }
+                RunOutcome::Relaunch => {
+                    info!("Relaunching {} in {:?}", version, initial_backoff);
+                    backoff = initial_backoff;
+                    self.sleep(backoff);
+                }
+                RunOutcome::UpdateRequested => {
+                    self.run_requested_update();
+                    (version, on_trial) = self.boot_target(None)?;
+                    backoff = initial_backoff;
+                }
+                RunOutcome::HandedOff {
+                    to,
+                    on_trial: trial,
+                    child,
+                } => {
+                    (version, on_trial) = (to, trial);
+                    adopted = Some(child);
+                    backoff = initial_backoff;
+                }
+                RunOutcome::Restarted | RunOutcome::Stopped => {}
+                RunOutcome::Crashed { status, uptime } => {
+                    on_trial = false;
+
+                    // A long healthy run starts the backoff over
+                    if uptime >= Duration::from_secs(Config::SUPERVISE_BACKOFF_RESET_SECS) {
+                        backoff = initial_backoff;
+                    }
+
+                    warn!(
+                        restart = true,
+                        "Version {} exited with {} after {:?}, restarting in {:?}",
+                        version,
+                        status,
+                        uptime,
+                        backoff
+                    );
+                    if let Some(notifier) = &self.notifier {
+                        notifier.status(&format!(
+                            "{} exited with {}; restarting in {:?}",
+                            version, status, backoff
+                        ));
+                    }
+                    self.sleep(backoff);
+                    backoff = (backoff * 2).min(max_backoff);
+                }
+            }
+        }
+    }
+
+    /// Runs a version in the foreground until it exits, following rollbacks and
+    /// restarts. A crash ends the run with an error.
+    pub fn run(&self, version: String, on_trial: bool) -> Result<()> {
+        let _pid_file = self.claim_supervisor()?;
+        let (mut version, mut on_trial) = (version, on_trial);
+        let mut adopted = None;
+
+        loop {
+            let outcome = match adopted.take() {
+                Some(child) => self.watch_app(&version, on_trial, child)?,
+                None => self.run_version(&version, on_trial)?,
+            };
+            match outcome {
+                RunOutcome::Clean | RunOutcome::Stopped => return Ok(()),
+                RunOutcome::HandedOff {
+                    to,
+                    on_trial: trial,
+                    child,
+                } => {
+                    (version, on_trial) = (to, trial);
+                    adopted = Some(child);
+                }
+                RunOutcome::Relaunch => {}
+                RunOutcome::RolledBack { to } => {
+                    version = to;
+                    on_trial = false;
+                }
+                RunOutcome::UpdateRequested => {
+                    self.run_requested_update();
+                    (version, on_trial) = self.boot_target(None)?;
+                }
+                RunOutcome::Restarted => {
+                    self.answer_restart(RestartResult::Relaunched);
+                    (version, on_trial) = self.boot_target(None)?;
+                }
+                RunOutcome::Crashed { status, .. } | RunOutcome::Halted { status } => {
+                    anyhow::bail!("Process exited with status: {}", status)
+                }
+            }
+        }
+    }
+
+    /// Counts a start of the `supervise` daemon and reports whether it is in safe mode.
+    ///
+    /// Starts are kept in their own file rather than the state file, so a corrupted
+    /// state file cannot hide the restart loop it causes. Reaching
+    /// `DAEMON_LOOP_THRESHOLD` starts within the window enters safe mode, which
+    /// lasts until an operator runs `restart`.
+    pub fn record_daemon_start(&self) -> Result<bool> {
+        // A second supervisor gets refused, which is not a restart of this one
+        if self.running_supervisor().is_some() {
+            return Ok(self.safe_mode().is_some());
+        }
+
+        let path = self.data_dir.join(Config::DAEMON_STARTS_FILE);
+        let now = Utc::now();
+        let cutoff = now - chrono::Duration::seconds(Config::DAEMON_LOOP_WINDOW_SECS as i64);
+        let mut starts: Vec<DateTime<Utc>> = fs::read_to_string(&path)
+            .unwrap_or_default()
+            .lines()
+            .filter_map(|line| DateTime::parse_from_rfc3339(line).ok())
+            .map(|start| start.with_timezone(&Utc))
+            .filter(|start| *start >= cutoff)
+            .collect();
+        starts.push(now);
+
+        let contents: String = starts
+            .iter()
+            .map(|start| format!("{}\n", start.to_rfc3339()))
+            .collect();
+        fs::write(&path, contents)
+            .with_context(|| format!("Failed to write {}", path.display()))?;
+
+        if self.safe_mode().is_some() {
+            return Ok(true);
+        }
+        if starts.len() < Config::DAEMON_LOOP_THRESHOLD {
+            return Ok(false);
+        }
+
+        let reason = format!(
+            "supervisor started {} times within {}s (since {})",
+            starts.len(),
+            Config::DAEMON_LOOP_WINDOW_SECS,
+            now.format("%Y-%m-%d %H:%M:%S UTC")
+        );
+        error!("Entering safe mode: {}", reason);
+        fs::write(self.data_dir.join(Config::SAFE_MODE_FILE), &reason)
+            .context("Failed to write safe mode marker")?;
+        self.events
+            .record(EventKind::SafeModeEntered, None, Some(reason));
+        Ok(true)
+    }
+
+    /// Why the daemon is in safe mode, if it is
+    pub fn safe_mode(&self) -> Option<String> {
+        fs::read_to_string(self.data_dir.join(Config::SAFE_MODE_FILE))
+            .ok()
+            .map(|reason| reason.trim().to_string())
+    }
+
+    /// Forgets a detected restart loop so the next `supervise` launches the app again
+    pub fn leave_safe_mode(&self) {
+        if self.safe_mode().is_some() {
+            info!("Leaving safe mode");
+            self.events.record(EventKind::SafeModeLeft, None, None);
+        }
+        fs::remove_file(self.data_dir.join(Config::SAFE_MODE_FILE)).ok();
+        self.clear_daemon_starts();
+    }
+
+    // A daemon that shut down on purpose was not looping
+    fn clear_daemon_starts(&self) {
+        fs::remove_file(self.data_dir.join(Config::DAEMON_STARTS_FILE)).ok();
+    }
+
+    // Keeps systemd satisfied without launching anything or acting on its own.
+    // Returns true once `restart` asks to resume, false when geist is stopped.
+    fn idle_in_safe_mode(&self, reason: &str) -> bool {
+        error!(
+            "Safe mode: {}. Not launching the app; fix the cause and run 'geist restart'",
+            reason
+        );
+        if let Some(notifier) = &self.notifier {
+            notifier.ready(&format!("Safe mode: {}", reason));
+        }
+
+        loop {
+            self.sleep(Duration::from_secs(1));
+            if signals::pending().is_some() {
+                return false;
+            }
+            if self.take_restart_request() {
+                self.answer_restart(RestartResult::Relaunched);
+                self.leave_safe_mode();
+                return true;
+            }
+        }
+    }
+
+    // Runs `geist update` for the app. A failed update is logged and the current
+    // boot version is launched again.
+    fn run_requested_update(&self) {
+        let result = env::current_exe()
+            .context("Failed to locate the geist executable")
+            .and_then(|exe| {
+                Command::new(exe)
+                    .args(["update", "--unattended"])
+                    .status()
+                    .context("Failed to run 'geist update'")
+            });
+        match result {
+            Ok(status) if status.success() => info!("Requested update finished"),
+            Ok(status) => warn!("Requested update failed with {}", status),
+            Err(e) => warn!("Requested update failed: {:#}", e),
+        }
+    }
+
+    /// Pid of the geist process currently running the app, if any
+    pub fn running_supervisor(&self) -> Option<u32> {
+        HostService::running_pid(&self.data_dir.join(Config::SUPERVISOR_PID_FILE))
+    }
+
+    /// Pid of the running app, if any
+    pub fn running_app(&self) -> Option<u32> {
+        HostService::running_pid(&self.data_dir.join(Config::APP_PID_FILE))
+    }
+
+    /// Asks the running supervisor to relaunch its boot version by leaving a restart
+    /// marker, and waits for its answer. If the supervisor has not picked it up
+    /// within the grace period, e.g. during a trial boot, the app is stopped with
+    /// SIGTERM, escalating to SIGKILL. `None` if the supervisor took the request
+    /// but did not answer in time.
+    pub fn request_restart(&self) -> Result<Option<RestartResult>> {
+        let marker = self.data_dir.join(Config::RESTART_REQUEST_FILE);
+        let answer = self.data_dir.join(Config::RESTART_RESULT_FILE);
+        fs::remove_file(&answer).ok();
+        fs::write(&marker, "").context("Failed to write restart request")?;
+
+        let grace = Duration::from_secs(Config::RESTART_GRACE_SECS);
+        let version_dir = self
+            .data_dir
+            .join(FileService::new(self.data_dir.clone()).current_version());
+        // The supervisor first gives the app time to acknowledge a pre-stop notification
+        let notice =
+            PreStop::load(&version_dir).map_or(Duration::ZERO, |pre_stop| pre_stop.grace());
+        let deadline = Instant::now() + notice + grace;
+        while marker.exists() && Instant::now() < deadline {
+            thread::sleep(POLL_INTERVAL);
+        }
+        if marker.exists() {
+            if let Some(pid) = self.running_app() {
+                Self::stop_process(pid, grace);
+            }
+            return Ok(Some(RestartResult::Stopped));
+        }
+
+        // A handoff answers once the new instance is ready or given up on
+        let handoff =
+            Handoff::load(&version_dir).map_or(Duration::ZERO, |handoff| handoff.ready_timeout());
+        let deadline = Instant::now() + handoff + notice + 2 * grace;
+        while Instant::now() < deadline {
+            if let Some(result) = fs::read_to_string(&answer)
+                .ok()
+                .and_then(|contents| serde_json::from_str(&contents).ok())
+            {
+                fs::remove_file(&answer).ok();
+                return Ok(Some(result));
+            }
+            thread::sleep(POLL_INTERVAL);
+        }
+        Ok(None)
+    }
+
+    // Takes the restart request and tells the `restart` waiting for it what became
+    // of it; losing the answer only leaves `restart` without one
+    fn answer_restart(&self, result: RestartResult) {
+        self.take_restart_request();
+        let path = self.data_dir.join(Config::RESTART_RESULT_FILE);
+        if let Err(e) = serde_json::to_string(&result)
+            .map_err(anyhow::Error::from)
+            .and_then(|json| Ok(fs::write(&path, json)?))
+        {
+            warn!("Failed to write {}: {:#}", path.display(), e);
+        }
+    }
+
+    /// Stops the app when no supervisor is around to do it
+    pub fn stop_app(&self) {
+        if let Some(pid) = self.running_app() {
+            info!("Stopping app process {}", pid);
+            Self::stop_process(pid, Duration::from_secs(Config::RESTART_GRACE_SECS));
+        }
+        fs::remove_file(self.data_dir.join(Config::APP_PID_FILE)).ok();
+    }
+
+    #[cfg(unix)]
+    fn stop_process(pid: u32, grace: Duration) {
+        unsafe { libc::kill(pid as libc::pid_t, libc::SIGTERM) };
+        let deadline = Instant::now() + grace;
+        while HostService::is_running(pid) && Instant::now() < deadline {
+            thread::sleep(Duration::from_millis(100));
+        }
+        if HostService::is_running(pid) {
+            warn!("Process {} ignored SIGTERM, killing it", pid);
+            unsafe { libc::kill(pid as libc::pid_t, libc::SIGKILL) };
+        }
+    }
+
+    // No app ever runs off-device, see `HostService::is_running`
+    #[cfg(not(unix))]
+    fn stop_process(_pid: u32, _grace: Duration) {}
+
+    fn restart_requested(&self) -> bool {
+        self.data_dir.join(Config::RESTART_REQUEST_FILE).exists()
+    }
+
+    fn take_restart_request(&self) -> bool {
+        fs::remove_file(self.data_dir.join(Config::RESTART_REQUEST_FILE)).is_ok()
+    }
+
+    // Records this process as the supervisor, refusing to run next to another one
+    fn claim_supervisor(&self) -> Result<PidFile> {
+        if let Some(pid) = self.running_supervisor() {
+            anyhow::bail!("Another supervisor is already running as pid {}", pid);
+        }
+        // A marker left behind by a supervisor that died is stale
+        self.take_restart_request();
+
+        // From here on Ctrl-C and SIGTERM stop the app and unwind through the pid file guards
+        signals::install();
+        self.write_pid_file(Config::SUPERVISOR_PID_FILE, std::process::id())
+    }
+
+    fn write_pid_file(&self, name: &str, pid: u32) -> Result<PidFile> {
+        let path = self.data_dir.join(name);
+        fs::write(&path, pid.to_string())
+            .with_context(|| format!("Failed to write {}", path.display()))?;
+        Ok(PidFile { path })
+    }
+
+    /// Waits for the child to exit, up to `timeout` if given. While it runs and is
+    /// not stopped, the systemd watchdog is pinged so a wedged supervisor gets restarted.
+    /// A SIGINT or SIGTERM sent to geist is forwarded to the child.
+    fn wait_child(
+        &self,
+        child: &mut Child,
+        timeout: Option<Duration>,
+    ) -> Result<Option<ExitStatus>> {
+        let deadline = timeout.map(|t| Instant::now() + t);
+
+        loop {
+            if let Some(status) = child.try_wait()? {
+                return Ok(Some(status));
+            }
+
+            if let Some(signal) = signals::pending() {
+                return self.forward_signal(child, signal).map(Some);
+            }
+
+            if self.watchdog_due() {
+                match HostService::process_state(child.id()) {
+                    Some('T') | Some('t') => {
+                        warn!(
+                            "App process {} is stopped, holding back the watchdog",
+                            child.id()
+                        );
+                        self.schedule_watchdog();
+                    }
+                    _ => self.ping_watchdog(),
+                }
+            }
+
+            if deadline.is_some_and(|d| Instant::now() >= d) {
+                return Ok(None);
+            }
+            thread::sleep(POLL_INTERVAL);
+        }
+    }
+
+    // Passes a stop signal on to the app, killing it if it outlives the grace period
+    fn forward_signal(&self, child: &mut Child, signal: i32) -> Result<ExitStatus> {
+        info!(
+            "Received signal {}, stopping app process {}",
+            signal,
+            child.id()
+        );
+        #[cfg(unix)]
+        unsafe {
+            libc::kill(child.id() as libc::pid_t, signal);
+        }
+
+        let grace = Duration::from_secs(Config::RESTART_GRACE_SECS);
+        if let Some(status) = AppService::wait_timeout(child, grace)? {
+            return Ok(status);
+        }
+        warn!(
+            "App process {} did not stop in {:?}, killing it",
+            child.id(),
+            grace
+        );
+        child.kill()?;
+        Ok(child.wait()?)
+    }
+
+    /// Sleeps between restarts, keeping the systemd watchdog satisfied.
+    /// Returns early when a stop signal arrives.
+    fn sleep(&self, duration: Duration) {
+        let deadline = Instant::now() + duration;
+
+        while signals::pending().is_none() {
+            let now = Instant::now();
+            if now >= deadline {
+                return;
+            }
+            if self.watchdog_due() {
+                self.ping_watchdog();
+            }
+            thread::sleep(POLL_INTERVAL.min(deadline - now));
+        }
+    }
+
+    // Whether the watchdog is due a ping, counting from the first check when it
+    // has not been pinged yet
+    fn watchdog_due(&self) -> bool {
+        if self.next_ping.get().is_none() {
+            self.schedule_watchdog();
+        }
+        self.next_ping.get().is_some_and(|at| Instant::now() >= at)
+    }
+
+    // Sets when the watchdog is due next, one interval from now
+    fn schedule_watchdog(&self) {
+        let interval = self.notifier.as_ref().and_then(Notifier::watchdog_interval);
+        self.next_ping
+            .set(interval.map(|interval| Instant::now() + interval));
+    }
+
+    fn ping_watchdog(&self) {
+        if let Some(notifier) = &self.notifier {
+            notifier.watchdog();
+        }
+        self.schedule_watchdog();
+    }
+}
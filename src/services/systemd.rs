@@ -0,0 +1,226 @@
+use crate::config::Config;
+use anyhow::{Context, Result};
+use std::env;
+use std::fs;
+#[cfg(target_os = "linux")]
+use std::os::linux::net::SocketAddrExt;
+#[cfg(target_os = "linux")]
+use std::os::unix::net::{SocketAddr, UnixDatagram};
+use std::path::PathBuf;
+use std::process::Command;
+use std::time::Duration;
+use tracing::info;
+
+/// Renders and manages the systemd unit that keeps the supervisor running
+pub struct SystemdService {
+    /// Manage a user unit instead of a system-wide one
+    pub user: bool,
+}
+
+impl SystemdService {
+    pub fn new(user: bool) -> Self {
+        Self { user }
+    }
+
+    pub fn unit_path(&self) -> Result<PathBuf> {
+        if self.user {
+            let home = env::var("HOME").context("HOME is required for a user unit")?;
+            Ok(PathBuf::from(home)
+                .join(".config/systemd/user")
+                .join(Config::systemd_unit_name()))
+        } else {
+            Ok(PathBuf::from("/etc/systemd/system").join(Config::systemd_unit_name()))
+        }
+    }
+
+    /// Renders a unit that runs `geist <subcommand>` from the current executable
+    pub fn render_unit(&self, subcommand: &str) -> Result<String> {
+        let exe = env::current_exe().context("Failed to locate the geist executable")?;
+
+        let mut unit = String::new();
+        unit.push_str("[Unit]\n");
+        unit.push_str("Description=Geist Supervisor for the Roc Camera\n");
+        unit.push_str("Wants=network-online.target\n");
+        unit.push_str("After=network-online.target\n\n");
+
+        unit.push_str("[Service]\n");
+        unit.push_str("Type=notify\n");
+        unit.push_str("NotifyAccess=main\n");
+        match Config::profile() {
+            Some(profile) => unit.push_str(&format!(
+                "ExecStart={} --profile {} {}\n",
+                exe.display(),
+                profile,
+                subcommand
+            )),
+            None => unit.push_str(&format!("ExecStart={} {}\n", exe.display(), subcommand)),
+        }
+        unit.push_str(&format!("WatchdogSec={}\n", Config::SYSTEMD_WATCHDOG_SECS));
+        unit.push_str("Restart=on-failure\n");
+        unit.push_str("RestartSec=5\n");
+        // Lets the supervisor put the app in a cgroup with its resource limits
+        unit.push_str("Delegate=yes\n");
+        unit.push_str("Environment=RUST_LOG=info\n");
+        // A data directory or configuration file in the installing user's home is
+        // found through HOME, which system services don't get
+        if let Ok(home) = env::var("HOME") {
+            unit.push_str(&format!("Environment=HOME={}\n", home));
+        }
+        unit.push('\n');
+
+        unit.push_str("[Install]\n");
+        let target = if self.user {
+            "default.target"
+        } else {
+            "multi-user.target"
+        };
+        unit.push_str(&format!("WantedBy={}\n", target));
+
+        Ok(unit)
+    }
+
+    /// Writes the unit file, reloads systemd, and enables and starts the unit
+    pub fn install(&self, subcommand: &str) -> Result<PathBuf> {
+        let unit_path = self.unit_path()?;
+        if let Some(parent) = unit_path.parent() {
+            fs::create_dir_all(parent)
+                .with_context(|| format!("Failed to create {}", parent.display()))?;
+        }
+
+        fs::write(&unit_path, self.render_unit(subcommand)?)
+            .with_context(|| format!("Failed to write {}", unit_path.display()))?;
+        info!("Wrote systemd unit to {}", unit_path.display());
+
+        self.systemctl(&["daemon-reload"])?;
+        self.systemctl(&["enable", "--now", &Config::systemd_unit_name()])?;
+        Ok(unit_path)
+    }
+
+    /// Stops and disables the unit and removes its file
+    pub fn uninstall(&self) -> Result<PathBuf> {
+        let unit_path = self.unit_path()?;
+        if !unit_path.exists() {
+            anyhow::bail!("No unit installed at {}", unit_path.display());
+        }
+
+        self.systemctl(&["disable", "--now", &Config::systemd_unit_name()])?;
+        fs::remove_file(&unit_path)
+            .with_context(|| format!("Failed to remove {}", unit_path.display()))?;
+        self.systemctl(&["daemon-reload"])?;
+        Ok(unit_path)
+    }
+
+    /// Shows `systemctl status` for the unit, passing its output through
+    pub fn status(&self) -> Result<()> {
+        let mut command = Command::new("systemctl");
+        if self.user {
+            command.arg("--user");
+        }
+        // systemctl status exits non-zero for inactive units, which is not an error here
+        command
+            .args(["status", "--no-pager", &Config::systemd_unit_name()])
+            .status()
+            .context("Failed to execute systemctl")?;
+        Ok(())
+    }
+
+    fn systemctl(&self, args: &[&str]) -> Result<()> {
+        let mut command = Command::new("systemctl");
+        if self.user {
+            command.arg("--user");
+        }
+
+        let output = command
+            .args(args)
+            .output()
+            .context("Failed to execute systemctl")?;
+
+        if !output.status.success() {
+            let stderr = String::from_utf8_lossy(&output.stderr);
+            anyhow::bail!("systemctl {} failed: {}", args.join(" "), stderr.trim());
+        }
+        Ok(())
+    }
+}
+
+/// Sends sd_notify messages when the supervisor runs as a `Type=notify` unit
+pub struct Notifier {
+    #[cfg(target_os = "linux")]
+    socket: UnixDatagram,
+    #[cfg(target_os = "linux")]
+    addr: SocketAddr,
+    watchdog_interval: Option<Duration>,
+}
+
+impl Notifier {
+    /// Connects to the socket in NOTIFY_SOCKET; None when not started by systemd
+    #[cfg(target_os = "linux")]
+    pub fn from_env() -> Option<Self> {
+        let path = env::var("NOTIFY_SOCKET").ok()?;
+        let addr = match path.strip_prefix('@') {
+            Some(name) => SocketAddr::from_abstract_name(name.as_bytes()),
+            None => SocketAddr::from_pathname(&path),
+        };
+        let addr = match addr {
+            Ok(addr) => addr,
+            Err(e) => {
+                tracing::warn!("Ignoring invalid NOTIFY_SOCKET {}: {}", path, e);
+                return None;
+            }
+        };
+        let socket = UnixDatagram::unbound().ok()?;
+
+        // systemd asks for pings at WATCHDOG_USEC; pinging at half of it leaves slack
+        let watchdog_for_us = env::var("WATCHDOG_PID")
+            .ok()
+            .and_then(|pid| pid.parse::<u32>().ok())
+            .is_none_or(|pid| pid == std::process::id());
+        let watchdog_interval = env::var("WATCHDOG_USEC")
+            .ok()
+            .and_then(|usec| usec.parse::<u64>().ok())
+            .filter(|usec| *usec > 0 && watchdog_for_us)
+            .map(|usec| Duration::from_micros(usec / 2));
+
+        Some(Self {
+            socket,
+            addr,
+            watchdog_interval,
+        })
+    }
+
+    // systemd only exists on Linux
+    #[cfg(not(target_os = "linux"))]
+    pub fn from_env() -> Option<Self> {
+        None
+    }
+
+    /// How often the watchdog expects a ping, if it is enabled
+    pub fn watchdog_interval(&self) -> Option<Duration> {
+        self.watchdog_interval
+    }
+
+    /// Tells systemd the service is up
+    pub fn ready(&self, status: &str) {
+        self.send(&format!("READY=1\nSTATUS={}", status));
+    }
+
+    pub fn status(&self, status: &str) {
+        self.send(&format!("STATUS={}", status));
+    }
+
+    /// Resets the watchdog timer
+    pub fn watchdog(&self) {
+        self.send("WATCHDOG=1");
+    }
+
+    #[cfg(target_os = "linux")]
+    fn send(&self, message: &str) {
+        // A lost notification is retried by the next ping, so failures are only logged
+        if let Err(e) = self.socket.send_to_addr(message.as_bytes(), &self.addr) {
+            tracing::debug!("Failed to notify systemd: {}", e);
+        }
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    fn send(&self, _message: &str) {}
+}
@@ -0,0 +1,69 @@
+use crate::services::{EventKind, EventService, FileService};
+use anyhow::Result;
+use std::path::PathBuf;
+use std::thread;
+use std::time::Duration;
+use tracing::{error, info, warn};
+
+/// Re-verifies the installed files of the current version against the hashes
+/// recorded when it was installed, for devices someone could tamper with in place
+pub struct TamperWatch {
+    fs_service: FileService,
+    events: EventService,
+}
+
+impl TamperWatch {
+    pub fn new(data_dir: PathBuf) -> Self {
+        Self {
+            fs_service: FileService::new(data_dir.clone()),
+            events: EventService::new(data_dir),
+        }
+    }
+
+    /// The changes to a version's files since it was installed, empty if none.
+    /// Fails when the version has no recorded hashes to compare with.
+    pub fn check(&self, version: &str) -> Result<Vec<String>> {
+        self.fs_service.verify_hashes(version)
+    }
+
+    /// Checks the current version once per interval and records a `tampered`
+    /// event whenever what changed differs from the last check. Never returns.
+    pub fn run_periodically(&self, interval: Duration) {
+        info!(
+            "Checking the current version's files for tampering every {:?}",
+            interval
+        );
+        let mut reported: Option<(String, Vec<String>)> = None;
+        let mut skipped: Option<String> = None;
+        loop {
+            let version = self.fs_service.current_version();
+            match self.check(&version) {
+                Ok(changes) if changes.is_empty() => reported = None,
+                Ok(changes) => {
+                    let finding = (version.clone(), changes);
+                    if reported.as_ref() != Some(&finding) {
+                        error!(
+                            "Files of version {} were modified outside the supervisor: {}",
+                            version,
+                            finding.1.join(", ")
+                        );
+                        self.events.record(
+                            EventKind::Tampered,
+                            Some(&version),
+                            Some(finding.1.join(", ")),
+                        );
+                        reported = Some(finding);
+                    }
+                }
+                // A version without a usable record cannot be judged here; say so once
+                Err(e) => {
+                    if skipped.as_ref() != Some(&version) {
+                        warn!("Tamper check of {} skipped: {:#}", version, e);
+                        skipped = Some(version);
+                    }
+                }
+            }
+            thread::sleep(interval);
+        }
+    }
+}
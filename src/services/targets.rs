@@ -0,0 +1,231 @@
+use crate::config::Config;
+use crate::services::{BundleFormat, KeyService, RegistryBackend, StateService};
+use anyhow::{Context, Result};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::Path;
+
+/// A release bundle as listed in the targets metadata
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TargetFile {
+    pub length: u64,
+    pub hashes: TargetHashes,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TargetHashes {
+    pub sha256: String,
+}
+
+/// Signed metadata listing every release bundle the registry vouches for, in the
+/// spirit of TUF's targets role.
+///
+/// A signature alone proves a file came from the publisher, not that it is the
+/// newest one: a mirror or attacker could keep serving an old, validly signed
+/// listing to hold devices on a vulnerable release. The metadata therefore
+/// carries a version that must never go down and an expiry after which it is no
+/// longer believed.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TargetsMetadata {
+    /// Incremented with every publication
+    pub version: u64,
+    pub expires: DateTime<Utc>,
+    /// Keyed by `<version>/<bundle file name>`, or `<version>/image` for the
+    /// container image of a release
+    pub targets: BTreeMap<String, TargetFile>,
+}
+
+/// The bundle of a release that the targets metadata lists
+pub struct TargetBundle {
+    pub format: BundleFormat,
+    pub sha256: String,
+    pub length: u64,
+}
+
+impl TargetsMetadata {
+    /// Fetches the registry's targets metadata and checks its signature against
+    /// the trusted keys, its expiry, and that it is not older than the metadata
+    /// seen before. The version seen is remembered in the supervisor state.
+    pub fn fetch_verified(registry: &dyn RegistryBackend, data_dir: &Path) -> Result<Self> {
+        let contents = registry
+            .fetch_metadata(Config::TARGETS_METADATA_FILE)?
+            .with_context(|| {
+                format!(
+                    "The {} registry publishes no {}",
+                    registry.name(),
+                    Config::TARGETS_METADATA_FILE
+                )
+            })?;
+        let signature = registry
+            .fetch_metadata(Config::TARGETS_SIGNATURE_FILE)?
+            .with_context(|| format!("{} is not signed", Config::TARGETS_METADATA_FILE))?;
+        let signer = KeyService::new(data_dir.to_path_buf())
+            .verify(contents.as_bytes(), &signature)
+            .with_context(|| format!("Refusing {}", Config::TARGETS_METADATA_FILE))?;
+
+        let metadata: Self = serde_json::from_str(&contents)
+            .with_context(|| format!("Failed to parse {}", Config::TARGETS_METADATA_FILE))?;
+        let state = StateService::new(data_dir.to_path_buf());
+        metadata.check_fresh(state.load()?.targets_version, Utc::now())?;
+        state.update(|state| state.targets_version = Some(metadata.version))?;
+        tracing::info!(
+            "Targets metadata version {} signed by key {}, expires {}",
+            metadata.version,
+            signer.key_id,
+            metadata.expires
+        );
+        Ok(metadata)
+    }
+
+    // Refuses metadata that expired by `now` or is older than the version `seen`
+    // before
+    fn check_fresh(&self, seen: Option<u64>, now: DateTime<Utc>) -> Result<()> {
+        if self.expires <= now {
+            anyhow::bail!(
+                "{} expired at {}; the registry may be withholding newer releases",
+                Config::TARGETS_METADATA_FILE,
+                self.expires
+            );
+        }
+        if let Some(seen) = seen {
+            if self.version < seen {
+                anyhow::bail!(
+                    "{} version {} is older than version {} seen before; refusing a rollback",
+                    Config::TARGETS_METADATA_FILE,
+                    self.version,
+                    seen
+                );
+            }
+        }
+        Ok(())
+    }
+
+    /// Versions with at least one listed bundle
+    pub fn versions(&self) -> Vec<String> {
+        let mut versions: Vec<String> = self
+            .targets
+            .keys()
+            .filter_map(|target| target.split_once('/'))
+            .map(|(version, _)| Config::normalize_version(version))
+            .collect();
+        versions.dedup();
+        versions
+    }
+
+    /// The newest listed version by semver, prereleases only if they are allowed
+    pub fn latest(&self) -> Option<String> {
+        Config::newest_version(self.versions(), &semver::VersionReq::STAR)
+    }
+
+    /// The manifest digest listed for the container image of a version, e.g.
+    /// `sha256:...`
+    pub fn image_digest(&self, version: &str) -> Result<String> {
+        let version = Config::normalize_version(version);
+        let target = self
+            .targets
+            .get(&format!("{}/{}", version, Config::TARGETS_IMAGE_NAME))
+            .with_context(|| {
+                format!(
+                    "The container image of {} is not listed in the signed {}",
+                    version,
+                    Config::TARGETS_METADATA_FILE
+                )
+            })?;
+        Ok(format!("sha256:{}", target.hashes.sha256.to_lowercase()))
+    }
+
+    /// The most preferred bundle format listed for a version
+    pub fn bundle(&self, version: &str) -> Result<TargetBundle> {
+        let version = Config::normalize_version(version);
+        BundleFormat::PREFERRED
+            .into_iter()
+            .find_map(|format| {
+                let target =
+                    self.targets
+                        .get(&format!("{}/{}", version, format.file_name(&version)))?;
+                Some(TargetBundle {
+                    format,
+                    sha256: target.hashes.sha256.to_lowercase(),
+                    length: target.length,
+                })
+            })
+            .with_context(|| {
+                format!(
+                    "Release {} is not listed in the signed {}",
+                    version,
+                    Config::TARGETS_METADATA_FILE
+                )
+            })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Duration;
+
+    fn key(version: &str, format: BundleFormat) -> String {
+        format!("{}/{}", version, format.file_name(version))
+    }
+
+    fn metadata(version: u64, expires: DateTime<Utc>) -> TargetsMetadata {
+        let target = |sha256: &str| TargetFile {
+            length: 1024,
+            hashes: TargetHashes {
+                sha256: sha256.to_string(),
+            },
+        };
+        TargetsMetadata {
+            version,
+            expires,
+            targets: BTreeMap::from([
+                (key("1.2.0", BundleFormat::Gzip), target("AA")),
+                (key("1.2.0", BundleFormat::Zstd), target("BB")),
+                ("1.2.0/image".to_string(), target("CC")),
+                (key("1.10.0", BundleFormat::Gzip), target("DD")),
+            ]),
+        }
+    }
+
+    #[test]
+    fn accepts_metadata_that_has_not_expired() {
+        let now = Utc::now();
+        let metadata = metadata(7, now + Duration::days(1));
+        assert!(metadata.check_fresh(None, now).is_ok());
+    }
+
+    #[test]
+    fn refuses_expired_metadata() {
+        let now = Utc::now();
+        let error = metadata(7, now - Duration::seconds(1))
+            .check_fresh(None, now)
+            .unwrap_err();
+        assert!(error.to_string().contains("expired"), "{:#}", error);
+        // Expiring this very moment is expired too
+        assert!(metadata(7, now).check_fresh(None, now).is_err());
+    }
+
+    #[test]
+    fn refuses_a_rollback_to_older_metadata() {
+        let now = Utc::now();
+        let metadata = metadata(7, now + Duration::days(1));
+        let error = metadata.check_fresh(Some(8), now).unwrap_err();
+        assert!(error.to_string().contains("rollback"), "{:#}", error);
+        // The same metadata again, or newer, is fine
+        assert!(metadata.check_fresh(Some(7), now).is_ok());
+        assert!(metadata.check_fresh(Some(6), now).is_ok());
+    }
+
+    #[test]
+    fn lists_the_preferred_bundle_and_the_image() {
+        let metadata = metadata(7, Utc::now() + Duration::days(1));
+        let bundle = metadata.bundle("v1.2.0").unwrap();
+        assert_eq!(bundle.format, BundleFormat::Zstd);
+        assert_eq!(bundle.sha256, "bb");
+        assert_eq!(metadata.image_digest("1.2.0").unwrap(), "sha256:cc");
+        assert!(metadata.bundle("1.3.0").is_err());
+        assert!(metadata.image_digest("1.10.0").is_err());
+        assert_eq!(metadata.latest().as_deref(), Some("1.10.0"));
+    }
+}
@@ -0,0 +1,154 @@
+use crate::config::Config;
+use anyhow::{Context, Result};
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::PathBuf;
+use tracing::{debug, info, warn};
+
+/// Registries whose tokens `auth login` can store
+pub const TOKEN_SERVICES: &[&str] = &["gcs", "github", "gitlab"];
+
+/// Where a stored token lives
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TokenLocation {
+    /// The platform keyring: Secret Service on Linux desktops, with the kernel
+    /// keyring as a cache, the Keychain on macOS, the Credential Manager on Windows
+    Keyring,
+    /// The plaintext credentials file under the data directory, for headless
+    /// devices without a keyring daemon
+    File,
+}
+
+/// Registry tokens saved by `geist auth login`, kept in the platform keyring when
+/// one is available and in a file readable only by the owner otherwise
+pub struct TokenStore {
+    path: PathBuf,
+}
+
+impl TokenStore {
+    pub fn new(data_dir: PathBuf) -> Self {
+        Self {
+            path: data_dir.join(Config::CREDENTIALS_FILE),
+        }
+    }
+
+    fn entry(service: &str) -> Result<keyring::Entry> {
+        keyring::Entry::new(&Config::keyring_service(), service)
+            .with_context(|| format!("Invalid keyring entry for {}", service))
+    }
+
+    /// Fails for services `auth login` does not know
+    pub fn check_service(service: &str) -> Result<()> {
+        if !TOKEN_SERVICES.contains(&service) {
+            anyhow::bail!(
+                "Unknown service '{}' (expected {})",
+                service,
+                TOKEN_SERVICES.join(", ")
+            );
+        }
+        Ok(())
+    }
+
+    /// Stores the token of a service, in the keyring unless `plaintext` is set or
+    /// no keyring can be reached
+    pub fn save(&self, service: &str, token: &str, plaintext: bool) -> Result<TokenLocation> {
+        Self::check_service(service)?;
+        if !plaintext {
+            match Self::entry(service)?.set_password(token) {
+                Ok(()) => {
+                    // Drop a copy saved in the file earlier, so no stale token lingers there
+                    self.remove_from_file(service)?;
+                    info!("Stored the {} token in the keyring", service);
+                    return Ok(TokenLocation::Keyring);
+                }
+                Err(e) => warn!(
+                    "No keyring available ({}); storing the {} token in {}",
+                    e,
+                    service,
+                    self.path.display()
+                ),
+            }
+        }
+
+        let mut tokens = self.load_file()?;
+        tokens.insert(service.to_string(), token.to_string());
+        self.save_file(&tokens)?;
+        Ok(TokenLocation::File)
+    }
+
+    /// The stored token of a service and where it was found: the keyring first,
+    /// then the credentials file
+    pub fn load(&self, service: &str) -> Result<Option<(String, TokenLocation)>> {
+        Self::check_service(service)?;
+        match Self::entry(service)?.get_password() {
+            Ok(token) => return Ok(Some((token, TokenLocation::Keyring))),
+            Err(keyring::Error::NoEntry) => {}
+            Err(e) => debug!("Keyring unavailable for the {} token: {}", service, e),
+        }
+        Ok(self
+            .load_file()?
+            .remove(service)
+            .map(|token| (token, TokenLocation::File)))
+    }
+
+    /// The stored token of a service, if any
+    pub fn token(&self, service: &str) -> Result<Option<String>> {
+        Ok(self.load(service)?.map(|(token, _)| token))
+    }
+
+    /// Forgets the token of a service wherever it is stored; false if there was none
+    pub fn delete(&self, service: &str) -> Result<bool> {
+        Self::check_service(service)?;
+        let in_keyring = match Self::entry(service)?.delete_credential() {
+            Ok(()) => true,
+            Err(keyring::Error::NoEntry) => false,
+            Err(e) => {
+                debug!("Keyring unavailable for the {} token: {}", service, e);
+                false
+            }
+        };
+        Ok(self.remove_from_file(service)? || in_keyring)
+    }
+
+    fn remove_from_file(&self, service: &str) -> Result<bool> {
+        let mut tokens = self.load_file()?;
+        if tokens.remove(service).is_none() {
+            return Ok(false);
+        }
+        self.save_file(&tokens)?;
+        Ok(true)
+    }
+
+    fn load_file(&self) -> Result<BTreeMap<String, String>> {
+        if !self.path.exists() {
+            return Ok(BTreeMap::new());
+        }
+        let contents = fs::read_to_string(&self.path)
+            .with_context(|| format!("Failed to read {}", self.path.display()))?;
+        serde_json::from_str(&contents)
+            .with_context(|| format!("Failed to parse {}", self.path.display()))
+    }
+
+    // Written through a temporary file created with owner-only permissions, so the
+    // tokens are never readable by others, not even briefly
+    fn save_file(&self, tokens: &BTreeMap<String, String>) -> Result<()> {
+        let tmp_path = self.path.with_extension("json.tmp");
+        let _ = fs::remove_file(&tmp_path);
+        let mut options = fs::OpenOptions::new();
+        options.write(true).create(true).truncate(true);
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::OpenOptionsExt;
+            options.mode(0o600);
+        }
+        let mut file = options
+            .open(&tmp_path)
+            .with_context(|| format!("Failed to write {}", tmp_path.display()))?;
+        std::io::Write::write_all(&mut file, serde_json::to_string_pretty(tokens)?.as_bytes())?;
+        fs::rename(&tmp_path, &self.path)
+            .with_context(|| format!("Failed to replace {}", self.path.display()))?;
+        Ok(())
+    }
+}
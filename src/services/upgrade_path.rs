@@ -0,0 +1,178 @@
+use crate::config::Config;
+use anyhow::{Context, Result};
+use serde::Deserialize;
+use std::fs;
+use std::path::Path;
+
+/// What a release needs from the device it is installed on.
+///
+/// Declared in a version's `manifest.yaml`:
+///
+/// ```yaml
+/// min_upgrade_from: 1.40.0
+/// min_supervisor_version: 0.9.0
+/// ```
+///
+/// `min_upgrade_from` is the oldest version the release can be installed over, for
+/// releases whose data migrations only know how to start from the data an earlier
+/// release left behind; devices on an older version have to pass through a release
+/// in between first. `min_supervisor_version` is the oldest geist that can install
+/// and run the release, for releases that change the bundle layout.
+#[derive(Debug, Clone)]
+pub struct UpgradeConstraint {
+    /// The version declaring the constraints
+    pub version: String,
+    pub min_upgrade_from: Option<semver::Version>,
+    pub min_supervisor_version: Option<semver::Version>,
+}
+
+// Only the part of the manifest the constraints care about
+#[derive(Deserialize)]
+struct Manifest {
+    #[serde(default)]
+    min_upgrade_from: Option<String>,
+    #[serde(default)]
+    min_supervisor_version: Option<String>,
+}
+
+impl UpgradeConstraint {
+    /// The constraints a version's manifest declares, if any
+    pub fn parse(version: &str, manifest: &str) -> Result<Option<Self>> {
+        let manifest: Manifest = serde_yaml::from_str(manifest)
+            .with_context(|| format!("Failed to parse the manifest of {}", version))?;
+        let parse = |key: &str, value: Option<String>| {
+            value
+                .map(|value| Config::parse_version(&value))
+                .transpose()
+                .with_context(|| format!("Invalid {} in the manifest of {}", key, version))
+        };
+        let constraint = Self {
+            version: version.to_string(),
+            min_upgrade_from: parse("min_upgrade_from", manifest.min_upgrade_from)?,
+            min_supervisor_version: parse(
+                "min_supervisor_version",
+                manifest.min_supervisor_version,
+            )?,
+        };
+        if constraint.min_upgrade_from.is_none() && constraint.min_supervisor_version.is_none() {
+            return Ok(None);
+        }
+        Ok(Some(constraint))
+    }
+
+    /// The constraints of an installed version or an extracted bundle, if any
+    pub fn load(manifest_dir: &Path, version: &str) -> Result<Option<Self>> {
+        let path = manifest_dir.join("manifest.yaml");
+        if !path.is_file() {
+            return Ok(None);
+        }
+        let contents = fs::read_to_string(&path)
+            .with_context(|| format!("Failed to read {}", path.display()))?;
+        Self::parse(version, &contents)
+    }
+
+    /// Whether the version can be installed over `installed`. Versions that are not
+    /// semver predate the constraint and are let through.
+    pub fn allows(&self, installed: &str) -> bool {
+        match (&self.min_upgrade_from, Config::parse_version(installed)) {
+            (Some(min), Ok(installed)) => installed >= *min,
+            _ => true,
+        }
+    }
+
+    /// The releases below the version that are new enough to install it over
+    pub fn bridge(&self) -> Result<Option<semver::VersionReq>> {
+        let Some(min) = &self.min_upgrade_from else {
+            return Ok(None);
+        };
+        let version = Config::parse_version(&self.version)?;
+        semver::VersionReq::parse(&format!(">={}, <{}", min, version))
+            .map(Some)
+            .context("Failed to build the version range of the upgrade path")
+    }
+
+    /// Fails if this geist is older than the release needs
+    pub fn check_supervisor(&self) -> Result<()> {
+        let Some(min) = &self.min_supervisor_version else {
+            return Ok(());
+        };
+        let running = Config::parse_version(Config::PKG_VERSION)?;
+        if running < *min {
+            anyhow::bail!(
+                "Release {} needs geist {} or later to install and run it, and this is geist {}. Update geist first with '{}', then run the update again.",
+                self.version,
+                min,
+                running,
+                Config::SELF_UPDATE_COMMAND
+            );
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn constraint(manifest: &str) -> UpgradeConstraint {
+        UpgradeConstraint::parse("2.0.0", manifest)
+            .unwrap()
+            .expect("the manifest declares constraints")
+    }
+
+    #[test]
+    fn parses_only_declared_constraints() {
+        assert!(UpgradeConstraint::parse("2.0.0", "version: 2.0.0\n")
+            .unwrap()
+            .is_none());
+        let constraint = constraint("min_upgrade_from: v1.5.0\n");
+        assert_eq!(
+            constraint.min_upgrade_from,
+            Some(semver::Version::new(1, 5, 0))
+        );
+        assert!(constraint.min_supervisor_version.is_none());
+        assert!(UpgradeConstraint::parse("2.0.0", "min_upgrade_from: soon\n").is_err());
+    }
+
+    #[test]
+    fn allows_only_versions_from_the_minimum() {
+        let constraint = constraint("min_upgrade_from: 1.5.0\n");
+        assert!(!constraint.allows("1.4.9"));
+        assert!(!constraint.allows("v1.5.0-rc.1"));
+        assert!(constraint.allows("1.5.0"));
+        assert!(constraint.allows("v1.6.2"));
+        // Older versions that are not semver predate the constraint
+        assert!(constraint.allows("nightly"));
+    }
+
+    #[test]
+    fn bridges_from_the_minimum_to_the_version() {
+        let bridge = constraint("min_upgrade_from: 1.5.0\n")
+            .bridge()
+            .unwrap()
+            .unwrap();
+        assert!(!bridge.matches(&semver::Version::new(1, 4, 0)));
+        assert!(bridge.matches(&semver::Version::new(1, 5, 0)));
+        assert!(bridge.matches(&semver::Version::new(1, 9, 3)));
+        assert!(!bridge.matches(&semver::Version::new(2, 0, 0)));
+        assert!(constraint("min_supervisor_version: 0.1.0\n")
+            .bridge()
+            .unwrap()
+            .is_none());
+    }
+
+    #[test]
+    fn checks_the_supervisor_version() {
+        assert!(constraint("min_supervisor_version: 0.0.1\n")
+            .check_supervisor()
+            .is_ok());
+        let error = constraint("min_supervisor_version: 999.0.0\n")
+            .check_supervisor()
+            .unwrap_err();
+        assert!(
+            error.to_string().contains("needs geist 999.0.0"),
+            "{:#}",
+            error
+        );
+    }
+}
@@ -0,0 +1,438 @@
+use crate::config::Config;
+use crate::services::{
+    registry, BundleFormat, BundleOrigin, CosignPolicy, FileService, KeyService, MigrationService,
+    QuarantineRecord, QuarantineService, StateService,
+};
+use anyhow::{Context, Result};
+use chrono::Utc;
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use serde::Deserialize;
+use std::collections::HashSet;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::Duration;
+use tracing::{error, info, warn};
+
+// Layout of the `geist/` directory on a stick
+const BUNDLES_DIR: &str = "bundles";
+const SUPPORT_DIR: &str = "support";
+const PLAN_FILE: &str = "plan.json";
+const PLAN_SIGNATURE_FILE: &str = "plan.json.minisig";
+
+/// Operations an inserted USB stick is allowed to trigger
+#[derive(Debug, Clone, Copy, Default)]
+pub struct UsbPolicy {
+    /// Install release bundles found in `geist/bundles`
+    pub import: bool,
+    /// Write a support bundle to `geist/support`
+    pub export: bool,
+    /// Apply a signed `geist/plan.json`
+    pub plan: bool,
+}
+
+impl UsbPolicy {
+    pub fn from_config() -> Self {
+        let mut policy = Self::default();
+        for op in Config::usb_policy() {
+            match op.as_str() {
+                "import" => policy.import = true,
+                "export" => policy.export = true,
+                "plan" => policy.plan = true,
+                other => warn!("Ignoring unknown USB policy entry: {}", other),
+            }
+        }
+        policy
+    }
+
+    pub fn allows_anything(&self) -> bool {
+        self.import || self.export || self.plan
+    }
+}
+
+/// A plan file signed by a trusted key, telling the device which version to boot
+#[derive(Debug, Deserialize)]
+pub struct UsbPlan {
+    pub version: String,
+}
+
+/// Detects USB sticks with a `geist/` directory and runs the offline operations
+/// the policy allows on them
+pub struct UsbService {
+    data_dir: PathBuf,
+    fs_service: FileService,
+    policy: UsbPolicy,
+}
+
+impl UsbService {
+    pub fn new(data_dir: PathBuf, policy: UsbPolicy) -> Self {
+        Self {
+            fs_service: FileService::new(data_dir.clone()),
+            data_dir,
+            policy,
+        }
+    }
+
+    /// `geist/` directories on the mounted sticks, e.g. `/media/<user>/<label>/geist`
+    pub fn find_sticks(&self) -> Vec<PathBuf> {
+        let mut sticks = Vec::new();
+        for root in Config::usb_mount_roots() {
+            for mount in subdirs(&root) {
+                if mount.join(Config::USB_DIR).is_dir() {
+                    sticks.push(mount.join(Config::USB_DIR));
+                    continue;
+                }
+                // Desktop automounters add a per-user level
+                for nested in subdirs(&mount) {
+                    if nested.join(Config::USB_DIR).is_dir() {
+                        sticks.push(nested.join(Config::USB_DIR));
+                    }
+                }
+            }
+        }
+        sticks
+    }
+
+    /// Polls for sticks, handling each one once per insertion. Never returns.
+    pub fn watch(&self, interval: Duration) {
+        info!(
+            "Watching for USB sticks (import: {}, export: {}, plan: {})",
+            self.policy.import, self.policy.export, self.policy.plan
+        );
+
+        let mut handled = HashSet::new();
+        loop {
+            let sticks = self.find_sticks();
+            for stick in &sticks {
+                if handled.insert(stick.clone()) {
+                    let _span = tracing::info_span!("usb", stick = %stick.display()).entered();
+                    if let Err(e) = self.process(stick) {
+                        error!("Failed to process USB stick: {:#}", e);
+                    }
+                }
+            }
+            // Forget removed sticks so they are handled again when reinserted
+            handled.retain(|stick| sticks.contains(stick));
+            thread::sleep(interval);
+        }
+    }
+
+    /// Runs every allowed operation against one stick
+    pub fn process(&self, stick: &Path) -> Result<()> {
+        info!("Found USB stick at {}", stick.display());
+
+        if self.policy.import {
+            let imported = self.import_bundles(stick)?;
+            // Only the newest import is staged; older ones stay available for rollbacks
+            if let Some(newest) = imported
+                .last()
+                .filter(|newest| !self.held(newest) && self.migrated(newest))
+            {
+                if self.fs_service.stage_in_inactive_slot(newest)? {
+                    info!(
+                        "Imported {} staged for a trial boot on next restart",
+                        newest
+                    );
+                }
+            }
+        }
+
+        // Plans run after the import so they can refer to a version from the same stick
+        if self.policy.plan {
+            self.apply_plan(stick)?;
+        }
+
+        if self.policy.export {
+            let path = self.export_support_bundle(stick)?;
+            info!("Wrote support bundle to {}", path.display());
+        }
+
+        Ok(())
+    }
+
+    /// Installs the release bundles in `geist/bundles` that are not installed yet,
+    /// checking them against `geist/bundles/checksums.txt`. Returns the installed versions.
+    pub fn import_bundles(&self, stick: &Path) -> Result<Vec<String>> {
+        let bundles_dir = stick.join(BUNDLES_DIR);
+        if !bundles_dir.is_dir() {
+            return Ok(Vec::new());
+        }
+
+        let checksums =
+            fs::read_to_string(bundles_dir.join(Config::checksum_file_name())).unwrap_or_default();
+
+        let mut bundles: Vec<(String, PathBuf)> = fs::read_dir(&bundles_dir)?
+            .filter_map(|entry| entry.ok().map(|e| e.path()))
+            .filter_map(|path| {
+                let name = path.file_name()?.to_str()?;
+                let (stem, _) = BundleFormat::split_file_name(name)?;
+                let version = stem
+                    .strip_prefix(Config::bundle_prefix().as_str())?
+                    .strip_prefix('-')?;
+                Some((format!("v{}", Config::normalize_version(version)), path))
+            })
+            .collect();
+        // Oldest first by semver, so the newest import is the last
+        bundles.sort_by_cached_key(|(version, _)| {
+            (Config::parse_version(version).ok(), version.clone())
+        });
+
+        let mut imported = Vec::new();
+        for (version, bundle) in bundles {
+            if self.data_dir.join(&version).is_dir() {
+                info!("Version {} is already installed, skipping", version);
+                continue;
+            }
+
+            match self.import_bundle(&version, &bundle, &checksums) {
+                Ok(()) => {
+                    info!("Imported version {} from USB", version);
+                    imported.push(version);
+                }
+                Err(e) => error!("Failed to import {}: {:#}", bundle.display(), e),
+            }
+        }
+        Ok(imported)
+    }
+
+    // Checks the detached signature next to a bundle on the stick, if there is one:
+    // a cosign bundle when cosign is configured, a minisign signature otherwise
+    fn bundle_signer(
+        &self,
+        bundle: &Path,
+        local_bundle: &Path,
+        sha256: &str,
+    ) -> Result<Option<String>> {
+        let sibling = |extension: &str| {
+            let mut name = bundle.as_os_str().to_owned();
+            name.push(format!(".{}", extension));
+            PathBuf::from(name)
+        };
+        if let Some(policy) = CosignPolicy::from_config()? {
+            let Ok(cosign) = fs::read_to_string(sibling(Config::COSIGN_BUNDLE_EXTENSION)) else {
+                return Ok(None);
+            };
+            let identity = policy
+                .verify(&cosign, sha256)
+                .context("Release bundle cosign signature does not verify")?;
+            return Ok(Some(identity));
+        }
+
+        let Ok(signature) = fs::read_to_string(sibling(Config::SIGNATURE_EXTENSION)) else {
+            return Ok(None);
+        };
+        let signer = KeyService::new(self.data_dir.clone())
+            .verify_file(local_bundle, &signature)
+            .context("Release bundle signature does not verify")?;
+        Ok(Some(signer.key_id))
+    }
+
+    fn import_bundle(&self, version: &str, bundle: &Path, checksums: &str) -> Result<()> {
+        self.fs_service
+            .ensure_disk_reserve(fs::metadata(bundle)?.len(), "the release bundle")?;
+
+        // Work on a local copy so pulling the stick mid-install can't corrupt anything,
+        // and so quarantining never deletes files from the stick
+        let temp_dir = tempfile::tempdir()?;
+        let local_bundle = temp_dir.path().join(Config::RELEASE_BUNDLE_NAME);
+        // Hashed while it is copied, so the stick is read once
+        let actual_sha256 = FileService::copy_with_sha256(bundle, &local_bundle)?;
+        let copied_at = Utc::now();
+
+        let file_name = bundle
+            .file_name()
+            .context("Bundle path has no file name")?
+            .to_string_lossy();
+        let expected_sha256 = registry::find_checksum(checksums, &file_name);
+
+        let installed = match &expected_sha256 {
+            Some(expected) => (|| -> Result<()> {
+                let bundle_signed_by = self.bundle_signer(bundle, &local_bundle, &actual_sha256)?;
+                let origin = BundleOrigin {
+                    backend: "usb".to_string(),
+                    source: bundle.display().to_string(),
+                    signed_by: None,
+                    bundle_signed_by,
+                };
+                self.fs_service.install_bundle(
+                    &local_bundle,
+                    temp_dir.path(),
+                    expected,
+                    &actual_sha256,
+                    version,
+                    origin,
+                )
+            })(),
+            None => Err(anyhow::anyhow!("No checksum listed for {}", file_name)),
+        };
+
+        if let Err(e) = installed {
+            let record = QuarantineRecord {
+                version: version.to_string(),
+                url: bundle.display().to_string(),
+                reason: format!("{:#}", e),
+                expected_sha256,
+                actual_sha256: Some(actual_sha256),
+                downloaded_at: copied_at,
+                quarantined_at: Utc::now(),
+            };
+            if let Err(qe) =
+                QuarantineService::new(self.data_dir.clone()).quarantine(&local_bundle, &record)
+            {
+                warn!("Failed to quarantine release bundle: {}", qe);
+            }
+            return Err(e);
+        }
+        Ok(())
+    }
+
+    /// Stages the version named by a signed `geist/plan.json` in the inactive slot.
+    /// Returns the planned version, or None when the stick carries no plan.
+    pub fn apply_plan(&self, stick: &Path) -> Result<Option<String>> {
+        let plan_path = stick.join(PLAN_FILE);
+        if !plan_path.is_file() {
+            return Ok(None);
+        }
+
+        let data = fs::read(&plan_path)?;
+        let signature = fs::read_to_string(stick.join(PLAN_SIGNATURE_FILE))
+            .with_context(|| format!("{} is not signed", plan_path.display()))?;
+        let signer = KeyService::new(self.data_dir.clone()).verify(&data, &signature)?;
+
+        let plan: UsbPlan = serde_json::from_slice(&data)
+            .with_context(|| format!("Failed to parse {}", plan_path.display()))?;
+        info!(
+            "Applying plan signed by key {}: boot {}",
+            signer.key_id, plan.version
+        );
+
+        if !self.held(&plan.version)
+            && self.migrated(&plan.version)
+            && self.fs_service.stage_in_inactive_slot(&plan.version)?
+        {
+            info!("Planned version {} staged for a trial boot", plan.version);
+        }
+        Ok(Some(plan.version))
+    }
+
+    // Whether the app data is ready for the version, running its migrations if it
+    // ships any; one that fails keeps the version from being staged
+    fn migrated(&self, version: &str) -> bool {
+        let from = self.fs_service.current_version();
+        match MigrationService::new(self.data_dir.clone()).migrate(version, &from) {
+            Ok(names) => {
+                if !names.is_empty() {
+                    info!(
+                        "Migrated the app data for {}: {}",
+                        version,
+                        names.join(", ")
+                    );
+                }
+                true
+            }
+            Err(e) => {
+                error!("Not staging {}: {:#}", version, e);
+                false
+            }
+        }
+    }
+
+    // Whether a pin keeps the version from being staged; it stays installed for later
+    fn held(&self, version: &str) -> bool {
+        let state = match StateService::new(self.data_dir.clone()).load() {
+            Ok(state) => state,
+            Err(e) => {
+                warn!("{:#}", e);
+                return false;
+            }
+        };
+        match state.hold_against(version) {
+            Some(hold) => {
+                info!("Not staging {}: the device is {}", version, hold.describe());
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Packs the supervisor state, event log, boot marker, trusted keys and quarantine metadata
+    /// into `geist/support/`
+    pub fn export_support_bundle(&self, stick: &Path) -> Result<PathBuf> {
+        let staging = tempfile::tempdir()?;
+
+        for file in [
+            Config::STATE_FILE,
+            Config::EVENTS_FILE,
+            Config::BOOT_MARKER_FILE,
+            Config::CURRENT_POINTER_FILE,
+            Config::CURRENT_VERSION_FILE,
+            Config::TRUSTED_KEYS_FILE,
+        ] {
+            let source = self.data_dir.join(file);
+            if source.is_file() {
+                fs::copy(&source, staging.path().join(file))?;
+            }
+        }
+
+        // Quarantined bundles can be large; their metadata is what matters for support
+        for entry in QuarantineService::new(self.data_dir.clone()).list()? {
+            let target = staging.path().join(Config::QUARANTINE_DIR).join(&entry.id);
+            fs::create_dir_all(&target)?;
+            fs::write(
+                target.join("metadata.json"),
+                serde_json::to_string_pretty(&entry.record)?,
+            )?;
+        }
+
+        let versions = self.fs_service.installed_versions()?;
+        fs::write(
+            staging.path().join("versions.txt"),
+            versions.join("\n") + "\n",
+        )?;
+
+        // Provenance of every installed binary
+        for version in &versions {
+            let record = self
+                .data_dir
+                .join(version)
+                .join(Config::INSTALL_RECORD_FILE);
+            if record.is_file() {
+                let target = staging.path().join("versions").join(version);
+                fs::create_dir_all(&target)?;
+                fs::copy(&record, target.join(Config::INSTALL_RECORD_FILE))?;
+            }
+        }
+
+        let support_dir = stick.join(SUPPORT_DIR);
+        fs::create_dir_all(&support_dir)?;
+        let archive = support_dir.join(format!(
+            "geist-support-{}.tar.gz",
+            Utc::now().format("%Y%m%dT%H%M%SZ")
+        ));
+
+        let file = fs::File::create(&archive)
+            .with_context(|| format!("Failed to create {}", archive.display()))?;
+        let mut builder = tar::Builder::new(GzEncoder::new(file, Compression::default()));
+        builder
+            .append_dir_all(".", staging.path())
+            .context("Failed to write support bundle")?;
+        let file = builder.into_inner()?.finish()?;
+
+        // Flush to the stick so it can be pulled right away
+        file.sync_all()?;
+        Ok(archive)
+    }
+}
+
+// Subdirectories of a directory, empty if it can't be read
+fn subdirs(dir: &Path) -> Vec<PathBuf> {
+    fs::read_dir(dir)
+        .map(|entries| {
+            entries
+                .filter_map(|entry| entry.ok().map(|e| e.path()))
+                .filter(|path| path.is_dir())
+                .collect()
+        })
+        .unwrap_or_default()
+}
@@ -0,0 +1,107 @@
+use crate::services::{ChangeKind, FileService, ManifestDiff};
+use anyhow::{Context, Result};
+use serde::Serialize;
+use std::collections::BTreeSet;
+use std::fs;
+use std::path::Path;
+
+/// One side of a file that differs between two versions
+#[derive(Debug, Clone, Serialize)]
+pub struct FileSide {
+    pub size: u64,
+    pub sha256: String,
+}
+
+/// A file added, removed or changed between two installed versions
+#[derive(Debug, Clone, Serialize)]
+pub struct FileChange {
+    pub path: String,
+    pub kind: ChangeKind,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub from: Option<FileSide>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub to: Option<FileSide>,
+}
+
+/// What actually differs between two installed versions: their files, compared by
+/// the hashes of what is on disk now, and their manifests, entry by entry
+#[derive(Debug, Clone, Serialize)]
+pub struct VersionDiff {
+    pub from_version: String,
+    pub to_version: String,
+    pub files: Vec<FileChange>,
+    /// None when either version has no manifest
+    pub manifest: Option<ManifestDiff>,
+}
+
+impl VersionDiff {
+    /// Compares two installed versions
+    pub fn between(fs_service: &FileService, from_version: &str, to_version: &str) -> Result<Self> {
+        let from_dir = fs_service.data_dir.join(from_version);
+        let to_dir = fs_service.data_dir.join(to_version);
+        for (version, dir) in [(from_version, &from_dir), (to_version, &to_dir)] {
+            if !dir.is_dir() {
+                anyhow::bail!("Version {} is not installed", version);
+            }
+        }
+
+        let from_files = FileService::file_hashes(&from_dir)?;
+        let to_files = FileService::file_hashes(&to_dir)?;
+        let paths: BTreeSet<&String> = from_files.keys().chain(to_files.keys()).collect();
+        let mut files = Vec::new();
+        for path in paths {
+            let (kind, from, to) = match (from_files.get(path), to_files.get(path)) {
+                (Some(from), Some(to)) if from == to => continue,
+                (Some(from), Some(to)) => (
+                    ChangeKind::Changed,
+                    Some(side(&from_dir, path, from)?),
+                    Some(side(&to_dir, path, to)?),
+                ),
+                (Some(from), None) => (
+                    ChangeKind::Removed,
+                    Some(side(&from_dir, path, from)?),
+                    None,
+                ),
+                (None, Some(to)) => (ChangeKind::Added, None, Some(side(&to_dir, path, to)?)),
+                (None, None) => continue,
+            };
+            files.push(FileChange {
+                path: path.clone(),
+                kind,
+                from,
+                to,
+            });
+        }
+
+        let from_manifest = from_dir.join("manifest.yaml");
+        let to_manifest = to_dir.join("manifest.yaml");
+        let manifest = if from_manifest.is_file() && to_manifest.is_file() {
+            Some(ManifestDiff::between(
+                from_version,
+                &fs::read_to_string(&from_manifest)?,
+                to_version,
+                &fs::read_to_string(&to_manifest)?,
+            )?)
+        } else {
+            None
+        };
+
+        Ok(Self {
+            from_version: from_version.to_string(),
+            to_version: to_version.to_string(),
+            files,
+            manifest,
+        })
+    }
+}
+
+fn side(dir: &Path, path: &str, sha256: &str) -> Result<FileSide> {
+    let file = dir.join(path);
+    let size = fs::symlink_metadata(&file)
+        .with_context(|| format!("Failed to read {}", file.display()))?
+        .len();
+    Ok(FileSide {
+        size,
+        sha256: sha256.to_string(),
+    })
+}
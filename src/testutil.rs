@@ -0,0 +1,327 @@
+use crate::config::Config;
+use crate::services::{BundleFormat, DeviceFixture, HostService};
+use anyhow::{Context, Result};
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use sha2::{Digest, Sha256};
+use std::fs;
+use std::io::{BufRead, BufReader, Write};
+use std::net::{TcpListener, TcpStream};
+use std::path::{Component, Path, PathBuf};
+use std::process::{Child, Command, Output};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+use tempfile::TempDir;
+
+// App used when a test release does not bring its own: stays up until stopped
+const DEFAULT_APP: &str = "#!/bin/sh\nwhile true; do sleep 1; done\n";
+
+/// A release to publish to a `TestRegistry`, with a shell script standing in for
+/// the `roc_camera` binary
+#[derive(Debug, Clone)]
+pub struct TestRelease {
+    pub version: String,
+    pub app: String,
+    pub manifest: String,
+}
+
+impl TestRelease {
+    pub fn new(version: &str) -> Self {
+        let version = Config::normalize_version(version);
+        Self {
+            manifest: format!("version: {}\n", version),
+            version,
+            app: DEFAULT_APP.to_string(),
+        }
+    }
+
+    /// Replaces the app, e.g. with `#!/bin/sh\nexit 1\n` for a release that crashes
+    pub fn app(mut self, script: &str) -> Self {
+        self.app = script.to_string();
+        self
+    }
+
+    /// Adds YAML to the manifest, e.g. a `health_probe` or `restart_policy` section
+    pub fn manifest(mut self, yaml: &str) -> Self {
+        self.manifest.push_str(yaml);
+        if !self.manifest.ends_with('\n') {
+            self.manifest.push('\n');
+        }
+        self
+    }
+
+    /// Writes the release bundle as `geist update` expects it
+    pub fn write_bundle(&self, path: &Path) -> Result<()> {
+        let file = fs::File::create(path)
+            .with_context(|| format!("Failed to create {}", path.display()))?;
+        let mut tar = tar::Builder::new(GzEncoder::new(file, Compression::default()));
+
+        Self::append(&mut tar, "roc_camera", self.app.as_bytes(), 0o755)?;
+        Self::append(&mut tar, "manifest.yaml", self.manifest.as_bytes(), 0o644)?;
+        Self::append(
+            &mut tar,
+            "roc_camera_app/index.txt",
+            self.version.as_bytes(),
+            0o644,
+        )?;
+        tar.into_inner()?.finish()?;
+        Ok(())
+    }
+
+    fn append<W: Write>(
+        tar: &mut tar::Builder<W>,
+        path: &str,
+        contents: &[u8],
+        mode: u32,
+    ) -> Result<()> {
+        let mut header = tar::Header::new_gnu();
+        header.set_size(contents.len() as u64);
+        header.set_mode(mode);
+        header.set_cksum();
+        tar.append_data(&mut header, path, contents)
+            .with_context(|| format!("Failed to add {} to the bundle", path))
+    }
+}
+
+/// A release registry on localhost laid out like the GCS bucket, for
+/// `GEIST_REGISTRY_URL`. Listing releases is not supported.
+pub struct TestRegistry {
+    root: TempDir,
+    url: String,
+    stop: Arc<AtomicBool>,
+}
+
+impl TestRegistry {
+    pub fn start() -> Result<Self> {
+        let root = tempfile::tempdir()?;
+        let listener = TcpListener::bind("127.0.0.1:0")?;
+        let url = format!("http://{}", listener.local_addr()?);
+        let stop = Arc::new(AtomicBool::new(false));
+
+        let dir = root.path().to_path_buf();
+        let stopped = stop.clone();
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                if stopped.load(Ordering::SeqCst) {
+                    break;
+                }
+                if let Ok(stream) = stream {
+                    Self::serve(&dir, stream).ok();
+                }
+            }
+        });
+        Ok(Self { root, url, stop })
+    }
+
+    pub fn url(&self) -> &str {
+        &self.url
+    }
+
+    /// Uploads a release with a checksum file listing its bundle
+    pub fn publish(&self, release: &TestRelease) -> Result<()> {
+        let dir = self.root.path().join("releases").join(&release.version);
+        fs::create_dir_all(&dir)?;
+
+        let bundle_name = BundleFormat::Gzip.file_name(&release.version);
+        let bundle_path = dir.join(&bundle_name);
+        release.write_bundle(&bundle_path)?;
+
+        let digest = hex::encode(Sha256::digest(fs::read(&bundle_path)?));
+        fs::write(
+            dir.join(Config::checksum_file_name()),
+            format!("{}  {}\n", digest, bundle_name),
+        )?;
+        Ok(())
+    }
+
+    /// Makes `version` what `geist update` without a version installs
+    pub fn set_latest(&self, version: &str) -> Result<()> {
+        let releases = self.root.path().join("releases");
+        fs::create_dir_all(&releases)?;
+        fs::write(
+            releases.join("latest"),
+            format!("v{}", Config::normalize_version(version)),
+        )?;
+        Ok(())
+    }
+
+    // Answers one GET or HEAD request with the file at its path
+    fn serve(root: &Path, mut stream: TcpStream) -> Result<()> {
+        let mut reader = BufReader::new(stream.try_clone()?);
+        let mut request_line = String::new();
+        reader.read_line(&mut request_line)?;
+        loop {
+            let mut header = String::new();
+            if reader.read_line(&mut header)? == 0 || header.trim().is_empty() {
+                break;
+            }
+        }
+
+        let mut parts = request_line.split_whitespace();
+        let method = parts.next().unwrap_or_default();
+        let target = parts.next().unwrap_or_default();
+        let path = Path::new(
+            target
+                .split('?')
+                .next()
+                .unwrap_or_default()
+                .trim_start_matches('/'),
+        );
+
+        let contents = path
+            .components()
+            .all(|component| matches!(component, Component::Normal(_)))
+            .then(|| fs::read(root.join(path)).ok())
+            .flatten();
+        let (status, body) = match contents {
+            Some(body) => ("200 OK", body),
+            None => ("404 Not Found", Vec::new()),
+        };
+
+        write!(
+            stream,
+            "HTTP/1.1 {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+            status,
+            body.len()
+        )?;
+        if method != "HEAD" {
+            stream.write_all(&body)?;
+        }
+        Ok(())
+    }
+}
+
+impl Drop for TestRegistry {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::SeqCst);
+        // Wakes the server thread so it sees the flag
+        TcpStream::connect(self.url.trim_start_matches("http://")).ok();
+    }
+}
+
+/// A simulated device with its own home directory, on which the geist binary is
+/// run with `--fake-device`. Integration tests pass `env!("CARGO_BIN_EXE_geist")`.
+pub struct TestDevice {
+    home: TempDir,
+    geist: PathBuf,
+    fixture_path: PathBuf,
+    registry_url: Option<String>,
+}
+
+impl TestDevice {
+    pub fn new(geist: impl Into<PathBuf>, fixture: &DeviceFixture) -> Result<Self> {
+        let home = tempfile::tempdir()?;
+        let fixture_path = home.path().join("device.yaml");
+        fs::write(&fixture_path, serde_yaml::to_string(fixture)?)?;
+        Ok(Self {
+            home,
+            geist: geist.into(),
+            fixture_path,
+            registry_url: None,
+        })
+    }
+
+    /// Points updates at a test registry
+    pub fn with_registry(mut self, registry: &TestRegistry) -> Self {
+        self.registry_url = Some(registry.url().to_string());
+        self
+    }
+
+    pub fn data_dir(&self) -> PathBuf {
+        self.home.path().join(".local/share/roc-supervisor")
+    }
+
+    /// Replaces the device facts, e.g. to let the CPU overheat mid-test
+    pub fn set_fixture(&self, fixture: &DeviceFixture) -> Result<()> {
+        fs::write(&self.fixture_path, serde_yaml::to_string(fixture)?)?;
+        Ok(())
+    }
+
+    /// A geist command line running on this device
+    pub fn command(&self, args: &[&str]) -> Command {
+        let mut command = Command::new(&self.geist);
+        command
+            .args(args)
+            .env("HOME", self.home.path())
+            // Pinned, since root would otherwise share the system-wide data directory
+            .env("GEIST_DATA_DIR", self.data_dir())
+            .env("GEIST_FAKE_DEVICE", &self.fixture_path)
+            .env("GEIST_REGISTRY_SOURCE", "gcs")
+            .env_remove("GOOGLE_APPLICATION_CREDENTIALS")
+            .env_remove("GEIST_CURRENT_VERSION")
+            .env_remove("GEIST_PROFILE")
+            .env("RUST_LOG", "warn");
+        if let Some(url) = &self.registry_url {
+            command.env("GEIST_REGISTRY_URL", url);
+        }
+        command
+    }
+
+    /// Runs geist to completion, failing with its output if it fails
+    pub fn geist(&self, args: &[&str]) -> Result<Output> {
+        let output = self
+            .command(args)
+            .output()
+            .with_context(|| format!("Failed to run geist {}", args.join(" ")))?;
+        if !output.status.success() {
+            anyhow::bail!(
+                "geist {} failed with {}:\n{}{}",
+                args.join(" "),
+                output.status,
+                String::from_utf8_lossy(&output.stdout),
+                String::from_utf8_lossy(&output.stderr)
+            );
+        }
+        Ok(output)
+    }
+
+    /// Starts a long-running command such as `supervise`, logging to a file in the home directory
+    pub fn spawn(&self, args: &[&str]) -> Result<Child> {
+        let log = fs::File::create(self.home.path().join("geist.log"))?;
+        Ok(self
+            .command(args)
+            .stdout(log.try_clone()?)
+            .stderr(log)
+            .spawn()?)
+    }
+
+    /// `geist status --json`
+    pub fn status(&self) -> Result<serde_json::Value> {
+        let output = self.geist(&["status", "--json"])?;
+        serde_json::from_slice(&output.stdout).context("Failed to parse status")
+    }
+
+    /// Polls the status until `condition` holds, e.g. until a trial version became current
+    pub fn wait_for(
+        &self,
+        timeout: Duration,
+        condition: impl Fn(&serde_json::Value) -> bool,
+    ) -> Result<serde_json::Value> {
+        let deadline = Instant::now() + timeout;
+        loop {
+            let status = self.status()?;
+            if condition(&status) {
+                return Ok(status);
+            }
+            if Instant::now() >= deadline {
+                anyhow::bail!("Timed out after {:?}, last status: {}", timeout, status);
+            }
+            thread::sleep(Duration::from_millis(200));
+        }
+    }
+}
+
+// Keeps spawned supervisors from outliving a failed test
+#[cfg(unix)]
+impl Drop for TestDevice {
+    fn drop(&mut self) {
+        // A killed supervisor leaves its app running
+        for file in [Config::SUPERVISOR_PID_FILE, Config::APP_PID_FILE] {
+            if let Some(pid) = HostService::running_pid(&self.data_dir().join(file)) {
+                unsafe { libc::kill(pid as libc::pid_t, libc::SIGTERM) };
+            }
+        }
+    }
+}
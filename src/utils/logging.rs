@@ -1,3 +1,4 @@
+use std::io::IsTerminal;
 use tracing_subscriber::{fmt, fmt::format::FmtSpan, EnvFilter};
 
 pub fn init_logging() {
@@ -9,6 +10,35 @@ pub fn init_logging() {
     // Initialize tracing subscriber with formatting and filtering
     let filter = EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("info"));
 
+    // Logs go to stderr so stdout stays parseable, e.g. for `--dry-run` plans.
+    // Keep escape codes out of log files, e.g. when running with `run --daemon`
+    let ansi = std::io::stderr().is_terminal();
+
+    // When a collector is configured, export spans alongside the regular log output
+    #[cfg(feature = "otel")]
+    {
+        use tracing_subscriber::layer::SubscriberExt;
+        use tracing_subscriber::util::SubscriberInitExt;
+
+        if let Some(otel_layer) = super::telemetry::layer() {
+            if tracing_subscriber::registry()
+                .with(filter)
+                .with(
+                    fmt::layer()
+                        .with_target(false)
+                        .with_ansi(ansi)
+                        .with_writer(std::io::stderr),
+                )
+                .with(otel_layer)
+                .try_init()
+                .is_err()
+            {
+                // Subscriber already set, nothing to do
+            }
+            return;
+        }
+    }
+
     // When debug assertions are enabled or the verbose_logging feature is enabled,
     // include more detailed information
     #[cfg(any(debug_assertions, feature = "verbose_logging"))]
@@ -16,6 +46,8 @@ pub fn init_logging() {
         if fmt::Subscriber::builder()
             .with_env_filter(filter)
             .with_span_events(FmtSpan::CLOSE)
+            .with_ansi(ansi)
+            .with_writer(std::io::stderr)
             .with_target(true)
             .with_thread_ids(true)
             .with_thread_names(true)
@@ -45,7 +77,8 @@ pub fn init_logging() {
             .with_thread_names(false)
             .with_file(false)
             .with_line_number(false)
-            .with_ansi(true)
+            .with_ansi(ansi)
+            .with_writer(std::io::stderr)
             .event_format(format)
             .try_init()
             .is_err()
@@ -1 +1,3 @@
 pub mod logging;
+pub mod signals;
+pub mod telemetry;
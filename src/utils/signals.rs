@@ -0,0 +1,35 @@
+// Stop requests from SIGINT/SIGTERM.
+//
+// The handlers only record the signal; the supervisor polls for it, forwards it
+// to the app and shuts down through its normal return path so pid files and
+// temporary state get cleaned up.
+
+use std::sync::atomic::{AtomicI32, Ordering};
+
+static RECEIVED: AtomicI32 = AtomicI32::new(0);
+
+#[cfg(unix)]
+extern "C" fn record(signal: libc::c_int) {
+    RECEIVED.store(signal, Ordering::SeqCst);
+}
+
+/// Routes SIGINT and SIGTERM to `pending` instead of terminating the process
+#[cfg(unix)]
+pub fn install() {
+    let handler = record as extern "C" fn(libc::c_int) as libc::sighandler_t;
+    unsafe {
+        libc::signal(libc::SIGINT, handler);
+        libc::signal(libc::SIGTERM, handler);
+    }
+}
+
+#[cfg(not(unix))]
+pub fn install() {}
+
+/// The stop signal received so far, if any
+pub fn pending() -> Option<i32> {
+    match RECEIVED.load(Ordering::SeqCst) {
+        0 => None,
+        signal => Some(signal),
+    }
+}
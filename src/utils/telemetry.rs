@@ -0,0 +1,71 @@
+// Optional OTLP export of tracing spans.
+//
+// Export is compiled in with the `otel` feature and only switched on when a
+// collector is configured through the standard OTEL_EXPORTER_OTLP_ENDPOINT (or
+// OTEL_EXPORTER_OTLP_TRACES_ENDPOINT) environment variable.
+
+#[cfg(feature = "otel")]
+mod otlp {
+    use opentelemetry::trace::TracerProvider;
+    use opentelemetry_otlp::SpanExporter;
+    use opentelemetry_sdk::trace::SdkTracerProvider;
+    use opentelemetry_sdk::Resource;
+    use std::env;
+    use std::sync::OnceLock;
+    use tracing_subscriber::registry::LookupSpan;
+
+    static PROVIDER: OnceLock<SdkTracerProvider> = OnceLock::new();
+
+    fn endpoint_configured() -> bool {
+        env::var_os("OTEL_EXPORTER_OTLP_ENDPOINT").is_some()
+            || env::var_os("OTEL_EXPORTER_OTLP_TRACES_ENDPOINT").is_some()
+    }
+
+    pub fn layer<S>(
+    ) -> Option<tracing_opentelemetry::OpenTelemetryLayer<S, opentelemetry_sdk::trace::Tracer>>
+    where
+        S: tracing::Subscriber + for<'span> LookupSpan<'span>,
+    {
+        if !endpoint_configured() {
+            return None;
+        }
+
+        let exporter = match SpanExporter::builder().with_http().build() {
+            Ok(exporter) => exporter,
+            Err(e) => {
+                eprintln!("Failed to create OTLP exporter: {}", e);
+                return None;
+            }
+        };
+
+        let provider = SdkTracerProvider::builder()
+            .with_batch_exporter(exporter)
+            .with_resource(
+                Resource::builder()
+                    .with_service_name("geist-supervisor")
+                    .build(),
+            )
+            .build();
+        let tracer = provider.tracer("geist_supervisor");
+        let _ = PROVIDER.set(provider);
+
+        Some(tracing_opentelemetry::layer().with_tracer(tracer))
+    }
+
+    pub fn shutdown() {
+        if let Some(provider) = PROVIDER.get() {
+            if let Err(e) = provider.shutdown() {
+                eprintln!("Failed to flush OTLP spans: {}", e);
+            }
+        }
+    }
+}
+
+#[cfg(feature = "otel")]
+pub use otlp::layer;
+
+/// Flushes any spans still buffered for export. A no-op without the `otel` feature.
+pub fn shutdown() {
+    #[cfg(feature = "otel")]
+    otlp::shutdown();
+}
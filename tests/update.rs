@@ -0,0 +1,81 @@
+#![cfg(unix)]
+
+use anyhow::Result;
+use geist_supervisor::config::Config;
+use geist_supervisor::services::{BundleFormat, DeviceFixture, FileService};
+use geist_supervisor::testutil::{TestDevice, TestRegistry, TestRelease};
+use sha2::{Digest, Sha256};
+use std::fs;
+
+// A minisign key, and its signature of a file other than any checksum file
+const KEY: &str =
+    "untrusted comment: test key\nRWQLvufulHnXfsMBrbh6y1gRmLROEIQje2Of+cabvuHowV8W73Ab9qe4\n";
+const SIGNATURE: &str = "untrusted comment: sig\nRUQLvufulHnXfiHpN7ZVYDFegRMLpZwgUzxO/vP1bUXCjWcI+F04TcCsJWR2hwq2QOAzvN3h0h1OSP+Ncnu1TUf0+9F4dZ/sygs=\ntrusted comment: timestamp:0\tfile:test\n4bJzXgouNCe2YsO5yilVj2/HVAj+XF8+57p4MVOLUERS8NtmH6u0a2Y5lJ4Jiynz3aeOmtF3RbENnRgqvn3RBg==\n";
+
+#[test]
+fn an_updated_version_is_listed_as_installed() -> Result<()> {
+    let registry = TestRegistry::start()?;
+    registry.publish(&TestRelease::new("1.0.0"))?;
+    registry.publish(&TestRelease::new("1.1.0"))?;
+    registry.set_latest("1.1.0")?;
+    let device = TestDevice::new(env!("CARGO_BIN_EXE_geist"), &DeviceFixture::default())?
+        .with_registry(&registry);
+    // Left by a geist that installed versions under the name they were given as
+    fs::create_dir_all(device.data_dir().join("0.9.0"))?;
+
+    device.geist(&["update", "1.0.0"])?;
+    device.geist(&["update"])?;
+
+    let installed = FileService::new(device.data_dir()).installed_versions()?;
+    assert_eq!(installed, ["0.9.0", "v1.0.0", "v1.1.0"]);
+    let listing = String::from_utf8(device.geist(&["versions"])?.stdout)?;
+    for version in &installed {
+        assert!(listing.contains(version.as_str()), "{}", listing);
+    }
+    Ok(())
+}
+
+#[test]
+fn a_bundle_whose_checksum_signature_does_not_verify_is_not_installed() -> Result<()> {
+    let device = TestDevice::new(env!("CARGO_BIN_EXE_geist"), &DeviceFixture::default())?;
+    let stick = tempfile::tempdir()?;
+    let key = stick.path().join("release.pub");
+    fs::write(&key, KEY)?;
+    device.geist(&["keys", "add", &key.to_string_lossy()])?;
+    let bundle = stick.path().join(BundleFormat::Gzip.file_name("1.0.0"));
+    TestRelease::new("1.0.0").write_bundle(&bundle)?;
+    fs::write(
+        stick.path().join(Config::checksum_file_name()),
+        format!(
+            "{}  {}\n",
+            hex::encode(Sha256::digest(fs::read(&bundle)?)),
+            BundleFormat::Gzip.file_name("1.0.0")
+        ),
+    )?;
+    fs::write(
+        stick.path().join(Config::checksum_signature_file_name()),
+        SIGNATURE,
+    )?;
+    let bundle = bundle.to_string_lossy();
+
+    let output = device
+        .command(&["update", "--from-file", &bundle])
+        .output()?;
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(!output.status.success());
+    assert!(
+        stderr.contains("Checksum file signature of v1.0.0 does not verify"),
+        "{}",
+        stderr
+    );
+    assert!(FileService::new(device.data_dir())
+        .installed_versions()?
+        .is_empty());
+
+    device.geist(&["update", "--from-file", &bundle, "--skip-signature"])?;
+    assert_eq!(
+        FileService::new(device.data_dir()).installed_versions()?,
+        ["v1.0.0"]
+    );
+    Ok(())
+}
@@ -0,0 +1,85 @@
+#![cfg(unix)]
+
+use anyhow::Result;
+use geist_supervisor::services::DeviceFixture;
+use geist_supervisor::testutil::{TestDevice, TestRegistry, TestRelease};
+use std::time::Duration;
+
+#[test]
+fn a_crashing_update_is_rolled_back() -> Result<()> {
+    let registry = TestRegistry::start()?;
+    registry.publish(&TestRelease::new("1.0.0"))?;
+    registry.publish(&TestRelease::new("1.1.0").app("#!/bin/sh\nexit 1\n"))?;
+    let device = TestDevice::new(env!("CARGO_BIN_EXE_geist"), &DeviceFixture::default())?
+        .with_registry(&registry);
+
+    // The first install has nothing to fall back to and becomes current right away
+    device.geist(&["update", "1.0.0"])?;
+    device.geist(&["update", "1.1.0"])?;
+    let status = device.status()?;
+    assert_eq!(status["current_version"], "v1.0.0");
+    assert_eq!(status["boot_marker"]["b"], "v1.1.0");
+    assert_eq!(status["boot_marker"]["trial"], "b");
+
+    let mut run = device.spawn(&["run"])?;
+    let status = device.wait_for(Duration::from_secs(60), |status| {
+        status["last_exit"]["version"] == "v1.1.0" && !status["app_pid"].is_null()
+    });
+    run.kill()?;
+    run.wait()?;
+    let status = status?;
+
+    assert_eq!(status["last_exit"]["success"], false);
+    assert_eq!(status["current_version"], "v1.0.0");
+    assert_eq!(status["last_known_good"], "v1.0.0");
+    assert!(status["boot_marker"]["trial"].is_null());
+    Ok(())
+}
+
+#[test]
+fn rollback_switches_to_a_good_version_and_refuses_bad_ones() -> Result<()> {
+    let registry = TestRegistry::start()?;
+    registry.publish(&TestRelease::new("1.0.0"))?;
+    registry.publish(&TestRelease::new("1.1.0"))?;
+    let device = TestDevice::new(env!("CARGO_BIN_EXE_geist"), &DeviceFixture::default())?
+        .with_registry(&registry);
+    device.geist(&["update", "1.0.0"])?;
+    device.geist(&["update", "1.1.0"])?;
+    let current = |device: &TestDevice| -> Result<serde_json::Value> {
+        let status = device.status()?;
+        let marker = &status["boot_marker"];
+        assert!(marker["trial"].is_null());
+        assert_eq!(
+            marker[marker["active"].as_str().unwrap()],
+            status["current_version"]
+        );
+        Ok(status["current_version"].clone())
+    };
+
+    // Any installed version can be named, also one still on trial
+    device.geist(&["rollback", "1.1.0"])?;
+    assert_eq!(current(&device)?, "v1.1.0");
+
+    // The first install became the last known-good version
+    device.geist(&["rollback"])?;
+    assert_eq!(current(&device)?, "v1.0.0");
+
+    device.geist(&["mark-bad", "1.1.0"])?;
+    let error = device.geist(&["rollback", "1.1.0"]).unwrap_err();
+    assert!(error.to_string().contains("is marked bad"), "{}", error);
+    assert_eq!(current(&device)?, "v1.0.0");
+    device.geist(&["rollback", "1.1.0", "--force"])?;
+    assert_eq!(current(&device)?, "v1.1.0");
+
+    device.geist(&["mark-bad", "1.0.0"])?;
+    let error = device.geist(&["rollback"]).unwrap_err();
+    assert!(
+        error.to_string().contains("No known-good version"),
+        "{}",
+        error
+    );
+    device.geist(&["mark-good", "1.0.0"])?;
+    device.geist(&["rollback"])?;
+    assert_eq!(current(&device)?, "v1.0.0");
+    Ok(())
+}